@@ -1,13 +1,22 @@
+//! Build MIR from the sources vendored in `mir/`, or with the `system-mir`
+//! feature link an installed `libmir`, found through `MIR_LIB_DIR` and
+//! `MIR_INCLUDE_DIR` if they're set. Either way, generate bindings to it.
+
 use std::env;
 use std::path::PathBuf;
 
 fn main() {
-    println!("cargo:rustc-link-search=./mir/");
-    println!("cargo:rustc-link-lib=mir");
-    println!("cargo:rerun-if-changed=mir.h");
+    let include = if env::var_os("CARGO_FEATURE_SYSTEM_MIR").is_some() {
+        system()
+    } else {
+        vendored()
+    };
 
     let bindings = bindgen::Builder::default()
-        .header("./mir/mir.h")
+        .header(include.join("mir.h").to_string_lossy())
+        .header(include.join("mir-gen.h").to_string_lossy())
+        .clang_arg(format!("-I{}", include.display()))
+        .prepend_enum_name(false)
         .parse_callbacks(Box::new(bindgen::CargoCallbacks))
         .generate()
         .expect("Unable to generate bindings to MIR!");
@@ -17,3 +26,42 @@ fn main() {
         .write_to_file(out_path.join("bindings.rs"))
         .expect("Couldn't write bindings!");
 }
+
+/// Compile `mir/` into a static library, returning where its headers are.
+/// `mir.c` and `mir-gen.c` include the interpreter and the code for the
+/// target themselves.
+fn vendored() -> PathBuf {
+    let dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("mir");
+    if !dir.join("mir.c").exists() {
+        panic!(
+            "the MIR sources aren't in {}: run `rs-mir/vendor-mir.sh`, \
+             or build with the `system-mir` feature",
+            dir.display()
+        );
+    }
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    cc::Build::new()
+        .file(dir.join("mir.c"))
+        .file(dir.join("mir-gen.c"))
+        .include(&dir)
+        .flag_if_supported("-std=gnu11")
+        .flag_if_supported("-fsigned-char")
+        .flag_if_supported("-Wno-abi")
+        .warnings(false)
+        .compile("mir");
+    dir
+}
+
+/// Link the installed MIR, returning where its headers are
+fn system() -> PathBuf {
+    println!("cargo:rerun-if-env-changed=MIR_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=MIR_INCLUDE_DIR");
+    if let Some(lib) = env::var_os("MIR_LIB_DIR") {
+        println!("cargo:rustc-link-search=native={}", lib.to_string_lossy());
+    }
+    println!("cargo:rustc-link-lib=mir");
+    env::var_os("MIR_INCLUDE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/usr/local/include"))
+}
@@ -0,0 +1,728 @@
+//! Safe construction of MIR modules through the C API
+//!
+//! MIR builds one module at a time, and one function at a time within it,
+//! so both are built inside closures which finish them when they return.
+//! Registers, labels and items are branded with the function or module they
+//! came from, and can't be used anywhere else.
+
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::ptr;
+use std::rc::Rc;
+
+use crate::ir::Type;
+use crate::sys;
+use crate::{MirError, MirResult};
+
+/// An invariant lifetime, naming one module or function being built
+type Brand<'id> = PhantomData<fn(&'id ()) -> &'id ()>;
+
+pub(crate) fn c_name(name: &str) -> MirResult<CString> {
+    CString::new(name).map_err(|_| MirError::Name(name.to_owned()))
+}
+
+impl Type {
+    pub(crate) fn to_sys(self) -> sys::MIR_type_t {
+        match self {
+            Self::I8 => sys::MIR_T_I8,
+            Self::U8 => sys::MIR_T_U8,
+            Self::I16 => sys::MIR_T_I16,
+            Self::U16 => sys::MIR_T_U16,
+            Self::I32 => sys::MIR_T_I32,
+            Self::U32 => sys::MIR_T_U32,
+            Self::I64 => sys::MIR_T_I64,
+            Self::U64 => sys::MIR_T_U64,
+            Self::F => sys::MIR_T_F,
+            Self::D => sys::MIR_T_D,
+            Self::LD => sys::MIR_T_LD,
+            Self::P => sys::MIR_T_P,
+        }
+    }
+}
+
+/// The types a register can have: arguments and memory can be narrower
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegType {
+    I64,
+    F,
+    D,
+    LD,
+}
+
+impl RegType {
+    pub fn of(ty: Type) -> Option<Self> {
+        match ty {
+            Type::I64 | Type::U64 | Type::P => Some(Self::I64),
+            Type::F => Some(Self::F),
+            Type::D => Some(Self::D),
+            Type::LD => Some(Self::LD),
+            _ => None,
+        }
+    }
+
+    /// The type of register a value of type `ty` is held in once loaded
+    pub fn holding(ty: Type) -> Self {
+        Self::of(ty).unwrap_or(Self::I64)
+    }
+
+    fn to_sys(self) -> sys::MIR_type_t {
+        match self {
+            Self::I64 => sys::MIR_T_I64,
+            Self::F => sys::MIR_T_F,
+            Self::D => sys::MIR_T_D,
+            Self::LD => sys::MIR_T_LD,
+        }
+    }
+}
+
+/// A typed argument in a signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arg {
+    pub ty: Type,
+    pub name: String,
+}
+
+/// An item of the module `'m`
+#[derive(Debug, Clone, Copy)]
+pub struct ItemRef<'m> {
+    pub(crate) item: sys::MIR_item_t,
+    _brand: Brand<'m>,
+}
+
+/// A prototype of the module `'m`, remembering its signature so calls
+/// through it can be checked
+#[derive(Debug, Clone)]
+pub struct ProtoRef<'m> {
+    item: ItemRef<'m>,
+    results: Rc<[Type]>,
+    args: Rc<[Type]>,
+}
+
+impl ProtoRef<'_> {
+    pub fn results(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn args(&self) -> usize {
+        self.args.len()
+    }
+}
+
+/// A register of the function `'f`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reg<'f> {
+    reg: sys::MIR_reg_t,
+    ty: RegType,
+    _brand: Brand<'f>,
+}
+
+impl Reg<'_> {
+    pub fn ty(&self) -> RegType {
+        self.ty
+    }
+}
+
+/// A label in the function `'f`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label<'f> {
+    insn: sys::MIR_insn_t,
+    _brand: Brand<'f>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scale {
+    S1 = 1,
+    S2 = 2,
+    S4 = 4,
+    S8 = 8,
+}
+
+/// A value of type `ty` in memory, at `disp + base + index * scale`
+#[derive(Debug, Clone, Copy)]
+pub struct Mem<'f> {
+    ty: Type,
+    disp: i64,
+    base: Option<Reg<'f>>,
+    index: Option<(Reg<'f>, Scale)>,
+}
+
+impl<'f> Mem<'f> {
+    pub fn at(ty: Type, disp: i64, base: Reg<'f>) -> Self {
+        Self {
+            ty,
+            disp,
+            base: Some(base),
+            index: None,
+        }
+    }
+
+    /// An absolute address
+    pub fn absolute(ty: Type, disp: i64) -> Self {
+        Self {
+            ty,
+            disp,
+            base: None,
+            index: None,
+        }
+    }
+
+    pub fn indexed(self, index: Reg<'f>, scale: Scale) -> Self {
+        Self {
+            index: Some((index, scale)),
+            ..self
+        }
+    }
+}
+
+/// Something an instruction can read
+#[derive(Debug, Clone, Copy)]
+pub enum Src<'m, 'f> {
+    Reg(Reg<'f>),
+    Int(i64),
+    Ref(ItemRef<'m>),
+    Mem(Mem<'f>),
+}
+
+/// Something an instruction can write
+#[derive(Debug, Clone, Copy)]
+pub enum Dst<'f> {
+    Reg(Reg<'f>),
+    Mem(Mem<'f>),
+}
+
+impl Src<'_, '_> {
+    /// The type of register the operand's value fits in
+    pub fn ty(&self) -> RegType {
+        match self {
+            Self::Reg(r) => r.ty,
+            Self::Int(_) | Self::Ref(_) => RegType::I64,
+            Self::Mem(m) => RegType::holding(m.ty),
+        }
+    }
+}
+
+impl<'m, 'f> From<Reg<'f>> for Src<'m, 'f> {
+    fn from(r: Reg<'f>) -> Self {
+        Self::Reg(r)
+    }
+}
+
+impl<'m, 'f> From<i64> for Src<'m, 'f> {
+    fn from(n: i64) -> Self {
+        Self::Int(n)
+    }
+}
+
+impl<'m, 'f> From<ItemRef<'m>> for Src<'m, 'f> {
+    fn from(item: ItemRef<'m>) -> Self {
+        Self::Ref(item)
+    }
+}
+
+impl<'m, 'f> From<Mem<'f>> for Src<'m, 'f> {
+    fn from(m: Mem<'f>) -> Self {
+        Self::Mem(m)
+    }
+}
+
+impl<'m, 'f> From<Dst<'f>> for Src<'m, 'f> {
+    fn from(d: Dst<'f>) -> Self {
+        match d {
+            Dst::Reg(r) => Self::Reg(r),
+            Dst::Mem(m) => Self::Mem(m),
+        }
+    }
+}
+
+impl<'f> From<Reg<'f>> for Dst<'f> {
+    fn from(r: Reg<'f>) -> Self {
+        Self::Reg(r)
+    }
+}
+
+impl<'f> From<Mem<'f>> for Dst<'f> {
+    fn from(m: Mem<'f>) -> Self {
+        Self::Mem(m)
+    }
+}
+
+/// Instructions taking a destination and two sources, on 64-bit integers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Udiv,
+    Mod,
+    Umod,
+    And,
+    Or,
+    Xor,
+    Lsh,
+    Rsh,
+    Ursh,
+    /// Set the overflow flag, for `FuncBuilder::branch_overflow`
+    Addo,
+    Subo,
+    Mulo,
+    Umulo,
+    Eq,
+    Ne,
+    Lt,
+    Ult,
+    Le,
+    Ule,
+    Gt,
+    Ugt,
+    Ge,
+    Uge,
+}
+
+impl BinOp {
+    fn to_sys(self) -> sys::MIR_insn_code_t {
+        match self {
+            Self::Add => sys::MIR_ADD,
+            Self::Sub => sys::MIR_SUB,
+            Self::Mul => sys::MIR_MUL,
+            Self::Div => sys::MIR_DIV,
+            Self::Udiv => sys::MIR_UDIV,
+            Self::Mod => sys::MIR_MOD,
+            Self::Umod => sys::MIR_UMOD,
+            Self::And => sys::MIR_AND,
+            Self::Or => sys::MIR_OR,
+            Self::Xor => sys::MIR_XOR,
+            Self::Lsh => sys::MIR_LSH,
+            Self::Rsh => sys::MIR_RSH,
+            Self::Ursh => sys::MIR_URSH,
+            Self::Addo => sys::MIR_ADDO,
+            Self::Subo => sys::MIR_SUBO,
+            Self::Mulo => sys::MIR_MULO,
+            Self::Umulo => sys::MIR_UMULO,
+            Self::Eq => sys::MIR_EQ,
+            Self::Ne => sys::MIR_NE,
+            Self::Lt => sys::MIR_LT,
+            Self::Ult => sys::MIR_ULT,
+            Self::Le => sys::MIR_LE,
+            Self::Ule => sys::MIR_ULE,
+            Self::Gt => sys::MIR_GT,
+            Self::Ugt => sys::MIR_UGT,
+            Self::Ge => sys::MIR_GE,
+            Self::Uge => sys::MIR_UGE,
+        }
+    }
+}
+
+/// Conditions for `FuncBuilder::branch`, comparing two 64-bit integers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Ult,
+    Le,
+    Ule,
+    Gt,
+    Ugt,
+    Ge,
+    Uge,
+}
+
+impl Cond {
+    fn to_sys(self) -> sys::MIR_insn_code_t {
+        match self {
+            Self::Eq => sys::MIR_BEQ,
+            Self::Ne => sys::MIR_BNE,
+            Self::Lt => sys::MIR_BLT,
+            Self::Ult => sys::MIR_UBLT,
+            Self::Le => sys::MIR_BLE,
+            Self::Ule => sys::MIR_UBLE,
+            Self::Gt => sys::MIR_BGT,
+            Self::Ugt => sys::MIR_UBGT,
+            Self::Ge => sys::MIR_BGE,
+            Self::Uge => sys::MIR_UBGE,
+        }
+    }
+}
+
+/// Make the C array of a signature. The names have to outlive the call
+/// using the array.
+fn vars(args: &[Arg]) -> MirResult<(Vec<CString>, Vec<sys::MIR_var_t>)> {
+    let names = args
+        .iter()
+        .map(|a| c_name(&a.name))
+        .collect::<MirResult<Vec<_>>>()?;
+    let vars = args
+        .iter()
+        .zip(&names)
+        .map(|(a, name)| sys::MIR_var_t {
+            type_: a.ty.to_sys(),
+            name: name.as_ptr(),
+            size: 0,
+        })
+        .collect();
+    Ok((names, vars))
+}
+
+/// The module being built, named `'m`
+pub struct ModuleBuilder<'m> {
+    ctx: sys::MIR_context_t,
+    names: HashSet<String>,
+    _brand: Brand<'m>,
+}
+
+impl<'m> ModuleBuilder<'m> {
+    /// Start a module in `ctx`, which must not be building one already
+    pub(crate) unsafe fn start(ctx: sys::MIR_context_t, name: &str) -> MirResult<Self> {
+        let name = c_name(name)?;
+        sys::MIR_new_module(ctx, name.as_ptr());
+        Ok(Self {
+            ctx,
+            names: HashSet::new(),
+            _brand: PhantomData,
+        })
+    }
+
+    pub(crate) fn finish(self) -> sys::MIR_module_t {
+        // SAFETY: this is the module being built
+        unsafe { sys::MIR_finish_module(self.ctx) };
+        // MIR keeps modules in order, so the one just finished is last
+        unsafe { sys::MIR_get_module_list(self.ctx).as_ref() }
+            .map_or(ptr::null_mut(), |list| list.tail)
+    }
+
+    fn item(
+        &mut self,
+        name: &str,
+        make: impl FnOnce(&CString) -> sys::MIR_item_t,
+    ) -> MirResult<ItemRef<'m>> {
+        if !self.names.insert(name.to_owned()) {
+            return Err(MirError::Duplicate(name.to_owned()));
+        }
+        let c = c_name(name)?;
+        Ok(ItemRef {
+            item: make(&c),
+            _brand: PhantomData,
+        })
+    }
+
+    pub fn import(&mut self, name: &str) -> MirResult<ItemRef<'m>> {
+        let ctx = self.ctx;
+        // SAFETY: `ctx` is building this module, and MIR copies the name
+        self.item(name, |c| unsafe { sys::MIR_new_import(ctx, c.as_ptr()) })
+    }
+
+    /// Export an item defined in this module
+    pub fn export(&mut self, name: &str) -> MirResult<()> {
+        let c = c_name(name)?;
+        // SAFETY: as for `import`
+        unsafe { sys::MIR_new_export(self.ctx, c.as_ptr()) };
+        Ok(())
+    }
+
+    /// An item defined later in the module, by a function or data
+    pub fn forward(&mut self, name: &str) -> MirResult<ItemRef<'m>> {
+        let c = c_name(name)?;
+        // SAFETY: as for `import`
+        let item = unsafe { sys::MIR_new_forward(self.ctx, c.as_ptr()) };
+        Ok(ItemRef {
+            item,
+            _brand: PhantomData,
+        })
+    }
+
+    /// `size` bytes of zeroed data
+    pub fn bss(&mut self, name: &str, size: usize) -> MirResult<ItemRef<'m>> {
+        let ctx = self.ctx;
+        // SAFETY: as for `import`
+        self.item(name, |c| unsafe { sys::MIR_new_bss(ctx, c.as_ptr(), size) })
+    }
+
+    pub fn proto(&mut self, name: &str, results: &[Type], args: &[Arg]) -> MirResult<ProtoRef<'m>> {
+        let ctx = self.ctx;
+        let (_names, mut vars) = vars(args)?;
+        let mut res: Vec<_> = results.iter().map(|t| t.to_sys()).collect();
+        let item = self.item(name, |c| unsafe {
+            // SAFETY: the arrays and names live until MIR has copied them
+            sys::MIR_new_proto_arr(
+                ctx,
+                c.as_ptr(),
+                res.len(),
+                res.as_mut_ptr(),
+                vars.len(),
+                vars.as_mut_ptr(),
+            )
+        })?;
+        Ok(ProtoRef {
+            item,
+            results: results.into(),
+            args: args.iter().map(|a| a.ty).collect(),
+        })
+    }
+
+    /// Define a function, whose body `body` builds. The function is finished
+    /// when it returns, even if it fails.
+    pub fn func<R>(
+        &mut self,
+        name: &str,
+        results: &[Type],
+        args: &[Arg],
+        body: impl for<'f> FnOnce(&mut FuncBuilder<'m, 'f>) -> MirResult<R>,
+    ) -> MirResult<(ItemRef<'m>, R)> {
+        let ctx = self.ctx;
+        let (_names, mut vars) = vars(args)?;
+        let mut res: Vec<_> = results.iter().map(|t| t.to_sys()).collect();
+        let item = self.item(name, |c| unsafe {
+            // SAFETY: as for `proto`
+            sys::MIR_new_func_arr(
+                ctx,
+                c.as_ptr(),
+                res.len(),
+                res.as_mut_ptr(),
+                vars.len(),
+                vars.as_mut_ptr(),
+            )
+        })?;
+
+        let mut f = FuncBuilder {
+            ctx,
+            item: item.item,
+            // SAFETY: `MIR_new_func_arr` always makes a function item
+            func: unsafe { (*item.item).u.func },
+            results: results.len(),
+            args: Vec::new(),
+            regs: HashSet::new(),
+            labels: Vec::new(),
+            placed: HashSet::new(),
+            _module: PhantomData,
+            _brand: PhantomData,
+        };
+        for arg in args {
+            let c = c_name(&arg.name)?;
+            // SAFETY: every argument has a register of the same name
+            let reg = unsafe { sys::MIR_reg(ctx, c.as_ptr(), f.func) };
+            f.regs.insert(arg.name.clone());
+            f.args.push(Reg {
+                reg,
+                ty: RegType::holding(arg.ty),
+                _brand: PhantomData,
+            });
+        }
+        let res = body(&mut f).and_then(|r| f.check().map(|_| r));
+        if res.is_err() {
+            // MIR can't finish a function branching to nowhere
+            for label in f.labels.clone() {
+                if f.placed.insert(label) {
+                    // SAFETY: as for `FuncBuilder::place`
+                    unsafe { sys::MIR_append_insn(ctx, f.item, label) };
+                }
+            }
+        }
+        // SAFETY: this is the function being built
+        unsafe { sys::MIR_finish_func(ctx) };
+        Ok((item, res?))
+    }
+}
+
+/// The function being built, named `'f`, in the module `'m`
+pub struct FuncBuilder<'m, 'f> {
+    ctx: sys::MIR_context_t,
+    item: sys::MIR_item_t,
+    func: sys::MIR_func_t,
+    results: usize,
+    args: Vec<Reg<'f>>,
+    regs: HashSet<String>,
+    labels: Vec<sys::MIR_insn_t>,
+    placed: HashSet<sys::MIR_insn_t>,
+    _module: Brand<'m>,
+    _brand: Brand<'f>,
+}
+
+impl<'m, 'f> FuncBuilder<'m, 'f> {
+    /// The register holding argument `i`
+    pub fn arg(&self, i: usize) -> Option<Reg<'f>> {
+        self.args.get(i).copied()
+    }
+
+    pub fn local(&mut self, ty: RegType, name: &str) -> MirResult<Reg<'f>> {
+        if !self.regs.insert(name.to_owned()) {
+            return Err(MirError::Duplicate(name.to_owned()));
+        }
+        let c = c_name(name)?;
+        // SAFETY: the name is new to this function
+        let reg = unsafe { sys::MIR_new_func_reg(self.ctx, self.func, ty.to_sys(), c.as_ptr()) };
+        Ok(Reg {
+            reg,
+            ty,
+            _brand: PhantomData,
+        })
+    }
+
+    /// A label to branch to, which has to be placed before the function is
+    /// finished
+    pub fn new_label(&mut self) -> Label<'f> {
+        // SAFETY: a label is only an instruction not yet in any function
+        let insn = unsafe { sys::MIR_new_label(self.ctx) };
+        self.labels.push(insn);
+        Label {
+            insn,
+            _brand: PhantomData,
+        }
+    }
+
+    /// Put `label` at the end of the function so far
+    pub fn place(&mut self, label: Label<'f>) -> MirResult<()> {
+        if !self.placed.insert(label.insn) {
+            return Err(MirError::LabelPlaced);
+        }
+        // SAFETY: the label is placed once, in the function it was made for
+        unsafe { sys::MIR_append_insn(self.ctx, self.item, label.insn) };
+        Ok(())
+    }
+
+    fn check(&self) -> MirResult<()> {
+        match self.labels.iter().any(|l| !self.placed.contains(l)) {
+            true => Err(MirError::LabelUnplaced),
+            false => Ok(()),
+        }
+    }
+
+    fn src(&self, src: Src<'m, 'f>) -> sys::MIR_op_t {
+        // SAFETY: every operand was made for this function or its module
+        unsafe {
+            match src {
+                Src::Reg(r) => sys::MIR_new_reg_op(self.ctx, r.reg),
+                Src::Int(n) => sys::MIR_new_int_op(self.ctx, n),
+                Src::Ref(item) => sys::MIR_new_ref_op(self.ctx, item.item),
+                Src::Mem(m) => sys::MIR_new_mem_op(
+                    self.ctx,
+                    m.ty.to_sys(),
+                    m.disp,
+                    // register 0 stands for none
+                    m.base.map_or(0, |r| r.reg),
+                    m.index.map_or(0, |(r, _)| r.reg),
+                    m.index.map_or(1, |(_, s)| s as u8),
+                ),
+            }
+        }
+    }
+
+    fn dst(&self, dst: Dst<'f>) -> sys::MIR_op_t {
+        self.src(dst.into())
+    }
+
+    fn label(&self, label: Label<'f>) -> sys::MIR_op_t {
+        // SAFETY: the label was made for this function
+        unsafe { sys::MIR_new_label_op(self.ctx, label.insn) }
+    }
+
+    fn emit(&mut self, code: sys::MIR_insn_code_t, mut ops: Vec<sys::MIR_op_t>) {
+        // SAFETY: the operands suit `code`, as the typed builders make sure
+        unsafe {
+            let insn = sys::MIR_new_insn_arr(self.ctx, code, ops.len(), ops.as_mut_ptr());
+            sys::MIR_append_insn(self.ctx, self.item, insn);
+        }
+    }
+
+    /// Check that every operand of an integer instruction is an integer
+    fn integers(what: &'static str, ops: &[Src<'m, 'f>]) -> MirResult<()> {
+        match ops.iter().all(|op| op.ty() == RegType::I64) {
+            true => Ok(()),
+            false => Err(MirError::Operands(what)),
+        }
+    }
+
+    pub fn mov(&mut self, dst: impl Into<Dst<'f>>, src: impl Into<Src<'m, 'f>>) -> MirResult<()> {
+        let (dst, src) = (dst.into(), src.into());
+        Self::integers("mov", &[dst.into(), src])?;
+        let ops = vec![self.dst(dst), self.src(src)];
+        self.emit(sys::MIR_MOV, ops);
+        Ok(())
+    }
+
+    pub fn binary(
+        &mut self,
+        op: BinOp,
+        dst: impl Into<Dst<'f>>,
+        a: impl Into<Src<'m, 'f>>,
+        b: impl Into<Src<'m, 'f>>,
+    ) -> MirResult<()> {
+        let (dst, a, b) = (dst.into(), a.into(), b.into());
+        Self::integers("a binary operation", &[dst.into(), a, b])?;
+        let ops = vec![self.dst(dst), self.src(a), self.src(b)];
+        self.emit(op.to_sys(), ops);
+        Ok(())
+    }
+
+    pub fn jmp(&mut self, label: Label<'f>) {
+        let ops = vec![self.label(label)];
+        self.emit(sys::MIR_JMP, ops)
+    }
+
+    /// Branch if `x` is nonzero, or zero when `unless`
+    pub fn branch_if(&mut self, label: Label<'f>, x: impl Into<Src<'m, 'f>>, unless: bool) {
+        let ops = vec![self.label(label), self.src(x.into())];
+        self.emit(if unless { sys::MIR_BF } else { sys::MIR_BT }, ops)
+    }
+
+    pub fn branch(
+        &mut self,
+        cond: Cond,
+        label: Label<'f>,
+        a: impl Into<Src<'m, 'f>>,
+        b: impl Into<Src<'m, 'f>>,
+    ) {
+        let ops = vec![self.label(label), self.src(a.into()), self.src(b.into())];
+        self.emit(cond.to_sys(), ops)
+    }
+
+    /// Branch if the `BinOp::Addo`, `Subo`, `Mulo` or `Umulo` just before
+    /// overflowed, or didn't when `unless`
+    pub fn branch_overflow(&mut self, label: Label<'f>, unless: bool) {
+        let ops = vec![self.label(label)];
+        self.emit(if unless { sys::MIR_BNO } else { sys::MIR_BO }, ops)
+    }
+
+    pub fn call(
+        &mut self,
+        proto: ProtoRef<'m>,
+        callee: impl Into<Src<'m, 'f>>,
+        results: &[Dst<'f>],
+        args: &[Src<'m, 'f>],
+    ) -> MirResult<()> {
+        if results.len() != proto.results() || args.len() != proto.args() {
+            return Err(MirError::Signature {
+                expected: (proto.results(), proto.args()),
+                given: (results.len(), args.len()),
+            });
+        }
+        let callee = callee.into();
+        let results_match = (results.iter())
+            .zip(proto.results.iter())
+            .all(|(r, ty)| Src::from(*r).ty() == RegType::holding(*ty));
+        let args_match = (args.iter())
+            .zip(proto.args.iter())
+            .all(|(a, ty)| a.ty() == RegType::holding(*ty));
+        if callee.ty() != RegType::I64 || !results_match || !args_match {
+            return Err(MirError::Operands("call"));
+        }
+        let mut ops = vec![self.src(proto.item.into()), self.src(callee)];
+        ops.extend(results.iter().map(|d| self.dst(*d)));
+        ops.extend(args.iter().map(|s| self.src(*s)));
+        self.emit(sys::MIR_CALL, ops);
+        Ok(())
+    }
+
+    pub fn ret(&mut self, values: &[Src<'m, 'f>]) -> MirResult<()> {
+        if values.len() != self.results {
+            return Err(MirError::Signature {
+                expected: (self.results, 0),
+                given: (values.len(), 0),
+            });
+        }
+        let ops = values.iter().map(|v| self.src(*v)).collect();
+        self.emit(sys::MIR_RET, ops);
+        Ok(())
+    }
+}
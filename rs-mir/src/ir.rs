@@ -0,0 +1,389 @@
+//! MIR modules built up on the Rust side, printed in MIR's textual format
+//! (see `mir-testing/test.mir`)
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    F,
+    D,
+    LD,
+    P,
+}
+
+impl Type {
+    pub const ALL: [Self; 12] = [
+        Self::I8,
+        Self::U8,
+        Self::I16,
+        Self::U16,
+        Self::I32,
+        Self::U32,
+        Self::I64,
+        Self::U64,
+        Self::F,
+        Self::D,
+        Self::LD,
+        Self::P,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::I8 => "i8",
+            Self::U8 => "u8",
+            Self::I16 => "i16",
+            Self::U16 => "u16",
+            Self::I32 => "i32",
+            Self::U32 => "u32",
+            Self::I64 => "i64",
+            Self::U64 => "u64",
+            Self::F => "f",
+            Self::D => "d",
+            Self::LD => "ld",
+            Self::P => "p",
+        }
+    }
+}
+
+/// A typed register: a function argument or local
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Var {
+    pub ty: Type,
+    pub name: String,
+}
+
+impl Var {
+    pub fn i64(name: impl Into<String>) -> Self {
+        Self {
+            ty: Type::I64,
+            name: name.into(),
+        }
+    }
+}
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.ty.name(), self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Reg(String),
+    Int(i64),
+    /// The address of a module item: a function, import, or data
+    Ref(String),
+    Label(String),
+    /// `ty:disp(base, index, scale)`
+    Mem {
+        ty: Type,
+        disp: i64,
+        base: String,
+        index: Option<String>,
+        scale: u8,
+    },
+}
+
+impl Operand {
+    pub fn reg(name: impl Into<String>) -> Self {
+        Self::Reg(name.into())
+    }
+
+    /// The `i64` at `disp` bytes past the address in `base`
+    pub fn mem(disp: i64, base: impl Into<String>) -> Self {
+        Self::Mem {
+            ty: Type::I64,
+            disp,
+            base: base.into(),
+            index: None,
+            scale: 1,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reg(name) | Self::Ref(name) | Self::Label(name) => write!(f, "{name}"),
+            Self::Int(n) => write!(f, "{n}"),
+            Self::Mem {
+                ty,
+                disp,
+                base,
+                index,
+                scale,
+            } => {
+                write!(f, "{}:{disp}", ty.name())?;
+                match index {
+                    Some(index) => write!(f, "({base}, {index}, {scale})"),
+                    None => write!(f, "({base})"),
+                }
+            }
+        }
+    }
+}
+
+/// The instructions this crate can build. Branches take their label first,
+/// and `call` takes its prototype, the function, its results, and then its
+/// arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    Mov,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    And,
+    Or,
+    Xor,
+    Lsh,
+    Rsh,
+    Ursh,
+    /// Arithmetic setting the overflow flag, for `Bo` and `Bno`
+    Addo,
+    Subo,
+    Mulo,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Jmp,
+    Bt,
+    Bf,
+    Bo,
+    Bno,
+    Beq,
+    Bne,
+    Blt,
+    Ble,
+    Bgt,
+    Bge,
+    Call,
+    Ret,
+}
+
+impl Code {
+    pub const ALL: [Self; 34] = [
+        Self::Mov,
+        Self::Add,
+        Self::Sub,
+        Self::Mul,
+        Self::Div,
+        Self::Mod,
+        Self::And,
+        Self::Or,
+        Self::Xor,
+        Self::Lsh,
+        Self::Rsh,
+        Self::Ursh,
+        Self::Addo,
+        Self::Subo,
+        Self::Mulo,
+        Self::Eq,
+        Self::Ne,
+        Self::Lt,
+        Self::Le,
+        Self::Gt,
+        Self::Ge,
+        Self::Jmp,
+        Self::Bt,
+        Self::Bf,
+        Self::Bo,
+        Self::Bno,
+        Self::Beq,
+        Self::Bne,
+        Self::Blt,
+        Self::Ble,
+        Self::Bgt,
+        Self::Bge,
+        Self::Call,
+        Self::Ret,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Mov => "mov",
+            Self::Add => "add",
+            Self::Sub => "sub",
+            Self::Mul => "mul",
+            Self::Div => "div",
+            Self::Mod => "mod",
+            Self::And => "and",
+            Self::Or => "or",
+            Self::Xor => "xor",
+            Self::Lsh => "lsh",
+            Self::Rsh => "rsh",
+            Self::Ursh => "ursh",
+            Self::Addo => "addo",
+            Self::Subo => "subo",
+            Self::Mulo => "mulo",
+            Self::Eq => "eq",
+            Self::Ne => "ne",
+            Self::Lt => "lt",
+            Self::Le => "le",
+            Self::Gt => "gt",
+            Self::Ge => "ge",
+            Self::Jmp => "jmp",
+            Self::Bt => "bt",
+            Self::Bf => "bf",
+            Self::Bo => "bo",
+            Self::Bno => "bno",
+            Self::Beq => "beq",
+            Self::Bne => "bne",
+            Self::Blt => "blt",
+            Self::Ble => "ble",
+            Self::Bgt => "bgt",
+            Self::Bge => "bge",
+            Self::Call => "call",
+            Self::Ret => "ret",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Insn {
+    Label(String),
+    Op(Code, Vec<Operand>),
+}
+
+impl fmt::Display for Insn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Label(name) => write!(f, "{name}:"),
+            Self::Op(code, ops) => {
+                write!(f, "\t{}", code.name())?;
+                for (i, op) in ops.iter().enumerate() {
+                    write!(f, "{}{op}", if i == 0 { "\t" } else { ", " })?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn count(n: usize, what: &str) -> String {
+    format!("{n} {what}{}", if n == 1 { "" } else { "s" })
+}
+
+/// Write a signature: the result types, then the typed arguments
+fn signature(f: &mut fmt::Formatter<'_>, results: &[Type], args: &[Var]) -> fmt::Result {
+    let results = results.iter().map(|t| t.name().to_owned());
+    let args = args.iter().map(|a| a.to_string());
+    let sig: Vec<String> = results.chain(args).collect();
+    if !sig.is_empty() {
+        write!(f, "\t{}", sig.join(", "))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Func {
+    pub name: String,
+    pub results: Vec<Type>,
+    pub args: Vec<Var>,
+    pub locals: Vec<Var>,
+    pub body: Vec<Insn>,
+}
+
+impl Func {
+    pub fn init(name: impl Into<String>, results: Vec<Type>, args: Vec<Var>) -> Self {
+        Self {
+            name: name.into(),
+            results,
+            args,
+            locals: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, code: Code, ops: Vec<Operand>) {
+        self.body.push(Insn::Op(code, ops))
+    }
+
+    pub fn label(&mut self, name: impl Into<String>) {
+        self.body.push(Insn::Label(name.into()))
+    }
+}
+
+impl fmt::Display for Func {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:\tfunc", self.name)?;
+        signature(f, &self.results, &self.args)?;
+        writeln!(f)?;
+        if self.locals.is_empty() {
+            writeln!(f)?;
+        } else {
+            let locals: Vec<String> = self.locals.iter().map(|l| l.to_string()).collect();
+            writeln!(f, "\tlocal\t{}", locals.join(", "))?;
+        }
+        writeln!(
+            f,
+            "# {}, {}",
+            count(self.args.len(), "arg"),
+            count(self.locals.len(), "local")
+        )?;
+        for insn in &self.body {
+            writeln!(f, "{insn}")?;
+        }
+        write!(f, "\tendfunc")
+    }
+}
+
+/// The signature of functions called through it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proto {
+    pub name: String,
+    pub results: Vec<Type>,
+    pub args: Vec<Var>,
+}
+
+impl fmt::Display for Proto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:\tproto", self.name)?;
+        signature(f, &self.results, &self.args)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Func(Func),
+    Proto(Proto),
+    Import(String),
+    Export(String),
+    /// `size` zeroed bytes
+    Bss {
+        name: String,
+        size: usize,
+    },
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Func(func) => write!(f, "{func}"),
+            Self::Proto(proto) => write!(f, "{proto}"),
+            Self::Import(name) => write!(f, "\timport\t{name}"),
+            Self::Export(name) => write!(f, "\texport\t{name}"),
+            Self::Bss { name, size } => write!(f, "{name}:\tbss\t{size}"),
+        }
+    }
+}
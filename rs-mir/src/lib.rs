@@ -1,33 +1,565 @@
+//! Building and running MIR code. Modules are put together either as
+//! `ir::Item`s, which can be printed and parsed back, or directly with
+//! `builder`; either way they end up in a `MIRContext`, which links them and
+//! hands out the addresses of their functions, and can save them in MIR's
+//! binary format. Written against the API of MIR 1.0.
+
 #![allow(unused, dead_code)]
-#![allow(non_upper_case_globals)]
-#![allow(non_camel_case_types)]
-#![allow(non_snake_case)]
 
-include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
-use std::ffi::CString;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ffi::{c_int, c_void, CStr, CString};
+use std::fmt;
+use std::ptr;
+
+/// The raw bindings to MIR's C API
+pub mod sys {
+    #![allow(non_upper_case_globals)]
+    #![allow(non_camel_case_types)]
+    #![allow(non_snake_case)]
+    #![allow(clippy::all)]
+
+    include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+}
+
+pub mod builder;
+pub mod ir;
+mod lower;
+mod parse;
+
+use builder::ModuleBuilder;
+use ir::Item;
+
+pub type MirResult<T> = Result<T, MirError>;
+
+/// Ways of building MIR which the C API would reject, usually by exiting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MirError {
+    /// A name with a nul byte in it
+    Name(String),
+    /// An item or register defined twice
+    Duplicate(String),
+    /// A name used without being defined
+    Unknown(String),
+    /// A register declared with a type registers can't have
+    RegType(String),
+    /// An instruction given the wrong kinds of operands
+    Operands(&'static str),
+    /// A call or return with the wrong number of results or arguments
+    Signature {
+        expected: (usize, usize),
+        given: (usize, usize),
+    },
+    LabelPlaced,
+    /// A function finished with a label branched to but never placed
+    LabelUnplaced,
+    /// Text `MIRModule::parse` can't read
+    Syntax {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for MirError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(f, "`{name}` isn't a valid MIR name"),
+            Self::Duplicate(name) => write!(f, "`{name}` is defined twice"),
+            Self::Unknown(name) => write!(f, "`{name}` isn't defined"),
+            Self::RegType(name) => write!(f, "register `{name}` can't have its type"),
+            Self::Operands(what) => write!(f, "wrong operands for {what}"),
+            Self::Signature { expected, given } => write!(
+                f,
+                "expected {} result(s) and {} argument(s), but got {} and {}",
+                expected.0, expected.1, given.0, given.1
+            ),
+            Self::LabelPlaced => write!(f, "a label was placed twice"),
+            Self::LabelUnplaced => write!(f, "a label was never placed"),
+            Self::Syntax { line, message } => write!(f, "line {line}: {message}"),
+        }
+    }
+}
 
+/// How `MIRContext::link` makes functions callable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exec {
+    /// Run them with MIR's interpreter
+    Interp,
+    /// Compile them to machine code, at the given optimisation level (0 to 3)
+    Gen(u32),
+}
+
+/// Owns a MIR context, which is finished when this is dropped, along with
+/// the `ir` of the modules added to it
 pub struct MIRContext {
+    ctx: sys::MIR_context_t,
     modules: Vec<MIRModule>,
+    /// Every module built in the context without error, in order
+    built: Vec<sys::MIR_module_t>,
+    /// How many of `built` have been loaded for linking
+    loaded: usize,
+    /// Whether the generator has been started, and must be finished
+    gen: bool,
+    /// The names given to `load_external`, which MIR keeps pointers to
+    externals: Vec<CString>,
 }
 
 impl MIRContext {
     pub fn init() -> Self {
+        // SAFETY: `MIR_init` is a static inline function, so bindgen leaves
+        // it out; this is all it does, besides the version check
+        let ctx = unsafe {
+            let version = sys::_MIR_get_api_version();
+            assert!(
+                version == sys::MIR_API_VERSION,
+                "the MIR library is version {version}, but its header is {}",
+                sys::MIR_API_VERSION
+            );
+            sys::_MIR_init(ptr::null_mut(), ptr::null_mut())
+        };
         Self {
+            ctx,
             modules: Vec::new(),
+            built: Vec::new(),
+            loaded: 0,
+            gen: false,
+            externals: Vec::new(),
+        }
+    }
+
+    /// Build a module with `body`. The module is finished when it returns,
+    /// even if it fails, but is then never loaded.
+    pub fn build_module<R>(
+        &mut self,
+        name: &str,
+        body: impl for<'m> FnOnce(&mut ModuleBuilder<'m>) -> MirResult<R>,
+    ) -> MirResult<R> {
+        // SAFETY: borrowing the context mutably means no other module is
+        // being built
+        let mut m = unsafe { ModuleBuilder::start(self.ctx, name)? };
+        let res = body(&mut m);
+        let module = m.finish();
+        if res.is_ok() {
+            self.built.push(module);
+        }
+        res
+    }
+
+    /// Build `module` in the context, keeping it around to print
+    pub fn add_module(&mut self, module: MIRModule) -> MirResult<()> {
+        self.build_module(&module.name, |m| lower::lower(&module, m))?;
+        self.modules.push(module);
+        Ok(())
+    }
+
+    pub fn modules(&self) -> &[MIRModule] {
+        &self.modules
+    }
+
+    /// Build every module in `text`, in the format `MIRModule` prints
+    pub fn scan(&mut self, text: &str) -> MirResult<()> {
+        for module in MIRModule::parse(text)? {
+            self.add_module(module)?;
+        }
+        Ok(())
+    }
+
+    /// Every module built in the context, in MIR's binary format
+    pub fn write(&self) -> Vec<u8> {
+        unsafe extern "C" fn writer(_: sys::MIR_context_t, byte: u8) -> c_int {
+            BYTES.with(|b| b.borrow_mut().0.push(byte));
+            1
+        }
+        BYTES.with(|b| *b.borrow_mut() = (Vec::new(), 0));
+        // SAFETY: `writer` only touches `BYTES`
+        unsafe { sys::MIR_write_with_func(self.ctx, Some(writer)) };
+        BYTES.with(|b| b.take().0)
+    }
+
+    /// Load the modules in `bytes`, written by `write`, which can then be
+    /// linked like the ones built.
+    ///
+    /// # Safety
+    /// `bytes` must be MIR's binary format, as written by the same version
+    /// of MIR: on anything else it exits the process, or worse.
+    pub unsafe fn read(&mut self, bytes: &[u8]) {
+        unsafe extern "C" fn reader(_: sys::MIR_context_t) -> c_int {
+            BYTES.with(|b| {
+                let (bytes, pos) = &mut *b.borrow_mut();
+                let byte = bytes.get(*pos).map_or(-1, |b| *b as c_int);
+                *pos += 1;
+                byte
+            })
+        }
+        let before = self.module_list().len();
+        BYTES.with(|b| *b.borrow_mut() = (bytes.to_vec(), 0));
+        sys::MIR_read_with_func(self.ctx, Some(reader));
+        BYTES.with(|b| b.take());
+        let list = self.module_list();
+        self.built.extend(&list[before..]);
+    }
+
+    /// Every module in the context, in the order they were made
+    fn module_list(&self) -> Vec<sys::MIR_module_t> {
+        let mut modules = Vec::new();
+        // SAFETY: modules live as long as the context
+        unsafe {
+            let mut module = (*sys::MIR_get_module_list(self.ctx)).head;
+            while !module.is_null() {
+                modules.push(module);
+                module = (*module).module_link.next;
+            }
+        }
+        modules
+    }
+
+    /// The items of `module`, which must be in the context
+    fn items(&self, module: sys::MIR_module_t) -> Vec<sys::MIR_item_t> {
+        let mut items = Vec::new();
+        // SAFETY: modules and their items live as long as the context
+        unsafe {
+            let mut item = (*module).items.head;
+            while !item.is_null() {
+                items.push(item);
+                item = (*item).item_link.next;
+            }
+        }
+        items
+    }
+
+    fn item_name(&self, item: sys::MIR_item_t) -> &CStr {
+        // SAFETY: as for `items`
+        unsafe { CStr::from_ptr(sys::MIR_item_name(self.ctx, item)) }
+    }
+
+    /// Make `addr` what modules importing `name` get when they're linked
+    ///
+    /// # Safety
+    /// `addr` must stay valid for as long as linked code may use it, and be
+    /// of the kind the importing code expects: a function with the
+    /// prototype it's called through, or data.
+    pub unsafe fn load_external(&mut self, name: &str, addr: *mut c_void) -> MirResult<()> {
+        let name = CString::new(name).map_err(|_| MirError::Name(name.to_owned()))?;
+        // MIR keeps the name's pointer rather than copying it
+        sys::MIR_load_external(self.ctx, name.as_ptr(), addr);
+        self.externals.push(name);
+        Ok(())
+    }
+
+    /// Load every module built since the last link, and link them, resolving
+    /// imports against exports and `load_external`. Fails without loading
+    /// anything on an import that can't be resolved, which MIR would exit on.
+    pub fn link(&mut self, exec: Exec) -> MirResult<()> {
+        let mut defined: HashSet<&CStr> = self.externals.iter().map(|n| n.as_c_str()).collect();
+        for module in &self.built {
+            for item in self.items(*module) {
+                // SAFETY: as for `items`
+                if unsafe { (*item).item_type } == sys::MIR_export_item {
+                    defined.insert(self.item_name(item));
+                }
+            }
+        }
+        for module in &self.built[self.loaded..] {
+            for item in self.items(*module) {
+                let name = self.item_name(item);
+                // SAFETY: as for `items`
+                if unsafe { (*item).item_type } == sys::MIR_import_item && !defined.contains(name) {
+                    return Err(MirError::Unknown(name.to_string_lossy().into_owned()));
+                }
+            }
+        }
+
+        // SAFETY: every module in `built` is finished, and its imports resolve
+        unsafe {
+            for module in &self.built[self.loaded..] {
+                sys::MIR_load_module(self.ctx, *module);
+            }
+            self.loaded = self.built.len();
+            let interface = match exec {
+                Exec::Interp => sys::MIR_set_interp_interface,
+                Exec::Gen(level) => {
+                    if !self.gen {
+                        sys::MIR_gen_init(self.ctx);
+                        self.gen = true;
+                    }
+                    sys::MIR_gen_set_optimize_level(self.ctx, level);
+                    sys::MIR_set_gen_interface
+                }
+            };
+            sys::MIR_link(self.ctx, Some(interface), None);
+        }
+        Ok(())
+    }
+
+    /// The address of the linked function `name`, to be cast to an `extern
+    /// "C" fn` matching its signature. Later modules shadow earlier ones.
+    pub fn function(&self, name: &str) -> Option<*mut c_void> {
+        for module in self.built[..self.loaded].iter().rev() {
+            for item in self.items(*module) {
+                // SAFETY: as for `items`
+                let (ty, addr) = unsafe { ((*item).item_type, (*item).addr) };
+                if ty == sys::MIR_func_item && self.item_name(item).to_bytes() == name.as_bytes() {
+                    return Some(addr);
+                }
+            }
         }
+        None
     }
+}
 
-    pub fn emit_number(n: i32) {
-        println!("ret {n}")
+impl Drop for MIRContext {
+    fn drop(&mut self) {
+        // SAFETY: the context is never used again
+        unsafe {
+            if self.gen {
+                sys::MIR_gen_finish(self.ctx);
+            }
+            sys::MIR_finish(self.ctx)
+        }
     }
 }
 
-pub struct MIRModule {}
+thread_local! {
+    /// The bytes `MIRContext::write` and `read` pass through MIR, with how
+    /// far it has read
+    static BYTES: RefCell<(Vec<u8>, usize)> = const { RefCell::new((Vec::new(), 0)) };
+}
+
+/// A module of `ir::Item`s, which prints as MIR's textual format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MIRModule {
+    pub name: String,
+    pub items: Vec<Item>,
+}
+
+impl MIRModule {
+    pub fn init(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            items: Vec::new(),
+        }
+    }
+
+    /// Read back the modules printed into `text`. Only what this crate can
+    /// build is understood.
+    pub fn parse(text: &str) -> MirResult<Vec<Self>> {
+        parse::modules(text)
+    }
+}
+
+impl fmt::Display for MIRModule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}:\tmodule", self.name)?;
+        for item in &self.items {
+            writeln!(f, "{item}")?;
+        }
+        writeln!(f, "\tendmodule")
+    }
+}
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    use ir::*;
+
+    fn test_module() -> MIRModule {
+        let mut test = Func::init("test", vec![Type::I32], vec![]);
+        test.push(Code::Ret, vec![Operand::Int(42)]);
+        let mut main = Func::init("main", vec![Type::I32], vec![]);
+        main.locals.push(Var::i64("i_0"));
+        main.push(
+            Code::Call,
+            vec![
+                Operand::Ref("proto0".into()),
+                Operand::Ref("test".into()),
+                Operand::reg("i_0"),
+            ],
+        );
+        main.push(Code::Ret, vec![Operand::Int(0)]);
+
+        let mut module = MIRModule::init("M0");
+        module.items = vec![
+            Item::Proto(Proto {
+                name: "proto0".into(),
+                results: vec![Type::I32],
+                args: vec![],
+            }),
+            Item::Func(test),
+            Item::Export("test".into()),
+            Item::Func(main),
+            Item::Export("main".into()),
+        ];
+        module
+    }
+
+    #[test]
+    fn module_test() {
+        assert_eq!(
+            test_module().to_string(),
+            include_str!("../../mir-testing/test.mir")
+        );
+    }
+
+    #[test]
+    fn load_test() {
+        let mut ctx = MIRContext::init();
+        assert_eq!(ctx.add_module(test_module()), Ok(()));
+        assert_eq!(ctx.modules().len(), 1);
+    }
+
+    #[test]
+    fn link_test() {
+        for exec in [Exec::Interp, Exec::Gen(2)] {
+            let mut ctx = MIRContext::init();
+            ctx.add_module(test_module()).unwrap();
+            ctx.link(exec).unwrap();
+            assert_eq!(ctx.function("missing"), None);
+            let test = ctx.function("test").expect("`test` is linked");
+            // SAFETY: `test` takes nothing and returns an `i32`
+            let test: extern "C" fn() -> i32 = unsafe { std::mem::transmute(test) };
+            assert_eq!(test(), 42);
+        }
+    }
+
+    #[test]
+    fn unresolved_test() {
+        let mut module = MIRModule::init("M1");
+        module.items = vec![Item::Import("nowhere".into())];
+        let mut ctx = MIRContext::init();
+        ctx.add_module(module).unwrap();
+        assert_eq!(
+            ctx.link(Exec::Interp),
+            Err(MirError::Unknown("nowhere".into()))
+        );
+    }
+
+    #[test]
+    fn parse_test() {
+        let text = include_str!("../../mir-testing/test.mir");
+        assert_eq!(MIRModule::parse(text), Ok(vec![test_module()]));
+
+        let mut f = Func::init("f", vec![Type::I64], vec![Var::i64("p")]);
+        f.locals.push(Var::i64("r"));
+        f.label("L1");
+        f.push(Code::Mov, vec![Operand::reg("r"), Operand::mem(-2, "p")]);
+        f.push(
+            Code::Beq,
+            vec![
+                Operand::Label("L1".into()),
+                Operand::reg("r"),
+                Operand::Int(-7),
+            ],
+        );
+        f.push(Code::Ret, vec![Operand::Ref("g".into())]);
+        let mut module = MIRModule::init("M1");
+        module.items = vec![
+            Item::Import("a".into()),
+            Item::Bss {
+                name: "g".into(),
+                size: 8,
+            },
+            Item::Func(f),
+        ];
+        let text = format!("{module}{}", test_module());
+        assert_eq!(MIRModule::parse(&text), Ok(vec![module, test_module()]));
+
+        let res = MIRModule::parse("M:\tmodule\n\tfoo\n\tendmodule\n");
+        assert!(matches!(res, Err(MirError::Syntax { line: 2, .. })));
+    }
+
     #[test]
-    fn module_test() {}
+    fn binary_test() {
+        let mut ctx = MIRContext::init();
+        ctx.scan(include_str!("../../mir-testing/test.mir"))
+            .unwrap();
+        let bytes = ctx.write();
+        assert!(!bytes.is_empty());
+
+        let mut ctx = MIRContext::init();
+        // SAFETY: `bytes` were just written by MIR
+        unsafe { ctx.read(&bytes) };
+        ctx.link(Exec::Interp).unwrap();
+        let test = ctx.function("test").expect("`test` is read back");
+        // SAFETY: `test` takes nothing and returns an `i32`
+        let test: extern "C" fn() -> i32 = unsafe { std::mem::transmute(test) };
+        assert_eq!(test(), 42);
+    }
+
+    #[test]
+    fn builder_test() {
+        use builder::*;
+
+        let mut ctx = MIRContext::init();
+        let res = ctx.build_module("M1", |m| {
+            m.func("f", &[Type::I64], &[], |f| {
+                f.local(RegType::I64, "r")?;
+                f.local(RegType::I64, "r").map(|_| ())
+            })
+            .map(|_| ())
+        });
+        assert_eq!(res.err(), Some(MirError::Duplicate("r".into())));
+
+        let res = ctx.build_module("M2", |m| {
+            m.func("f", &[Type::I64], &[], |f| {
+                let l = f.new_label();
+                f.jmp(l);
+                f.ret(&[Src::Int(0)])
+            })
+            .map(|_| ())
+        });
+        assert_eq!(res.err(), Some(MirError::LabelUnplaced));
+
+        let res = ctx.build_module("M3", |m| {
+            let p = m.proto("p", &[Type::I64], &[])?;
+            m.func("f", &[Type::I64], &[], |f| {
+                let r = f.local(RegType::I64, "r")?;
+                f.call(p, Src::Int(0), &[], &[])?;
+                f.ret(&[r.into()])
+            })
+            .map(|_| ())
+        });
+        assert_eq!(
+            res.err(),
+            Some(MirError::Signature {
+                expected: (1, 0),
+                given: (0, 0)
+            })
+        );
+
+        let res = ctx.build_module("M4", |m| {
+            m.func("f", &[Type::I64], &[], |f| {
+                let x = f.local(RegType::D, "x")?;
+                f.mov(x, Src::Int(1))?;
+                f.ret(&[Src::Int(0)])
+            })
+            .map(|_| ())
+        });
+        assert_eq!(res.err(), Some(MirError::Operands("mov")));
+
+        let res = ctx.build_module("M5", |m| {
+            m.func("f", &[Type::I64], &[], |f| {
+                let r = f.local(RegType::I64, "r")?;
+                f.binary(BinOp::Add, r, r, Mem::at(Type::D, 0, r))?;
+                f.ret(&[r.into()])
+            })
+            .map(|_| ())
+        });
+        assert_eq!(res.err(), Some(MirError::Operands("a binary operation")));
+
+        let res = ctx.build_module("M6", |m| {
+            let p = m.proto("p", &[Type::I64], &[])?;
+            m.func("f", &[Type::I64], &[], |f| {
+                let x = f.local(RegType::D, "x")?;
+                f.call(p, Src::Int(0), &[x.into()], &[])?;
+                f.ret(&[Src::Int(0)])
+            })
+            .map(|_| ())
+        });
+        assert_eq!(res.err(), Some(MirError::Operands("call")));
+
+        // none of the modules which failed is loaded
+        assert_eq!(ctx.link(Exec::Interp), Ok(()));
+        assert_eq!(ctx.function("f"), None);
+    }
 }
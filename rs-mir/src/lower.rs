@@ -0,0 +1,266 @@
+//! Load an `ir` module into a MIR context through `builder`
+
+use std::collections::HashMap;
+
+use crate::builder::{
+    Arg, BinOp, Cond, Dst, FuncBuilder, ItemRef, Label, Mem, ModuleBuilder, ProtoRef, Reg, RegType,
+    Scale, Src,
+};
+use crate::ir::{Code, Func, Insn, Item, Operand};
+use crate::{MIRModule, MirError, MirResult};
+
+fn binary(code: Code) -> Option<BinOp> {
+    match code {
+        Code::Add => Some(BinOp::Add),
+        Code::Sub => Some(BinOp::Sub),
+        Code::Mul => Some(BinOp::Mul),
+        Code::Div => Some(BinOp::Div),
+        Code::Mod => Some(BinOp::Mod),
+        Code::And => Some(BinOp::And),
+        Code::Or => Some(BinOp::Or),
+        Code::Xor => Some(BinOp::Xor),
+        Code::Lsh => Some(BinOp::Lsh),
+        Code::Rsh => Some(BinOp::Rsh),
+        Code::Ursh => Some(BinOp::Ursh),
+        Code::Addo => Some(BinOp::Addo),
+        Code::Subo => Some(BinOp::Subo),
+        Code::Mulo => Some(BinOp::Mulo),
+        Code::Eq => Some(BinOp::Eq),
+        Code::Ne => Some(BinOp::Ne),
+        Code::Lt => Some(BinOp::Lt),
+        Code::Le => Some(BinOp::Le),
+        Code::Gt => Some(BinOp::Gt),
+        Code::Ge => Some(BinOp::Ge),
+        _ => None,
+    }
+}
+
+fn cond(code: Code) -> Option<Cond> {
+    match code {
+        Code::Beq => Some(Cond::Eq),
+        Code::Bne => Some(Cond::Ne),
+        Code::Blt => Some(Cond::Lt),
+        Code::Ble => Some(Cond::Le),
+        Code::Bgt => Some(Cond::Gt),
+        Code::Bge => Some(Cond::Ge),
+        _ => None,
+    }
+}
+
+/// The items of the module loaded so far
+struct Items<'m> {
+    items: HashMap<String, ItemRef<'m>>,
+    protos: HashMap<String, ProtoRef<'m>>,
+}
+
+pub(crate) fn lower<'m>(module: &MIRModule, m: &mut ModuleBuilder<'m>) -> MirResult<()> {
+    let mut items = Items {
+        items: HashMap::new(),
+        protos: HashMap::new(),
+    };
+    let args = |vars: &[crate::ir::Var]| -> Vec<Arg> {
+        vars.iter()
+            .map(|v| Arg {
+                ty: v.ty,
+                name: v.name.clone(),
+            })
+            .collect()
+    };
+
+    for item in &module.items {
+        match item {
+            Item::Proto(p) => {
+                let proto = m.proto(&p.name, &p.results, &args(&p.args))?;
+                items.protos.insert(p.name.clone(), proto);
+            }
+            Item::Import(name) => {
+                let import = m.import(name)?;
+                items.items.insert(name.clone(), import);
+            }
+            Item::Export(name) => m.export(name)?,
+            Item::Bss { name, size } => {
+                let bss = m.bss(name, *size)?;
+                items.items.insert(name.clone(), bss);
+            }
+            Item::Func(func) => {
+                // functions can refer to items defined after them
+                for insn in &func.body {
+                    if let Insn::Op(_, ops) = insn {
+                        for op in ops {
+                            if let Operand::Ref(name) = op {
+                                if !items.items.contains_key(name)
+                                    && !items.protos.contains_key(name)
+                                {
+                                    let forward = m.forward(name)?;
+                                    items.items.insert(name.clone(), forward);
+                                }
+                            }
+                        }
+                    }
+                }
+                let (item, ()) = m.func(&func.name, &func.results, &args(&func.args), |f| {
+                    FuncLowering::init(&items).lower(func, f)
+                })?;
+                items.items.insert(func.name.clone(), item);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Loading one function, whose registers and labels are named
+struct FuncLowering<'i, 'm, 'f> {
+    items: &'i Items<'m>,
+    regs: HashMap<String, Reg<'f>>,
+    labels: HashMap<String, Label<'f>>,
+}
+
+impl<'i, 'm, 'f> FuncLowering<'i, 'm, 'f> {
+    fn init(items: &'i Items<'m>) -> Self {
+        Self {
+            items,
+            regs: HashMap::new(),
+            labels: HashMap::new(),
+        }
+    }
+
+    fn lower(mut self, func: &Func, f: &mut FuncBuilder<'m, 'f>) -> MirResult<()> {
+        for (i, arg) in func.args.iter().enumerate() {
+            let reg = f.arg(i).expect("`func` declares every argument");
+            self.regs.insert(arg.name.clone(), reg);
+        }
+        for local in &func.locals {
+            let ty = RegType::of(local.ty).ok_or_else(|| MirError::RegType(local.name.clone()))?;
+            let reg = f.local(ty, &local.name)?;
+            self.regs.insert(local.name.clone(), reg);
+        }
+        for insn in &func.body {
+            match insn {
+                Insn::Label(name) => {
+                    let label = self.label(f, name);
+                    f.place(label)?
+                }
+                Insn::Op(code, ops) => self.insn(f, *code, ops)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn label(&mut self, f: &mut FuncBuilder<'m, 'f>, name: &str) -> Label<'f> {
+        *self
+            .labels
+            .entry(name.to_owned())
+            .or_insert_with(|| f.new_label())
+    }
+
+    fn reg(&self, name: &str) -> MirResult<Reg<'f>> {
+        self.regs
+            .get(name)
+            .copied()
+            .ok_or_else(|| MirError::Unknown(name.to_owned()))
+    }
+
+    fn src(&self, op: &Operand) -> MirResult<Src<'m, 'f>> {
+        match op {
+            Operand::Reg(name) => Ok(Src::Reg(self.reg(name)?)),
+            Operand::Int(n) => Ok(Src::Int(*n)),
+            Operand::Ref(name) => self
+                .items
+                .items
+                .get(name)
+                .map(|item| Src::Ref(*item))
+                .ok_or_else(|| MirError::Unknown(name.clone())),
+            Operand::Label(name) => Err(MirError::Unknown(name.clone())),
+            Operand::Mem {
+                ty,
+                disp,
+                base,
+                index,
+                scale,
+            } => {
+                let mut mem = Mem::at(*ty, *disp, self.reg(base)?);
+                if let Some(index) = index {
+                    let scale = match scale {
+                        1 => Scale::S1,
+                        2 => Scale::S2,
+                        4 => Scale::S4,
+                        8 => Scale::S8,
+                        _ => return Err(MirError::Operands("mem")),
+                    };
+                    mem = mem.indexed(self.reg(index)?, scale);
+                }
+                Ok(Src::Mem(mem))
+            }
+        }
+    }
+
+    fn dst(&self, op: &Operand) -> MirResult<Dst<'f>> {
+        match self.src(op)? {
+            Src::Reg(r) => Ok(Dst::Reg(r)),
+            Src::Mem(m) => Ok(Dst::Mem(m)),
+            _ => Err(MirError::Operands("a destination")),
+        }
+    }
+
+    fn target(&mut self, f: &mut FuncBuilder<'m, 'f>, op: &Operand) -> MirResult<Label<'f>> {
+        match op {
+            Operand::Label(name) => Ok(self.label(f, name)),
+            _ => Err(MirError::Operands("a branch")),
+        }
+    }
+
+    fn insn(&mut self, f: &mut FuncBuilder<'m, 'f>, code: Code, ops: &[Operand]) -> MirResult<()> {
+        let wrong = || MirError::Operands(code.name());
+        match (code, ops) {
+            (Code::Mov, [d, s]) => f.mov(self.dst(d)?, self.src(s)?)?,
+            (Code::Jmp, [l]) => {
+                let l = self.target(f, l)?;
+                f.jmp(l)
+            }
+            (Code::Bt | Code::Bf, [l, x]) => {
+                let l = self.target(f, l)?;
+                f.branch_if(l, self.src(x)?, code == Code::Bf)
+            }
+            (Code::Bo | Code::Bno, [l]) => {
+                let l = self.target(f, l)?;
+                f.branch_overflow(l, code == Code::Bno)
+            }
+            (Code::Call, [proto, callee, rest @ ..]) => {
+                let proto_ref = match proto {
+                    Operand::Ref(name) => self.items.protos.get(name).cloned(),
+                    _ => None,
+                }
+                .ok_or_else(wrong)?;
+                let n = proto_ref.results().min(rest.len());
+                let results = rest[..n]
+                    .iter()
+                    .map(|r| self.dst(r))
+                    .collect::<MirResult<Vec<_>>>()?;
+                let args = rest[n..]
+                    .iter()
+                    .map(|a| self.src(a))
+                    .collect::<MirResult<Vec<_>>>()?;
+                f.call(proto_ref, self.src(callee)?, &results, &args)?
+            }
+            (Code::Ret, values) => {
+                let values = values
+                    .iter()
+                    .map(|v| self.src(v))
+                    .collect::<MirResult<Vec<_>>>()?;
+                f.ret(&values)?
+            }
+            (code, [l, a, b]) if cond(code).is_some() => {
+                let l = self.target(f, l)?;
+                f.branch(cond(code).ok_or_else(wrong)?, l, self.src(a)?, self.src(b)?)
+            }
+            (code, [d, a, b]) if binary(code).is_some() => f.binary(
+                binary(code).ok_or_else(wrong)?,
+                self.dst(d)?,
+                self.src(a)?,
+                self.src(b)?,
+            )?,
+            _ => return Err(wrong()),
+        }
+        Ok(())
+    }
+}
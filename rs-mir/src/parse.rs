@@ -0,0 +1,258 @@
+//! Read MIR's textual format back into `ir`, as far as `MIRModule` prints it
+
+use std::collections::HashSet;
+
+use crate::ir::{Code, Func, Insn, Item, Operand, Proto, Type, Var};
+use crate::{MIRModule, MirError, MirResult};
+
+/// A line with its comment taken off: `label: word rest`
+struct Line<'t> {
+    number: usize,
+    label: Option<&'t str>,
+    word: Option<&'t str>,
+    rest: &'t str,
+}
+
+impl<'t> Line<'t> {
+    fn error(&self, message: impl Into<String>) -> MirError {
+        MirError::Syntax {
+            line: self.number,
+            message: message.into(),
+        }
+    }
+
+    fn label(&self) -> MirResult<&'t str> {
+        self.label
+            .ok_or_else(|| self.error(format!("`{}` needs a name", self.word.unwrap_or(""))))
+    }
+
+    /// The operands, split on the commas outside of parentheses
+    fn operands(&self) -> Vec<&'t str> {
+        let mut ops = Vec::new();
+        let (mut depth, mut start) = (0, 0);
+        for (i, c) in self.rest.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    ops.push(self.rest[start..i].trim());
+                    start = i + 1;
+                }
+                _ => (),
+            }
+        }
+        if !self.rest[start..].trim().is_empty() {
+            ops.push(self.rest[start..].trim());
+        }
+        ops
+    }
+}
+
+fn lines(text: &str) -> Vec<Line<'_>> {
+    let mut lines = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (first, rest) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(first, rest)| (first, rest.trim()));
+        let (label, word, rest) = match first.strip_suffix(':') {
+            Some(label) => {
+                let (word, rest) = rest
+                    .split_once(char::is_whitespace)
+                    .map_or((rest, ""), |(word, rest)| (word, rest.trim()));
+                (Some(label), (!word.is_empty()).then_some(word), rest)
+            }
+            None => (None, Some(first), rest),
+        };
+        lines.push(Line {
+            number: i + 1,
+            label,
+            word,
+            rest,
+        });
+    }
+    lines
+}
+
+/// Parse every module in `text`
+pub(crate) fn modules(text: &str) -> MirResult<Vec<MIRModule>> {
+    let lines = lines(text);
+    let mut modules = Vec::new();
+    let mut module: Option<MIRModule> = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = &lines[i];
+        i += 1;
+        let word = line
+            .word
+            .ok_or_else(|| line.error("a label outside a function"))?;
+        if word == "module" {
+            if module.is_some() {
+                return Err(line.error("a module inside a module"));
+            }
+            module = Some(MIRModule::init(line.label()?));
+            continue;
+        }
+        let Some(m) = module.as_mut() else {
+            return Err(line.error(format!("`{word}` outside a module")));
+        };
+        match word {
+            "endmodule" => modules.extend(module.take()),
+            "proto" => {
+                let (results, args) = signature(line)?;
+                m.items.push(Item::Proto(Proto {
+                    name: line.label()?.to_owned(),
+                    results,
+                    args,
+                }))
+            }
+            "import" | "export" => {
+                for name in line.operands() {
+                    m.items.push(if word == "import" {
+                        Item::Import(name.to_owned())
+                    } else {
+                        Item::Export(name.to_owned())
+                    })
+                }
+            }
+            "bss" => {
+                let size = line
+                    .rest
+                    .parse()
+                    .map_err(|_| line.error("`bss` needs a size"))?;
+                m.items.push(Item::Bss {
+                    name: line.label()?.to_owned(),
+                    size,
+                })
+            }
+            "func" => {
+                let end = lines[i..]
+                    .iter()
+                    .position(|l| l.word == Some("endfunc"))
+                    .ok_or_else(|| line.error("a function without `endfunc`"))?;
+                m.items.push(Item::Func(func(line, &lines[i..i + end])?));
+                i += end + 1;
+            }
+            other => return Err(line.error(format!("unknown item `{other}`"))),
+        }
+    }
+    match module {
+        Some(m) => Err(MirError::Syntax {
+            line: text.lines().count(),
+            message: format!("module `{}` without `endmodule`", m.name),
+        }),
+        None => Ok(modules),
+    }
+}
+
+fn ty(line: &Line, name: &str) -> MirResult<Type> {
+    Type::parse(name).ok_or_else(|| line.error(format!("unknown type `{name}`")))
+}
+
+fn var(line: &Line, op: &str) -> MirResult<Var> {
+    let (t, name) = op
+        .split_once(':')
+        .ok_or_else(|| line.error(format!("`{op}` is not `type:name`")))?;
+    Ok(Var {
+        ty: ty(line, t)?,
+        name: name.to_owned(),
+    })
+}
+
+/// The result types, and then the typed arguments, of a `proto` or `func`
+fn signature(line: &Line) -> MirResult<(Vec<Type>, Vec<Var>)> {
+    let (mut results, mut args) = (Vec::new(), Vec::new());
+    for op in line.operands() {
+        if op.contains(':') {
+            args.push(var(line, op)?);
+        } else if args.is_empty() {
+            results.push(ty(line, op)?);
+        } else {
+            return Err(line.error("a result type after the arguments"));
+        }
+    }
+    Ok((results, args))
+}
+
+fn func(line: &Line, body: &[Line]) -> MirResult<Func> {
+    let (results, args) = signature(line)?;
+    let mut func = Func::init(line.label()?, results, args);
+    for l in body.iter().filter(|l| l.word == Some("local")) {
+        for op in l.operands() {
+            func.locals.push(var(l, op)?);
+        }
+    }
+    let regs: HashSet<&str> = (func.args.iter().chain(&func.locals))
+        .map(|v| v.name.as_str())
+        .collect();
+    let labels: HashSet<&str> = body.iter().filter_map(|l| l.label).collect();
+
+    let mut insns = Vec::new();
+    for l in body {
+        if let Some(label) = l.label {
+            insns.push(Insn::Label(label.to_owned()));
+        }
+        match l.word {
+            None | Some("local") => (),
+            Some(word) => {
+                let code =
+                    Code::parse(word).ok_or_else(|| l.error(format!("unknown code `{word}`")))?;
+                let ops = (l.operands().into_iter())
+                    .map(|op| operand(l, op, &regs, &labels))
+                    .collect::<MirResult<_>>()?;
+                insns.push(Insn::Op(code, ops));
+            }
+        }
+    }
+    func.body = insns;
+    Ok(func)
+}
+
+fn operand(
+    line: &Line,
+    op: &str,
+    regs: &HashSet<&str>,
+    labels: &HashSet<&str>,
+) -> MirResult<Operand> {
+    if op.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+        return op
+            .parse()
+            .map(Operand::Int)
+            .map_err(|_| line.error(format!("`{op}` is not an integer")));
+    }
+    if let Some((t, addr)) = op.split_once(':') {
+        let bad = || line.error(format!("`{op}` is not `type:disp(base, index, scale)`"));
+        let (disp, regs) = addr
+            .strip_suffix(')')
+            .and_then(|a| a.split_once('('))
+            .ok_or_else(bad)?;
+        let disp = match disp {
+            "" => 0,
+            disp => disp.parse().map_err(|_| bad())?,
+        };
+        let regs: Vec<&str> = regs.split(',').map(str::trim).collect();
+        let (base, index, scale) = match regs.as_slice() {
+            [base] => (base, None, 1),
+            [base, index] => (base, Some(index), 1),
+            [base, index, scale] => (base, Some(index), scale.parse().map_err(|_| bad())?),
+            _ => return Err(bad()),
+        };
+        return Ok(Operand::Mem {
+            ty: ty(line, t)?,
+            disp,
+            base: base.to_string(),
+            index: index.map(|i| i.to_string()),
+            scale,
+        });
+    }
+    Ok(if regs.contains(op) {
+        Operand::Reg(op.to_owned())
+    } else if labels.contains(op) {
+        Operand::Label(op.to_owned())
+    } else {
+        Operand::Ref(op.to_owned())
+    })
+}
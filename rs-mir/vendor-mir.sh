@@ -0,0 +1,16 @@
+#!/bin/sh
+# Vendor the MIR sources `build.rs` compiles into `mir/`, from the release
+# the bindings are written against (or `$MIR_TAG`). The commit they came from
+# is kept in `mir/VERSION`; check the result in.
+set -eu
+
+tag=${MIR_TAG:-v1.0.0}
+cd "$(dirname "$0")"
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+git clone --quiet --depth 1 --branch "$tag" https://github.com/vnmakarov/mir "$tmp/mir"
+rm -rf mir
+mkdir mir
+cp "$tmp"/mir/*.c "$tmp"/mir/*.h "$tmp"/mir/LICENSE mir/
+echo "$tag $(git -C "$tmp/mir" rev-parse HEAD)" > mir/VERSION
@@ -0,0 +1,903 @@
+//! Lower a closure-converted program straight to x86-64 assembly for the
+//! GNU assembler, made of the runtime in `runtime.s` and then the program,
+//! which `cc` can assemble and link against the C library alone
+//!
+//! Values are words laid out as `word` describes, with a procedure's
+//! function as the code of its closures. Every value lives in a slot of its
+//! function's frame, addressed from `%rsp` as it was on entry, so nothing is
+//! kept in registers across calls but the heap pointer in `%r15` and the end
+//! of the heap in `%r14`, which the runtime sets up. That makes the slots and
+//! the globals the roots of its copying collector, which finds the slots in
+//! use at each call that may collect in a stack map made along with the code.
+//! Errors are reported by the runtime, which exits right away.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::cgen::string;
+use crate::closure::{Flat, Program};
+use crate::datum::Datum;
+use crate::eval::{EvalError, EvalResult};
+use crate::prim::{self, Prim};
+use crate::primsyn::Type;
+use crate::symbol::Symbol;
+use crate::types::Types;
+use crate::word::{self, Word, CLOSURE_TAG, FALSE, NULL, PAIR_TAG, TRUE, UNBOUND, UNSPECIFIED};
+
+const RUNTIME: &str = include_str!("runtime.s");
+
+/// Turn a name into something the assembler accepts as part of a symbol
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// The symbol of the word holding the quoted list `i`, among the globals
+fn quoted(i: usize) -> String {
+    format!("q{i}")
+}
+
+/// The slot `i` of the frame, counting from 1
+fn at(i: usize) -> String {
+    format!("-{}(%rsp)", 8 * i)
+}
+
+/// The offset of the free variable `i` from a pointer to its closure
+fn free(i: usize) -> i64 {
+    16 + 8 * i as i64 - CLOSURE_TAG
+}
+
+/// Load the constant `n` into `reg`, which takes a longer instruction unless
+/// it fits in 32 bits
+fn load_imm(n: i64, reg: &str) -> String {
+    if i32::try_from(n).is_ok() {
+        format!("movq ${n}, {reg}")
+    } else {
+        format!("movabsq ${n}, {reg}")
+    }
+}
+
+/// An argument to a function of the runtime
+enum Arg {
+    /// A string, as the label of its copy in the program
+    Str(String),
+    Slot(usize),
+    Int(i64),
+}
+
+/// What the code generator needs to know about a procedure besides its body
+struct ProcInfo {
+    func: String,
+    arity: usize,
+}
+
+/// The function being generated
+struct Function {
+    func: String,
+    lines: Vec<String>,
+    /// The slots holding the locals in scope
+    regs: HashMap<Symbol, usize>,
+    /// The names of the free variables of its closure
+    free: Vec<Symbol>,
+    /// The slot holding its closure
+    clo: usize,
+    /// The slots in use, the first ones holding the arguments. Any slot past
+    /// these is free, to use as the frame of a call for one.
+    slots: usize,
+    /// The most slots ever in use
+    max: usize,
+}
+
+impl Function {
+    fn init(func: &str, formals: &[Symbol], free: Vec<Symbol>) -> Self {
+        let mut f = Self {
+            func: func.to_owned(),
+            lines: Vec::new(),
+            regs: HashMap::new(),
+            free,
+            clo: formals.len() + 1,
+            slots: formals.len() + 1,
+            max: formals.len() + 1,
+        };
+        for (i, formal) in formals.iter().enumerate() {
+            f.regs.insert(*formal, i + 1);
+        }
+        f
+    }
+
+    fn slot(&mut self) -> usize {
+        self.slots += 1;
+        self.max = self.max.max(self.slots);
+        self.slots
+    }
+
+    fn push(&mut self, line: impl Into<String>) {
+        self.lines.push(format!("        {}", line.into()))
+    }
+
+    fn label(&mut self, label: &str) {
+        self.lines.push(format!("{label}:"))
+    }
+
+    /// A new slot holding the value in `%rax`
+    fn store(&mut self) -> usize {
+        let r = self.slot();
+        self.push(format!("movq %rax, {}", at(r)));
+        r
+    }
+
+    /// A new slot holding the constant `n`
+    fn constant(&mut self, n: i64) -> usize {
+        self.push(load_imm(n, "%rax"));
+        self.store()
+    }
+
+    /// A new slot holding `#t` if the condition code `cc` holds
+    fn boolean(&mut self, cc: &str) -> usize {
+        self.push(format!("set{cc} %al"));
+        self.push("movzbl %al, %eax");
+        self.push(format!("leaq {FALSE}(,%rax,8), %rax"));
+        self.store()
+    }
+
+    /// Leave with the value in slot `r`
+    fn ret(&mut self, r: usize) {
+        self.push(format!("movq {}, %rax", at(r)));
+        self.push("ret");
+    }
+
+    /// Write out the function, preceded by the number of free variables of
+    /// its closures for the collector. It starts by storing its closure and
+    /// clearing the rest of its slots, so the collector never finds a stale
+    /// value in one.
+    fn finish(self, out: &mut String) {
+        let n = self.free.len();
+        writeln!(out, "        .p2align 3\n        .quad {n}\n{}:", self.func).unwrap();
+        writeln!(out, "        movq %rdi, {}", at(self.clo)).unwrap();
+        for i in self.clo + 1..=self.max {
+            writeln!(out, "        movq $0, {}", at(i)).unwrap();
+        }
+        for line in &self.lines {
+            writeln!(out, "{line}").unwrap();
+        }
+        out.push('\n');
+    }
+}
+
+/// Generates assembly for a whole program, in the style of the incremental
+/// Scheme compilers. A procedure is called with its closure in `%rdi` and
+/// its arguments just below the return address, where they make the first
+/// slots of its frame. Calls check the callee's tag and arity before calling
+/// through its code pointer, except for calls to top-level procedures
+/// defined once, which are called directly. A call in tail position moves
+/// its arguments over those of the caller and jumps, so every tail call
+/// runs in constant space.
+pub struct AsmGen<'t> {
+    types: &'t Types,
+    procs: Vec<ProcInfo>,
+    globals: Vec<Symbol>,
+    /// Top-level procedures defined once, with no free variables
+    direct: HashMap<Symbol, usize>,
+    /// The labels of the strings the program uses
+    strings: HashMap<String, String>,
+    /// The quoted lists, each made once before the program runs
+    consts: Vec<Datum>,
+    /// The symbols the program quotes, by the index their words hold
+    symbols: Vec<Symbol>,
+    /// The stack map: the return address of each call that may collect, and
+    /// the bytes of its caller's frame in use there
+    frames: Vec<(String, usize)>,
+    labels: usize,
+}
+
+impl<'t> AsmGen<'t> {
+    pub fn init(types: &'t Types) -> Self {
+        Self {
+            types,
+            procs: Vec::new(),
+            globals: Vec::new(),
+            direct: HashMap::new(),
+            strings: HashMap::new(),
+            consts: Vec::new(),
+            symbols: Vec::new(),
+            frames: Vec::new(),
+            labels: 0,
+        }
+    }
+
+    pub fn generate(mut self, program: &Program) -> EvalResult<String> {
+        self.globals = program.globals.clone();
+        for (i, proc) in program.procs.iter().enumerate() {
+            let name = proc.name.as_deref().unwrap_or("lambda");
+            self.procs.push(ProcInfo {
+                func: format!("f{i}_{}", sanitize(name)),
+                arity: proc.formals.len(),
+            });
+            if let (Some(name), true) = (&proc.global, proc.free.is_empty()) {
+                self.direct.insert(*name, i);
+            }
+        }
+
+        let mut funcs = Vec::new();
+        for (i, proc) in program.procs.iter().enumerate() {
+            let func = self.procs[i].func.clone();
+            let mut f = Function::init(&func, &proc.formals, proc.free.clone());
+            let r = self.expr(&mut f, &proc.body)?;
+            f.ret(r);
+            funcs.push(f);
+        }
+
+        let mut f = Function::init("sg_main", &[], vec![]);
+        self.collecting(&mut f, "sg_constants");
+        let r = self.expr(&mut f, &program.main)?;
+        f.ret(r);
+        funcs.push(f);
+
+        let mut f = Function::init("sg_constants", &[], vec![]);
+        let consts = std::mem::take(&mut self.consts);
+        for (i, d) in consts.iter().enumerate() {
+            let mark = f.slots;
+            let r = self.datum(&mut f, d)?;
+            f.push(format!("movq {}, %rax", at(r)));
+            f.push(format!("movq %rax, {}(%rip)", quoted(i)));
+            f.slots = mark;
+        }
+        f.push("ret");
+        funcs.push(f);
+
+        let mut names = Vec::new();
+        for (info, proc) in self.procs.iter().zip(&program.procs) {
+            if let Some(name) = &proc.name {
+                names.push((info.func.clone(), name.clone()));
+            }
+        }
+        let names: Vec<(String, String)> = names
+            .into_iter()
+            .map(|(func, name)| (func, self.string(&name)))
+            .collect();
+        let symbols: Vec<String> = (self.symbols.clone().into_iter())
+            .map(|sym| self.string(&sym))
+            .collect();
+
+        let mut out = String::from(RUNTIME);
+        out.push_str("\n# The program\n\n        .data\nsg_globals:\n");
+        for i in 0..self.globals.len() {
+            writeln!(out, "{}:\n        .quad {UNBOUND}", self.global(i)).unwrap();
+        }
+        for i in 0..consts.len() {
+            writeln!(out, "{}:\n        .quad 0", quoted(i)).unwrap();
+        }
+        out.push_str("sg_globals_end:\n");
+        // the functions come out in the order they were made, so these are
+        // sorted
+        let count = self.frames.len();
+        writeln!(out, "sg_frame_count:\n        .quad {count}\nsg_frames:").unwrap();
+        for (label, bytes) in &self.frames {
+            writeln!(out, "        .quad {label}, {bytes}").unwrap();
+        }
+        out.push_str("# the name of each procedure named, by its code\nsg_names:\n");
+        for (func, name) in &names {
+            writeln!(out, "        .quad {func}, {name}").unwrap();
+        }
+        out.push_str(
+            "        .quad 0\n# the name of each symbol quoted, by its index\nsg_symbols:\n",
+        );
+        for name in &symbols {
+            writeln!(out, "        .quad {name}").unwrap();
+        }
+        out.push_str("\n        .section .rodata\n");
+        let mut strings: Vec<(&String, &String)> = self.strings.iter().collect();
+        strings.sort_by_key(|(_, label)| label[3..].parse::<usize>().unwrap());
+        for (s, label) in strings {
+            writeln!(out, "{label}:\n        .string {}", string(s)).unwrap();
+        }
+        out.push_str("\n        .text\n");
+        for f in funcs {
+            f.finish(&mut out);
+        }
+        out.push_str("        .section .note.GNU-stack,\"\",@progbits\n");
+        Ok(out)
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    /// The label of the string `s`
+    fn string(&mut self, s: &str) -> String {
+        let n = self.strings.len();
+        (self.strings)
+            .entry(s.to_owned())
+            .or_insert_with(|| format!(".LS{n}"))
+            .clone()
+    }
+
+    /// The symbol of the word holding `globals[i]`
+    fn global(&self, i: usize) -> String {
+        format!("g{i}_{}", sanitize(&self.globals[i]))
+    }
+
+    fn global_index(&self, name: Symbol) -> usize {
+        self.globals
+            .iter()
+            .position(|g| *g == name)
+            .expect("`Converter` collects every global")
+    }
+
+    /// Call `func` in the runtime, with `%rsp` moved past the slots in use
+    fn runtime(&mut self, f: &mut Function, func: &str) {
+        let frame = 8 * f.slots;
+        f.push(format!("subq ${frame}, %rsp"));
+        f.push(format!("call {func}"));
+        f.push(format!("addq ${frame}, %rsp"));
+    }
+
+    /// Call `target`, which may collect, noting where it returns to in the
+    /// stack map
+    fn collecting(&mut self, f: &mut Function, target: &str) {
+        let frame = 8 * f.slots;
+        let back = self.label();
+        f.push(format!("subq ${frame}, %rsp"));
+        f.push(format!("call {target}"));
+        f.label(&back);
+        f.push(format!("addq ${frame}, %rsp"));
+        self.frames.push((back, frame));
+    }
+
+    /// Report a fault through `func` in the runtime, which never returns
+    fn fail(&mut self, f: &mut Function, func: &str, args: &[Arg]) {
+        for (arg, reg) in args.iter().zip(["%rdi", "%rsi", "%rdx"]) {
+            let line = match arg {
+                Arg::Str(s) => format!("leaq {}(%rip), {reg}", self.string(s)),
+                Arg::Slot(r) => format!("movq {}, {reg}", at(*r)),
+                Arg::Int(n) => load_imm(*n, reg),
+            };
+            f.push(line);
+        }
+        self.runtime(f, func);
+    }
+
+    /// Report a fault through `func` unless slot `r` holds a value of type
+    /// `ty`
+    fn guard(&mut self, f: &mut Function, r: usize, ty: Type, func: &str, args: &[Arg]) {
+        let ok = self.label();
+        match word::type_test(ty) {
+            // the low half of a fixnum is zero
+            Some((0xFFFF_FFFF, 0)) => {
+                f.push(format!("movq {}, %rax", at(r)));
+                f.push("testl %eax, %eax");
+            }
+            Some((mask, expected)) => {
+                f.push(format!("movq {}, %rax", at(r)));
+                f.push(format!("andq ${mask}, %rax"));
+                f.push(format!("cmpq ${expected}, %rax"));
+            }
+            None => {
+                self.fail(f, func, args);
+                return;
+            }
+        }
+        f.push(format!("je {ok}"));
+        self.fail(f, func, args);
+        f.label(&ok);
+    }
+
+    /// Report an overflow in `prim` if the last instruction overflowed
+    fn overflow(&mut self, f: &mut Function, prim: &str) {
+        let ok = self.label();
+        f.push(format!("jno {ok}"));
+        self.fail(f, "sg_overflow", &[Arg::Str(prim.to_owned())]);
+        f.label(&ok);
+    }
+
+    /// Allocate `size` bytes into `%rax`, collecting if there's no room left
+    fn alloc(&mut self, f: &mut Function, size: usize) {
+        let done = self.label();
+        f.push("movq %r15, %rax");
+        f.push(format!("addq ${size}, %r15"));
+        f.push("cmpq %r14, %r15");
+        f.push(format!("jbe {done}"));
+        f.push(format!("movq ${size}, %rdi"));
+        self.collecting(f, "sg_grow");
+        f.label(&done);
+    }
+
+    fn cons(&mut self, f: &mut Function, car: usize, cdr: usize) -> usize {
+        self.alloc(f, 16);
+        f.push(format!("movq {}, %rcx", at(car)));
+        f.push("movq %rcx, (%rax)");
+        f.push(format!("movq {}, %rcx", at(cdr)));
+        f.push("movq %rcx, 8(%rax)");
+        f.push(format!("addq ${PAIR_TAG}, %rax"));
+        f.store()
+    }
+
+    /// Make a closure of `procs[i]` in slot `dst`, without filling in its
+    /// free variables
+    fn closure(&mut self, f: &mut Function, i: usize, free: usize, dst: usize) {
+        self.alloc(f, 16 + 8 * free);
+        let info = &self.procs[i];
+        f.push(format!("leaq {}(%rip), %rcx", info.func));
+        f.push("movq %rcx, (%rax)");
+        f.push(format!("movq ${}, 8(%rax)", info.arity));
+        f.push(format!("addq ${CLOSURE_TAG}, %rax"));
+        f.push(format!("movq %rax, {}", at(dst)));
+    }
+
+    /// Fill in the free variables of the closure in slot `dst`
+    fn capture(&mut self, f: &mut Function, dst: usize, captured: &[Flat]) -> EvalResult<()> {
+        for (i, c) in captured.iter().enumerate() {
+            let v = self.expr(f, c)?;
+            f.push(format!("movq {}, %rax", at(dst)));
+            f.push(format!("movq {}, %rcx", at(v)));
+            f.push(format!("movq %rcx, {}(%rax)", free(i)));
+        }
+        Ok(())
+    }
+
+    /// The value of the quoted `d`, which is always the same object
+    fn constant(&mut self, f: &mut Function, d: &Datum) -> EvalResult<usize> {
+        if let Datum::List(_) | Datum::DottedList(..) = d {
+            if Word::immediate(d).is_none() {
+                self.consts.push(d.clone());
+                f.push(format!(
+                    "movq {}(%rip), %rax",
+                    quoted(self.consts.len() - 1)
+                ));
+                return Ok(f.store());
+            }
+        }
+        self.datum(f, d)
+    }
+
+    /// Make `d` afresh
+    fn datum(&mut self, f: &mut Function, d: &Datum) -> EvalResult<usize> {
+        if let Some(Word(word)) = Word::immediate(d) {
+            return Ok(f.constant(word));
+        }
+        match d {
+            Datum::Symbol(sym) => {
+                let Word(word) = Word::symbol(word::intern(&mut self.symbols, *sym));
+                Ok(f.constant(word))
+            }
+            Datum::List(items) => self.list(f, items, &Datum::Null),
+            Datum::DottedList(items, tail) => self.list(f, items, tail),
+            other => Err(EvalError::Unsupported(format!(
+                "the constant `{other}` in compiled code"
+            ))),
+        }
+    }
+
+    fn list(&mut self, f: &mut Function, items: &[Datum], tail: &Datum) -> EvalResult<usize> {
+        let mut r = self.datum(f, tail)?;
+        for item in items.iter().rev() {
+            let car = self.datum(f, item)?;
+            r = self.cons(f, car, r);
+        }
+        Ok(r)
+    }
+
+    /// Is `e` known to evaluate to a value of type `ty`?
+    fn known(&self, f: &Function, e: &Flat, ty: Type) -> bool {
+        let t = match e {
+            Flat::Local(name) => self.types.of_var(*name),
+            Flat::Free(i) => self.types.of_var(f.free[*i]),
+            Flat::Const(d) => crate::types::datum_type(d),
+            Flat::Prim(p, _) => prim::result_type(p.name),
+            Flat::Closure(..) => Some(Type::Proc),
+            Flat::The(t, _) => Some(*t),
+            _ => None,
+        };
+        t.is_some_and(|t| t.within(ty))
+    }
+
+    /// The slot holding the value of `e`. The slots it used on the way are
+    /// free again afterwards, with its value moved down to the first of them.
+    fn expr(&mut self, f: &mut Function, e: &Flat) -> EvalResult<usize> {
+        let mark = f.slots;
+        let r = self.compute(f, e)?;
+        if r <= mark {
+            f.slots = mark;
+            return Ok(r);
+        }
+        if r != mark + 1 {
+            f.push(format!("movq {}, %rax", at(r)));
+            f.push(format!("movq %rax, {}", at(mark + 1)));
+        }
+        f.slots = mark + 1;
+        Ok(mark + 1)
+    }
+
+    fn compute(&mut self, f: &mut Function, e: &Flat) -> EvalResult<usize> {
+        let r = match e {
+            Flat::Local(name) => f.regs[name],
+            Flat::Free(i) => {
+                f.push(format!("movq {}, %rax", at(f.clo)));
+                f.push(format!("movq {}(%rax), %rax", free(*i)));
+                f.store()
+            }
+            Flat::Global(name) => {
+                let g = self.global(self.global_index(*name));
+                let ok = self.label();
+                f.push(format!("movq {g}(%rip), %rax"));
+                f.push(format!("cmpq ${UNBOUND}, %rax"));
+                f.push(format!("jne {ok}"));
+                self.fail(f, "sg_unbound", &[Arg::Str(name.to_string())]);
+                f.label(&ok);
+                f.store()
+            }
+            Flat::Const(d) => self.constant(f, d)?,
+            Flat::PrimRef(p) => {
+                return Err(EvalError::Unsupported(format!(
+                    "the primitive `{}` as a value in compiled code",
+                    p.name
+                )))
+            }
+            Flat::Closure(i, captured) => {
+                let r = f.slot();
+                self.closure(f, *i, captured.len(), r);
+                self.capture(f, r, captured)?;
+                r
+            }
+            Flat::If(c, t, e) => {
+                let r = f.slot();
+                let (otherwise, done) = (self.label(), self.label());
+                let c = self.expr(f, c)?;
+                f.push(format!("cmpq ${FALSE}, {}", at(c)));
+                f.push(format!("je {otherwise}"));
+                let t = self.expr(f, t)?;
+                f.push(format!("movq {}, %rax", at(t)));
+                f.push(format!("movq %rax, {}", at(r)));
+                f.push(format!("jmp {done}"));
+                f.label(&otherwise);
+                let e = self.expr(f, e)?;
+                f.push(format!("movq {}, %rax", at(e)));
+                f.push(format!("movq %rax, {}", at(r)));
+                f.label(&done);
+                r
+            }
+            Flat::Call(rator, rands, tail) => self.call(f, rator, rands, *tail)?,
+            Flat::Prim(p, rands) => self.prim(f, p, rands)?,
+            Flat::Let(bs, body) => {
+                // nothing assigns to a slot once it holds a local's value
+                for (name, init) in bs {
+                    let v = self.expr(f, init)?;
+                    f.regs.insert(*name, v);
+                }
+                self.expr(f, body)?
+            }
+            Flat::LetRec(procs, body) => {
+                for (name, i, captured) in procs {
+                    let r = f.slot();
+                    f.regs.insert(*name, r);
+                    self.closure(f, *i, captured.len(), r);
+                }
+                for (name, _, captured) in procs {
+                    let r = f.regs[name];
+                    self.capture(f, r, captured)?;
+                }
+                self.expr(f, body)?
+            }
+            Flat::Begin(es) => {
+                let mut r = None;
+                for e in es {
+                    r = Some(self.expr(f, e)?);
+                }
+                match r {
+                    Some(r) => r,
+                    None => f.constant(UNSPECIFIED),
+                }
+            }
+            Flat::The(ty, e) => {
+                let r = self.expr(f, e)?;
+                // the types of variables are inferred trusting this check
+                let var = matches!(**e, Flat::Local(_) | Flat::Free(_));
+                if var || !self.known(f, e, *ty) {
+                    let args = [Arg::Str(ty.name().to_owned()), Arg::Slot(r)];
+                    self.guard(f, r, *ty, "sg_annotation_failed", &args);
+                }
+                r
+            }
+            Flat::Define(name, e) => {
+                let v = self.expr(f, e)?;
+                let g = self.global(self.global_index(*name));
+                f.push(format!("movq {}, %rax", at(v)));
+                f.push(format!("movq %rax, {g}(%rip)"));
+                f.constant(UNSPECIFIED)
+            }
+        };
+        Ok(r)
+    }
+
+    fn call(
+        &mut self,
+        f: &mut Function,
+        rator: &Flat,
+        rands: &[Flat],
+        tail: bool,
+    ) -> EvalResult<usize> {
+        let direct = match rator {
+            Flat::Global(name) => self
+                .direct
+                .get(name)
+                .copied()
+                .filter(|i| self.procs[*i].arity == rands.len()),
+            _ => None,
+        };
+        let callee = self.expr(f, rator)?;
+        let mut args = Vec::new();
+        for rand in rands {
+            args.push(self.expr(f, rand)?);
+        }
+
+        let n = rands.len();
+        let target = match direct {
+            Some(i) => self.procs[i].func.clone(),
+            None => {
+                let args = [Arg::Slot(callee)];
+                self.guard(f, callee, Type::Proc, "sg_not_a_procedure", &args);
+                let ok = self.label();
+                f.push(format!("movq {}, %rax", at(callee)));
+                f.push(format!("cmpq ${n}, {}(%rax)", 8 - CLOSURE_TAG));
+                f.push(format!("je {ok}"));
+                let args = [Arg::Slot(callee), Arg::Int(n as i64)];
+                self.fail(f, "sg_wrong_arg_count", &args);
+                f.label(&ok);
+                format!("*{}(%rdi)", -CLOSURE_TAG)
+            }
+        };
+
+        let frame = f.slots;
+        if tail {
+            // stage the arguments past the frame, then move them down over
+            // those of this call, in order so none is overwritten unread
+            for (i, a) in args.iter().enumerate() {
+                f.push(format!("movq {}, %rax", at(*a)));
+                f.push(format!("movq %rax, {}", at(frame + 1 + i)));
+            }
+            f.push(format!("movq {}, %rdi", at(callee)));
+            for i in 0..n {
+                f.push(format!("movq {}, %rax", at(frame + 1 + i)));
+                f.push(format!("movq %rax, {}", at(1 + i)));
+            }
+            f.push(format!("jmp {target}"));
+            // never reached, but its value is expected somewhere
+            return Ok(f.slot());
+        }
+        // the callee's return address goes in the slot just past the frame,
+        // with its arguments after that
+        for (i, a) in args.iter().enumerate() {
+            f.push(format!("movq {}, %rax", at(*a)));
+            f.push(format!("movq %rax, {}", at(frame + 2 + i)));
+        }
+        f.push(format!("movq {}, %rdi", at(callee)));
+        self.collecting(f, &target);
+        Ok(f.store())
+    }
+
+    fn prim(&mut self, f: &mut Function, p: &'static Prim, rands: &[Flat]) -> EvalResult<usize> {
+        let name = p.name.to_owned();
+        let mut args = Vec::new();
+        for (i, rand) in rands.iter().enumerate() {
+            let a = self.expr(f, rand)?;
+            if let Some(kind) = prim::arg_kind(p.name, i) {
+                if !self.known(f, rand, kind.as_type()) {
+                    let report = [
+                        Arg::Str(name.to_string()),
+                        Arg::Str(kind.describe().to_owned()),
+                        Arg::Slot(a),
+                    ];
+                    self.guard(f, a, kind.as_type(), "sg_wrong_type", &report);
+                }
+            }
+            args.push(a);
+        }
+
+        let r = match (p.name, args.as_slice()) {
+            ("+", []) => f.constant(word::fixnum(0)),
+            ("*", []) => f.constant(word::fixnum(1)),
+            ("+" | "*" | "-", [first, rest @ ..]) if !rest.is_empty() => {
+                f.push(format!("movq {}, %rax", at(*first)));
+                for a in rest {
+                    match p.name {
+                        "+" => f.push(format!("addq {}, %rax", at(*a))),
+                        "-" => f.push(format!("subq {}, %rax", at(*a))),
+                        // only one factor keeps its shift
+                        _ => {
+                            f.push("sarq $32, %rax");
+                            f.push(format!("imulq {}, %rax", at(*a)));
+                        }
+                    }
+                    self.overflow(f, &name);
+                }
+                f.store()
+            }
+            ("+" | "*", [a]) => *a,
+            ("-", [a]) => {
+                f.push("xorl %eax, %eax");
+                f.push(format!("subq {}, %rax", at(*a)));
+                self.overflow(f, &name);
+                f.store()
+            }
+            ("add1" | "sub1", [a]) => {
+                let op = if p.name == "add1" { "addq" } else { "subq" };
+                f.push(load_imm(word::fixnum(1), "%rcx"));
+                f.push(format!("movq {}, %rax", at(*a)));
+                f.push(format!("{op} %rcx, %rax"));
+                self.overflow(f, &name);
+                f.store()
+            }
+            ("quotient" | "remainder" | "modulo", [a, b]) => {
+                let ok = self.label();
+                f.push(format!("movq {}, %rcx", at(*b)));
+                f.push("testq %rcx, %rcx");
+                f.push(format!("jne {ok}"));
+                self.fail(f, "sg_division_by_zero", &[Arg::Str(name.to_string())]);
+                f.label(&ok);
+                f.push("sarq $32, %rcx");
+                f.push(format!("movq {}, %rax", at(*a)));
+                f.push("sarq $32, %rax");
+                f.push("cqto");
+                f.push("idivq %rcx");
+                match p.name {
+                    "quotient" => {
+                        // only the most negative fixnum over -1 overflows
+                        let ok = self.label();
+                        f.push("movq %rax, %rsi");
+                        f.push("shlq $32, %rax");
+                        f.push("movq %rax, %rdx");
+                        f.push("sarq $32, %rdx");
+                        f.push("cmpq %rsi, %rdx");
+                        f.push(format!("je {ok}"));
+                        self.fail(f, "sg_overflow", &[Arg::Str(name.to_string())]);
+                        f.label(&ok);
+                    }
+                    "remainder" => {
+                        f.push("movq %rdx, %rax");
+                        f.push("shlq $32, %rax");
+                    }
+                    _ => {
+                        // `modulo` takes the sign of the divisor rather than
+                        // the dividend
+                        let done = self.label();
+                        f.push("testq %rdx, %rdx");
+                        f.push(format!("je {done}"));
+                        f.push("movq %rdx, %rsi");
+                        f.push("xorq %rcx, %rsi");
+                        f.push(format!("jns {done}"));
+                        f.push("addq %rcx, %rdx");
+                        f.label(&done);
+                        f.push("movq %rdx, %rax");
+                        f.push("shlq $32, %rax");
+                    }
+                }
+                f.store()
+            }
+            ("abs", [a]) => {
+                let done = self.label();
+                f.push(format!("movq {}, %rax", at(*a)));
+                f.push("testq %rax, %rax");
+                f.push(format!("jns {done}"));
+                f.push("negq %rax");
+                f.push(format!("jno {done}"));
+                self.fail(f, "sg_overflow", &[Arg::Str(name.to_string())]);
+                f.label(&done);
+                f.store()
+            }
+            ("=" | "<" | ">" | "<=" | ">=", [_]) => f.constant(TRUE),
+            ("=" | "<" | ">" | "<=" | ">=", _) => {
+                let cc = match p.name {
+                    "=" => "e",
+                    "<" => "l",
+                    ">" => "g",
+                    "<=" => "le",
+                    _ => "ge",
+                };
+                // shifting keeps the order, so compare the words themselves
+                f.push("movl $1, %edx");
+                for pair in args.windows(2) {
+                    f.push(format!("movq {}, %rax", at(pair[0])));
+                    f.push(format!("cmpq {}, %rax", at(pair[1])));
+                    f.push(format!("set{cc} %cl"));
+                    f.push("andb %cl, %dl");
+                }
+                f.push("testb %dl, %dl");
+                f.boolean("ne")
+            }
+            ("zero?", [a]) => {
+                f.push(format!("cmpq $0, {}", at(*a)));
+                f.boolean("e")
+            }
+            ("even?" | "odd?", [a]) => {
+                f.push(format!("movq {}, %rax", at(*a)));
+                f.push("btq $32, %rax");
+                f.boolean(if p.name == "even?" { "nc" } else { "c" })
+            }
+            ("not", [a]) => {
+                f.push(format!("cmpq ${FALSE}, {}", at(*a)));
+                f.boolean("e")
+            }
+            ("eq?" | "eqv?", [a, b]) => {
+                f.push(format!("movq {}, %rax", at(*a)));
+                f.push(format!("cmpq {}, %rax", at(*b)));
+                f.boolean("e")
+            }
+            ("null?", [a]) => {
+                f.push(format!("cmpq ${NULL}, {}", at(*a)));
+                f.boolean("e")
+            }
+            (
+                "pair?" | "procedure?" | "boolean?" | "char?" | "symbol?" | "fixnum?" | "integer?"
+                | "number?",
+                [a],
+            ) => {
+                let ty = match p.name {
+                    "pair?" => Type::Pair,
+                    "procedure?" => Type::Proc,
+                    "boolean?" => Type::Bool,
+                    "char?" => Type::Char,
+                    "symbol?" => Type::Symbol,
+                    _ => Type::Fixnum,
+                };
+                f.push(format!("movq {}, %rax", at(*a)));
+                match word::type_test(ty).expect("these types have tests") {
+                    (0xFFFF_FFFF, 0) => f.push("testl %eax, %eax"),
+                    (mask, expected) => {
+                        f.push(format!("andq ${mask}, %rax"));
+                        f.push(format!("cmpq ${expected}, %rax"));
+                    }
+                }
+                f.boolean("e")
+            }
+            ("cons", [a, b]) => self.cons(f, *a, *b),
+            ("car" | "cdr", [a]) => {
+                let offset = if p.name == "car" { 0 } else { 8 };
+                f.push(format!("movq {}, %rax", at(*a)));
+                f.push(format!("movq {}(%rax), %rax", offset - PAIR_TAG));
+                f.store()
+            }
+            ("set-car!" | "set-cdr!", [a, b]) => {
+                let offset = if p.name == "set-car!" { 0 } else { 8 };
+                f.push(format!("movq {}, %rax", at(*a)));
+                f.push(format!("movq {}, %rcx", at(*b)));
+                f.push(format!("movq %rcx, {}(%rax)", offset - PAIR_TAG));
+                f.constant(UNSPECIFIED)
+            }
+            ("list", items) => {
+                let mut r = f.constant(NULL);
+                for item in items.iter().rev() {
+                    r = self.cons(f, *item, r);
+                }
+                r
+            }
+            ("char->integer", [a]) => {
+                f.push(format!("movq {}, %rax", at(*a)));
+                f.push("shrq $8, %rax");
+                f.push("shlq $32, %rax");
+                f.store()
+            }
+            ("display" | "write", [a]) => {
+                f.push(format!("movq {}, %rdi", at(*a)));
+                self.runtime(f, &format!("sg_{}", p.name));
+                f.store()
+            }
+            ("newline", []) => {
+                self.runtime(f, "sg_newline");
+                f.store()
+            }
+            ("collect", []) => {
+                self.collecting(f, "sg_collect");
+                f.store()
+            }
+            _ => {
+                return Err(EvalError::Unsupported(format!(
+                    "the primitive `{}` in compiled code",
+                    p.name
+                )))
+            }
+        };
+        Ok(r)
+    }
+}
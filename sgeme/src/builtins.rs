@@ -0,0 +1,715 @@
+//! The interpreter's implementations of the primitives in `prim::PRIMS`
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::io::Write;
+use std::ops::{Add, Mul, Sub};
+use std::rc::Rc;
+use std::sync::LazyLock;
+
+use num_bigint::BigInt;
+use num_integer::{Integer, Roots};
+use num_rational::BigRational;
+use num_traits::{One, Signed, Zero};
+
+use crate::interp::{RuntimeError, RuntimeResult};
+use crate::number::Number;
+use crate::prim::Kind;
+use crate::symbol::Symbol;
+use crate::value::{equal, eqv, Forced, Parameter, Promise, Record, Value};
+
+/// The tag of the records made by `error`, which no program can spell
+static ERROR_TAG: LazyLock<Symbol> = LazyLock::new(|| Symbol::uninterned("error-object"));
+
+/// The message of the error raised when a handler returns from `raise`,
+/// with the object raised as its irritant
+pub const HANDLER_RETURNED: &str = "handler returned from `raise`";
+
+fn wrong_type(name: &str, kind: Kind, given: &Value) -> RuntimeError {
+    RuntimeError::WrongType {
+        proc: name.to_owned(),
+        expected: kind.describe(),
+        given: given.to_string(),
+    }
+}
+
+fn fixnum(name: &str, v: &Value) -> RuntimeResult<i32> {
+    match v {
+        Value::Fixnum(n) => Ok(*n),
+        Value::Big(_) | Value::Ratio(_) | Value::Flonum(_) => Err(RuntimeError::WrongType {
+            proc: name.to_owned(),
+            expected: "a fixnum",
+            given: v.to_string(),
+        }),
+        other => Err(wrong_type(name, Kind::Number, other)),
+    }
+}
+
+fn number(name: &str, v: &Value) -> RuntimeResult<Number> {
+    Number::of(v).ok_or_else(|| wrong_type(name, Kind::Number, v))
+}
+
+/// The flonum nearest to the number `v`
+fn flonum(name: &str, v: &Value) -> RuntimeResult<f64> {
+    Ok(number(name, v)?.to_f64())
+}
+
+/// The integer `v` is, made exact if it's a flonum
+fn integer(name: &str, v: &Value) -> RuntimeResult<BigInt> {
+    match number(name, v)?.exact() {
+        Some(Number::Int(n)) => Ok(n),
+        _ => Err(RuntimeError::WrongType {
+            proc: name.to_owned(),
+            expected: "an integer",
+            given: v.to_string(),
+        }),
+    }
+}
+
+/// `n` as a value, made inexact if it was computed from an inexact number
+fn inexact_if<'a>(inexact: bool, n: Number) -> Value<'a> {
+    if inexact {
+        n.inexact().into_value()
+    } else {
+        n.into_value()
+    }
+}
+
+/// The radix given to `number->string` or `string->number`, if any
+fn radix(name: &str, args: &[Value]) -> RuntimeResult<u32> {
+    match args {
+        [] => Ok(10),
+        [r] => match fixnum(name, r)? {
+            r @ (2 | 8 | 10 | 16) => Ok(r as u32),
+            _ => Err(RuntimeError::WrongType {
+                proc: name.to_owned(),
+                expected: "a radix of 2, 8, 10 or 16",
+                given: r.to_string(),
+            }),
+        },
+        _ => unreachable!("`{name}` takes at most 2 arguments"),
+    }
+}
+
+fn index(name: &str, v: &Value, len: usize) -> RuntimeResult<usize> {
+    let i = fixnum(name, v)?;
+    if i < 0 || i as usize >= len {
+        Err(RuntimeError::IndexOutOfRange {
+            proc: name.to_owned(),
+            index: i,
+            len,
+        })
+    } else {
+        Ok(i as usize)
+    }
+}
+
+/// Combine `args` from left to right, starting from `init`: with `f` while
+/// both sides are fixnums and the result fits in one, and with `g` otherwise
+fn fold<'a>(
+    name: &str,
+    init: Value<'a>,
+    args: &[Value<'a>],
+    f: fn(i32, i32) -> Option<i32>,
+    g: fn(Number, Number) -> Number,
+) -> RuntimeResult<Value<'a>> {
+    let mut acc = init;
+    for a in args {
+        acc = match (&acc, a) {
+            (Value::Fixnum(x), Value::Fixnum(y)) => match f(*x, *y) {
+                Some(n) => Value::Fixnum(n),
+                None => g(Number::from(*x), Number::from(*y)).into_value(),
+            },
+            _ => g(number(name, &acc)?, number(name, a)?).into_value(),
+        };
+    }
+    Ok(acc)
+}
+
+fn divide<'a>(name: &str, a: &Value<'a>, b: &Value<'a>) -> RuntimeResult<Value<'a>> {
+    if let (Value::Fixnum(x), Value::Fixnum(y)) = (a, b) {
+        if let (Some(0), Some(q)) = (x.checked_rem(*y), x.checked_div(*y)) {
+            return Ok(Value::Fixnum(q));
+        }
+    }
+    number(name, a)?
+        .checked_div(number(name, b)?)
+        .map(Number::into_value)
+        .ok_or_else(|| RuntimeError::DivisionByZero(name.to_owned()))
+}
+
+/// `quotient`, `remainder` or `modulo`, as `name` says
+fn divide_integers<'a>(name: &str, a: &Value<'a>, b: &Value<'a>) -> RuntimeResult<Value<'a>> {
+    if let (Value::Fixnum(x), Value::Fixnum(y)) = (a, b) {
+        let r = match name {
+            "quotient" => x.checked_div(*y),
+            "remainder" => x.checked_rem(*y),
+            _ => x.checked_rem(*y).map(|r| {
+                if r != 0 && (r < 0) != (*y < 0) {
+                    r + y
+                } else {
+                    r
+                }
+            }),
+        };
+        if let Some(r) = r {
+            return Ok(Value::Fixnum(r));
+        }
+    }
+    let (x, y) = (integer(name, a)?, integer(name, b)?);
+    if y.is_zero() {
+        return Err(RuntimeError::DivisionByZero(name.to_owned()));
+    }
+    let r = match name {
+        "quotient" => x / y,
+        "remainder" => x % y,
+        _ => x.mod_floor(&y),
+    };
+    let inexact = matches!(a, Value::Flonum(_)) || matches!(b, Value::Flonum(_));
+    Ok(inexact_if(inexact, Number::Int(r)))
+}
+
+/// `n` rounded to an integer by `floor`, `ceiling`, `truncate` or `round`,
+/// as `name` says; `round` goes to the even integer when `n` is halfway
+fn round(name: &str, n: Number) -> Number {
+    match n {
+        Number::Int(_) => n,
+        Number::Ratio(r) => Number::Int(match name {
+            "floor" => r.floor().to_integer(),
+            "ceiling" => r.ceil().to_integer(),
+            "truncate" => r.trunc().to_integer(),
+            _ => {
+                let floor = r.floor();
+                let rest = &r - &floor;
+                let floor = floor.to_integer();
+                match (&rest + &rest).cmp(&BigRational::one()) {
+                    Ordering::Less => floor,
+                    Ordering::Equal if floor.is_even() => floor,
+                    _ => floor + 1,
+                }
+            }
+        }),
+        Number::Flo(x) => Number::Flo(match name {
+            "floor" => x.floor(),
+            "ceiling" => x.ceil(),
+            "truncate" => x.trunc(),
+            _ => x.round_ties_even(),
+        }),
+    }
+}
+
+/// Whether `f` holds of each number in `args` and the next
+fn compare<'a>(
+    name: &str,
+    args: &[Value<'a>],
+    f: fn(Ordering) -> bool,
+) -> RuntimeResult<Value<'a>> {
+    if let [a] = args {
+        number(name, a)?;
+    }
+    let mut holds = true;
+    for w in args.windows(2) {
+        holds &= match (&w[0], &w[1]) {
+            (Value::Fixnum(x), Value::Fixnum(y)) => f(x.cmp(y)),
+            (a, b) => number(name, a)?
+                .partial_cmp(&number(name, b)?)
+                .is_some_and(f),
+        };
+    }
+    Ok(Value::Bool(holds))
+}
+
+/// The first of `args` which no other is `better` than, made inexact if any
+/// of them is
+fn extreme<'a>(name: &str, args: &[Value<'a>], better: Ordering) -> RuntimeResult<Value<'a>> {
+    let mut best = &args[0];
+    let mut n = number(name, best)?;
+    for a in &args[1..] {
+        let m = number(name, a)?;
+        if m.partial_cmp(&n) == Some(better) {
+            (best, n) = (a, m);
+        }
+    }
+    if args.iter().any(|a| matches!(a, Value::Flonum(_))) {
+        Ok(n.inexact().into_value())
+    } else {
+        Ok(best.clone())
+    }
+}
+
+fn string(name: &str, v: &Value) -> RuntimeResult<String> {
+    match v {
+        Value::Str(s) => Ok(s.borrow().clone()),
+        other => Err(wrong_type(name, Kind::Str, other)),
+    }
+}
+
+fn list<'a>(name: &str, v: &Value<'a>) -> RuntimeResult<Vec<Value<'a>>> {
+    v.to_vec().ok_or_else(|| RuntimeError::WrongType {
+        proc: name.to_owned(),
+        expected: "a list",
+        given: v.to_string(),
+    })
+}
+
+fn error_object<'a>(message: Value<'a>, irritants: Value<'a>) -> Value<'a> {
+    Value::Record(Rc::new(Record {
+        tag: Value::Symbol(*ERROR_TAG),
+        fields: RefCell::new(vec![message, irritants]),
+    }))
+}
+
+/// The message and irritants of `v`, if it's an error object
+fn error_fields<'a>(v: &Value<'a>) -> Option<(Value<'a>, Value<'a>)> {
+    match v {
+        Value::Record(r) if matches!(r.tag, Value::Symbol(t) if t == *ERROR_TAG) => {
+            let fields = r.fields.borrow();
+            Some((fields[0].clone(), fields[1].clone()))
+        }
+        _ => None,
+    }
+}
+
+/// The object `(error message irritant ...)` raises
+pub fn error(mut args: Vec<Value>) -> RuntimeResult<Value> {
+    let irritants = args.split_off(1);
+    string("error", &args[0])?;
+    let message = args.pop().expect("`error` takes at least 1 argument");
+    Ok(error_object(message, Value::list(irritants, Value::Null)))
+}
+
+/// The object raised for a fault found while running the program
+pub fn condition<'a>(e: &RuntimeError) -> Value<'a> {
+    error_object(Value::string(e.to_string()), Value::Null)
+}
+
+/// The error for `v` being raised with no handler installed
+pub fn uncaught(v: &Value) -> RuntimeError {
+    match error_fields(v) {
+        Some((message, irritants)) => RuntimeError::Error {
+            message: message.display(),
+            irritants: irritants
+                .to_vec()
+                .unwrap_or_default()
+                .iter()
+                .map(|i| i.to_string())
+                .collect(),
+        },
+        None => RuntimeError::Uncaught(v.to_string()),
+    }
+}
+
+/// The bindings `outer` with `param` bound to `v` inside them
+pub fn parameterize<'a>(
+    param: &Value<'a>,
+    v: Value<'a>,
+    outer: &Value<'a>,
+) -> RuntimeResult<Value<'a>> {
+    match param {
+        Value::Parameter(_) => Ok(Value::cons(Value::cons(param.clone(), v), outer.clone())),
+        other => Err(RuntimeError::WrongType {
+            proc: "parameterize".to_owned(),
+            expected: "a parameter",
+            given: other.to_string(),
+        }),
+    }
+}
+
+/// Call the primitive `name` on `args`, whose number has already been
+/// checked against its arity. Output goes to `out`.
+pub fn call<'a>(name: &str, args: Vec<Value<'a>>, out: &mut dyn Write) -> RuntimeResult<Value<'a>> {
+    let v = match (name, args.as_slice()) {
+        ("+", _) => fold(name, Value::Fixnum(0), &args, i32::checked_add, Number::add)?,
+        ("*", _) => fold(name, Value::Fixnum(1), &args, i32::checked_mul, Number::mul)?,
+        ("-", [Value::Fixnum(n)]) if *n != i32::MIN => Value::Fixnum(-n),
+        ("-", [n]) => (-number(name, n)?).into_value(),
+        ("-", [first, rest @ ..]) => {
+            fold(name, first.clone(), rest, i32::checked_sub, Number::sub)?
+        }
+        ("/", [n]) => divide(name, &Value::Fixnum(1), n)?,
+        ("/", [first, rest @ ..]) => {
+            let mut acc = first.clone();
+            for n in rest {
+                acc = divide(name, &acc, n)?;
+            }
+            acc
+        }
+        ("quotient" | "remainder" | "modulo", [a, b]) => divide_integers(name, a, b)?,
+        ("gcd" | "lcm", _) => {
+            let mut acc = BigInt::from((name == "lcm") as i32);
+            let inexact = args.iter().any(|a| matches!(a, Value::Flonum(_)));
+            for a in &args {
+                let n = integer(name, a)?;
+                acc = if name == "gcd" {
+                    acc.gcd(&n)
+                } else {
+                    acc.lcm(&n)
+                };
+            }
+            inexact_if(inexact, Number::Int(acc))
+        }
+        ("expt", [base, Value::Fixnum(e)]) => {
+            number(name, base)?
+                .pow(*e)
+                .map(Number::into_value)
+                .ok_or_else(|| RuntimeError::DivisionByZero(name.to_owned()))?
+        }
+        ("expt", [base, e]) => match (number(name, base)?, number(name, e)?) {
+            // too big to be worked out exactly
+            (b, Number::Int(_)) if b.is_exact() => {
+                return Err(RuntimeError::WrongType {
+                    proc: name.to_owned(),
+                    expected: "a fixnum",
+                    given: e.to_string(),
+                })
+            }
+            (b, e) => Value::Flonum(b.to_f64().powf(e.to_f64())),
+        },
+        ("exact-integer-sqrt", [v]) => {
+            let k = integer(name, v)?;
+            if k.is_negative() || matches!(v, Value::Flonum(_)) {
+                return Err(RuntimeError::WrongType {
+                    proc: name.to_owned(),
+                    expected: "a non-negative exact integer",
+                    given: v.to_string(),
+                });
+            }
+            let s = k.sqrt();
+            let r = &k - &s * &s;
+            // both at once, as a list, there being no multiple values
+            Value::list(
+                vec![Number::Int(s).into_value(), Number::Int(r).into_value()],
+                Value::Null,
+            )
+        }
+        ("numerator" | "denominator", [v]) => {
+            let n = number(name, v)?;
+            let part = if name == "numerator" {
+                n.numerator()
+            } else {
+                n.denominator()
+            };
+            part.map(Number::into_value)
+                .ok_or_else(|| RuntimeError::WrongType {
+                    proc: name.to_owned(),
+                    expected: "a rational number",
+                    given: v.to_string(),
+                })?
+        }
+        ("exact", [v]) => number(name, v)?
+            .exact()
+            .map(Number::into_value)
+            .ok_or_else(|| RuntimeError::WrongType {
+                proc: name.to_owned(),
+                expected: "a finite number",
+                given: v.to_string(),
+            })?,
+        ("inexact", [n]) => number(name, n)?.inexact().into_value(),
+        ("floor" | "ceiling" | "truncate" | "round", [n]) => {
+            round(name, number(name, n)?).into_value()
+        }
+        ("sqrt", [n]) => number(name, n)?.sqrt().into_value(),
+        ("exp" | "sin" | "cos" | "tan" | "asin" | "acos", [x]) => {
+            let x = flonum(name, x)?;
+            Value::Flonum(match name {
+                "exp" => x.exp(),
+                "sin" => x.sin(),
+                "cos" => x.cos(),
+                "tan" => x.tan(),
+                "asin" => x.asin(),
+                _ => x.acos(),
+            })
+        }
+        ("log", [x]) => Value::Flonum(flonum(name, x)?.ln()),
+        ("log", [x, base]) => Value::Flonum(flonum(name, x)?.ln() / flonum(name, base)?.ln()),
+        ("atan", [x]) => Value::Flonum(flonum(name, x)?.atan()),
+        ("atan", [y, x]) => Value::Flonum(flonum(name, y)?.atan2(flonum(name, x)?)),
+        ("add1", [n]) => fold(
+            name,
+            n.clone(),
+            &[Value::Fixnum(1)],
+            i32::checked_add,
+            Number::add,
+        )?,
+        ("sub1", [n]) => fold(
+            name,
+            n.clone(),
+            &[Value::Fixnum(1)],
+            i32::checked_sub,
+            Number::sub,
+        )?,
+        ("abs", [Value::Fixnum(n)]) if *n != i32::MIN => Value::Fixnum(n.abs()),
+        ("abs", [n]) => number(name, n)?.abs().into_value(),
+        ("min", _) => extreme(name, &args, Ordering::Less)?,
+        ("max", _) => extreme(name, &args, Ordering::Greater)?,
+        ("=", _) => compare(name, &args, Ordering::is_eq)?,
+        ("<", _) => compare(name, &args, Ordering::is_lt)?,
+        (">", _) => compare(name, &args, Ordering::is_gt)?,
+        ("<=", _) => compare(name, &args, Ordering::is_le)?,
+        (">=", _) => compare(name, &args, Ordering::is_ge)?,
+        ("zero?", [Value::Fixnum(n)]) => Value::Bool(*n == 0),
+        ("zero?", [n]) => Value::Bool(number(name, n)?.is_zero()),
+        ("positive?" | "negative?", [n]) => {
+            let sign = match n {
+                Value::Fixnum(n) => n.cmp(&0),
+                _ => number(name, n)?
+                    .partial_cmp(&Number::from(0))
+                    .unwrap_or(Ordering::Equal),
+            };
+            let wanted = if name == "positive?" {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+            Value::Bool(sign == wanted)
+        }
+        ("even?" | "odd?", [n]) => {
+            let even = match n {
+                Value::Fixnum(n) => n % 2 == 0,
+                _ => integer(name, n)?.is_even(),
+            };
+            Value::Bool(even == (name == "even?"))
+        }
+        ("not", [v]) => Value::Bool(!v.is_true()),
+        ("eq?" | "eqv?", [a, b]) => Value::Bool(eqv(a, b)),
+        ("equal?", [a, b]) => Value::Bool(equal(a, b)),
+        ("null?", [v]) => Value::Bool(matches!(v, Value::Null)),
+        ("pair?", [v]) => Value::Bool(matches!(v, Value::Pair(_))),
+        ("list?", [v]) => Value::Bool(v.to_vec().is_some()),
+        ("boolean?", [v]) => Value::Bool(matches!(v, Value::Bool(_))),
+        ("char?", [v]) => Value::Bool(matches!(v, Value::Char(_))),
+        ("number?" | "complex?" | "real?", [v]) => Value::Bool(Number::of(v).is_some()),
+        ("rational?", [v]) => Value::Bool(match v {
+            Value::Flonum(x) => x.is_finite(),
+            _ => Number::of(v).is_some(),
+        }),
+        ("integer?", [v]) => Value::Bool(Number::of(v).is_some_and(|n| n.is_integer())),
+        ("exact-integer?", [v]) => Value::Bool(matches!(v, Value::Fixnum(_) | Value::Big(_))),
+        ("fixnum?", [v]) => Value::Bool(matches!(v, Value::Fixnum(_))),
+        ("exact?" | "inexact?", [v]) => {
+            Value::Bool(number(name, v)?.is_exact() == (name == "exact?"))
+        }
+        ("nan?" | "infinite?" | "finite?", [v]) => {
+            let x = match number(name, v)? {
+                Number::Flo(x) => x,
+                _ => 0.0,
+            };
+            Value::Bool(match name {
+                "nan?" => x.is_nan(),
+                "infinite?" => x.is_infinite(),
+                _ => x.is_finite(),
+            })
+        }
+        ("string?", [v]) => Value::Bool(matches!(v, Value::Str(_))),
+        ("symbol?", [v]) => Value::Bool(matches!(v, Value::Symbol(_))),
+        ("vector?", [v]) => Value::Bool(matches!(v, Value::Vector(_))),
+        ("procedure?", [v]) => Value::Bool(v.is_procedure()),
+        ("cons", [a, b]) => Value::cons(a.clone(), b.clone()),
+        ("car", [Value::Pair(p)]) => p.car.borrow().clone(),
+        ("cdr", [Value::Pair(p)]) => p.cdr.borrow().clone(),
+        ("set-car!", [Value::Pair(p), v]) => {
+            *p.car.borrow_mut() = v.clone();
+            Value::Unspecified
+        }
+        ("set-cdr!", [Value::Pair(p), v]) => {
+            *p.cdr.borrow_mut() = v.clone();
+            Value::Unspecified
+        }
+        ("car" | "cdr" | "set-car!" | "set-cdr!", [other, ..]) => {
+            return Err(wrong_type(name, Kind::Pair, other))
+        }
+        ("list", _) => Value::list(args, Value::Null),
+        ("length", [l]) => Value::Fixnum(list(name, l)?.len() as i32),
+        ("append", []) => Value::Null,
+        ("append", [init @ .., last]) => {
+            let mut items = Vec::new();
+            for l in init {
+                items.extend(list(name, l)?);
+            }
+            Value::list(items, last.clone())
+        }
+        ("reverse", [l]) => {
+            let mut items = list(name, l)?;
+            items.reverse();
+            Value::list(items, Value::Null)
+        }
+        ("vector", _) => Value::Vector(Rc::new(RefCell::new(args))),
+        ("make-vector", [n, fill]) => {
+            let n = fixnum(name, n)?;
+            if n < 0 {
+                return Err(RuntimeError::IndexOutOfRange {
+                    proc: name.to_owned(),
+                    index: n,
+                    len: 0,
+                });
+            }
+            Value::Vector(Rc::new(RefCell::new(vec![fill.clone(); n as usize])))
+        }
+        ("vector-length", [Value::Vector(vs)]) => Value::Fixnum(vs.borrow().len() as i32),
+        ("vector-ref", [Value::Vector(vs), i]) => {
+            let vs = vs.borrow();
+            vs[index(name, i, vs.len())?].clone()
+        }
+        ("vector-set!", [Value::Vector(vs), i, v]) => {
+            let mut vs = vs.borrow_mut();
+            let i = index(name, i, vs.len())?;
+            vs[i] = v.clone();
+            Value::Unspecified
+        }
+        ("vector-length" | "vector-ref" | "vector-set!", [other, ..]) => {
+            return Err(wrong_type(name, Kind::Vector, other))
+        }
+        ("string-length", [s]) => Value::Fixnum(string(name, s)?.chars().count() as i32),
+        ("string-ref", [s, i]) => {
+            let s = string(name, s)?;
+            let chars: Vec<char> = s.chars().collect();
+            Value::Char(chars[index(name, i, chars.len())?])
+        }
+        ("string=?", _) => {
+            let ss: Vec<String> = args
+                .iter()
+                .map(|s| string(name, s))
+                .collect::<Result<_, _>>()?;
+            Value::Bool(ss.windows(2).all(|w| w[0] == w[1]))
+        }
+        ("string-append", _) => {
+            let ss: Vec<String> = args
+                .iter()
+                .map(|s| string(name, s))
+                .collect::<Result<_, _>>()?;
+            Value::string(ss.concat())
+        }
+        ("symbol->string", [Value::Symbol(s)]) => Value::string(s.to_string()),
+        ("symbol->string", [other]) => return Err(wrong_type(name, Kind::Symbol, other)),
+        ("string->symbol", [s]) => Value::symbol(&string(name, s)?),
+        ("number->string", [n, r @ ..]) => {
+            let radix = radix(name, r)?;
+            match number(name, n)?.to_string_radix(radix) {
+                Some(s) => Value::string(s),
+                None => {
+                    return Err(RuntimeError::WrongType {
+                        proc: name.to_owned(),
+                        expected: "an exact number, to write in any radix but 10",
+                        given: n.to_string(),
+                    })
+                }
+            }
+        }
+        ("string->number", [s, r @ ..]) => {
+            let radix = radix(name, r)?;
+            Number::parse(&string(name, s)?, radix).map_or(Value::Bool(false), Number::into_value)
+        }
+        ("char->integer", [Value::Char(c)]) => Value::Fixnum(*c as i32),
+        ("char->integer", [other]) => return Err(wrong_type(name, Kind::Char, other)),
+        ("integer->char", [n]) => {
+            let n = fixnum(name, n)?;
+            match char::from_u32(n as u32) {
+                Some(c) => Value::Char(c),
+                None => {
+                    return Err(RuntimeError::WrongType {
+                        proc: name.to_owned(),
+                        expected: "a character code",
+                        given: n.to_string(),
+                    })
+                }
+            }
+        }
+        ("char=?", _) => {
+            let cs = args
+                .iter()
+                .map(|c| match c {
+                    Value::Char(c) => Ok(*c),
+                    other => Err(wrong_type(name, Kind::Char, other)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Value::Bool(cs.windows(2).all(|w| w[0] == w[1]))
+        }
+        ("display", [v]) => {
+            write!(out, "{}", v.display()).map_err(|e| RuntimeError::Io(e.to_string()))?;
+            Value::Unspecified
+        }
+        ("write", [v]) => {
+            write!(out, "{v}").map_err(|e| RuntimeError::Io(e.to_string()))?;
+            Value::Unspecified
+        }
+        ("newline", []) => {
+            writeln!(out).map_err(|e| RuntimeError::Io(e.to_string()))?;
+            Value::Unspecified
+        }
+        // values are reference counted, so one is freed as soon as nothing
+        // refers to it, unless it refers to itself: a cycle, such as a
+        // `letrec` closure and the scope holding it, is never freed
+        ("collect", []) => Value::Unspecified,
+        ("error-object?", [v]) => Value::Bool(error_fields(v).is_some()),
+        ("error-object-message" | "error-object-irritants", [v]) => match error_fields(v) {
+            Some((message, _)) if name == "error-object-message" => message,
+            Some((_, irritants)) => irritants,
+            None => {
+                return Err(RuntimeError::WrongType {
+                    proc: name.to_owned(),
+                    expected: "an error object",
+                    given: v.to_string(),
+                })
+            }
+        },
+        ("make-promise", [v @ Value::Promise(_)]) => v.clone(),
+        ("make-promise", [v]) => Value::Promise(Rc::new(Promise::init(Forced::Done(v.clone())))),
+        ("promise?", [v]) => Value::Bool(matches!(v, Value::Promise(_))),
+        // promises are forced by the machine, anything else is a value already
+        ("force", [v]) => v.clone(),
+        ("%make-lazy-promise", [thunk]) => {
+            Value::Promise(Rc::new(Promise::init(Forced::Delayed(thunk.clone()))))
+        }
+        ("%promise-done?", [Value::Promise(p)]) => Value::Bool(matches!(p.get(), Forced::Done(_))),
+        ("%promise-value", [Value::Promise(p)]) => match p.get() {
+            Forced::Done(v) | Forced::Delayed(v) => v,
+        },
+        ("%promise-update!", [Value::Promise(new), Value::Promise(old)]) => {
+            old.update(new);
+            Value::Unspecified
+        }
+        ("%promise-done?" | "%promise-value" | "%promise-update!", [other, ..]) => {
+            return Err(RuntimeError::WrongType {
+                proc: "force".to_owned(),
+                expected: "a promise",
+                given: other.to_string(),
+            })
+        }
+        ("make-parameter", [init]) => Value::Parameter(Rc::new(Parameter {
+            init: init.clone(),
+            converter: Value::Bool(false),
+        })),
+        ("%make-parameter", [init, converter]) => Value::Parameter(Rc::new(Parameter {
+            init: init.clone(),
+            converter: converter.clone(),
+        })),
+        ("%parameter-converter", [Value::Parameter(p)]) => p.converter.clone(),
+        ("%parameter-converter", [other]) => {
+            return Err(RuntimeError::WrongType {
+                proc: "parameterize".to_owned(),
+                expected: "a parameter",
+                given: other.to_string(),
+            })
+        }
+        ("%make-record", [tag, fields @ ..]) => Value::Record(Rc::new(Record {
+            tag: tag.clone(),
+            fields: RefCell::new(fields.to_vec()),
+        })),
+        ("%record?", [v, tag]) => Value::Bool(matches!(v, Value::Record(r) if eqv(&r.tag, tag))),
+        ("%record-ref", [v, tag, i]) => match v {
+            Value::Record(r) if eqv(&r.tag, tag) => {
+                let fields = r.fields.borrow();
+                fields[index(name, i, fields.len())?].clone()
+            }
+            other => {
+                return Err(RuntimeError::WrongType {
+                    proc: name.to_owned(),
+                    expected: "a record",
+                    given: other.to_string(),
+                })
+            }
+        },
+        _ => unreachable!("primitive `{name}` called with {} arguments", args.len()),
+    };
+    Ok(v)
+}
@@ -0,0 +1,186 @@
+//! The instruction set run by `vm::Vm`, and how it prints
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::prim::PRIMS;
+use crate::primsyn::Type;
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+/// A single instruction. Operands index into the running procedure's
+/// constants, its stack frame, its closure's upvalues, or the globals.
+/// Locals live on the stack above the frame's base, parameters first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Const(u32),
+    Local(u32),
+    Upval(u32),
+    Global(u32),
+    /// Pop into a global
+    DefGlobal(u32),
+    /// Push a fresh cell, for a variable bound by `letrec`
+    PushCell,
+    /// Replace the cell on top of the stack with its contents
+    Unbox,
+    /// Pop into the cell held by a local
+    SetCell(u32),
+    /// Make a closure out of a nested procedure, capturing its upvalues
+    Closure(u32),
+    Jump(u32),
+    /// Pop, and jump if it was `#f`
+    JumpUnless(u32),
+    /// Call the procedure under the top `n` values
+    Call(u32),
+    /// Call, reusing the current frame
+    TailCall(u32),
+    /// Call the primitive `PRIMS[i]` on the top `n` values
+    Prim(u16, u16),
+    Add,
+    Sub,
+    Mul,
+    NumEq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Cons,
+    Car,
+    Cdr,
+    NullP,
+    Not,
+    /// Fail unless the top of the stack has this type
+    Check(Type),
+    Pop,
+    /// Drop `n` values from under the top of the stack
+    Slide(u32),
+    Return,
+    /// Push the `dynamic-wind`s being run, as a list of `(before . after)`
+    Winders,
+    /// Pop into the `dynamic-wind`s being run
+    SetWinders,
+    /// Push the exception handlers installed, as a list
+    Handlers,
+    /// Pop into the exception handlers installed
+    SetHandlers,
+    /// Push the bindings made by `parameterize`, as a list of
+    /// `(parameter . value)`
+    Parameters,
+    /// Pop into the bindings made by `parameterize`
+    SetParameters,
+}
+
+/// Where a closure gets one of its upvalues from, when it's made
+#[derive(Debug, Clone, Copy)]
+pub enum Capture {
+    Local(u32),
+    Upval(u32),
+}
+
+/// A compiled procedure
+#[derive(Debug, Default)]
+pub struct Proto<'a> {
+    pub name: Option<String>,
+    pub arity: usize,
+    pub code: Vec<Op>,
+    pub consts: Vec<Value<'a>>,
+    /// Procedures nested inside this one, made with `Op::Closure`
+    pub protos: Vec<Rc<Proto<'a>>>,
+    pub captures: Vec<Capture>,
+}
+
+#[derive(Debug)]
+pub struct Closure<'a> {
+    pub proto: Rc<Proto<'a>>,
+    pub upvals: Vec<Value<'a>>,
+}
+
+impl Closure<'_> {
+    pub fn name(&self) -> &str {
+        self.proto.name.as_deref().unwrap_or("procedure")
+    }
+}
+
+/// A whole compiled program: the procedure running its top-level forms, and
+/// the names of the globals it uses
+#[derive(Debug)]
+pub struct Module<'a> {
+    pub main: Rc<Proto<'a>>,
+    pub globals: Vec<Symbol>,
+}
+
+impl Proto<'_> {
+    fn disassemble(&self, f: &mut fmt::Formatter<'_>, globals: &[Symbol]) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({} parameter(s), {} upvalue(s))",
+            self.name.as_deref().unwrap_or("lambda"),
+            self.arity,
+            self.captures.len()
+        )?;
+        for (pc, op) in self.code.iter().enumerate() {
+            write!(f, "{pc:>6}  ")?;
+            match op {
+                Op::Const(i) => writeln!(f, "const {i}  ; {}", self.consts[*i as usize])?,
+                Op::Global(i) => writeln!(f, "global {i}  ; {}", globals[*i as usize])?,
+                Op::DefGlobal(i) => writeln!(f, "def-global {i}  ; {}", globals[*i as usize])?,
+                Op::Closure(i) => {
+                    let proto = &self.protos[*i as usize];
+                    writeln!(
+                        f,
+                        "closure {i}  ; {} {:?}",
+                        proto.name.as_deref().unwrap_or("lambda"),
+                        proto.captures
+                    )?
+                }
+                Op::Prim(i, n) => writeln!(f, "prim {} {n}", PRIMS[*i as usize].name)?,
+                Op::Check(ty) => writeln!(f, "check {}", ty.name())?,
+                Op::Local(i) => writeln!(f, "local {i}")?,
+                Op::Upval(i) => writeln!(f, "upval {i}")?,
+                Op::SetCell(i) => writeln!(f, "set-cell {i}")?,
+                Op::Jump(t) => writeln!(f, "jump {t}")?,
+                Op::JumpUnless(t) => writeln!(f, "jump-unless {t}")?,
+                Op::Call(n) => writeln!(f, "call {n}")?,
+                Op::TailCall(n) => writeln!(f, "tail-call {n}")?,
+                Op::Slide(n) => writeln!(f, "slide {n}")?,
+                Op::PushCell => writeln!(f, "push-cell")?,
+                Op::Unbox => writeln!(f, "unbox")?,
+                Op::Add => writeln!(f, "add")?,
+                Op::Sub => writeln!(f, "sub")?,
+                Op::Mul => writeln!(f, "mul")?,
+                Op::NumEq => writeln!(f, "num-eq")?,
+                Op::Lt => writeln!(f, "lt")?,
+                Op::Gt => writeln!(f, "gt")?,
+                Op::Le => writeln!(f, "le")?,
+                Op::Ge => writeln!(f, "ge")?,
+                Op::Eq => writeln!(f, "eq")?,
+                Op::Cons => writeln!(f, "cons")?,
+                Op::Car => writeln!(f, "car")?,
+                Op::Cdr => writeln!(f, "cdr")?,
+                Op::NullP => writeln!(f, "null?")?,
+                Op::Not => writeln!(f, "not")?,
+                Op::Pop => writeln!(f, "pop")?,
+                Op::Return => writeln!(f, "return")?,
+                Op::Winders => writeln!(f, "winders")?,
+                Op::SetWinders => writeln!(f, "set-winders")?,
+                Op::Handlers => writeln!(f, "handlers")?,
+                Op::SetHandlers => writeln!(f, "set-handlers")?,
+                Op::Parameters => writeln!(f, "parameters")?,
+                Op::SetParameters => writeln!(f, "set-parameters")?,
+            }
+        }
+        for proto in &self.protos {
+            writeln!(f)?;
+            proto.disassemble(f, globals)?;
+        }
+        Ok(())
+    }
+}
+
+/// The disassembly of every procedure in the program
+impl fmt::Display for Module<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.main.disassemble(f, &self.globals)
+    }
+}
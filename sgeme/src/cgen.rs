@@ -0,0 +1,739 @@
+//! Lower a closure-converted program into a single C translation unit, made
+//! of the runtime in `runtime.c` and then the program, which any C compiler
+//! can build into a standalone executable
+//!
+//! Values are `sg_value` words laid out as `word` describes, through the
+//! runtime's macros, with a procedure's function as the code of its
+//! closures. Errors are reported by the runtime, which exits right away, so
+//! the generated code never has to check for them.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::closure::{Flat, Program};
+use crate::datum::Datum;
+use crate::eval::{EvalError, EvalResult};
+use crate::prim::{self, Prim};
+use crate::primsyn::Type;
+use crate::symbol::Symbol;
+use crate::types::Types;
+use crate::word;
+
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// The runtime's test for values of type `ty`
+fn type_test(ty: Type) -> Option<&'static str> {
+    match ty {
+        // every number made by compiled code is a fixnum
+        Type::Fixnum | Type::Number => Some("SG_FIXNUM_P"),
+        Type::Bool => Some("SG_BOOLEAN_P"),
+        Type::Char => Some("SG_CHAR_P"),
+        Type::Pair => Some("SG_PAIR_P"),
+        Type::Proc => Some("SG_PROCEDURE_P"),
+        Type::Symbol => Some("SG_SYMBOL_P"),
+        // no value made by compiled code has these types
+        Type::Str | Type::Vector => None,
+    }
+}
+
+/// An error for `what`, which this backend can't compile
+fn unsupported(what: &str) -> EvalError {
+    EvalError::Unsupported(format!("{what} in the C backend"))
+}
+
+/// What the primitive `name` works on, if it's data the runtime has no
+/// layout for
+fn needs_layout(name: &str) -> Option<&'static str> {
+    match name {
+        "string?" | "string-length" | "string-ref" | "string-append" | "string=?"
+        | "number->string" | "string->number" | "symbol->string" | "string->symbol" => {
+            Some("strings")
+        }
+        "vector?" | "vector" | "make-vector" | "vector-length" | "vector-ref" | "vector-set!" => {
+            Some("vectors")
+        }
+        "%make-record" | "%record?" | "%record-ref" => Some("records"),
+        _ => None,
+    }
+}
+
+/// Turn a name into something C accepts as part of an identifier
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// The variable holding a parameter
+fn formal(name: &str) -> String {
+    format!("a_{}", sanitize(name))
+}
+
+/// `s` as a C string literal, which the GNU assembler reads the same way
+pub fn string(s: &str) -> String {
+    let mut lit = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                lit.push('\\');
+                lit.push(c)
+            }
+            ' '..='~' => lit.push(c),
+            _ => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    write!(lit, "\\{b:03o}").unwrap();
+                }
+            }
+        }
+    }
+    lit.push('"');
+    lit
+}
+
+/// The variable holding the quoted list `i`
+fn quoted(i: usize) -> String {
+    format!("sg_q{i}")
+}
+
+/// The type of the code of procedures taking `n` arguments
+fn call_type(n: usize) -> String {
+    format!("sg_call{n}")
+}
+
+/// What the code generator needs to know about a procedure besides its body
+struct ProcInfo {
+    func: String,
+    arity: usize,
+}
+
+/// The C function being generated
+struct Function {
+    /// Its declaration, up to the body
+    head: String,
+    lines: Vec<String>,
+    /// Every variable it uses other than its parameters
+    vars: Vec<String>,
+    /// The variables holding the locals in scope
+    regs: HashMap<Symbol, String>,
+    /// The names of the free variables of its closure
+    free: Vec<Symbol>,
+    /// The parameters, which calls to itself in tail position assign
+    formals: Vec<String>,
+    /// The global naming this procedure, and the label at the top of its
+    /// body, for turning calls to itself in tail position into jumps
+    this: Option<(Symbol, String)>,
+    /// Whether it jumps to that label
+    jumps: bool,
+    depth: usize,
+    temps: usize,
+}
+
+impl Function {
+    fn init(head: String, formals: Vec<String>, free: Vec<Symbol>) -> Self {
+        Self {
+            head,
+            lines: Vec::new(),
+            vars: Vec::new(),
+            regs: HashMap::new(),
+            free,
+            formals,
+            this: None,
+            jumps: false,
+            depth: 1,
+            temps: 0,
+        }
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        let name = format!("t{}", self.temps);
+        self.vars.push(name.clone());
+        name
+    }
+
+    /// A fresh variable for the local `name`
+    fn bind(&mut self, name: Symbol) -> String {
+        self.temps += 1;
+        let r = format!("v{}_{}", self.temps, sanitize(&name));
+        self.vars.push(r.clone());
+        self.regs.insert(name, r.clone());
+        r
+    }
+
+    fn push(&mut self, line: impl Into<String>) {
+        self.lines
+            .push(format!("{}{}", "    ".repeat(self.depth), line.into()))
+    }
+
+    fn label(&mut self, label: &str) {
+        self.lines.push(format!("{label}:;"))
+    }
+
+    /// A new variable holding the value of the C expression `e`
+    fn assign(&mut self, e: impl AsRef<str>) -> String {
+        let t = self.temp();
+        self.push(format!("{t} = {};", e.as_ref()));
+        t
+    }
+
+    /// Start a block, following `head`
+    fn open(&mut self, head: impl AsRef<str>) {
+        self.push(format!("{} {{", head.as_ref()));
+        self.depth += 1;
+    }
+
+    /// Close a block, and start the one following it after `head`
+    fn reopen(&mut self, head: &str) {
+        self.depth -= 1;
+        self.open(format!("}} {head}"));
+    }
+
+    fn close(&mut self) {
+        self.depth -= 1;
+        self.push("}");
+    }
+
+    fn finish(self, out: &mut String) {
+        writeln!(out, "{} {{", self.head).unwrap();
+        for chunk in self.vars.chunks(8) {
+            writeln!(out, "    sg_value {};", chunk.join(", ")).unwrap();
+        }
+        let skip = usize::from(self.this.is_some() && !self.jumps);
+        for line in &self.lines[skip..] {
+            writeln!(out, "{line}").unwrap();
+        }
+        out.push_str("}\n\n");
+    }
+}
+
+/// Generates C for a whole program. Every procedure becomes a function
+/// taking its closure and then its arguments, and calls check the callee's
+/// tag and arity before calling through its code pointer, except for calls
+/// to top-level procedures defined once, which are called directly. Calls a
+/// procedure makes to itself in tail position become jumps. Other tail calls
+/// return `SG_TAIL_CALL`, leaving the callee and arguments for the
+/// trampoline at the call they return to, so every tail call runs in
+/// constant space.
+pub struct CGen<'t> {
+    types: &'t Types,
+    procs: Vec<ProcInfo>,
+    globals: Vec<Symbol>,
+    /// Top-level procedures defined once, with no free variables
+    direct: HashMap<Symbol, usize>,
+    /// The arities of every call made, each needing a function type
+    arities: BTreeSet<usize>,
+    /// The arities of the tail calls left to the trampoline
+    tails: BTreeSet<usize>,
+    /// The quoted lists, each made once before the program runs
+    consts: Vec<Datum>,
+    /// The symbols the program quotes, by the index their words hold
+    symbols: Vec<Symbol>,
+    labels: usize,
+}
+
+impl<'t> CGen<'t> {
+    pub fn init(types: &'t Types) -> Self {
+        Self {
+            types,
+            procs: Vec::new(),
+            globals: Vec::new(),
+            direct: HashMap::new(),
+            arities: BTreeSet::new(),
+            tails: BTreeSet::new(),
+            consts: Vec::new(),
+            symbols: Vec::new(),
+            labels: 0,
+        }
+    }
+
+    pub fn generate(mut self, program: &Program) -> EvalResult<String> {
+        self.globals = program.globals.clone();
+        for (i, proc) in program.procs.iter().enumerate() {
+            let name = proc.name.as_deref().unwrap_or("lambda");
+            self.procs.push(ProcInfo {
+                func: format!("f{i}_{}", sanitize(name)),
+                arity: proc.formals.len(),
+            });
+            if let (Some(name), true) = (&proc.global, proc.free.is_empty()) {
+                self.direct.insert(*name, i);
+            }
+        }
+
+        let mut funcs = Vec::new();
+        for (i, proc) in program.procs.iter().enumerate() {
+            let formals: Vec<String> = proc.formals.iter().map(|f| formal(f)).collect();
+            let head = format!("static sg_value {}", self.signature(i, &formals));
+            let mut f = Function::init(head, formals, proc.free.clone());
+            for formal in &proc.formals {
+                f.regs.insert(*formal, self::formal(formal));
+            }
+            if let Some(name) = &proc.global {
+                // dropped if nothing jumps to it
+                let start = self.label();
+                f.label(&start);
+                f.this = Some((*name, start));
+            }
+            f.push("(void)clo;");
+            let r = self.expr(&mut f, &proc.body)?;
+            f.push(format!("return {r};"));
+            funcs.push(f);
+        }
+
+        let head = "static sg_value sg_main(void)".to_owned();
+        let mut f = Function::init(head, vec![], vec![]);
+        let r = self.expr(&mut f, &program.main)?;
+        f.push(format!("return {r};"));
+        funcs.push(f);
+
+        let head = "static void sg_constants(void)".to_owned();
+        let mut f = Function::init(head, vec![], vec![]);
+        let consts = std::mem::take(&mut self.consts);
+        for (i, d) in consts.iter().enumerate() {
+            let r = self.datum(&mut f, d)?;
+            f.push(format!("{} = {r};", quoted(i)));
+        }
+        funcs.push(f);
+
+        let mut out = String::from(RUNTIME);
+        out.push_str("\n/* The program */\n\n");
+        for n in &self.arities {
+            let args = vec!["sg_value"; n + 1].join(", ");
+            writeln!(out, "typedef sg_value (*{})({args});", call_type(*n)).unwrap();
+        }
+        for i in 0..self.globals.len() {
+            writeln!(out, "static sg_value {} = SG_UNBOUND;", self.global(i)).unwrap();
+        }
+        for i in 0..consts.len() {
+            writeln!(out, "static sg_value {};", quoted(i)).unwrap();
+        }
+        self.trampoline(&mut out);
+        for (i, proc) in program.procs.iter().enumerate() {
+            let formals: Vec<String> = proc.formals.iter().map(|f| formal(f)).collect();
+            writeln!(out, "static sg_value {};", self.signature(i, &formals)).unwrap();
+        }
+        out.push_str("\nstatic const char *sg_proc_name(sg_value code) {\n    (void)code;\n");
+        for (info, proc) in self.procs.iter().zip(&program.procs) {
+            if let Some(name) = &proc.name {
+                writeln!(
+                    out,
+                    "    if (code == (sg_value)(intptr_t){}) return {};",
+                    info.func,
+                    string(name)
+                )
+                .unwrap();
+            }
+        }
+        out.push_str("    return NULL;\n}\n\n");
+        out.push_str("static const char *sg_symbol_name(int64_t i) {\n    (void)i;\n");
+        for (i, sym) in self.symbols.iter().enumerate() {
+            writeln!(out, "    if (i == {i}) return {};", string(sym)).unwrap();
+        }
+        out.push_str("    return NULL;\n}\n\n");
+        for f in funcs {
+            f.finish(&mut out);
+        }
+        out.push_str(
+            "int main(void) {\n    sg_constants();\n    return sg_finish(sg_trampoline(sg_main()));\n}\n",
+        );
+        Ok(out)
+    }
+
+    /// Write out where tail calls leave their callee and arguments, and
+    /// `sg_trampoline`, which makes the calls left by the value `r` a call
+    /// returned until one returns something else
+    fn trampoline(&self, out: &mut String) {
+        out.push_str("static sg_value sg_tail_callee;\n");
+        // left out when nothing uses it, as C has no empty arrays
+        if let Some(n) = self.tails.last().filter(|n| **n > 0) {
+            writeln!(out, "static sg_value sg_tail_args[{n}];").unwrap();
+        }
+        out.push_str("\nstatic inline sg_value sg_trampoline(sg_value r) {\n");
+        out.push_str("    sg_value clo;\n    while (r == SG_TAIL_CALL) {\n");
+        out.push_str("        clo = sg_tail_callee;\n        switch (SG_ARITY(clo)) {\n");
+        for n in &self.tails {
+            let args: Vec<String> = std::iter::once("clo".to_owned())
+                .chain((0..*n).map(|i| format!("sg_tail_args[{i}]")))
+                .collect();
+            writeln!(
+                out,
+                "        case {n}: r = (({})(intptr_t)SG_CODE(clo))({}); break;",
+                call_type(*n),
+                args.join(", ")
+            )
+            .unwrap();
+        }
+        out.push_str("        }\n    }\n    return r;\n}\n");
+    }
+
+    /// The declarator of the function of `procs[i]`
+    fn signature(&self, i: usize, formals: &[String]) -> String {
+        let args: Vec<String> = std::iter::once("clo")
+            .chain(formals.iter().map(String::as_str))
+            .map(|a| format!("sg_value {a}"))
+            .collect();
+        format!("{}({})", self.procs[i].func, args.join(", "))
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    /// The variable holding `globals[i]`
+    fn global(&self, i: usize) -> String {
+        format!("g{i}_{}", sanitize(&self.globals[i]))
+    }
+
+    /// Report `report` unless `r` holds a value of type `ty`
+    fn guard(&mut self, f: &mut Function, r: &str, ty: Type, report: String) {
+        match type_test(ty) {
+            Some(test) => f.push(format!("if (!{test}({r})) {report};")),
+            None => f.push(format!("{report};")),
+        }
+    }
+
+    /// Make a closure of `procs[i]` in `dst`, without filling in its free
+    /// variables
+    fn closure(&mut self, f: &mut Function, i: usize, free: usize, dst: &str) {
+        let info = &self.procs[i];
+        f.push(format!(
+            "{dst} = sg_closure((sg_code){}, {}, {free});",
+            info.func, info.arity
+        ));
+    }
+
+    /// Fill in the free variables of the closure in `dst`
+    fn capture(&mut self, f: &mut Function, dst: &str, captured: &[Flat]) -> EvalResult<()> {
+        for (i, c) in captured.iter().enumerate() {
+            let v = self.expr(f, c)?;
+            f.push(format!("SG_FREE({dst}, {i}) = {v};"));
+        }
+        Ok(())
+    }
+
+    /// The value of the quoted `d`, which is always the same object
+    fn constant(&mut self, f: &mut Function, d: &Datum) -> EvalResult<String> {
+        match d {
+            Datum::List(items) if !items.is_empty() => {}
+            Datum::DottedList(..) => {}
+            _ => return self.datum(f, d),
+        }
+        self.consts.push(d.clone());
+        Ok(f.assign(quoted(self.consts.len() - 1)))
+    }
+
+    /// Make `d` afresh
+    fn datum(&mut self, f: &mut Function, d: &Datum) -> EvalResult<String> {
+        let word = match d {
+            Datum::Fixnum(n) => format!("SG_FIX({n})"),
+            Datum::Bool(true) => "SG_TRUE".to_owned(),
+            Datum::Bool(false) => "SG_FALSE".to_owned(),
+            Datum::Char(c) => format!("SG_CHAR({:#x})", *c as u32),
+            Datum::Null => "SG_NULL".to_owned(),
+            Datum::List(items) if items.is_empty() => "SG_NULL".to_owned(),
+            Datum::Undefined => "SG_UNSPECIFIED".to_owned(),
+            Datum::List(items) => return self.list(f, items, &Datum::Null),
+            Datum::DottedList(items, tail) => return self.list(f, items, tail),
+            Datum::Str(_) => return Err(unsupported("strings")),
+            Datum::Vector(_) | Datum::ByteVector(_) => return Err(unsupported("vectors")),
+            Datum::Symbol(sym) => format!("SG_SYMBOL({})", word::intern(&mut self.symbols, *sym)),
+            other => return Err(unsupported(&format!("the constant `{other}`"))),
+        };
+        Ok(f.assign(word))
+    }
+
+    fn list(&mut self, f: &mut Function, items: &[Datum], tail: &Datum) -> EvalResult<String> {
+        let mut r = self.datum(f, tail)?;
+        for item in items.iter().rev() {
+            let car = self.datum(f, item)?;
+            r = f.assign(format!("sg_cons({car}, {r})"));
+        }
+        Ok(r)
+    }
+
+    /// Is `e` known to evaluate to a value of type `ty`?
+    fn known(&self, f: &Function, e: &Flat, ty: Type) -> bool {
+        let t = match e {
+            Flat::Local(name) => self.types.of_var(*name),
+            Flat::Free(i) => self.types.of_var(f.free[*i]),
+            Flat::Const(d) => crate::types::datum_type(d),
+            Flat::Prim(p, _) => prim::result_type(p.name),
+            Flat::Closure(..) => Some(Type::Proc),
+            Flat::The(t, _) => Some(*t),
+            _ => None,
+        };
+        t.is_some_and(|t| t.within(ty))
+    }
+
+    fn global_index(&self, name: Symbol) -> usize {
+        self.globals
+            .iter()
+            .position(|g| *g == name)
+            .expect("`Converter` collects every global")
+    }
+
+    fn expr(&mut self, f: &mut Function, e: &Flat) -> EvalResult<String> {
+        let r = match e {
+            Flat::Local(name) => f.regs[name].clone(),
+            Flat::Free(i) => f.assign(format!("SG_FREE(clo, {i})")),
+            Flat::Global(name) => {
+                let g = self.global(self.global_index(*name));
+                let r = f.assign(&g);
+                let name = string(name);
+                f.push(format!("if ({r} == SG_UNBOUND) sg_unbound({name});"));
+                r
+            }
+            Flat::Const(d) => self.constant(f, d)?,
+            Flat::PrimRef(p) => {
+                return Err(unsupported(&format!(
+                    "the primitive `{}` as a value",
+                    p.name
+                )))
+            }
+            Flat::Closure(i, captured) => {
+                let r = f.temp();
+                self.closure(f, *i, captured.len(), &r);
+                self.capture(f, &r, captured)?;
+                r
+            }
+            Flat::If(c, t, e) => {
+                let r = f.temp();
+                let c = self.expr(f, c)?;
+                f.open(format!("if ({c} != SG_FALSE)"));
+                let t = self.expr(f, t)?;
+                f.push(format!("{r} = {t};"));
+                f.reopen("else");
+                let e = self.expr(f, e)?;
+                f.push(format!("{r} = {e};"));
+                f.close();
+                r
+            }
+            Flat::Call(rator, rands, tail) => self.call(f, rator, rands, *tail)?,
+            Flat::Prim(p, rands) => self.prim(f, p, rands)?,
+            Flat::Let(bs, body) => {
+                for (name, init) in bs {
+                    let v = self.expr(f, init)?;
+                    let r = f.bind(*name);
+                    f.push(format!("{r} = {v};"));
+                }
+                self.expr(f, body)?
+            }
+            Flat::LetRec(procs, body) => {
+                for (name, i, captured) in procs {
+                    let r = f.bind(*name);
+                    self.closure(f, *i, captured.len(), &r);
+                }
+                for (name, _, captured) in procs {
+                    let r = f.regs[name].clone();
+                    self.capture(f, &r, captured)?;
+                }
+                self.expr(f, body)?
+            }
+            Flat::Begin(es) => {
+                let mut r = None;
+                for e in es {
+                    if let Some(r) = r {
+                        f.push(format!("(void){r};"));
+                    }
+                    r = Some(self.expr(f, e)?);
+                }
+                match r {
+                    Some(r) => r,
+                    None => f.assign("SG_UNSPECIFIED"),
+                }
+            }
+            Flat::The(ty, e) => {
+                let r = self.expr(f, e)?;
+                // the types of variables are inferred trusting this check
+                let var = matches!(**e, Flat::Local(_) | Flat::Free(_));
+                if var || !self.known(f, e, *ty) {
+                    let report = format!("sg_annotation_failed({}, {r})", string(ty.name()));
+                    self.guard(f, &r, *ty, report);
+                }
+                r
+            }
+            Flat::Define(name, e) => {
+                let v = self.expr(f, e)?;
+                let g = self.global(self.global_index(*name));
+                f.push(format!("{g} = {v};"));
+                f.assign("SG_UNSPECIFIED")
+            }
+        };
+        Ok(r)
+    }
+
+    fn call(
+        &mut self,
+        f: &mut Function,
+        rator: &Flat,
+        rands: &[Flat],
+        tail: bool,
+    ) -> EvalResult<String> {
+        let direct = match rator {
+            Flat::Global(name) => self
+                .direct
+                .get(name)
+                .copied()
+                .filter(|i| self.procs[*i].arity == rands.len()),
+            _ => None,
+        };
+        let jump = match (&f.this, rator) {
+            (Some((this, start)), Flat::Global(name)) if tail && this == name => {
+                direct.map(|_| start.clone())
+            }
+            _ => None,
+        };
+        let callee = match jump {
+            Some(_) => None,
+            None => Some(self.expr(f, rator)?),
+        };
+        let mut args = Vec::new();
+        for rand in rands {
+            args.push(self.expr(f, rand)?);
+        }
+
+        let Some(callee) = callee else {
+            // copy the arguments first, as they may use each other
+            let temps: Vec<String> = args.iter().map(|a| f.assign(a)).collect();
+            let formals = f.formals.clone();
+            for (formal, t) in formals.iter().zip(&temps) {
+                f.push(format!("{formal} = {t};"));
+            }
+            f.push(format!("goto {};", jump.expect("no callee for a jump")));
+            f.jumps = true;
+            return Ok(f.assign("SG_UNSPECIFIED"));
+        };
+
+        let n = rands.len();
+        if direct.is_none() {
+            f.push(format!("sg_check_call({callee}, {n});"));
+        }
+        if tail {
+            self.arities.insert(n);
+            self.tails.insert(n);
+            for (i, a) in args.iter().enumerate() {
+                f.push(format!("sg_tail_args[{i}] = {a};"));
+            }
+            f.push(format!("sg_tail_callee = {callee};"));
+            f.push("return SG_TAIL_CALL;");
+            return Ok(f.assign("SG_UNSPECIFIED"));
+        }
+
+        let code = match direct {
+            Some(i) => self.procs[i].func.clone(),
+            None => {
+                self.arities.insert(n);
+                format!("(({})(intptr_t)SG_CODE({callee}))", call_type(n))
+            }
+        };
+        let args: Vec<&str> = std::iter::once(callee.as_str())
+            .chain(args.iter().map(String::as_str))
+            .collect();
+        Ok(f.assign(format!("sg_trampoline({code}({}))", args.join(", "))))
+    }
+
+    fn prim(&mut self, f: &mut Function, p: &'static Prim, rands: &[Flat]) -> EvalResult<String> {
+        if let Some(data) = needs_layout(p.name) {
+            return Err(unsupported(data));
+        }
+        let name = string(p.name);
+        let mut args = Vec::new();
+        for (i, rand) in rands.iter().enumerate() {
+            let a = self.expr(f, rand)?;
+            if let Some(kind) = prim::arg_kind(p.name, i) {
+                if !self.known(f, rand, kind.as_type()) {
+                    let expected = string(kind.describe());
+                    let report = format!("sg_wrong_type({name}, {expected}, {a})");
+                    self.guard(f, &a, kind.as_type(), report);
+                }
+            }
+            args.push(a);
+        }
+
+        let r = match (p.name, args.as_slice()) {
+            ("+", []) => f.assign("SG_FIX(0)"),
+            ("*", []) => f.assign("SG_FIX(1)"),
+            ("+" | "*" | "-", [first, rest @ ..]) if !rest.is_empty() => {
+                let op = match p.name {
+                    "+" => "sg_add",
+                    "*" => "sg_mul",
+                    _ => "sg_sub",
+                };
+                let r = f.assign(first);
+                for a in rest {
+                    f.push(format!("{r} = {op}({r}, {a}, {name});"));
+                }
+                r
+            }
+            ("+" | "*", [a]) => f.assign(a),
+            ("-", [a]) => f.assign(format!("sg_sub(SG_FIX(0), {a}, {name})")),
+            ("add1", [a]) => f.assign(format!("sg_add({a}, SG_FIX(1), {name})")),
+            ("sub1", [a]) => f.assign(format!("sg_sub({a}, SG_FIX(1), {name})")),
+            ("quotient" | "remainder" | "modulo", [a, b]) => {
+                let op = p.name.chars().next().unwrap();
+                f.assign(format!("sg_divide({a}, {b}, '{op}', {name})"))
+            }
+            ("abs", [a]) => f.assign(format!("{a} < 0 ? sg_sub(SG_FIX(0), {a}, {name}) : {a}")),
+            ("=" | "<" | ">" | "<=" | ">=", [_]) => f.assign("SG_TRUE"),
+            ("=" | "<" | ">" | "<=" | ">=", _) => {
+                let op = if p.name == "=" { "==" } else { p.name };
+                // shifting keeps the order, so compare the words themselves
+                let tests: Vec<String> = args
+                    .windows(2)
+                    .map(|pair| format!("{} {op} {}", pair[0], pair[1]))
+                    .collect();
+                f.assign(format!("SG_BOOL({})", tests.join(" && ")))
+            }
+            ("zero?", [a]) => f.assign(format!("SG_BOOL({a} == 0)")),
+            ("even?", [a]) => f.assign(format!("SG_BOOL(({a} & SG_FIX(1)) == 0)")),
+            ("odd?", [a]) => f.assign(format!("SG_BOOL(({a} & SG_FIX(1)) != 0)")),
+            ("not", [a]) => f.assign(format!("SG_BOOL({a} == SG_FALSE)")),
+            ("eq?" | "eqv?", [a, b]) => f.assign(format!("SG_BOOL({a} == {b})")),
+            ("null?", [a]) => f.assign(format!("SG_BOOL({a} == SG_NULL)")),
+            (
+                "pair?" | "procedure?" | "boolean?" | "char?" | "symbol?" | "fixnum?" | "integer?"
+                | "number?",
+                [a],
+            ) => {
+                let ty = match p.name {
+                    "pair?" => Type::Pair,
+                    "procedure?" => Type::Proc,
+                    "boolean?" => Type::Bool,
+                    "char?" => Type::Char,
+                    "symbol?" => Type::Symbol,
+                    _ => Type::Fixnum,
+                };
+                let test = type_test(ty).expect("these types have tests");
+                f.assign(format!("SG_BOOL({test}({a}))"))
+            }
+            ("cons", [a, b]) => f.assign(format!("sg_cons({a}, {b})")),
+            ("car", [a]) => f.assign(format!("SG_CAR({a})")),
+            ("cdr", [a]) => f.assign(format!("SG_CDR({a})")),
+            ("set-car!" | "set-cdr!", [a, b]) => {
+                let field = if p.name == "set-car!" {
+                    "SG_CAR"
+                } else {
+                    "SG_CDR"
+                };
+                f.push(format!("{field}({a}) = {b};"));
+                f.assign("SG_UNSPECIFIED")
+            }
+            ("list", items) => {
+                let mut r = f.assign("SG_NULL");
+                for item in items.iter().rev() {
+                    f.push(format!("{r} = sg_cons({item}, {r});"));
+                }
+                r
+            }
+            ("char->integer", [a]) => f.assign(format!("SG_FIX((uint64_t){a} >> 8)")),
+            ("display", [a]) => f.assign(format!("sg_display({a})")),
+            ("write", [a]) => f.assign(format!("sg_write({a})")),
+            ("newline", []) => f.assign("sg_newline()"),
+            // the runtime has no collector, and never frees what it allocates
+            ("collect", []) => f.assign("SG_UNSPECIFIED"),
+            _ => return Err(unsupported(&format!("the primitive `{}`", p.name))),
+        };
+        Ok(r)
+    }
+}
@@ -0,0 +1,181 @@
+//! Static checks over resolved `Core`, catching calls which are bound to
+//! fail at runtime
+
+use std::collections::{HashMap, HashSet};
+
+use crate::core_former::Core;
+use crate::datum::Datum;
+use crate::diagnostics::Diagnostics;
+use crate::prim::{self, Arity};
+use crate::resolve::source_name;
+use crate::symbol::Symbol;
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+fn describe_arity(arity: Arity) -> String {
+    match arity {
+        Arity::Exactly(n) => format!("{n} argument{}", plural(n)),
+        Arity::AtLeast(n) => format!("at least {n} argument{}", plural(n)),
+        Arity::Between(lo, hi) => format!("{lo} to {hi} arguments"),
+    }
+}
+
+/// Warns about calls to known procedures with the wrong number of arguments,
+/// constants of the wrong type given to primitives, and calls to things
+/// which are obviously not procedures. Nothing here stops compilation, as
+/// the program might never take the offending path.
+pub struct Checker<'a> {
+    diags: &'a mut Diagnostics,
+    /// Arity of every variable known to hold a particular `lambda`
+    known: HashMap<Symbol, Arity>,
+    /// Top-level definitions, which shadow any primitive of the same name
+    globals: HashSet<Symbol>,
+    /// The top-level definition being checked, for the messages
+    context: Option<Symbol>,
+}
+
+impl<'a> Checker<'a> {
+    pub fn init(diags: &'a mut Diagnostics) -> Self {
+        Self {
+            diags,
+            known: HashMap::new(),
+            globals: HashSet::new(),
+            context: None,
+        }
+    }
+
+    pub fn check(&mut self, core: &Core) {
+        let forms = match core {
+            Core::Begin(forms) => forms.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+
+        let mut defined: HashMap<Symbol, usize> = HashMap::new();
+        for form in forms {
+            if let Core::Define(name, _) = form {
+                *defined.entry(*name).or_default() += 1;
+                self.globals.insert(*name);
+            }
+        }
+        for form in forms {
+            if let Core::Define(name, expr) = form {
+                if let (1, Core::Lambda(formals, _)) = (defined[name], expr.as_ref()) {
+                    self.known.insert(*name, Arity::Exactly(formals.len()));
+                }
+            }
+        }
+
+        for form in forms {
+            self.check_expr(form);
+        }
+    }
+
+    fn warn(&mut self, message: String) {
+        match &self.context {
+            Some(name) => self.diags.warn(format!("in `{name}`: {message}")),
+            None => self.diags.warn(message),
+        }
+    }
+
+    fn learn(&mut self, bs: &[(Symbol, Core)]) {
+        for (name, init) in bs {
+            if let Core::Lambda(formals, _) = init {
+                self.known.insert(*name, Arity::Exactly(formals.len()));
+            }
+        }
+    }
+
+    fn check_expr(&mut self, core: &Core) {
+        match core {
+            Core::Var(_) | Core::Const(_) => (),
+            Core::Lambda(_, body) => self.check_expr(body),
+            Core::If(c, t, e) => {
+                self.check_expr(c);
+                self.check_expr(t);
+                self.check_expr(e);
+            }
+            Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+                self.check_call(rator, rands);
+                self.check_expr(rator);
+                for rand in rands {
+                    self.check_expr(rand);
+                }
+            }
+            Core::Let(bs, body) | Core::LetRec(bs, body) => {
+                self.learn(bs);
+                for (_, init) in bs {
+                    self.check_expr(init);
+                }
+                self.check_expr(body);
+            }
+            Core::Begin(exprs) => exprs.iter().for_each(|e| self.check_expr(e)),
+            Core::The(_, expr) => self.check_expr(expr),
+            Core::Define(name, expr) => {
+                self.context = Some(*name);
+                self.check_expr(expr);
+                self.context = None;
+            }
+        }
+    }
+
+    fn check_call(&mut self, rator: &Core, rands: &[Core]) {
+        match rator {
+            Core::Var(name) => {
+                if let Some(arity) = self.known.get(name).copied() {
+                    if !arity.accepts(rands.len()) {
+                        self.warn(format!(
+                            "`{}` expects {}, but is called with {}",
+                            source_name(name),
+                            describe_arity(arity),
+                            rands.len()
+                        ));
+                    }
+                } else if let Some(p) = prim::lookup(name).filter(|_| !self.globals.contains(name))
+                {
+                    if !p.arity.accepts(rands.len()) {
+                        self.warn(format!(
+                            "`{}` expects {}, but is called with {}",
+                            p.name,
+                            describe_arity(p.arity),
+                            rands.len()
+                        ));
+                    }
+                    self.check_prim_args(p.name, rands);
+                }
+            }
+            Core::Lambda(formals, _) if formals.len() != rands.len() => {
+                self.warn(format!(
+                    "`lambda` expects {}, but is called with {}",
+                    describe_arity(Arity::Exactly(formals.len())),
+                    rands.len()
+                ));
+            }
+            Core::Const(d) => self.warn(format!("`{d}` is not a procedure, but is called")),
+            _ => (),
+        }
+    }
+
+    fn check_prim_args(&mut self, name: &str, rands: &[Core]) {
+        for (i, rand) in rands.iter().enumerate() {
+            let Some(kind) = prim::arg_kind(name, i) else {
+                continue;
+            };
+            let given = match rand {
+                Core::Const(d) if !kind.admits(d) => format!("`{d}`"),
+                Core::Lambda(..) => "a procedure".to_owned(),
+                _ => continue,
+            };
+            self.warn(format!(
+                "`{name}` expects {} as argument {}, but is given {given}",
+                kind.describe(),
+                i + 1
+            ));
+        }
+    }
+}
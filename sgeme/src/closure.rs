@@ -0,0 +1,332 @@
+//! Closure conversion: turn every `lambda` of a resolved `Core` program into
+//! a top-level procedure taking its free variables from a closure object,
+//! which is what the native backends need to work with
+
+use std::collections::HashSet;
+
+use crate::core_former::Core;
+use crate::datum::Datum;
+use crate::eval::{EvalError, EvalResult};
+use crate::prim::{self, Prim};
+use crate::primsyn::Type;
+use crate::resolve::source_name;
+use crate::symbol::Symbol;
+
+/// An expression in a closure-converted program. Variables are sorted into
+/// locals of the running procedure, free variables it takes from its
+/// closure, and globals.
+#[derive(Debug, Clone)]
+pub enum Flat {
+    Local(Symbol),
+    /// The `i`th free variable of the running procedure
+    Free(usize),
+    Global(Symbol),
+    Const(Datum),
+    /// A primitive used as a value rather than called
+    PrimRef(&'static Prim),
+    /// Make a closure of `procs[i]`, capturing the given values
+    Closure(usize, Vec<Self>),
+    If(Box<Self>, Box<Self>, Box<Self>),
+    /// A call to a procedure, and whether it is in tail position
+    Call(Box<Self>, Vec<Self>, bool),
+    /// A call to a primitive nothing shadows, with an arity it accepts
+    Prim(&'static Prim, Vec<Self>),
+    Let(Vec<(Symbol, Self)>, Box<Self>),
+    /// Closures which may capture each other: all of them are made before
+    /// any of their captured values are filled in
+    LetRec(Vec<(Symbol, usize, Vec<Self>)>, Box<Self>),
+    Begin(Vec<Self>),
+    The(Type, Box<Self>),
+    Define(Symbol, Box<Self>),
+}
+
+/// A `lambda` lifted out to the top level
+#[derive(Debug, Clone)]
+pub struct Proc {
+    /// The name it's bound to where it's made, for messages
+    pub name: Option<String>,
+    /// The global this procedure is defined as, if it's the only definition
+    pub global: Option<Symbol>,
+    pub formals: Vec<Symbol>,
+    /// The variables captured by its closures, in order
+    pub free: Vec<Symbol>,
+    pub body: Flat,
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub procs: Vec<Proc>,
+    /// The top-level forms
+    pub main: Flat,
+    /// Every global defined or referred to
+    pub globals: Vec<Symbol>,
+}
+
+/// The variables a procedure can see, other than globals
+struct Scope {
+    locals: HashSet<Symbol>,
+    free: Vec<Symbol>,
+}
+
+/// Closure-converts a whole program. Relies on `resolve::Resolver` having
+/// given every local a unique name.
+pub struct Converter {
+    procs: Vec<Proc>,
+    globals: Vec<Symbol>,
+    /// Globals defined exactly once
+    single: HashSet<Symbol>,
+}
+
+impl Converter {
+    pub fn init() -> Self {
+        Self {
+            procs: Vec::new(),
+            globals: Vec::new(),
+            single: HashSet::new(),
+        }
+    }
+
+    pub fn convert(mut self, core: &Core) -> EvalResult<Program> {
+        let forms = match core {
+            Core::Begin(forms) => forms.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+        for form in forms {
+            if let Core::Define(name, _) = form {
+                if self.globals.contains(name) {
+                    self.single.remove(name);
+                } else {
+                    self.globals.push(*name);
+                    self.single.insert(*name);
+                }
+            }
+        }
+
+        let mut scope = Scope {
+            locals: HashSet::new(),
+            free: Vec::new(),
+        };
+        let main = self.expr(core, &mut scope)?;
+        Ok(Program {
+            procs: self.procs,
+            main,
+            globals: self.globals,
+        })
+    }
+
+    fn var(&mut self, name: Symbol, scope: &Scope) -> Flat {
+        if scope.locals.contains(&name) {
+            Flat::Local(name)
+        } else if let Some(i) = scope.free.iter().position(|f| *f == name) {
+            Flat::Free(i)
+        } else if let Some(p) = self.prim(name, scope) {
+            Flat::PrimRef(p)
+        } else {
+            // a global never defined is still one, which is always unbound
+            if !self.globals.contains(&name) {
+                self.globals.push(name);
+            }
+            Flat::Global(name)
+        }
+    }
+
+    /// The primitive `name` refers to, unless a variable shadows it
+    fn prim(&self, name: Symbol, scope: &Scope) -> Option<&'static Prim> {
+        if scope.locals.contains(&name)
+            || scope.free.contains(&name)
+            || self.globals.contains(&name)
+        {
+            None
+        } else {
+            prim::lookup(&name)
+        }
+    }
+
+    fn exprs(&mut self, cores: &[Core], scope: &mut Scope) -> EvalResult<Vec<Flat>> {
+        cores.iter().map(|c| self.expr(c, scope)).collect()
+    }
+
+    fn expr(&mut self, core: &Core, scope: &mut Scope) -> EvalResult<Flat> {
+        let flat = match core {
+            Core::Var(name) => self.var(*name, scope),
+            Core::Const(d) => Flat::Const(d.clone()),
+            Core::Lambda(formals, body) => self.lambda(None, None, formals, body, scope)?,
+            Core::If(c, t, e) => Flat::If(
+                Box::new(self.expr(c, scope)?),
+                Box::new(self.expr(t, scope)?),
+                Box::new(self.expr(e, scope)?),
+            ),
+            Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+                let rands = self.exprs(rands, scope)?;
+                let prim = match rator.as_ref() {
+                    Core::Var(name) => self
+                        .prim(*name, scope)
+                        .filter(|p| p.arity.accepts(rands.len())),
+                    _ => None,
+                };
+                match prim {
+                    Some(p) => Flat::Prim(p, rands),
+                    None => Flat::Call(
+                        Box::new(self.expr(rator, scope)?),
+                        rands,
+                        matches!(core, Core::TailCall(..)),
+                    ),
+                }
+            }
+            Core::Let(bs, body) => {
+                let mut flat_bs = Vec::new();
+                for (name, init) in bs {
+                    flat_bs.push((*name, self.binding(*name, init, scope)?));
+                }
+                scope.locals.extend(bs.iter().map(|(name, _)| *name));
+                Flat::Let(flat_bs, Box::new(self.expr(body, scope)?))
+            }
+            Core::LetRec(bs, body) => {
+                scope.locals.extend(bs.iter().map(|(name, _)| *name));
+                let mut procs = Vec::new();
+                let mut values = Vec::new();
+                for (name, init) in bs {
+                    match init {
+                        Core::Lambda(formals, lbody) => {
+                            let shown = Some(source_name(name).to_owned());
+                            match self.lambda(shown, None, formals, lbody, scope)? {
+                                Flat::Closure(i, captured) => procs.push((*name, i, captured)),
+                                _ => unreachable!("`lambda` converts to a closure"),
+                            }
+                        }
+                        // values are bound after the closures, which must not
+                        // have captured them before they exist
+                        other => {
+                            if bs.iter().any(|(_, i)| captures(i, *name)) {
+                                return Err(EvalError::Unsupported(format!(
+                                    "`letrec` procedures capturing the non-procedure `{name}`"
+                                )));
+                            }
+                            values.push((*name, self.expr(other, scope)?));
+                        }
+                    }
+                }
+                let mut body = self.expr(body, scope)?;
+                if !values.is_empty() {
+                    body = Flat::Let(values, Box::new(body));
+                }
+                Flat::LetRec(procs, Box::new(body))
+            }
+            Core::Begin(exprs) => Flat::Begin(self.exprs(exprs, scope)?),
+            Core::The(ty, expr) => Flat::The(*ty, Box::new(self.expr(expr, scope)?)),
+            Core::Define(name, expr) => {
+                let expr = match expr.as_ref() {
+                    Core::Lambda(formals, body) if self.single.contains(name) => {
+                        let shown = Some(source_name(name).to_owned());
+                        self.lambda(shown, Some(*name), formals, body, scope)?
+                    }
+                    other => self.binding(*name, other, scope)?,
+                };
+                Flat::Define(*name, Box::new(expr))
+            }
+        };
+        Ok(flat)
+    }
+
+    /// Convert the value bound to `name`, which names it if it's a procedure
+    fn binding(&mut self, name: Symbol, init: &Core, scope: &mut Scope) -> EvalResult<Flat> {
+        match init {
+            Core::Lambda(formals, body) => {
+                let shown = Some(source_name(&name).to_owned());
+                self.lambda(shown, None, formals, body, scope)
+            }
+            other => self.expr(other, scope),
+        }
+    }
+
+    fn lambda(
+        &mut self,
+        name: Option<String>,
+        global: Option<Symbol>,
+        formals: &[Symbol],
+        body: &Core,
+        scope: &mut Scope,
+    ) -> EvalResult<Flat> {
+        let free: Vec<Symbol> = free_variables(formals, body)
+            .into_iter()
+            .filter(|v| scope.locals.contains(v) || scope.free.contains(v))
+            .collect();
+        let captured = free.iter().map(|v| self.var(*v, scope)).collect();
+
+        let mut inner = Scope {
+            locals: formals.iter().cloned().collect(),
+            free: free.clone(),
+        };
+        let body = self.expr(body, &mut inner)?;
+        self.procs.push(Proc {
+            name,
+            global,
+            formals: formals.to_vec(),
+            free,
+            body,
+        });
+        Ok(Flat::Closure(self.procs.len() - 1, captured))
+    }
+}
+
+/// Does any `lambda` in `core` refer to `name`?
+fn captures(core: &Core, name: Symbol) -> bool {
+    match core {
+        Core::Lambda(formals, body) => free_variables(formals, body).contains(&name),
+        Core::Var(_) | Core::Const(_) => false,
+        Core::If(c, t, e) => captures(c, name) || captures(t, name) || captures(e, name),
+        Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+            captures(rator, name) || rands.iter().any(|r| captures(r, name))
+        }
+        Core::Let(bs, body) | Core::LetRec(bs, body) => {
+            bs.iter().any(|(_, i)| captures(i, name)) || captures(body, name)
+        }
+        Core::Begin(exprs) => exprs.iter().any(|e| captures(e, name)),
+        Core::The(_, expr) | Core::Define(_, expr) => captures(expr, name),
+    }
+}
+
+/// The variables `(lambda formals body)` refers to without binding them, in
+/// the order they first appear. Globals and primitives are included.
+pub fn free_variables(formals: &[Symbol], body: &Core) -> Vec<Symbol> {
+    let mut refs = Vec::new();
+    let mut bound: HashSet<Symbol> = formals.iter().copied().collect();
+    references(body, &mut refs, &mut bound);
+    refs.retain(|r| !bound.contains(r));
+    refs
+}
+
+/// Every variable `core` refers to, in the order they first appear, and
+/// every variable it binds
+fn references(core: &Core, refs: &mut Vec<Symbol>, bound: &mut HashSet<Symbol>) {
+    match core {
+        Core::Var(name) => {
+            if !refs.contains(name) {
+                refs.push(*name)
+            }
+        }
+        Core::Const(_) => (),
+        Core::Lambda(formals, body) => {
+            bound.extend(formals.iter().copied());
+            references(body, refs, bound)
+        }
+        Core::If(c, t, e) => {
+            references(c, refs, bound);
+            references(t, refs, bound);
+            references(e, refs, bound);
+        }
+        Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+            references(rator, refs, bound);
+            rands.iter().for_each(|r| references(r, refs, bound));
+        }
+        Core::Let(bs, body) | Core::LetRec(bs, body) => {
+            for (name, init) in bs {
+                bound.insert(*name);
+                references(init, refs, bound);
+            }
+            references(body, refs, bound)
+        }
+        Core::Begin(exprs) => exprs.iter().for_each(|e| references(e, refs, bound)),
+        Core::The(_, expr) | Core::Define(_, expr) => references(expr, refs, bound),
+    }
+}
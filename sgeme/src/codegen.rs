@@ -0,0 +1,832 @@
+//! Lower a closure-converted program into a `rs_mir::MIRModule`
+//!
+//! Every value is a 64-bit word laid out as `word` describes, with the
+//! address of a procedure's function as the code of its closures.
+//!
+//! Allocation, printing and errors are left to the runtime, imported as
+//! `sgeme_alloc`, `sgeme_display`, `sgeme_write`, `sgeme_newline` and
+//! `sgeme_error`. Once the error is reported, the code returns `FAULT`, and
+//! so does every caller up to `main`.
+
+use std::collections::{BTreeSet, HashMap};
+
+use rs_mir::ir::{Code, Func, Item, Operand, Proto, Type as MirType, Var};
+use rs_mir::MIRModule;
+
+use crate::closure::{Flat, Program};
+use crate::datum::Datum;
+use crate::eval::{EvalError, EvalResult};
+use crate::prim::{self, Prim, PRIMS};
+use crate::primsyn::Type;
+use crate::symbol::Symbol;
+use crate::types::Types;
+use crate::word::*;
+
+/// Turn a name into something MIR accepts as an identifier
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// The register holding a parameter
+fn formal(name: &str) -> String {
+    format!("a_{}", sanitize(name))
+}
+
+fn reg(name: &str) -> Operand {
+    Operand::reg(name)
+}
+
+fn int(n: i64) -> Operand {
+    Operand::Int(n)
+}
+
+fn label(name: &str) -> Operand {
+    Operand::Label(name.to_owned())
+}
+
+fn item(name: &str) -> Operand {
+    Operand::Ref(name.to_owned())
+}
+
+/// The data item holding the quoted list `i`
+fn quoted(i: usize) -> String {
+    format!("q{i}")
+}
+
+/// The name of the prototype for procedures taking `n` arguments
+fn call_proto(n: usize) -> String {
+    format!("p_call{n}")
+}
+
+/// What the code generator needs to know about a procedure besides its body
+struct ProcInfo {
+    func: String,
+    arity: usize,
+}
+
+/// The MIR function being generated
+struct Function {
+    func: Func,
+    /// The registers holding the locals in scope
+    regs: HashMap<Symbol, String>,
+    /// The names of the free variables of its closure
+    free: Vec<Symbol>,
+    /// The global naming this procedure, and the label after its prologue,
+    /// for turning calls to itself in tail position into jumps
+    this: Option<(Symbol, String)>,
+    temps: usize,
+}
+
+impl Function {
+    fn init(func: Func, free: Vec<Symbol>) -> Self {
+        Self {
+            func,
+            regs: HashMap::new(),
+            free,
+            this: None,
+            temps: 0,
+        }
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        let name = format!("t{}", self.temps);
+        self.func.locals.push(Var::i64(name.clone()));
+        name
+    }
+
+    /// A fresh register for the local `name`
+    fn bind(&mut self, name: Symbol) -> String {
+        self.temps += 1;
+        let r = format!("v{}_{}", self.temps, sanitize(&name));
+        self.func.locals.push(Var::i64(r.clone()));
+        self.regs.insert(name, r.clone());
+        r
+    }
+
+    fn push(&mut self, code: Code, ops: Vec<Operand>) {
+        self.func.push(code, ops)
+    }
+
+    /// A new register holding the constant `n`
+    fn constant(&mut self, n: i64) -> String {
+        let r = self.temp();
+        self.push(Code::Mov, vec![reg(&r), int(n)]);
+        r
+    }
+
+    /// A new register holding `r` compared by `code` against `other`, as a
+    /// boolean
+    fn compare(&mut self, code: Code, r: &str, other: Operand) -> String {
+        let t = self.temp();
+        self.push(code, vec![reg(&t), reg(r), other]);
+        self.boolean(&t)
+    }
+
+    /// Turn `0` or `1` in `r` into `#f` or `#t`
+    fn boolean(&mut self, r: &str) -> String {
+        self.push(Code::Lsh, vec![reg(r), reg(r), int(3)]);
+        self.push(Code::Add, vec![reg(r), reg(r), int(FALSE)]);
+        r.to_owned()
+    }
+}
+
+/// Generates MIR for a whole program. Every procedure becomes a function
+/// taking its closure and then its arguments, and calls check the callee's
+/// tag and arity before calling through its code pointer, except for calls
+/// to top-level procedures defined once, which are called directly. Calls a
+/// procedure makes to itself in tail position become jumps; other tail calls
+/// use the native stack.
+pub struct CodeGen<'t> {
+    types: &'t Types,
+    procs: Vec<ProcInfo>,
+    globals: Vec<Symbol>,
+    /// Top-level procedures defined once, with no free variables
+    direct: HashMap<Symbol, usize>,
+    /// The arities of every call made, each needing a prototype
+    arities: BTreeSet<usize>,
+    /// The quoted lists, each made once before the program runs
+    consts: Vec<Datum>,
+    /// The symbols the program quotes, by the index their words hold
+    symbols: Vec<Symbol>,
+    labels: usize,
+}
+
+impl<'t> CodeGen<'t> {
+    pub fn init(types: &'t Types) -> Self {
+        Self {
+            types,
+            procs: Vec::new(),
+            globals: Vec::new(),
+            direct: HashMap::new(),
+            arities: BTreeSet::new(),
+            consts: Vec::new(),
+            symbols: Vec::new(),
+            labels: 0,
+        }
+    }
+
+    pub fn generate(mut self, program: &Program) -> EvalResult<(MIRModule, Symbols)> {
+        self.globals = program.globals.clone();
+        for (i, proc) in program.procs.iter().enumerate() {
+            let name = proc.name.as_deref().unwrap_or("lambda");
+            self.procs.push(ProcInfo {
+                func: format!("f{i}_{}", sanitize(name)),
+                arity: proc.formals.len(),
+            });
+            if let (Some(name), true) = (&proc.global, proc.free.is_empty()) {
+                self.direct.insert(*name, i);
+            }
+        }
+
+        let mut funcs = Vec::new();
+        for (i, proc) in program.procs.iter().enumerate() {
+            let args = std::iter::once(Var::i64("clo"))
+                .chain(proc.formals.iter().map(|f| Var::i64(formal(f))))
+                .collect();
+            let func = Func::init(&self.procs[i].func, vec![MirType::I64], args);
+            let mut f = Function::init(func, proc.free.clone());
+            for formal in &proc.formals {
+                f.regs.insert(*formal, self::formal(formal));
+            }
+            if let Some(name) = &proc.global {
+                let start = self.label();
+                f.func.label(&start);
+                f.this = Some((*name, start));
+            }
+            let r = self.expr(&mut f, &proc.body)?;
+            f.push(Code::Ret, vec![reg(&r)]);
+            funcs.push(f.func);
+        }
+
+        let mut f = Function::init(Func::init("main", vec![MirType::I64], vec![]), vec![]);
+        for i in 0..self.globals.len() {
+            let a = f.temp();
+            f.push(Code::Mov, vec![reg(&a), item(&self.global(i))]);
+            f.push(Code::Mov, vec![Operand::mem(0, &a), int(UNBOUND)]);
+        }
+        f.push(Code::Call, vec![item("p_constants"), item("constants")]);
+        let r = self.expr(&mut f, &program.main)?;
+        f.push(Code::Ret, vec![reg(&r)]);
+        funcs.push(f.func);
+
+        let mut f = Function::init(Func::init("constants", vec![], vec![]), vec![]);
+        let consts = std::mem::take(&mut self.consts);
+        for (i, d) in consts.iter().enumerate() {
+            let r = self.datum(&mut f, d)?;
+            let a = f.temp();
+            f.push(Code::Mov, vec![reg(&a), item(&quoted(i))]);
+            f.push(Code::Mov, vec![Operand::mem(0, &a), reg(&r)]);
+        }
+        f.push(Code::Ret, vec![]);
+        funcs.push(f.func);
+
+        let mut module = MIRModule::init("sgeme");
+        module.items.extend(self.prototypes());
+        for import in [
+            "sgeme_alloc",
+            "sgeme_error",
+            "sgeme_display",
+            "sgeme_write",
+            "sgeme_newline",
+        ] {
+            module.items.push(Item::Import(import.to_owned()));
+        }
+        for i in 0..self.globals.len() {
+            module.items.push(Item::Bss {
+                name: self.global(i),
+                size: 8,
+            });
+        }
+        for i in 0..consts.len() {
+            module.items.push(Item::Bss {
+                name: quoted(i),
+                size: 8,
+            });
+        }
+        module.items.extend(funcs.into_iter().map(Item::Func));
+        module.items.push(Item::Export("main".to_owned()));
+
+        let symbols = Symbols {
+            globals: program.globals.clone(),
+            procs: (self.procs.iter().zip(&program.procs))
+                .map(|(info, proc)| (info.func.clone(), proc.name.clone()))
+                .collect(),
+            symbols: self.symbols,
+        };
+        Ok((module, symbols))
+    }
+
+    fn prototypes(&self) -> Vec<Item> {
+        let proto = |name: &str, results: Vec<MirType>, args: &[&str]| {
+            Item::Proto(Proto {
+                name: name.to_owned(),
+                results,
+                args: args.iter().map(|a| Var::i64(*a)).collect(),
+            })
+        };
+        let mut protos = vec![
+            proto("p_alloc", vec![MirType::I64], &["size"]),
+            proto("p_error", vec![], &["fault", "detail", "value"]),
+            proto("p_print", vec![], &["value"]),
+            proto("p_newline", vec![], &[]),
+            proto("p_constants", vec![], &[]),
+        ];
+        for n in &self.arities {
+            let args: Vec<String> = (0..*n).map(|i| format!("a{i}")).collect();
+            let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+            args.insert(0, "clo");
+            protos.push(proto(&call_proto(*n), vec![MirType::I64], &args));
+        }
+        protos
+    }
+
+    fn label(&mut self) -> String {
+        self.labels += 1;
+        format!("L{}", self.labels)
+    }
+
+    /// The data item holding `globals[i]`
+    fn global(&self, i: usize) -> String {
+        format!("g{i}_{}", sanitize(&self.globals[i]))
+    }
+
+    /// Report a fault to the runtime, and return `FAULT`
+    fn fail(&mut self, f: &mut Function, fault: Fault, detail: Operand, value: Operand) {
+        f.push(
+            Code::Call,
+            vec![
+                item("p_error"),
+                item("sgeme_error"),
+                int(fault as i64),
+                detail,
+                value,
+            ],
+        );
+        f.push(Code::Ret, vec![int(FAULT)]);
+    }
+
+    /// Fail unless `r` holds a value of type `ty`
+    fn guard(&mut self, f: &mut Function, r: &str, ty: Type, fault: Fault, detail: i64) {
+        let Some((mask, expected)) = type_test(ty) else {
+            return self.fail(f, fault, int(detail), reg(r));
+        };
+        let ok = self.label();
+        let t = f.temp();
+        f.push(Code::And, vec![reg(&t), reg(r), int(mask)]);
+        f.push(Code::Beq, vec![label(&ok), reg(&t), int(expected)]);
+        self.fail(f, fault, int(detail), reg(r));
+        f.func.label(&ok);
+    }
+
+    /// Fail if the arithmetic just done overflowed
+    fn overflow(&mut self, f: &mut Function, detail: i64) {
+        let ok = self.label();
+        f.push(Code::Bno, vec![label(&ok)]);
+        self.fail(f, Fault::Overflow, int(detail), int(0));
+        f.func.label(&ok);
+    }
+
+    /// A fresh block of `size` bytes
+    fn alloc(&mut self, f: &mut Function, size: i64) -> String {
+        let r = f.temp();
+        f.push(
+            Code::Call,
+            vec![item("p_alloc"), item("sgeme_alloc"), reg(&r), int(size)],
+        );
+        r
+    }
+
+    fn cons(&mut self, f: &mut Function, car: &str, cdr: &str) -> String {
+        let r = self.alloc(f, 16);
+        f.push(Code::Mov, vec![Operand::mem(0, &r), reg(car)]);
+        f.push(Code::Mov, vec![Operand::mem(8, &r), reg(cdr)]);
+        f.push(Code::Add, vec![reg(&r), reg(&r), int(PAIR_TAG)]);
+        r
+    }
+
+    /// Make a closure of `procs[i]` in `dst`, without filling in its free
+    /// variables
+    fn closure(&mut self, f: &mut Function, i: usize, free: usize, dst: &str) {
+        let size = 16 + 8 * free as i64;
+        f.push(
+            Code::Call,
+            vec![item("p_alloc"), item("sgeme_alloc"), reg(dst), int(size)],
+        );
+        let code = self.procs[i].func.clone();
+        f.push(Code::Mov, vec![Operand::mem(0, dst), item(&code)]);
+        let arity = self.procs[i].arity as i64;
+        f.push(Code::Mov, vec![Operand::mem(8, dst), int(arity)]);
+        f.push(Code::Add, vec![reg(dst), reg(dst), int(CLOSURE_TAG)]);
+    }
+
+    /// Fill in the free variables of the closure in `dst`
+    fn capture(&mut self, f: &mut Function, dst: &str, captured: &[Flat]) -> EvalResult<()> {
+        for (i, c) in captured.iter().enumerate() {
+            let v = self.expr(f, c)?;
+            let disp = 16 + 8 * i as i64 - CLOSURE_TAG;
+            f.push(Code::Mov, vec![Operand::mem(disp, dst), reg(&v)]);
+        }
+        Ok(())
+    }
+
+    /// The value of the quoted `d`, which is always the same object
+    fn constant(&mut self, f: &mut Function, d: &Datum) -> EvalResult<String> {
+        if let Datum::List(_) | Datum::DottedList(..) = d {
+            if Word::immediate(d).is_none() {
+                self.consts.push(d.clone());
+                let a = f.temp();
+                f.push(
+                    Code::Mov,
+                    vec![reg(&a), item(&quoted(self.consts.len() - 1))],
+                );
+                let r = f.temp();
+                f.push(Code::Mov, vec![reg(&r), Operand::mem(0, &a)]);
+                return Ok(r);
+            }
+        }
+        self.datum(f, d)
+    }
+
+    /// Make `d` afresh
+    fn datum(&mut self, f: &mut Function, d: &Datum) -> EvalResult<String> {
+        if let Some(Word(word)) = Word::immediate(d) {
+            return Ok(f.constant(word));
+        }
+        match d {
+            Datum::Symbol(sym) => Ok(f.constant(Word::symbol(intern(&mut self.symbols, *sym)).0)),
+            Datum::List(items) => self.list(f, items, &Datum::Null),
+            Datum::DottedList(items, tail) => self.list(f, items, tail),
+            other => Err(EvalError::Unsupported(format!(
+                "the constant `{other}` in compiled code"
+            ))),
+        }
+    }
+
+    fn list(&mut self, f: &mut Function, items: &[Datum], tail: &Datum) -> EvalResult<String> {
+        let mut r = self.datum(f, tail)?;
+        for item in items.iter().rev() {
+            let car = self.datum(f, item)?;
+            r = self.cons(f, &car, &r);
+        }
+        Ok(r)
+    }
+
+    /// Is `e` known to evaluate to a value of type `ty`?
+    fn known(&self, f: &Function, e: &Flat, ty: Type) -> bool {
+        let t = match e {
+            Flat::Local(name) => self.types.of_var(*name),
+            Flat::Free(i) => self.types.of_var(f.free[*i]),
+            Flat::Const(d) => crate::types::datum_type(d),
+            Flat::Prim(p, _) => prim::result_type(p.name),
+            Flat::Closure(..) => Some(Type::Proc),
+            Flat::The(t, _) => Some(*t),
+            _ => None,
+        };
+        t.is_some_and(|t| t.within(ty))
+    }
+
+    fn load_global(&mut self, f: &mut Function, name: Symbol) -> String {
+        let i = self
+            .globals
+            .iter()
+            .position(|g| *g == name)
+            .expect("globals are all defined at the top level");
+        let a = f.temp();
+        f.push(Code::Mov, vec![reg(&a), item(&self.global(i))]);
+        let r = f.temp();
+        f.push(Code::Mov, vec![reg(&r), Operand::mem(0, &a)]);
+        let ok = self.label();
+        f.push(Code::Bne, vec![label(&ok), reg(&r), int(UNBOUND)]);
+        self.fail(f, Fault::Unbound, int(i as i64), int(0));
+        f.func.label(&ok);
+        r
+    }
+
+    fn expr(&mut self, f: &mut Function, e: &Flat) -> EvalResult<String> {
+        let r = match e {
+            Flat::Local(name) => f.regs[name].clone(),
+            Flat::Free(i) => {
+                let r = f.temp();
+                let disp = 16 + 8 * *i as i64 - CLOSURE_TAG;
+                f.push(Code::Mov, vec![reg(&r), Operand::mem(disp, "clo")]);
+                r
+            }
+            Flat::Global(name) => self.load_global(f, *name),
+            Flat::Const(d) => self.constant(f, d)?,
+            Flat::PrimRef(p) => {
+                return Err(EvalError::Unsupported(format!(
+                    "the primitive `{}` as a value in compiled code",
+                    p.name
+                )))
+            }
+            Flat::Closure(i, captured) => {
+                let r = f.temp();
+                self.closure(f, *i, captured.len(), &r);
+                self.capture(f, &r, captured)?;
+                r
+            }
+            Flat::If(c, t, e) => {
+                let r = f.temp();
+                let (otherwise, end) = (self.label(), self.label());
+                let c = self.expr(f, c)?;
+                f.push(Code::Beq, vec![label(&otherwise), reg(&c), int(FALSE)]);
+                let t = self.expr(f, t)?;
+                f.push(Code::Mov, vec![reg(&r), reg(&t)]);
+                f.push(Code::Jmp, vec![label(&end)]);
+                f.func.label(&otherwise);
+                let e = self.expr(f, e)?;
+                f.push(Code::Mov, vec![reg(&r), reg(&e)]);
+                f.func.label(&end);
+                r
+            }
+            Flat::Call(rator, rands, tail) => self.call(f, rator, rands, *tail)?,
+            Flat::Prim(p, rands) => self.prim(f, p, rands)?,
+            Flat::Let(bs, body) => {
+                for (name, init) in bs {
+                    let v = self.expr(f, init)?;
+                    let r = f.bind(*name);
+                    f.push(Code::Mov, vec![reg(&r), reg(&v)]);
+                }
+                self.expr(f, body)?
+            }
+            Flat::LetRec(procs, body) => {
+                for (name, i, captured) in procs {
+                    let r = f.bind(*name);
+                    self.closure(f, *i, captured.len(), &r);
+                }
+                for (name, _, captured) in procs {
+                    let r = f.regs[name].clone();
+                    self.capture(f, &r, captured)?;
+                }
+                self.expr(f, body)?
+            }
+            Flat::Begin(es) => {
+                let mut r = None;
+                for e in es {
+                    r = Some(self.expr(f, e)?);
+                }
+                match r {
+                    Some(r) => r,
+                    None => f.constant(UNSPECIFIED),
+                }
+            }
+            Flat::The(ty, e) => {
+                let r = self.expr(f, e)?;
+                // the types of variables are inferred trusting this check
+                let var = matches!(**e, Flat::Local(_) | Flat::Free(_));
+                if var || !self.known(f, e, *ty) {
+                    self.guard(f, &r, *ty, Fault::AnnotationFailed, *ty as i64);
+                }
+                r
+            }
+            Flat::Define(name, e) => {
+                let v = self.expr(f, e)?;
+                let i = self
+                    .globals
+                    .iter()
+                    .position(|g| g == name)
+                    .expect("`Converter` collects every definition");
+                let a = f.temp();
+                f.push(Code::Mov, vec![reg(&a), item(&self.global(i))]);
+                f.push(Code::Mov, vec![Operand::mem(0, &a), reg(&v)]);
+                f.constant(UNSPECIFIED)
+            }
+        };
+        Ok(r)
+    }
+
+    fn call(
+        &mut self,
+        f: &mut Function,
+        rator: &Flat,
+        rands: &[Flat],
+        tail: bool,
+    ) -> EvalResult<String> {
+        let direct = match rator {
+            Flat::Global(name) => self
+                .direct
+                .get(name)
+                .copied()
+                .filter(|i| self.procs[*i].arity == rands.len()),
+            _ => None,
+        };
+        let jump = match (&f.this, rator) {
+            (Some((this, start)), Flat::Global(name)) if tail && this == name => {
+                direct.map(|_| start.clone())
+            }
+            _ => None,
+        };
+        let callee = match jump {
+            Some(_) => None,
+            None => Some(self.expr(f, rator)?),
+        };
+        let mut args = Vec::new();
+        for rand in rands {
+            args.push(self.expr(f, rand)?);
+        }
+
+        let Some(callee) = callee else {
+            // copy the arguments first, as they may use each other
+            let temps: Vec<String> = args
+                .iter()
+                .map(|a| {
+                    let t = f.temp();
+                    f.push(Code::Mov, vec![reg(&t), reg(a)]);
+                    t
+                })
+                .collect();
+            let formals: Vec<String> = f.func.args[1..].iter().map(|a| a.name.clone()).collect();
+            for (formal, t) in formals.iter().zip(&temps) {
+                f.push(Code::Mov, vec![reg(formal), reg(t)]);
+            }
+            f.push(Code::Jmp, vec![label(&jump.expect("no callee for a jump"))]);
+            return Ok(f.constant(UNSPECIFIED));
+        };
+
+        let n = rands.len();
+        self.arities.insert(n);
+        let code = match direct {
+            Some(i) => item(&self.procs[i].func),
+            None => {
+                self.guard(f, &callee, Type::Proc, Fault::NotAProcedure, 0);
+                let arity = f.temp();
+                f.push(
+                    Code::Mov,
+                    vec![reg(&arity), Operand::mem(8 - CLOSURE_TAG, &callee)],
+                );
+                let ok = self.label();
+                f.push(Code::Beq, vec![label(&ok), reg(&arity), int(n as i64)]);
+                self.fail(f, Fault::WrongArgCount, int(n as i64), reg(&callee));
+                f.func.label(&ok);
+                let code = f.temp();
+                f.push(
+                    Code::Mov,
+                    vec![reg(&code), Operand::mem(-CLOSURE_TAG, &callee)],
+                );
+                reg(&code)
+            }
+        };
+        let r = f.temp();
+        let mut ops = vec![item(&call_proto(n)), code, reg(&r), reg(&callee)];
+        ops.extend(args.iter().map(|a| reg(a)));
+        f.push(Code::Call, ops);
+        let ok = self.label();
+        f.push(Code::Bne, vec![label(&ok), reg(&r), int(FAULT)]);
+        f.push(Code::Ret, vec![reg(&r)]);
+        f.func.label(&ok);
+        Ok(r)
+    }
+
+    fn prim(&mut self, f: &mut Function, p: &'static Prim, rands: &[Flat]) -> EvalResult<String> {
+        let index = PRIMS
+            .iter()
+            .position(|q| std::ptr::eq(q, p))
+            .expect("`Flat::Prim` holds one of `PRIMS`") as i64;
+        let mut args = Vec::new();
+        for (i, rand) in rands.iter().enumerate() {
+            let a = self.expr(f, rand)?;
+            if let Some(kind) = prim::arg_kind(p.name, i) {
+                if !self.known(f, rand, kind.as_type()) {
+                    let detail = index | (i as i64) << 16;
+                    self.guard(f, &a, kind.as_type(), Fault::WrongType, detail);
+                }
+            }
+            args.push(a);
+        }
+
+        let r = match (p.name, args.as_slice()) {
+            ("+", []) => f.constant(0),
+            ("*", []) => f.constant(fixnum(1)),
+            ("+" | "*", [first, rest @ ..]) => {
+                let r = f.temp();
+                f.push(Code::Mov, vec![reg(&r), reg(first)]);
+                for a in rest {
+                    if p.name == "+" {
+                        f.push(Code::Addo, vec![reg(&r), reg(&r), reg(a)]);
+                    } else {
+                        // only one side can stay shifted
+                        f.push(Code::Rsh, vec![reg(&r), reg(&r), int(FIXNUM_SHIFT)]);
+                        f.push(Code::Mulo, vec![reg(&r), reg(&r), reg(a)]);
+                    }
+                    self.overflow(f, index);
+                }
+                r
+            }
+            ("-", [a]) => {
+                let r = f.constant(0);
+                f.push(Code::Subo, vec![reg(&r), reg(&r), reg(a)]);
+                self.overflow(f, index);
+                r
+            }
+            ("-", [first, rest @ ..]) => {
+                let r = f.temp();
+                f.push(Code::Mov, vec![reg(&r), reg(first)]);
+                for a in rest {
+                    f.push(Code::Subo, vec![reg(&r), reg(&r), reg(a)]);
+                    self.overflow(f, index);
+                }
+                r
+            }
+            ("add1" | "sub1", [a]) => {
+                let r = f.temp();
+                let code = if p.name == "add1" {
+                    Code::Addo
+                } else {
+                    Code::Subo
+                };
+                f.push(code, vec![reg(&r), reg(a), int(fixnum(1))]);
+                self.overflow(f, index);
+                r
+            }
+            ("quotient" | "remainder" | "modulo", [a, b]) => {
+                let ok = self.label();
+                f.push(Code::Bne, vec![label(&ok), reg(b), int(0)]);
+                self.fail(f, Fault::DivisionByZero, int(index), int(0));
+                f.func.label(&ok);
+                let (x, y) = (f.temp(), f.temp());
+                f.push(Code::Rsh, vec![reg(&x), reg(a), int(FIXNUM_SHIFT)]);
+                f.push(Code::Rsh, vec![reg(&y), reg(b), int(FIXNUM_SHIFT)]);
+                let q = f.temp();
+                if p.name == "quotient" {
+                    f.push(Code::Div, vec![reg(&q), reg(&x), reg(&y)]);
+                } else {
+                    f.push(Code::Mod, vec![reg(&q), reg(&x), reg(&y)]);
+                }
+                if p.name == "modulo" {
+                    // take the sign of the divisor rather than the dividend
+                    let done = self.label();
+                    f.push(Code::Beq, vec![label(&done), reg(&q), int(0)]);
+                    let s = f.temp();
+                    f.push(Code::Xor, vec![reg(&s), reg(&q), reg(&y)]);
+                    f.push(Code::Bge, vec![label(&done), reg(&s), int(0)]);
+                    f.push(Code::Add, vec![reg(&q), reg(&q), reg(&y)]);
+                    f.func.label(&done);
+                }
+                let r = f.temp();
+                f.push(Code::Lsh, vec![reg(&r), reg(&q), int(FIXNUM_SHIFT)]);
+                if p.name == "quotient" {
+                    // only the most negative fixnum divided by -1 is too big
+                    let back = f.temp();
+                    f.push(Code::Rsh, vec![reg(&back), reg(&r), int(FIXNUM_SHIFT)]);
+                    let ok = self.label();
+                    f.push(Code::Beq, vec![label(&ok), reg(&back), reg(&q)]);
+                    self.fail(f, Fault::Overflow, int(index), int(0));
+                    f.func.label(&ok);
+                }
+                r
+            }
+            ("abs", [a]) => {
+                let r = f.temp();
+                f.push(Code::Mov, vec![reg(&r), reg(a)]);
+                let done = self.label();
+                f.push(Code::Bge, vec![label(&done), reg(&r), int(0)]);
+                let zero = f.constant(0);
+                f.push(Code::Subo, vec![reg(&r), reg(&zero), reg(a)]);
+                self.overflow(f, index);
+                f.func.label(&done);
+                r
+            }
+            ("=" | "<" | ">" | "<=" | ">=", [_]) => f.constant(TRUE),
+            ("=" | "<" | ">" | "<=" | ">=", _) => {
+                let code = match p.name {
+                    "=" => Code::Eq,
+                    "<" => Code::Lt,
+                    ">" => Code::Gt,
+                    "<=" => Code::Le,
+                    _ => Code::Ge,
+                };
+                // shifting keeps the order, so compare the words themselves
+                let r = f.constant(1);
+                let t = f.temp();
+                for pair in args.windows(2) {
+                    f.push(code, vec![reg(&t), reg(&pair[0]), reg(&pair[1])]);
+                    f.push(Code::And, vec![reg(&r), reg(&r), reg(&t)]);
+                }
+                f.boolean(&r)
+            }
+            ("zero?", [a]) => f.compare(Code::Eq, a, int(0)),
+            ("even?" | "odd?", [a]) => {
+                let t = f.temp();
+                f.push(Code::And, vec![reg(&t), reg(a), int(fixnum(1))]);
+                let code = if p.name == "even?" {
+                    Code::Eq
+                } else {
+                    Code::Ne
+                };
+                f.compare(code, &t, int(0))
+            }
+            ("not", [a]) => f.compare(Code::Eq, a, int(FALSE)),
+            ("eq?" | "eqv?", [a, b]) => f.compare(Code::Eq, a, reg(b)),
+            ("null?", [a]) => f.compare(Code::Eq, a, int(NULL)),
+            (
+                "pair?" | "procedure?" | "boolean?" | "char?" | "symbol?" | "fixnum?" | "integer?"
+                | "number?",
+                [a],
+            ) => {
+                let ty = match p.name {
+                    "pair?" => Type::Pair,
+                    "procedure?" => Type::Proc,
+                    "boolean?" => Type::Bool,
+                    "char?" => Type::Char,
+                    "symbol?" => Type::Symbol,
+                    _ => Type::Fixnum,
+                };
+                let (mask, expected) = type_test(ty).expect("these types have tests");
+                let t = f.temp();
+                f.push(Code::And, vec![reg(&t), reg(a), int(mask)]);
+                f.compare(Code::Eq, &t, int(expected))
+            }
+            ("cons", [a, b]) => self.cons(f, a, b),
+            ("car" | "cdr", [a]) => {
+                let r = f.temp();
+                let disp = if p.name == "car" { 0 } else { 8 };
+                f.push(Code::Mov, vec![reg(&r), Operand::mem(disp - PAIR_TAG, a)]);
+                r
+            }
+            ("set-car!" | "set-cdr!", [a, b]) => {
+                let disp = if p.name == "set-car!" { 0 } else { 8 };
+                f.push(Code::Mov, vec![Operand::mem(disp - PAIR_TAG, a), reg(b)]);
+                f.constant(UNSPECIFIED)
+            }
+            ("list", items) => {
+                let mut r = f.constant(NULL);
+                for item in items.iter().rev() {
+                    r = self.cons(f, item, &r);
+                }
+                r
+            }
+            ("char->integer", [a]) => {
+                let r = f.temp();
+                f.push(Code::Ursh, vec![reg(&r), reg(a), int(8)]);
+                f.push(Code::Lsh, vec![reg(&r), reg(&r), int(FIXNUM_SHIFT)]);
+                r
+            }
+            ("display" | "write", [a]) => {
+                let import = if p.name == "display" {
+                    "sgeme_display"
+                } else {
+                    "sgeme_write"
+                };
+                f.push(Code::Call, vec![item("p_print"), item(import), reg(a)]);
+                f.constant(UNSPECIFIED)
+            }
+            ("newline", []) => {
+                f.push(Code::Call, vec![item("p_newline"), item("sgeme_newline")]);
+                f.constant(UNSPECIFIED)
+            }
+            // the runtime has no collector, and objects live as long as the
+            // program
+            ("collect", []) => f.constant(UNSPECIFIED),
+            _ => {
+                return Err(EvalError::Unsupported(format!(
+                    "the primitive `{}` in compiled code",
+                    p.name
+                )))
+            }
+        };
+        Ok(r)
+    }
+}
@@ -0,0 +1,392 @@
+//! Compile resolved `Core` into bytecode for `vm::Vm`
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::bytecode::{Capture, Module, Op, Proto};
+use crate::closure::free_variables;
+use crate::core_former::Core;
+use crate::prim::{self, Prim, PRIMS};
+use crate::resolve::source_name;
+use crate::symbol::Symbol;
+use crate::value::Value;
+
+/// How many values `op` leaves on the stack, less how many it takes off
+fn stack_effect(op: Op) -> i64 {
+    match op {
+        Op::Const(_)
+        | Op::Local(_)
+        | Op::Upval(_)
+        | Op::Global(_)
+        | Op::PushCell
+        | Op::Closure(_)
+        | Op::Winders
+        | Op::Handlers
+        | Op::Parameters => 1,
+        Op::DefGlobal(_)
+        | Op::SetCell(_)
+        | Op::JumpUnless(_)
+        | Op::Pop
+        | Op::Return
+        | Op::SetWinders
+        | Op::SetHandlers
+        | Op::SetParameters => -1,
+        Op::Unbox | Op::Jump(_) | Op::Check(_) | Op::Car | Op::Cdr | Op::NullP | Op::Not => 0,
+        Op::Add
+        | Op::Sub
+        | Op::Mul
+        | Op::NumEq
+        | Op::Lt
+        | Op::Gt
+        | Op::Le
+        | Op::Ge
+        | Op::Eq
+        | Op::Cons => -1,
+        Op::Call(n) | Op::TailCall(n) | Op::Slide(n) => -(n as i64),
+        Op::Prim(_, n) => 1 - n as i64,
+    }
+}
+
+/// The instruction for a primitive with its own opcode, when called with
+/// that many arguments
+fn fast_op(name: &str, argc: usize) -> Option<Op> {
+    let op = match (name, argc) {
+        ("+", 2) => Op::Add,
+        ("-", 2) => Op::Sub,
+        ("*", 2) => Op::Mul,
+        ("=", 2) => Op::NumEq,
+        ("<", 2) => Op::Lt,
+        (">", 2) => Op::Gt,
+        ("<=", 2) => Op::Le,
+        (">=", 2) => Op::Ge,
+        ("eq?" | "eqv?", 2) => Op::Eq,
+        ("cons", 2) => Op::Cons,
+        ("car", 1) => Op::Car,
+        ("cdr", 1) => Op::Cdr,
+        ("null?", 1) => Op::NullP,
+        ("not", 1) => Op::Not,
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// The procedure being compiled, and where its variables live
+struct Function<'a> {
+    proto: Proto<'a>,
+    /// The locals in scope, with their slot in the frame
+    locals: Vec<(Symbol, u32)>,
+    upvals: Vec<Symbol>,
+    /// How many values the frame holds at this point in the code
+    depth: u32,
+}
+
+impl<'a> Function<'a> {
+    fn init(name: Option<String>, formals: &[Symbol], upvals: Vec<Symbol>) -> Self {
+        Self {
+            proto: Proto {
+                name,
+                arity: formals.len(),
+                ..Default::default()
+            },
+            locals: formals
+                .iter()
+                .enumerate()
+                .map(|(i, f)| (*f, i as u32))
+                .collect(),
+            upvals,
+            depth: formals.len() as u32,
+        }
+    }
+
+    fn local(&self, name: Symbol) -> Option<u32> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, slot)| *slot)
+    }
+
+    fn upval(&self, name: Symbol) -> Option<u32> {
+        self.upvals
+            .iter()
+            .position(|u| *u == name)
+            .map(|i| i as u32)
+    }
+}
+
+/// Compiles a whole program into a `bytecode::Module`. Closures are flat:
+/// they copy the values of their free variables when they are made, which
+/// is enough as `Core` has no assignment. The exception is `letrec`, whose
+/// procedures need to capture each other before they exist, so its
+/// variables are kept in cells.
+pub struct Compiler<'a> {
+    globals: Vec<Symbol>,
+    global_index: HashMap<Symbol, u32>,
+    /// Top-level definitions, which shadow any primitive of the same name
+    defined: HashSet<Symbol>,
+    /// Variables bound by `letrec`
+    boxed: HashSet<Symbol>,
+    /// The procedure being compiled, and the ones it's nested in
+    fns: Vec<Function<'a>>,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn init() -> Self {
+        Self {
+            globals: Vec::new(),
+            global_index: HashMap::new(),
+            defined: HashSet::new(),
+            boxed: HashSet::new(),
+            fns: Vec::new(),
+        }
+    }
+
+    pub fn compile(mut self, core: &Core) -> Module<'a> {
+        let forms = match core {
+            Core::Begin(forms) => forms.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+        for form in forms {
+            if let Core::Define(name, _) = form {
+                self.defined.insert(*name);
+            }
+        }
+
+        self.fns
+            .push(Function::init(Some("main".to_owned()), &[], Vec::new()));
+        self.expr(core, true);
+        let main = self
+            .fns
+            .pop()
+            .expect("`main` is still being compiled")
+            .proto;
+        Module {
+            main: Rc::new(main),
+            globals: self.globals,
+        }
+    }
+
+    fn func(&mut self) -> &mut Function<'a> {
+        self.fns.last_mut().expect("always inside some procedure")
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let func = self.func();
+        func.depth = (func.depth as i64 + stack_effect(op)) as u32;
+        func.proto.code.push(op);
+        func.proto.code.len() - 1
+    }
+
+    /// Point the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let code = &mut self.func().proto.code;
+        let target = code.len() as u32;
+        match &mut code[at] {
+            Op::Jump(t) | Op::JumpUnless(t) => *t = target,
+            other => unreachable!("patching {other:?}"),
+        }
+    }
+
+    fn constant(&mut self, v: Value<'a>) {
+        let consts = &mut self.func().proto.consts;
+        consts.push(v);
+        let i = consts.len() - 1;
+        self.emit(Op::Const(i as u32));
+    }
+
+    fn global(&mut self, name: Symbol) -> u32 {
+        if let Some(i) = self.global_index.get(&name) {
+            return *i;
+        }
+        let i = self.globals.len() as u32;
+        self.globals.push(name);
+        self.global_index.insert(name, i);
+        i
+    }
+
+    /// The primitive `name` refers to, unless a variable shadows it
+    fn prim(&mut self, name: Symbol) -> Option<&'static Prim> {
+        let func = self.func();
+        if func.local(name).is_some() || func.upval(name).is_some() || self.defined.contains(&name)
+        {
+            None
+        } else {
+            prim::lookup(&name)
+        }
+    }
+
+    fn load(&mut self, name: Symbol) {
+        let func = self.func();
+        if let Some(slot) = func.local(name) {
+            self.emit(Op::Local(slot));
+        } else if let Some(i) = func.upval(name) {
+            self.emit(Op::Upval(i));
+        } else if let Some(p) = self.prim(name) {
+            self.constant(Value::Prim(p));
+            return;
+        } else {
+            let i = self.global(name);
+            self.emit(Op::Global(i));
+            return;
+        }
+        if self.boxed.contains(&name) {
+            self.emit(Op::Unbox);
+        }
+    }
+
+    /// Compile `core`, leaving its value on the stack; in tail position, the
+    /// code returns it instead
+    fn expr(&mut self, core: &Core, tail: bool) {
+        match core {
+            Core::Var(name) => self.load(*name),
+            Core::Const(d) => self.constant(Value::from_datum(d)),
+            Core::Lambda(formals, body) => self.lambda(None, formals, body),
+            Core::If(c, t, e) => {
+                self.expr(c, false);
+                let unless = self.emit(Op::JumpUnless(0));
+                let depth = self.func().depth;
+                self.expr(t, tail);
+                let jump = (!tail).then(|| self.emit(Op::Jump(0)));
+                self.patch(unless);
+                self.func().depth = depth;
+                self.expr(e, tail);
+                if let Some(jump) = jump {
+                    self.patch(jump);
+                }
+                return;
+            }
+            Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+                return self.call(rator, rands, tail)
+            }
+            Core::Let(bs, body) => {
+                for (name, init) in bs {
+                    self.binding(*name, init);
+                }
+                let base = self.func().depth - bs.len() as u32;
+                for (i, (name, _)) in bs.iter().enumerate() {
+                    self.func().locals.push((*name, base + i as u32));
+                }
+                return self.scope(bs.len(), body, tail);
+            }
+            Core::LetRec(bs, body) => {
+                let base = self.func().depth;
+                for (i, (name, _)) in bs.iter().enumerate() {
+                    self.emit(Op::PushCell);
+                    self.func().locals.push((*name, base + i as u32));
+                    self.boxed.insert(*name);
+                }
+                for (i, (name, init)) in bs.iter().enumerate() {
+                    self.binding(*name, init);
+                    self.emit(Op::SetCell(base + i as u32));
+                }
+                return self.scope(bs.len(), body, tail);
+            }
+            Core::Begin(exprs) => match exprs.split_last() {
+                None => self.constant(Value::Unspecified),
+                Some((last, init)) => {
+                    for expr in init {
+                        self.expr(expr, false);
+                        self.emit(Op::Pop);
+                    }
+                    return self.expr(last, tail);
+                }
+            },
+            Core::The(ty, expr) => {
+                self.expr(expr, false);
+                self.emit(Op::Check(*ty));
+            }
+            Core::Define(name, expr) => {
+                self.binding(*name, expr);
+                let i = self.global(*name);
+                self.emit(Op::DefGlobal(i));
+                self.constant(Value::Unspecified);
+            }
+        }
+        if tail {
+            self.emit(Op::Return);
+        }
+    }
+
+    /// Compile the value bound to `name`, which names it if it's a procedure
+    fn binding(&mut self, name: Symbol, init: &Core) {
+        match init {
+            Core::Lambda(formals, body) => {
+                self.lambda(Some(source_name(&name).to_owned()), formals, body)
+            }
+            other => self.expr(other, false),
+        }
+    }
+
+    /// Compile the body of a `let` or `letrec` whose `n` locals were just
+    /// pushed, and drop them again
+    fn scope(&mut self, n: usize, body: &Core, tail: bool) {
+        self.expr(body, tail);
+        let func = self.func();
+        func.locals.truncate(func.locals.len() - n);
+        if !tail {
+            self.emit(Op::Slide(n as u32));
+        }
+    }
+
+    fn call(&mut self, rator: &Core, rands: &[Core], tail: bool) {
+        if let Core::Var(name) = rator {
+            // primitives calling back into procedures, like `apply`, need to
+            // go through the calling convention; so does any call with the
+            // wrong arity, to report it
+            if let Some(p) = self
+                .prim(*name)
+                .filter(|p| !prim::calls_back(p) && p.arity.accepts(rands.len()))
+            {
+                for rand in rands {
+                    self.expr(rand, false);
+                }
+                let op = fast_op(p.name, rands.len()).unwrap_or_else(|| {
+                    let i = PRIMS.iter().position(|q| q.name == p.name).unwrap();
+                    Op::Prim(i as u16, rands.len() as u16)
+                });
+                self.emit(op);
+                if tail {
+                    self.emit(Op::Return);
+                }
+                return;
+            }
+        }
+
+        self.expr(rator, false);
+        for rand in rands {
+            self.expr(rand, false);
+        }
+        let n = rands.len() as u32;
+        self.emit(if tail { Op::TailCall(n) } else { Op::Call(n) });
+    }
+
+    fn lambda(&mut self, name: Option<String>, formals: &[Symbol], body: &Core) {
+        let parent = self.func();
+        let mut upvals = Vec::new();
+        let mut captures = Vec::new();
+        for name in free_variables(formals, body) {
+            let capture = match (parent.local(name), parent.upval(name)) {
+                (Some(slot), _) => Capture::Local(slot),
+                (None, Some(i)) => Capture::Upval(i),
+                // a global or primitive
+                (None, None) => continue,
+            };
+            upvals.push(name);
+            captures.push(capture);
+        }
+
+        let mut func = Function::init(name, formals, upvals);
+        func.proto.captures = captures;
+        self.fns.push(func);
+        self.expr(body, true);
+        let func = self
+            .fns
+            .pop()
+            .expect("the `lambda` is still being compiled");
+
+        let protos = &mut self.func().proto.protos;
+        protos.push(Rc::new(func.proto));
+        let i = protos.len() - 1;
+        self.emit(Op::Closure(i as u32));
+    }
+}
@@ -1,11 +1,34 @@
 //! Core special forms
 
-use crate::{eval::EvalError, primsyn::*};
+use std::fmt;
 
-pub enum Core {}
+use crate::{datum::Datum, eval::EvalError, primsyn::*, symbol::Symbol};
+
+/// The handful of forms every derived form in `primsyn` is simplified into;
+/// everything after the expander works on this
+#[derive(Debug, Clone)]
+pub enum Core {
+    Var(Symbol),
+    Const(Datum),
+    Lambda(Vec<Symbol>, Box<Self>),
+    If(Box<Self>, Box<Self>, Box<Self>),
+    Call(Box<Self>, Vec<Self>),
+    /// A call in tail position, see `tail::TailMarker`
+    TailCall(Box<Self>, Vec<Self>),
+    Let(Vec<(Symbol, Self)>, Box<Self>),
+    LetRec(Vec<(Symbol, Self)>, Box<Self>),
+    Begin(Vec<Self>),
+    /// Evaluate to the value of the inner expression, checking it has the
+    /// given type; this is where a type annotation is enforced
+    The(Type, Box<Self>),
+    /// Top-level definition, only ever found directly inside the program `Begin`
+    Define(Symbol, Box<Self>),
+}
 
 #[derive(Debug)]
-pub enum CoreError {}
+pub enum CoreError {
+    UnquoteOutsideQuasi(Datum),
+}
 
 impl From<CoreError> for EvalError {
     fn from(value: CoreError) -> Self {
@@ -15,14 +38,313 @@ impl From<CoreError> for EvalError {
 
 pub type CoreFormError<T> = Result<T, CoreError>;
 
-pub struct CoreFormer {}
+/// Turn the derived forms of `primsyn` into `Core`, naming the temporaries
+/// it has to introduce with uninterned symbols
+pub struct CoreFormer;
 
 impl CoreFormer {
     pub fn init() -> Self {
-        Self {}
+        Self
+    }
+
+    /// Generate a name which can't clash with anything the program names, as
+    /// the reader only makes interned symbols
+    fn fresh(&mut self, prefix: &str) -> Symbol {
+        Symbol::uninterned(prefix)
+    }
+
+    /// Simplify a whole program into a single `Core::Begin` of its statements
+    pub fn simplify(&mut self, stmts: &[Stmt]) -> CoreFormError<Core> {
+        let mut forms = Vec::new();
+        for stmt in stmts {
+            match stmt {
+                Stmt::Def(Def::DefValue(name, expr)) => {
+                    forms.push(Core::Define(*name, Box::new(self.simplify_expr(expr)?)))
+                }
+                Stmt::Def(Def::DefFunc(name, formals, body)) => forms.push(Core::Define(
+                    *name,
+                    Box::new(self.simplify_lambda(formals, body)?),
+                )),
+                Stmt::Def(Def::DefRecord(name, fields)) => {
+                    forms.extend(self.simplify_record(*name, fields))
+                }
+                Stmt::Expr(expr) => forms.push(self.simplify_expr(expr)?),
+            }
+        }
+        Ok(Core::Begin(forms))
+    }
+
+    /// `(define-record point (x y))` defines `make-point`, `point?`, `point-x`
+    /// and `point-y` on top of the `%record` primitives
+    fn simplify_record(&mut self, name: Symbol, fields: &[Symbol]) -> Vec<Core> {
+        let tag = || Core::Const(Datum::Symbol(name));
+        let mut defs = Vec::new();
+
+        let mut make_args = vec![tag()];
+        make_args.extend(fields.iter().map(|f| Core::Var(*f)));
+        defs.push(Core::Define(
+            Symbol::intern(&format!("make-{name}")),
+            Box::new(Core::Lambda(
+                fields.to_vec(),
+                Box::new(prim_call("%make-record", make_args)),
+            )),
+        ));
+
+        let obj = self.fresh("obj");
+        defs.push(Core::Define(
+            Symbol::intern(&format!("{name}?")),
+            Box::new(Core::Lambda(
+                vec![obj],
+                Box::new(prim_call("%record?", vec![Core::Var(obj), tag()])),
+            )),
+        ));
+
+        for (i, field) in fields.iter().enumerate() {
+            let obj = self.fresh("obj");
+            defs.push(Core::Define(
+                Symbol::intern(&format!("{name}-{field}")),
+                Box::new(Core::Lambda(
+                    vec![obj],
+                    Box::new(prim_call(
+                        "%record-ref",
+                        vec![Core::Var(obj), tag(), Core::Const(Datum::Fixnum(i as i32))],
+                    )),
+                )),
+            ));
+        }
+
+        defs
+    }
+
+    /// Annotated parameters are checked as soon as the procedure is entered,
+    /// `(lambda ([x : Fixnum]) e)` becoming `(lambda (x) (begin (the Fixnum x) e))`
+    fn simplify_lambda(&mut self, formals: &[Formal], body: &Expr) -> CoreFormError<Core> {
+        let mut exprs: Vec<Core> = formals
+            .iter()
+            .filter_map(|(name, ty)| Some(Core::The((*ty)?, Box::new(Core::Var(*name)))))
+            .collect();
+        let names = formals.iter().map(|(name, _)| *name).collect();
+        let body = self.simplify_expr(body)?;
+        let body = if exprs.is_empty() {
+            body
+        } else {
+            exprs.push(body);
+            Core::Begin(exprs)
+        };
+        Ok(Core::Lambda(names, Box::new(body)))
+    }
+
+    fn simplify_seq(&mut self, seq: &[Expr]) -> CoreFormError<Core> {
+        let mut exprs = Vec::new();
+        for expr in seq {
+            exprs.push(self.simplify_expr(expr)?)
+        }
+        match exprs.len() {
+            0 => Ok(Core::Const(Datum::Undefined)),
+            1 => Ok(exprs.pop().unwrap()),
+            _ => Ok(Core::Begin(exprs)),
+        }
     }
 
-    pub fn simplify(&self, stmst: &[Stmt]) -> CoreFormError<Core> {
-        todo!()
+    fn simplify_bindings(&mut self, bs: &[BindingSpec]) -> CoreFormError<Vec<(Symbol, Core)>> {
+        let mut bindings = Vec::new();
+        for (name, expr) in bs {
+            bindings.push((*name, self.simplify_expr(expr)?))
+        }
+        Ok(bindings)
+    }
+
+    fn simplify_expr(&mut self, expr: &Expr) -> CoreFormError<Core> {
+        match expr {
+            Expr::Symbol(s) => Ok(Core::Var(*s)),
+            Expr::Bool(b) => Ok(Core::Const(Datum::Bool(*b))),
+            Expr::Fixnum(f) => Ok(Core::Const(Datum::Fixnum(*f))),
+            Expr::Vector(v) => Ok(Core::Const(Datum::Vector(v.clone()))),
+            Expr::Char(c) => Ok(Core::Const(Datum::Char(*c))),
+            Expr::Str(s) => Ok(Core::Const(Datum::Str(s.clone()))),
+            Expr::Quote(d) => Ok(Core::Const(d.clone())),
+            Expr::Unquote(d) => Err(CoreError::UnquoteOutsideQuasi(d.clone())),
+            Expr::ProcCall(rator, rands) => {
+                let rator = self.simplify_expr(rator)?;
+                let mut args = Vec::new();
+                for rand in rands {
+                    args.push(self.simplify_expr(rand)?)
+                }
+                Ok(Core::Call(Box::new(rator), args))
+            }
+            Expr::Lambda(formals, body) => self.simplify_lambda(formals, body),
+            Expr::If(c, t, e) => Ok(Core::If(
+                Box::new(self.simplify_expr(c)?),
+                Box::new(self.simplify_expr(t)?),
+                Box::new(self.simplify_expr(e)?),
+            )),
+            Expr::Cond(branches, r#else) => {
+                let mut acc = self.simplify_expr(r#else)?;
+                for (test, body) in branches.iter().rev() {
+                    acc = Core::If(
+                        Box::new(self.simplify_expr(test)?),
+                        Box::new(self.simplify_expr(body)?),
+                        Box::new(acc),
+                    );
+                }
+                Ok(acc)
+            }
+            Expr::Case(key, branches, r#else) => {
+                let tmp = self.fresh("key");
+                let mut acc = self.simplify_seq(r#else)?;
+                for (data, body) in branches.iter().rev() {
+                    let tests = data
+                        .iter()
+                        .map(|d| prim_call("eqv?", vec![Core::Var(tmp), Core::Const(d.clone())]))
+                        .collect();
+                    acc = Core::If(
+                        Box::new(or_chain(tests)),
+                        Box::new(self.simplify_seq(body)?),
+                        Box::new(acc),
+                    );
+                }
+                Ok(Core::Let(
+                    vec![(tmp, self.simplify_expr(key)?)],
+                    Box::new(acc),
+                ))
+            }
+            Expr::And(exprs) => {
+                let mut iter = exprs.iter().rev();
+                let mut acc = match iter.next() {
+                    Some(last) => self.simplify_expr(last)?,
+                    None => return Ok(Core::Const(Datum::Bool(true))),
+                };
+                for expr in iter {
+                    acc = Core::If(
+                        Box::new(self.simplify_expr(expr)?),
+                        Box::new(acc),
+                        Box::new(Core::Const(Datum::Bool(false))),
+                    );
+                }
+                Ok(acc)
+            }
+            Expr::Or(exprs) => {
+                let mut iter = exprs.iter().rev();
+                let mut acc = match iter.next() {
+                    Some(last) => self.simplify_expr(last)?,
+                    None => return Ok(Core::Const(Datum::Bool(false))),
+                };
+                for expr in iter {
+                    let tmp = self.fresh("or");
+                    acc = Core::Let(
+                        vec![(tmp, self.simplify_expr(expr)?)],
+                        Box::new(Core::If(
+                            Box::new(Core::Var(tmp)),
+                            Box::new(Core::Var(tmp)),
+                            Box::new(acc),
+                        )),
+                    );
+                }
+                Ok(acc)
+            }
+            Expr::When(test, body) => Ok(Core::If(
+                Box::new(self.simplify_expr(test)?),
+                Box::new(self.simplify_seq(body)?),
+                Box::new(Core::Const(Datum::Undefined)),
+            )),
+            Expr::Unless(test, body) => Ok(Core::If(
+                Box::new(self.simplify_expr(test)?),
+                Box::new(Core::Const(Datum::Undefined)),
+                Box::new(self.simplify_seq(body)?),
+            )),
+            Expr::Let(bs, body) => Ok(Core::Let(
+                self.simplify_bindings(bs)?,
+                Box::new(self.simplify_expr(body)?),
+            )),
+            Expr::LetRec(bs, body) => Ok(Core::LetRec(
+                self.simplify_bindings(bs)?,
+                Box::new(self.simplify_expr(body)?),
+            )),
+            Expr::Begin(seq) => self.simplify_seq(seq),
+        }
+    }
+}
+
+/// Call a primitive by name
+pub fn prim_call(name: &str, args: Vec<Core>) -> Core {
+    Core::Call(Box::new(Core::Var(Symbol::intern(name))), args)
+}
+
+/// `(or t ...)` for tests without side effects, so no temporaries are needed
+fn or_chain(mut tests: Vec<Core>) -> Core {
+    let mut acc = match tests.pop() {
+        Some(last) => last,
+        None => return Core::Const(Datum::Bool(false)),
+    };
+    while let Some(test) = tests.pop() {
+        acc = Core::If(
+            Box::new(test),
+            Box::new(Core::Const(Datum::Bool(true))),
+            Box::new(acc),
+        );
+    }
+    acc
+}
+
+fn write_all(f: &mut fmt::Formatter<'_>, cs: &[Core]) -> fmt::Result {
+    for c in cs {
+        write!(f, " {c}")?;
+    }
+    Ok(())
+}
+
+fn write_bindings(f: &mut fmt::Formatter<'_>, bs: &[(Symbol, Core)]) -> fmt::Result {
+    write!(f, "(")?;
+    for (i, (name, init)) in bs.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "[{name} {init}]")?;
+    }
+    write!(f, ")")
+}
+
+/// Print `Core` as an s-expression, for dumping the tree between passes
+impl fmt::Display for Core {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Var(name) => write!(f, "{name}"),
+            Self::Const(d @ (Datum::List(_) | Datum::DottedList(..) | Datum::Symbol(_))) => {
+                write!(f, "'{d}")
+            }
+            Self::Const(d) => write!(f, "{d}"),
+            Self::Lambda(formals, body) => {
+                let formals: Vec<&str> = formals.iter().map(|f| f.as_str()).collect();
+                write!(f, "(lambda ({}) {body})", formals.join(" "))
+            }
+            Self::If(c, t, e) => write!(f, "(if {c} {t} {e})"),
+            Self::Call(rator, rands) => {
+                write!(f, "({rator}")?;
+                write_all(f, rands)?;
+                write!(f, ")")
+            }
+            Self::TailCall(rator, rands) => {
+                write!(f, "(tail-call {rator}")?;
+                write_all(f, rands)?;
+                write!(f, ")")
+            }
+            Self::Let(bs, body) => {
+                write!(f, "(let ")?;
+                write_bindings(f, bs)?;
+                write!(f, " {body})")
+            }
+            Self::LetRec(bs, body) => {
+                write!(f, "(letrec ")?;
+                write_bindings(f, bs)?;
+                write!(f, " {body})")
+            }
+            Self::Begin(exprs) => {
+                write!(f, "(begin")?;
+                write_all(f, exprs)?;
+                write!(f, ")")
+            }
+            Self::The(ty, expr) => write!(f, "(the {} {expr})", ty.name()),
+            Self::Define(name, expr) => write!(f, "(define {name} {expr})"),
+        }
     }
 }
@@ -1,5 +1,13 @@
 //! Module specifying the results of a the `read` function, following R7RS standards
 
+use std::fmt;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
+use crate::number;
+use crate::symbol::Symbol;
+
 /// The result of the `read::Read` function
 #[derive(Debug, Clone)]
 pub enum Datum {
@@ -9,11 +17,16 @@ pub enum Datum {
     Char(char),
     DottedList(Vec<Self>, Box<Self>),
     Fixnum(i32),
+    /// An integer too big to be a fixnum
+    Big(BigInt),
+    /// A ratio of integers, in lowest terms, which isn't a whole number
+    Ratio(Box<BigRational>),
+    Flonum(f64),
     Label(u32),
     List(Vec<Self>),
     Set(u32, Box<Self>),
     Str(String),
-    Symbol(String),
+    Symbol(Symbol),
     Vector(Vec<Self>),
     Ellipses,
     Null,
@@ -50,9 +63,16 @@ impl Datum {
         }
     }
 
+    pub fn get_symbol(&self) -> Symbol {
+        match self {
+            Self::Symbol(s) => *s,
+            _ => unreachable!(),
+        }
+    }
+
     pub fn get_symbol_name(&self) -> String {
         match self {
-            Self::Symbol(s) => s.to_owned(),
+            Self::Symbol(s) => s.to_string(),
             _ => unreachable!(),
         }
     }
@@ -65,3 +85,72 @@ pub enum AbbrevPrefix {
     Comma,
     CommaAt,
 }
+
+fn write_seq(f: &mut fmt::Formatter<'_>, ds: &[Datum]) -> fmt::Result {
+    for (i, d) in ds.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{d}")?;
+    }
+    Ok(())
+}
+
+/// Print a `Datum` back out the way `write` would
+impl fmt::Display for Datum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Quote(prefix, d) => {
+                let prefix = match prefix {
+                    AbbrevPrefix::Quote => "'",
+                    AbbrevPrefix::Quasi => "`",
+                    AbbrevPrefix::Comma => ",",
+                    AbbrevPrefix::CommaAt => ",@",
+                };
+                write!(f, "{prefix}{d}")
+            }
+            Self::Bool(true) => write!(f, "#t"),
+            Self::Bool(false) => write!(f, "#f"),
+            Self::ByteVector(bs) => {
+                write!(f, "#u8(")?;
+                for (i, b) in bs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{b}")?;
+                }
+                write!(f, ")")
+            }
+            Self::Char(' ') => write!(f, "#\\space"),
+            Self::Char('\n') => write!(f, "#\\newline"),
+            Self::Char(c) => write!(f, "#\\{c}"),
+            Self::DottedList(ds, tl) => {
+                write!(f, "(")?;
+                write_seq(f, ds)?;
+                write!(f, " . {tl})")
+            }
+            Self::Fixnum(n) => write!(f, "{n}"),
+            Self::Big(n) => write!(f, "{n}"),
+            Self::Ratio(r) => write!(f, "{r}"),
+            Self::Flonum(x) => write!(f, "{}", number::flonum_to_string(*x)),
+            Self::Label(n) => write!(f, "#{n}#"),
+            Self::List(ds) => {
+                write!(f, "(")?;
+                write_seq(f, ds)?;
+                write!(f, ")")
+            }
+            Self::Set(n, d) => write!(f, "#{n}={d}"),
+            Self::Str(s) => write!(f, "{s:?}"),
+            Self::Symbol(s) => write!(f, "{s}"),
+            Self::Vector(ds) => {
+                write!(f, "#(")?;
+                write_seq(f, ds)?;
+                write!(f, ")")
+            }
+            Self::Ellipses => write!(f, "..."),
+            Self::Null => write!(f, "()"),
+            Self::Undefined => write!(f, "#<unspecified>"),
+            Self::Eof => write!(f, "#<eof>"),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Warnings and errors collected by the compiler passes, reported together
+//! once compilation is done
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.level {
+            Level::Warning => write!(f, "warning: {}", self.message),
+            Level::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn init() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    pub fn warn(&mut self, message: String) {
+        self.items.push(Diagnostic {
+            level: Level::Warning,
+            message,
+        })
+    }
+
+    pub fn error(&mut self, message: String) {
+        self.items.push(Diagnostic {
+            level: Level::Error,
+            message,
+        })
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.items.iter().any(|d| d.level == Level::Error)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter()
+    }
+}
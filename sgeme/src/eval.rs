@@ -1,10 +1,28 @@
 //! Convert `Datum` into `Value`
 
-use crate::core_former::{CoreError, CoreFormer};
+use std::fmt;
+use std::fs;
+
+use crate::asmgen::AsmGen;
+use crate::cgen::CGen;
+use crate::check::Checker;
+use crate::closure::Converter;
+#[cfg(feature = "mir")]
+use crate::codegen::CodeGen;
+use crate::core_former::{Core, CoreError, CoreFormer};
 use crate::datum::Datum;
+use crate::diagnostics::Diagnostics;
+use crate::inline::Inliner;
+use crate::optimize::{OptLevel, Optimizer};
 use crate::primsyn::*;
+use crate::resolve::Resolver;
+use crate::tail::TailMarker;
+use crate::types::Types;
+use crate::wasmgen::WasmGen;
+use crate::word::Symbols;
 
-use rs_mir::MIRContext;
+#[cfg(feature = "mir")]
+use rs_mir::{MIRContext, MirError};
 
 pub type EvalResult<T> = Result<T, EvalError>;
 
@@ -12,26 +30,161 @@ pub type EvalResult<T> = Result<T, EvalError>;
 pub enum EvalError {
     UnboundVariable(String),
     Simplify(CoreError),
+    /// Something a backend can't compile yet, named in a phrase
+    Unsupported(String),
+    #[cfg(feature = "mir")]
+    Mir(MirError),
+    /// Writing out the module failed
+    Io(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnboundVariable(name) => write!(f, "unbound variable `{name}`"),
+            Self::Simplify(CoreError::UnquoteOutsideQuasi(d)) => {
+                write!(f, "`unquote` outside of `quasiquote`: `{d}`")
+            }
+            Self::Unsupported(what) => write!(f, "unsupported: {what}"),
+            #[cfg(feature = "mir")]
+            Self::Mir(e) => write!(f, "{e}"),
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Options for the passes between the expander and a backend:
+/// + `opt_level`: see `optimize::OptLevel`
+/// + `dump_core`: print the `Core` tree before and after optimisation
+/// + `inline_size`: the largest procedure body `inline::Inliner` copies at `O2`
+/// + `dump_mir`: print the MIR module made by `Evaluator::compile_program`
+/// + `emit_mir`: write that module to a file, in MIR's binary format if its
+///   name ends in `.bmir` and as text otherwise
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub opt_level: OptLevel,
+    pub dump_core: bool,
+    pub inline_size: usize,
+    pub dump_mir: bool,
+    pub emit_mir: Option<String>,
+}
+
+impl Options {
+    pub fn init() -> Self {
+        Self {
+            opt_level: OptLevel::O1,
+            dump_core: false,
+            inline_size: 16,
+            dump_mir: false,
+            emit_mir: None,
+        }
+    }
+}
+
+fn dump(title: &str, core: &Core) {
+    eprintln!(";; {title}");
+    match core {
+        Core::Begin(forms) => forms.iter().for_each(|f| eprintln!("{f}")),
+        _ => eprintln!("{core}"),
+    }
+}
+
+/// Run the statements of a program through every pass up to the point where
+/// a backend takes over, collecting any warnings into `diags`. Alongside the
+/// final tree comes what inference found out about the types in it.
+pub fn core_program(
+    prgrm: &Program,
+    opts: &Options,
+    diags: &mut Diagnostics,
+) -> EvalResult<(Core, Types)> {
+    let mut core_former = CoreFormer::init();
+    let core = core_former.simplify(&prgrm.stmts)?;
+    let mut resolver = Resolver::init();
+    let mut core = resolver.resolve(core);
+    Checker::init(diags).check(&core);
+
+    if opts.dump_core {
+        dump("before optimisation", &core);
+    }
+    if opts.opt_level >= OptLevel::O2 {
+        core = Inliner::init(&mut resolver, opts.inline_size).inline(core);
+    }
+    let mut core = Optimizer::init(opts.opt_level).optimize(core);
+    if opts.dump_core {
+        dump(&format!("after optimisation ({:?})", opts.opt_level), &core);
+    }
+
+    TailMarker::init().mark(&mut core);
+    let types = Types::infer(&core, diags);
+    Ok((core, types))
 }
 
+/// Compile a program into a C translation unit, runtime included
+pub fn c_program(prgrm: &Program, opts: &Options, diags: &mut Diagnostics) -> EvalResult<String> {
+    let (core, types) = core_program(prgrm, opts, diags)?;
+    let program = Converter::init().convert(&core)?;
+    CGen::init(&types).generate(&program)
+}
+
+/// Compile a program into x86-64 assembly, runtime included
+pub fn asm_program(prgrm: &Program, opts: &Options, diags: &mut Diagnostics) -> EvalResult<String> {
+    let (core, types) = core_program(prgrm, opts, diags)?;
+    let program = Converter::init().convert(&core)?;
+    AsmGen::init(&types).generate(&program)
+}
+
+/// Compile a program into the text of a WebAssembly module, along with
+/// what running it needs to describe its values
+pub fn wasm_program(
+    prgrm: &Program,
+    opts: &Options,
+    diags: &mut Diagnostics,
+) -> EvalResult<(String, Symbols)> {
+    let (core, types) = core_program(prgrm, opts, diags)?;
+    let program = Converter::init().convert(&core)?;
+    WasmGen::init(&types).generate(&program)
+}
+
+#[cfg(feature = "mir")]
 pub struct Evaluator<'a> {
     ctx: &'a mut MIRContext,
+    opts: Options,
+    pub diagnostics: Diagnostics,
 }
 
+#[cfg(feature = "mir")]
 impl<'a> Evaluator<'a> {
-    pub fn init(ctx: &'a mut MIRContext) -> Self {
-        Self { ctx }
+    pub fn init(ctx: &'a mut MIRContext, opts: Options) -> Self {
+        Self {
+            ctx,
+            opts,
+            diagnostics: Diagnostics::init(),
+        }
     }
 
-    /// Take some `primsyn::Program`, and evaluate using the `rs-mir` crate
-    pub fn compile_program(&mut self, prgrm: &Program) -> EvalResult<()> {
+    /// Take some `primsyn::Program`, and compile it into a MIR module in the
+    /// context, exporting `main`. What comes back is what `runtime::run`
+    /// needs to describe its values.
+    pub fn compile_program(&mut self, prgrm: &Program) -> EvalResult<Symbols> {
         // TODO: figure out imports!
         let _imports: &[Import] = &prgrm.imports;
-        let stmts = &prgrm.stmts;
-
-        let core_former = CoreFormer::init();
-        let core_stmts = core_former.simplify(stmts)?;
+        let (core, types) = core_program(prgrm, &self.opts, &mut self.diagnostics)?;
 
-        Ok(())
+        let program = Converter::init().convert(&core)?;
+        let (module, symbols) = CodeGen::init(&types).generate(&program)?;
+        if self.opts.dump_mir {
+            eprint!("{module}");
+        }
+        let text = module.to_string();
+        self.ctx.add_module(module).map_err(EvalError::Mir)?;
+        if let Some(path) = &self.opts.emit_mir {
+            let res = if path.ends_with(".bmir") {
+                fs::write(path, self.ctx.write())
+            } else {
+                fs::write(path, text)
+            };
+            res.map_err(|e| EvalError::Io(format!("{path}: {e}")))?;
+        }
+        Ok(symbols)
     }
 }
@@ -2,9 +2,11 @@
 //! and then transform `Datum` into the new forms
 
 use std::collections::HashMap;
+use std::fmt;
 
 use crate::datum::*;
 use crate::primsyn::*;
+use crate::symbol::Symbol;
 
 /// Syntax expander, with accompanying primitive
 /// syntax expansions for bootstrapping
@@ -19,9 +21,26 @@ pub enum ExpanderError {
     CondElseExpected(String),
     IllegalContext(String),
     StringExpected(String),
+    TypeExpected(String),
     UnexpectedEof,
 }
 
+impl fmt::Display for ExpanderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IllegalNonatomic(s) => write!(f, "illegal non-atomic form: {s}"),
+            Self::IllegalNumberOfArgs(s) => write!(f, "wrong number of arguments: {s}"),
+            Self::IdentifierExpected(s) => write!(f, "identifier expected: {s}"),
+            Self::ListExpected(s) => write!(f, "list expected: {s}"),
+            Self::CondElseExpected(s) => write!(f, "`else` expected: {s}"),
+            Self::IllegalContext(s) => write!(f, "not allowed here: {s}"),
+            Self::StringExpected(s) => write!(f, "string expected: {s}"),
+            Self::TypeExpected(s) => write!(f, "type expected: {s}"),
+            Self::UnexpectedEof => write!(f, "unexpected end of input"),
+        }
+    }
+}
+
 pub type ExpanderResult<T> = Result<T, ExpanderError>;
 
 fn split_three<T>(ls: &[T]) -> Option<(&T, &T, &[T])> {
@@ -51,6 +70,7 @@ impl Expander {
         match d {
             Datum::Bool(b) => Ok(Expr::Bool(*b)),
             Datum::Fixnum(f) => Ok(Expr::Fixnum(*f)),
+            Datum::Big(_) | Datum::Ratio(_) | Datum::Flonum(_) => Ok(Expr::Quote(d.clone())),
             Datum::Char(c) => Ok(Expr::Char(*c)),
             Datum::Vector(v) => Ok(Expr::Vector(v.clone())),
             Datum::Str(s) => Ok(Expr::Str(s.clone())),
@@ -60,7 +80,7 @@ impl Expander {
                 AbbrevPrefix::Quote => Ok(Expr::Quote(*datum.clone())),
                 _ => todo!(),
             },
-            Datum::Symbol(s) => Ok(Expr::Symbol(s.clone())),
+            Datum::Symbol(s) => Ok(Expr::Symbol(*s)),
             Datum::List(ds) => match ds.split_first() {
                 Some((head, tail)) => match head {
                     Datum::Symbol(s) => match s.as_ref() {
@@ -84,6 +104,10 @@ impl Expander {
                         "let" => Ok(self.expand_let(tail)?),
                         "letrec" => Ok(self.expand_letrec(tail)?),
                         "begin" => Ok(self.expand_begin(tail)?),
+                        "guard" => Ok(self.expand_guard(tail)?),
+                        "delay" => Ok(self.expand_delay(tail)?),
+                        "delay-force" => Ok(self.expand_delay_force(tail)?),
+                        "parameterize" => Ok(self.expand_parameterize(tail)?),
                         _ => {
                             let rator = self.expand_expr(head)?;
                             let mut rand = Vec::new();
@@ -119,27 +143,20 @@ impl Expander {
             }
 
             match formals {
-                Datum::List(ls) => {
-                    if ls.iter().all(|x| x.is_symbol()) {
-                        let names: Vec<String> = ls.iter().map(|x| x.get_symbol_name()).collect();
-                        if let Some((name, formals)) = names.split_first() {
-                            Ok(Def::DefFunc(
-                                name.to_owned(),
-                                formals.into(),
-                                self.expand_expr(body)?,
-                            ))
-                        } else {
-                            return Err(ExpanderError::ListExpected(
-                                "(define (<ident>+) ...) ; we need names".into(),
-                            ));
-                        }
-                    } else {
-                        Err(ExpanderError::IdentifierExpected(
-                            "(define (<ident>+) <expr>) ; pls".into(),
-                        ))
-                    }
-                }
-                Datum::Symbol(name) => Ok(Def::DefValue(name.to_string(), self.expand_expr(body)?)),
+                Datum::List(ls) => match ls.split_first() {
+                    Some((Datum::Symbol(name), formals)) => Ok(Def::DefFunc(
+                        *name,
+                        self.expand_formals(formals, "(define (<ident> <formal>*) <expr>) ; pls")?,
+                        self.expand_expr(body)?,
+                    )),
+                    Some(_) => Err(ExpanderError::IdentifierExpected(
+                        "(define (<ident> <formal>*) <expr>) ; pls".into(),
+                    )),
+                    None => Err(ExpanderError::ListExpected(
+                        "(define (<ident>+) ...) ; we need names".into(),
+                    )),
+                },
+                Datum::Symbol(name) => Ok(Def::DefValue(*name, self.expand_expr(body)?)),
                 _ => Err(ExpanderError::IdentifierExpected(
                     "(define <ident> <expr>) OR (define (<ident>+) <expr>) ; pls".into(),
                 )),
@@ -164,9 +181,9 @@ impl Expander {
                                 match mems {
                                     Datum::List(ms) => {
                                         if ms.iter().all(|x| x.is_symbol()) {
-                                            let name = head.get_symbol_name();
+                                            let name = head.get_symbol();
                                             let members =
-                                                ms.iter().map(|x| x.get_symbol_name()).collect();
+                                                ms.iter().map(|x| x.get_symbol()).collect();
                                             Ok(Def::DefRecord(name, members))
                                         } else {
                                             Err(ExpanderError::IdentifierExpected(
@@ -226,16 +243,44 @@ impl Expander {
         }
     }
 
+    /// Expand the parameters of a `lambda` or `define`, each of which is either
+    /// `<ident>` or annotated with its type as `[<ident> : <type>]`
+    fn expand_formals(&self, ds: &[Datum], usage: &str) -> ExpanderResult<Vec<Formal>> {
+        let mut formals = Vec::new();
+        for datum in ds {
+            match datum {
+                Datum::Symbol(name) => formals.push((*name, None)),
+                Datum::List(ann) => match ann.as_slice() {
+                    [Datum::Symbol(name), Datum::Symbol(colon), Datum::Symbol(ty)]
+                        if colon == ":" =>
+                    {
+                        match Type::parse(ty) {
+                            Some(ty) => formals.push((*name, Some(ty))),
+                            None => {
+                                return Err(ExpanderError::TypeExpected(format!(
+                                    "[<ident> : <type>] ; `{ty}` isn't a type"
+                                )))
+                            }
+                        }
+                    }
+                    _ => {
+                        return Err(ExpanderError::IdentifierExpected(
+                            "[<ident> : <type>] ; annotated parameter expected".into(),
+                        ))
+                    }
+                },
+                _ => return Err(ExpanderError::IdentifierExpected(usage.into())),
+            }
+        }
+        Ok(formals)
+    }
+
     /// Expand a datum of the form `(lambda (...) ...)` to `primsyn::Expr::Lambda`
     fn expand_lambda(&self, ds: &[Datum]) -> ExpanderResult<Expr> {
         match ds.split_first() {
             Some((head, tail)) => match head {
                 Datum::List(fs) => {
-                    if !fs.iter().all(|x| x.is_symbol()) {
-                        return Err(ExpanderError::IdentifierExpected(
-                            "(lambda (<ident>*) <expr>)".into(),
-                        ));
-                    }
+                    let formals = self.expand_formals(fs, "(lambda (<formal>*) <expr>)")?;
 
                     match tail.split_first() {
                         Some((x, xs)) => {
@@ -245,7 +290,6 @@ impl Expander {
                                 ));
                             }
 
-                            let formals = fs.iter().map(|x| x.get_symbol_name()).collect();
                             let body = self.expand_expr(x)?;
 
                             Ok(Expr::Lambda(formals, Box::new(body)))
@@ -374,7 +418,7 @@ impl Expander {
                 if let Some((head, tail)) = ls.split_first() {
                     if let Datum::List(data) = head {
                         let mut exprs = Vec::new();
-                        for d in data {
+                        for d in tail {
                             exprs.push(self.expand_expr(d)?)
                         }
                         branches.push((data.clone(), exprs))
@@ -395,11 +439,18 @@ impl Expander {
 
     fn expand_case_else(&self, d: &Datum) -> ExpanderResult<Sequence> {
         if let Datum::List(ds) = d {
-            let mut exprs = Vec::new();
-            for datum in ds {
-                exprs.push(self.expand_expr(datum)?);
+            match ds.split_first() {
+                Some((Datum::Symbol(s), tail)) if s == "else" => {
+                    let mut exprs = Vec::new();
+                    for datum in tail {
+                        exprs.push(self.expand_expr(datum)?);
+                    }
+                    Ok(exprs)
+                }
+                _ => Err(ExpanderError::CondElseExpected(
+                    "(else <sequence>) ; else expected in final case branch".into(),
+                )),
             }
-            Ok(exprs)
         } else {
             Err(ExpanderError::ListExpected(
                 "(else <sequence>) ; list expected as else case clause".into(),
@@ -412,7 +463,7 @@ impl Expander {
         if let Some((analysand, branches)) = ds.split_first() {
             if let Some((last, init)) = branches.split_last() {
                 let analysand_expr = self.expand_expr(analysand)?;
-                let branches = self.expand_case_branches(branches)?;
+                let branches = self.expand_case_branches(init)?;
                 let r#else = self.expand_case_else(last)?;
                 Ok(Expr::Case(Box::new(analysand_expr), branches, r#else))
             } else {
@@ -490,7 +541,7 @@ impl Expander {
                                 let name = r#as[0].clone();
                                 let assign = r#as[1].clone();
                                 if name.is_symbol() {
-                                    let name_sym = name.get_symbol_name();
+                                    let name_sym = name.get_symbol();
                                     let assign_expr = self.expand_expr(&assign)?;
                                     branch_assignments.push((name_sym, assign_expr))
                                 } else {
@@ -544,7 +595,7 @@ impl Expander {
                                 let name = r#as[0].clone();
                                 let assign = r#as[1].clone();
                                 if name.is_symbol() {
-                                    let name_sym = name.get_symbol_name();
+                                    let name_sym = name.get_symbol();
                                     let assign_expr = self.expand_expr(&assign)?;
                                     branch_assignments.push((name_sym, assign_expr))
                                 } else {
@@ -590,6 +641,212 @@ impl Expander {
         Ok(Expr::Begin(exprs))
     }
 
+    /// Rewrite the `cond`-like clauses of a `guard` into nested `if`s, ending
+    /// in `otherwise` when no clause applies
+    fn guard_clauses(&self, clauses: &[Datum], otherwise: Datum) -> ExpanderResult<Datum> {
+        let sym = |s: &str| Datum::Symbol(Symbol::intern(s));
+        let begin = |body: &[Datum]| {
+            let mut ds = vec![sym("begin")];
+            ds.extend_from_slice(body);
+            Datum::List(ds)
+        };
+
+        let mut rest = otherwise;
+        for clause in clauses.iter().rev() {
+            let Datum::List(parts) = clause else {
+                return Err(ExpanderError::ListExpected(
+                    "(guard (<ident> <clause> ...) <expr> ...) ; guard clauses are lists".into(),
+                ));
+            };
+            rest = match parts.as_slice() {
+                [] => {
+                    return Err(ExpanderError::IllegalNumberOfArgs(
+                        "(<expr> <expr> ...) ; empty guard clause".into(),
+                    ))
+                }
+                [Datum::Symbol(s), body @ ..] if *s == "else" && !body.is_empty() => begin(body),
+                [test] => {
+                    let t = Datum::Symbol(Symbol::uninterned("t"));
+                    Datum::List(vec![
+                        sym("let"),
+                        Datum::List(vec![Datum::List(vec![t.clone(), test.clone()])]),
+                        Datum::List(vec![sym("if"), t.clone(), t, rest]),
+                    ])
+                }
+                [test, Datum::Symbol(s), f] if *s == "=>" => {
+                    let t = Datum::Symbol(Symbol::uninterned("t"));
+                    Datum::List(vec![
+                        sym("let"),
+                        Datum::List(vec![Datum::List(vec![t.clone(), test.clone()])]),
+                        Datum::List(vec![
+                            sym("if"),
+                            t.clone(),
+                            Datum::List(vec![f.clone(), t]),
+                            rest,
+                        ]),
+                    ])
+                }
+                [test, body @ ..] => Datum::List(vec![sym("if"), test.clone(), begin(body), rest]),
+            };
+        }
+        Ok(rest)
+    }
+
+    /// Expand a datum of the form `(guard (<ident> <clause> ...) <expr> ...)`,
+    /// as R7RS defines it: the body runs with a handler which goes back out to
+    /// the `guard` to try the clauses, and, if none applies, back in to raise
+    /// the condition again with `raise-continuable`
+    fn expand_guard(&self, ds: &[Datum]) -> ExpanderResult<Expr> {
+        let usage = "(guard (<ident> <clause> ...) <expr> ...)";
+        let Some((Datum::List(spec), body)) = ds.split_first() else {
+            return Err(ExpanderError::ListExpected(usage.into()));
+        };
+        let Some((Datum::Symbol(var), clauses)) = spec.split_first() else {
+            return Err(ExpanderError::IdentifierExpected(usage.into()));
+        };
+        if body.is_empty() {
+            return Err(ExpanderError::IllegalNumberOfArgs(usage.into()));
+        }
+
+        let sym = |s: &str| Datum::Symbol(Symbol::intern(s));
+        let list = Datum::List;
+        let lambda =
+            |formals: Vec<Datum>, body: Datum| list(vec![sym("lambda"), list(formals), body]);
+        let guard_k = Datum::Symbol(Symbol::uninterned("guard-k"));
+        let handler_k = Datum::Symbol(Symbol::uninterned("handler-k"));
+        let condition = Datum::Symbol(Symbol::uninterned("condition"));
+        let v = Datum::Symbol(Symbol::uninterned("v"));
+
+        let reraise = list(vec![
+            handler_k.clone(),
+            lambda(
+                vec![],
+                list(vec![sym("raise-continuable"), condition.clone()]),
+            ),
+        ]);
+        let clauses = list(vec![
+            sym("let"),
+            list(vec![list(vec![Datum::Symbol(*var), condition.clone()])]),
+            self.guard_clauses(clauses, reraise)?,
+        ]);
+        let handler = lambda(
+            vec![condition],
+            list(vec![list(vec![
+                sym("call/cc"),
+                lambda(
+                    vec![handler_k],
+                    list(vec![guard_k.clone(), lambda(vec![], clauses)]),
+                ),
+            ])]),
+        );
+
+        let mut begin = vec![sym("begin")];
+        begin.extend_from_slice(body);
+        let thunk = lambda(
+            vec![],
+            list(vec![
+                sym("let"),
+                list(vec![list(vec![v.clone(), list(begin)])]),
+                list(vec![guard_k.clone(), lambda(vec![], v)]),
+            ]),
+        );
+
+        let expansion = list(vec![list(vec![
+            sym("call/cc"),
+            lambda(
+                vec![guard_k],
+                list(vec![sym("with-exception-handler"), handler, thunk]),
+            ),
+        ])]);
+        self.expand_expr(&expansion)
+    }
+
+    /// Expand a datum of the form `(delay-force <expr>)` into a promise whose
+    /// thunk gives the promise to force in its place
+    fn expand_delay_force(&self, ds: &[Datum]) -> ExpanderResult<Expr> {
+        let [expr] = ds else {
+            return Err(ExpanderError::IllegalNumberOfArgs(
+                "(delay-force <expr>)".into(),
+            ));
+        };
+        let sym = |s: &str| Datum::Symbol(Symbol::intern(s));
+        let thunk = Datum::List(vec![sym("lambda"), Datum::List(vec![]), expr.clone()]);
+        self.expand_expr(&Datum::List(vec![sym("%make-lazy-promise"), thunk]))
+    }
+
+    /// Expand a datum of the form `(delay <expr>)`, which is
+    /// `(delay-force (make-promise <expr>))`
+    fn expand_delay(&self, ds: &[Datum]) -> ExpanderResult<Expr> {
+        let [expr] = ds else {
+            return Err(ExpanderError::IllegalNumberOfArgs("(delay <expr>)".into()));
+        };
+        let make = Datum::List(vec![
+            Datum::Symbol(Symbol::intern("make-promise")),
+            expr.clone(),
+        ]);
+        self.expand_delay_force(&[make])
+    }
+
+    /// Expand a datum of the form `(parameterize ((<expr> <expr>) ...) <expr>
+    /// ...)`: every parameter and value is evaluated, and the values passed
+    /// through the parameters' converters, before the body runs inside one
+    /// `%parameterize` per binding
+    fn expand_parameterize(&self, ds: &[Datum]) -> ExpanderResult<Expr> {
+        let usage = "(parameterize ((<expr> <expr>) ...) <expr> ...)";
+        let Some((Datum::List(bindings), body)) = ds.split_first() else {
+            return Err(ExpanderError::ListExpected(usage.into()));
+        };
+        if body.is_empty() {
+            return Err(ExpanderError::IllegalNumberOfArgs(usage.into()));
+        }
+
+        let sym = |s: &str| Datum::Symbol(Symbol::intern(s));
+        let list = Datum::List;
+        let mut evaluated = Vec::new();
+        let mut converted = Vec::new();
+        let mut bound = Vec::new();
+        for binding in bindings {
+            let Datum::List(pair) = binding else {
+                return Err(ExpanderError::ListExpected(usage.into()));
+            };
+            let [param, value] = pair.as_slice() else {
+                return Err(ExpanderError::IllegalNumberOfArgs(
+                    "(<expr> <expr>) ; a parameter and its value".into(),
+                ));
+            };
+            let p = Datum::Symbol(Symbol::uninterned("param"));
+            let v = Datum::Symbol(Symbol::uninterned("value"));
+            let c = Datum::Symbol(Symbol::uninterned("converter"));
+            let w = Datum::Symbol(Symbol::uninterned("converted"));
+            evaluated.push(list(vec![p.clone(), param.clone()]));
+            evaluated.push(list(vec![v.clone(), value.clone()]));
+            let convert = list(vec![
+                sym("let"),
+                list(vec![list(vec![
+                    c.clone(),
+                    list(vec![sym("%parameter-converter"), p.clone()]),
+                ])]),
+                list(vec![sym("if"), c.clone(), list(vec![c, v.clone()]), v]),
+            ]);
+            converted.push(list(vec![w.clone(), convert]));
+            bound.push((p, w));
+        }
+
+        let mut inner = vec![sym("begin")];
+        inner.extend_from_slice(body);
+        let mut inner = list(inner);
+        for (p, w) in bound.into_iter().rev() {
+            let thunk = list(vec![sym("lambda"), list(vec![]), inner]);
+            inner = list(vec![sym("%parameterize"), p, w, thunk]);
+        }
+        let expansion = list(vec![
+            sym("let"),
+            list(evaluated),
+            list(vec![sym("let"), list(converted), inner]),
+        ]);
+        self.expand_expr(&expansion)
+    }
+
     fn expand_datum(&self, d: &Datum, prgrm: &mut Program) -> ExpanderResult<()> {
         match d {
             Datum::List(ds) => match ds.split_first() {
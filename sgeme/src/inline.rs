@@ -0,0 +1,234 @@
+//! Inline small and single-use procedures at their call sites
+
+use std::collections::{HashMap, HashSet};
+
+use crate::core_former::Core;
+use crate::datum::Datum;
+use crate::optimize::occurrences;
+use crate::prim;
+use crate::resolve::Resolver;
+use crate::symbol::Symbol;
+
+/// How deep inlined bodies may be inlined into in turn, which bounds what
+/// mutually recursive procedures can do to us
+const MAX_DEPTH: usize = 4;
+
+/// Inlines calls to known procedures: top-level definitions and `let`/`letrec`
+/// bindings of a `lambda`, which are either
+/// + small: their body is at most `small` nodes, or
+/// + single-use: bound locally and called from exactly one place.
+///
+/// Small ones are copied, so they mustn't quote anything with an identity,
+/// which every copy would have its own of.
+///
+/// A procedure is never inlined into its own body, and the whole program is
+/// allowed to grow by at most its own size. Calls become `let`s binding the
+/// parameters, for `optimize::Optimizer` to clean up afterwards.
+pub struct Inliner<'a> {
+    resolver: &'a mut Resolver,
+    small: usize,
+    known: HashMap<Symbol, (Vec<Symbol>, Core)>,
+    single_use: HashSet<Symbol>,
+    /// Procedures whose bodies are currently being inlined
+    active: Vec<Symbol>,
+    budget: usize,
+}
+
+impl<'a> Inliner<'a> {
+    pub fn init(resolver: &'a mut Resolver, small: usize) -> Self {
+        Self {
+            resolver,
+            small,
+            known: HashMap::new(),
+            single_use: HashSet::new(),
+            active: Vec::new(),
+            budget: 0,
+        }
+    }
+
+    pub fn inline(&mut self, core: Core) -> Core {
+        let forms = match core {
+            Core::Begin(forms) => forms,
+            other => vec![other],
+        };
+        self.budget = forms.iter().map(size).sum();
+
+        let mut defined: HashMap<Symbol, usize> = HashMap::new();
+        for form in &forms {
+            if let Core::Define(name, _) = form {
+                *defined.entry(*name).or_default() += 1;
+            }
+        }
+        let mut known = Vec::new();
+        for form in &forms {
+            if let Core::Define(name, expr) = form {
+                if defined[name] == 1 {
+                    if let Core::Lambda(formals, body) = expr.as_ref() {
+                        if self.is_small(body) && occurrences(body, *name) == 0 {
+                            known.push((*name, (formals.clone(), *body.clone())));
+                        }
+                    }
+                }
+            }
+        }
+        self.known.extend(known);
+
+        Core::Begin(forms.into_iter().map(|f| self.walk(f)).collect())
+    }
+
+    fn is_small(&self, body: &Core) -> bool {
+        size(body) <= self.small && !quotes_object(body)
+    }
+
+    /// Remember which `let` or `letrec` bound procedures are worth inlining;
+    /// `group` holds the names bound alongside, which they mustn't call
+    fn learn(&mut self, bs: &[(Symbol, Core)], body: &Core, group: &[Symbol]) {
+        for (name, init) in bs {
+            if let Core::Lambda(formals, lbody) = init {
+                if group.iter().any(|g| occurrences(lbody, *g) > 0) {
+                    continue;
+                }
+                let uses = occurrences(body, *name)
+                    + bs.iter().map(|(_, i)| occurrences(i, *name)).sum::<usize>();
+                let single = uses == 1 && called_once(body, *name);
+                if single || self.is_small(lbody) {
+                    if single {
+                        self.single_use.insert(*name);
+                    }
+                    self.known.insert(*name, (formals.clone(), *lbody.clone()));
+                }
+            }
+        }
+    }
+
+    fn walk(&mut self, core: Core) -> Core {
+        match core {
+            Core::Var(_) | Core::Const(_) => core,
+            Core::Lambda(formals, body) => Core::Lambda(formals, Box::new(self.walk(*body))),
+            Core::If(c, t, e) => Core::If(
+                Box::new(self.walk(*c)),
+                Box::new(self.walk(*t)),
+                Box::new(self.walk(*e)),
+            ),
+            Core::Call(rator, rands) => self.walk_call(*rator, rands, false),
+            Core::TailCall(rator, rands) => self.walk_call(*rator, rands, true),
+            Core::Let(bs, body) => {
+                self.learn(&bs, &body, &[]);
+                let bs = bs
+                    .into_iter()
+                    .map(|(name, init)| (name, self.walk(init)))
+                    .collect();
+                Core::Let(bs, Box::new(self.walk(*body)))
+            }
+            Core::LetRec(bs, body) => {
+                let group: Vec<Symbol> = bs.iter().map(|(name, _)| *name).collect();
+                self.learn(&bs, &body, &group);
+                let bs = bs
+                    .into_iter()
+                    .map(|(name, init)| (name, self.walk(init)))
+                    .collect();
+                Core::LetRec(bs, Box::new(self.walk(*body)))
+            }
+            Core::Begin(exprs) => Core::Begin(exprs.into_iter().map(|e| self.walk(e)).collect()),
+            Core::The(ty, expr) => Core::The(ty, Box::new(self.walk(*expr))),
+            Core::Define(name, expr) => {
+                self.active.push(name);
+                let expr = self.walk(*expr);
+                self.active.pop();
+                Core::Define(name, Box::new(expr))
+            }
+        }
+    }
+
+    fn walk_call(&mut self, rator: Core, rands: Vec<Core>, tail: bool) -> Core {
+        let rands: Vec<Core> = rands.into_iter().map(|r| self.walk(r)).collect();
+
+        if let Core::Var(name) = &rator {
+            if let Some((formals, body)) = self.known.get(name) {
+                let cost = size(body);
+                let single = self.single_use.contains(name);
+                if formals.len() == rands.len()
+                    && !self.active.contains(name)
+                    && self.active.len() < MAX_DEPTH
+                    && (single || cost <= self.budget)
+                {
+                    if !single {
+                        self.budget -= cost;
+                    }
+                    let copy = Core::Lambda(formals.clone(), Box::new(body.clone()));
+                    let name = *name;
+                    if let Core::Lambda(formals, body) = self.resolver.resolve(copy) {
+                        self.active.push(name);
+                        let body = self.walk(*body);
+                        self.active.pop();
+                        return Core::Let(formals.into_iter().zip(rands).collect(), Box::new(body));
+                    }
+                }
+            }
+        }
+
+        let rator = self.walk(rator);
+        if tail {
+            Core::TailCall(Box::new(rator), rands)
+        } else {
+            Core::Call(Box::new(rator), rands)
+        }
+    }
+}
+
+/// Does the only reference to `name` in `core` call it? Anything else, like
+/// passing it as an argument, could end up calling it many times
+fn called_once(core: &Core, name: Symbol) -> bool {
+    match core {
+        Core::Var(_) | Core::Const(_) => false,
+        Core::Lambda(_, body) => called_once(body, name),
+        Core::If(c, t, e) => called_once(c, name) || called_once(t, name) || called_once(e, name),
+        Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+            matches!(rator.as_ref(), Core::Var(n) if *n == name)
+                || called_once(rator, name)
+                || rands.iter().any(|r| called_once(r, name))
+        }
+        Core::Let(bs, body) | Core::LetRec(bs, body) => {
+            bs.iter().any(|(_, i)| called_once(i, name)) || called_once(body, name)
+        }
+        Core::Begin(exprs) => exprs.iter().any(|e| called_once(e, name)),
+        Core::The(_, expr) | Core::Define(_, expr) => called_once(expr, name),
+    }
+}
+
+/// Does `core` quote a pair, vector or string, which `eq?` could tell apart
+/// from a copy?
+fn quotes_object(core: &Core) -> bool {
+    match core {
+        // symbols are told apart by name
+        Core::Var(_) | Core::Const(Datum::Symbol(_)) => false,
+        Core::Const(d) => !prim::is_immediate(d),
+        Core::Lambda(_, body) => quotes_object(body),
+        Core::If(c, t, e) => quotes_object(c) || quotes_object(t) || quotes_object(e),
+        Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+            quotes_object(rator) || rands.iter().any(quotes_object)
+        }
+        Core::Let(bs, body) | Core::LetRec(bs, body) => {
+            bs.iter().any(|(_, i)| quotes_object(i)) || quotes_object(body)
+        }
+        Core::Begin(exprs) => exprs.iter().any(quotes_object),
+        Core::The(_, expr) | Core::Define(_, expr) => quotes_object(expr),
+    }
+}
+
+/// Number of nodes in `core`, as a rough measure of how much code it becomes
+pub fn size(core: &Core) -> usize {
+    match core {
+        Core::Var(_) | Core::Const(_) => 1,
+        Core::Lambda(_, body) => 1 + size(body),
+        Core::If(c, t, e) => 1 + size(c) + size(t) + size(e),
+        Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+            1 + size(rator) + rands.iter().map(size).sum::<usize>()
+        }
+        Core::Let(bs, body) | Core::LetRec(bs, body) => {
+            1 + bs.iter().map(|(_, i)| size(i)).sum::<usize>() + size(body)
+        }
+        Core::Begin(exprs) => 1 + exprs.iter().map(size).sum::<usize>(),
+        Core::The(_, expr) | Core::Define(_, expr) => 1 + size(expr),
+    }
+}
@@ -0,0 +1,716 @@
+//! An interpreter running `Core` directly, which serves as the reference
+//! semantics for the compiling backends
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+
+use crate::builtins;
+use crate::core_former::Core;
+use crate::datum::Datum;
+use crate::prim::{self, Arity};
+use crate::primsyn::Type;
+use crate::resolve::source_name;
+use crate::symbol::Symbol;
+use crate::value::{self, Closure, Env, Forced, Names, Parameter, Promise, Scope, Value};
+
+pub type RuntimeResult<T> = Result<T, RuntimeError>;
+
+/// Everything that can go wrong while running a program. The values
+/// involved are kept as their printed form, as they don't outlive the run.
+#[derive(Debug)]
+pub enum RuntimeError {
+    UnboundVariable(String),
+    NotAProcedure(String),
+    WrongArgCount {
+        proc: String,
+        expected: Arity,
+        given: usize,
+    },
+    WrongType {
+        proc: String,
+        expected: &'static str,
+        given: String,
+    },
+    AnnotationFailed {
+        expected: Type,
+        given: String,
+    },
+    IndexOutOfRange {
+        proc: String,
+        index: i32,
+        len: usize,
+    },
+    DivisionByZero(String),
+    /// Only from the native backends, which have fixnums alone and so can't
+    /// promote a result that doesn't fit one as the interpreter and the VM do
+    Overflow(String),
+    Io(String),
+    /// Compiled code stopped by the machine running it, past its own checks
+    Trap(String),
+    /// An error object raised with no handler to catch it
+    Error {
+        message: String,
+        irritants: Vec<String>,
+    },
+    /// Anything else raised with no handler to catch it
+    Uncaught(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnboundVariable(name) => write!(f, "unbound variable `{name}`"),
+            Self::NotAProcedure(v) => write!(f, "`{v}` is not a procedure, but is called"),
+            Self::WrongArgCount {
+                proc,
+                expected,
+                given,
+            } => {
+                let expected = match expected {
+                    Arity::Exactly(n) => format!("{n}"),
+                    Arity::AtLeast(n) => format!("at least {n}"),
+                    Arity::Between(lo, hi) => format!("{lo} to {hi}"),
+                };
+                write!(
+                    f,
+                    "`{proc}` expects {expected} argument(s), but is called with {given}"
+                )
+            }
+            Self::WrongType {
+                proc,
+                expected,
+                given,
+            } => write!(f, "`{proc}` expects {expected}, but is given `{given}`"),
+            Self::AnnotationFailed { expected, given } => {
+                write!(f, "`{given}` is not a {}", expected.name())
+            }
+            Self::IndexOutOfRange { proc, index, len } => {
+                write!(
+                    f,
+                    "`{proc}`: index {index} is out of range for length {len}"
+                )
+            }
+            Self::DivisionByZero(proc) => write!(f, "`{proc}`: division by zero"),
+            Self::Overflow(proc) => {
+                write!(
+                    f,
+                    "`{proc}`: fixnum overflow, not supported on this backend"
+                )
+            }
+            Self::Io(e) => write!(f, "i/o error: {e}"),
+            Self::Trap(e) => write!(f, "trap: {e}"),
+            Self::Error { message, irritants } => {
+                f.write_str(message)?;
+                for irritant in irritants {
+                    write!(f, " {irritant}")?;
+                }
+                Ok(())
+            }
+            Self::Uncaught(v) => write!(f, "uncaught exception: `{v}`"),
+        }
+    }
+}
+
+/// What to do with a value once it has been computed: the continuation of
+/// the interpreter, kept as an explicit stack rather than on the Rust stack
+#[derive(Clone)]
+enum Kont<'a> {
+    /// Choose a branch of an `if`
+    If(&'a Core, &'a Core, Env<'a>),
+    /// The operator of a call is done, evaluate the operands
+    Rator(&'a Core, &'a [Core], Env<'a>),
+    /// Another operand is done, as are the ones in `done`
+    Rands {
+        rator: &'a Core,
+        f: Value<'a>,
+        rands: &'a [Core],
+        done: Vec<Value<'a>>,
+        env: Env<'a>,
+    },
+    Let {
+        bs: &'a [(Symbol, Core)],
+        done: Vec<Value<'a>>,
+        body: &'a Core,
+        env: Env<'a>,
+    },
+    /// `letrec` evaluates into a scope which already holds its names
+    LetRec {
+        bs: &'a [(Symbol, Core)],
+        i: usize,
+        scope: Rc<Scope<'a>>,
+        body: &'a Core,
+    },
+    /// Evaluate the rest of a `begin`, which is never empty
+    Begin(&'a [Core], Env<'a>),
+    The(Type),
+    Define(Symbol),
+    /// The `before` of a `dynamic-wind` is done, run `thunk` inside it
+    Wind {
+        before: Value<'a>,
+        thunk: Value<'a>,
+        after: Value<'a>,
+    },
+    /// The `thunk` of a `dynamic-wind` is done, leave it for `outer`
+    Unwind {
+        after: Value<'a>,
+        outer: Value<'a>,
+    },
+    /// Hand back this value rather than the one computed
+    Deliver(Value<'a>),
+    /// A thunk run on the way to `k` is done, now inside the winders `then`
+    Rethrow {
+        k: Rc<Continuation<'a>>,
+        v: Value<'a>,
+        then: Value<'a>,
+    },
+    /// Go back to these exception handlers, passing the value on
+    Handlers(Value<'a>),
+    /// A handler returned from `raise`ing this object, which is an error
+    Raised(Value<'a>),
+    /// The thunk of this promise is done, giving the promise to take over from
+    Force(Rc<Promise<'a>>),
+    /// The converter given to `make-parameter` is done with the initial value
+    MakeParameter(Value<'a>),
+    /// Go back to these `parameterize` bindings, passing the value on
+    Parameters(Value<'a>),
+}
+
+/// The rest of the program at some point, captured by `call/cc`: what to do
+/// with the value, and the `dynamic-wind`s, exception handlers and
+/// `parameterize` bindings it is inside of
+pub struct Continuation<'a> {
+    stack: Vec<Kont<'a>>,
+    winders: Value<'a>,
+    handlers: Value<'a>,
+    parameters: Value<'a>,
+}
+
+impl fmt::Debug for Continuation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Continuation({} frames)", self.stack.len())
+    }
+}
+
+/// The interpreter either has an expression left to evaluate, or a value to
+/// hand to the innermost continuation
+enum Step<'a> {
+    Eval(&'a Core, Env<'a>),
+    Return(Value<'a>),
+}
+
+/// Evaluates `Core` produced by `eval::core_program`. Continuations are
+/// heap-allocated frames, and no frame is pushed for the body of a procedure,
+/// so tail calls run in constant space whether or not they were marked as a
+/// `Core::TailCall`.
+pub struct Interpreter<'a> {
+    /// Top-level definitions, starting out as the primitives
+    globals: HashMap<Symbol, Value<'a>>,
+    /// Quoted constants, made once so that each evaluation gives the same object
+    constants: HashMap<*const Datum, Value<'a>>,
+    stack: Vec<Kont<'a>>,
+    /// The `dynamic-wind`s being run, as `(before . after)` pairs, innermost
+    /// first
+    winders: Value<'a>,
+    /// The handlers installed by `with-exception-handler`, innermost first
+    handlers: Value<'a>,
+    /// The bindings made by `parameterize`, as `(parameter . value)` pairs,
+    /// innermost first
+    parameters: Value<'a>,
+    out: Box<dyn Write>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn init() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    /// An interpreter sending everything `display`ed to `out`
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        Self {
+            globals: prim::PRIMS
+                .iter()
+                .map(|p| (Symbol::intern(p.name), Value::Prim(p)))
+                .collect(),
+            constants: HashMap::new(),
+            stack: Vec::new(),
+            winders: Value::Null,
+            handlers: Value::Null,
+            parameters: Value::Null,
+            out,
+        }
+    }
+
+    /// Run a program, returning the value of its last form
+    pub fn run(&mut self, core: &'a Core) -> RuntimeResult<Value<'a>> {
+        self.stack.clear();
+        self.winders = Value::Null;
+        self.handlers = Value::Null;
+        self.parameters = Value::Null;
+        let mut step = Step::Eval(core, None);
+        let res = loop {
+            let next = match step {
+                Step::Eval(core, env) => self.eval(core, env),
+                Step::Return(v) => match self.stack.pop() {
+                    None => break Ok(v),
+                    Some(k) => self.resume(k, v),
+                },
+            };
+            step = match next {
+                Ok(step) => step,
+                Err(e) => match self.fault(e) {
+                    Ok(step) => step,
+                    Err(e) => break Err(e),
+                },
+            }
+        };
+        self.out
+            .flush()
+            .map_err(|e| RuntimeError::Io(e.to_string()))?;
+        res
+    }
+
+    fn eval(&mut self, core: &'a Core, env: Env<'a>) -> RuntimeResult<Step<'a>> {
+        let step = match core {
+            Core::Var(name) => Step::Return(self.variable(*name, &env)?),
+            Core::Const(d) => Step::Return(self.constant(d)),
+            Core::Lambda(formals, body) => {
+                Step::Return(Value::Closure(Rc::new(Closure { formals, body, env })))
+            }
+            Core::If(c, t, e) => {
+                self.stack.push(Kont::If(t, e, env.clone()));
+                Step::Eval(c, env)
+            }
+            Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+                self.stack.push(Kont::Rator(rator, rands, env.clone()));
+                Step::Eval(rator, env)
+            }
+            Core::Let(bs, body) => match bs.first() {
+                None => Step::Eval(body, env),
+                Some((_, init)) => {
+                    self.stack.push(Kont::Let {
+                        bs,
+                        done: Vec::with_capacity(bs.len()),
+                        body,
+                        env: env.clone(),
+                    });
+                    Step::Eval(init, env)
+                }
+            },
+            Core::LetRec(bs, body) => {
+                let env = value::extend(
+                    &env,
+                    Names::Bindings(bs),
+                    vec![Value::Unspecified; bs.len()],
+                );
+                match (bs.first(), &env) {
+                    (Some((_, init)), Some(scope)) => {
+                        self.stack.push(Kont::LetRec {
+                            bs,
+                            i: 0,
+                            scope: scope.clone(),
+                            body,
+                        });
+                        Step::Eval(init, env)
+                    }
+                    _ => Step::Eval(body, env),
+                }
+            }
+            Core::Begin(exprs) => match exprs.split_first() {
+                None => Step::Return(Value::Unspecified),
+                Some((first, rest)) => {
+                    if !rest.is_empty() {
+                        self.stack.push(Kont::Begin(rest, env.clone()));
+                    }
+                    Step::Eval(first, env)
+                }
+            },
+            Core::The(ty, expr) => {
+                self.stack.push(Kont::The(*ty));
+                Step::Eval(expr, env)
+            }
+            Core::Define(name, expr) => {
+                self.stack.push(Kont::Define(*name));
+                Step::Eval(expr, env)
+            }
+        };
+        Ok(step)
+    }
+
+    fn resume(&mut self, k: Kont<'a>, v: Value<'a>) -> RuntimeResult<Step<'a>> {
+        let step = match k {
+            Kont::If(t, e, env) => Step::Eval(if v.is_true() { t } else { e }, env),
+            Kont::Rator(rator, rands, env) => {
+                self.operands(rator, v, rands, Vec::with_capacity(rands.len()), env)?
+            }
+            Kont::Rands {
+                rator,
+                f,
+                rands,
+                mut done,
+                env,
+            } => {
+                done.push(v);
+                self.operands(rator, f, rands, done, env)?
+            }
+            Kont::Let {
+                bs,
+                mut done,
+                body,
+                env,
+            } => {
+                done.push(v);
+                match bs.get(done.len()) {
+                    None => Step::Eval(body, value::extend(&env, Names::Bindings(bs), done)),
+                    Some((_, init)) => {
+                        self.stack.push(Kont::Let {
+                            bs,
+                            done,
+                            body,
+                            env: env.clone(),
+                        });
+                        Step::Eval(init, env)
+                    }
+                }
+            }
+            Kont::LetRec { bs, i, scope, body } => {
+                scope.values.borrow_mut()[i] = v;
+                match bs.get(i + 1) {
+                    None => Step::Eval(body, Some(scope)),
+                    Some((_, init)) => {
+                        self.stack.push(Kont::LetRec {
+                            bs,
+                            i: i + 1,
+                            scope: scope.clone(),
+                            body,
+                        });
+                        Step::Eval(init, Some(scope))
+                    }
+                }
+            }
+            Kont::Begin(exprs, env) => {
+                let (first, rest) = exprs.split_first().expect("`Kont::Begin` is never empty");
+                if !rest.is_empty() {
+                    self.stack.push(Kont::Begin(rest, env.clone()));
+                }
+                Step::Eval(first, env)
+            }
+            Kont::The(ty) => {
+                if !v.has_type(ty) {
+                    return Err(RuntimeError::AnnotationFailed {
+                        expected: ty,
+                        given: v.to_string(),
+                    });
+                }
+                Step::Return(v)
+            }
+            Kont::Define(name) => {
+                self.globals.insert(name, v);
+                Step::Return(Value::Unspecified)
+            }
+            Kont::Wind {
+                before,
+                thunk,
+                after,
+            } => {
+                let outer = self.winders.clone();
+                self.winders = Value::cons(Value::cons(before, after.clone()), outer.clone());
+                self.stack.push(Kont::Unwind { after, outer });
+                self.apply(None, thunk, Vec::new())?
+            }
+            Kont::Unwind { after, outer } => {
+                self.winders = outer;
+                self.stack.push(Kont::Deliver(v));
+                self.apply(None, after, Vec::new())?
+            }
+            Kont::Deliver(v) => Step::Return(v),
+            Kont::Rethrow { k, v, then } => {
+                self.winders = then;
+                self.throw(k, v)?
+            }
+            Kont::Handlers(handlers) => {
+                self.handlers = handlers;
+                Step::Return(v)
+            }
+            Kont::Raised(obj) => {
+                let message = Value::string(builtins::HANDLER_RETURNED.to_owned());
+                let e = builtins::error(vec![message, obj])?;
+                self.raise(e, false)?
+            }
+            Kont::Force(p) => {
+                let Value::Promise(new) = v else {
+                    return Err(RuntimeError::WrongType {
+                        proc: "force".to_owned(),
+                        expected: "a promise",
+                        given: v.to_string(),
+                    });
+                };
+                p.update(&new);
+                self.force(p)?
+            }
+            Kont::MakeParameter(converter) => {
+                Step::Return(Value::Parameter(Rc::new(Parameter { init: v, converter })))
+            }
+            Kont::Parameters(parameters) => {
+                self.parameters = parameters;
+                Step::Return(v)
+            }
+        };
+        Ok(step)
+    }
+
+    /// Evaluate the next operand of a call, or make the call once they are
+    /// all done
+    fn operands(
+        &mut self,
+        rator: &'a Core,
+        f: Value<'a>,
+        rands: &'a [Core],
+        done: Vec<Value<'a>>,
+        env: Env<'a>,
+    ) -> RuntimeResult<Step<'a>> {
+        match rands.get(done.len()) {
+            None => self.apply(Some(rator), f, done),
+            Some(next) => {
+                self.stack.push(Kont::Rands {
+                    rator,
+                    f,
+                    rands,
+                    done,
+                    env: env.clone(),
+                });
+                Ok(Step::Eval(next, env))
+            }
+        }
+    }
+
+    /// Call `f`, named by the expression `rator` in any error message
+    fn apply(
+        &mut self,
+        rator: Option<&'a Core>,
+        f: Value<'a>,
+        mut args: Vec<Value<'a>>,
+    ) -> RuntimeResult<Step<'a>> {
+        match f {
+            Value::Closure(c) => {
+                if c.formals.len() != args.len() {
+                    return Err(RuntimeError::WrongArgCount {
+                        proc: rator.map_or_else(|| "procedure".to_owned(), shown),
+                        expected: Arity::Exactly(c.formals.len()),
+                        given: args.len(),
+                    });
+                }
+                let env = value::extend(&c.env, Names::Formals(c.formals), args);
+                Ok(Step::Eval(c.body, env))
+            }
+            Value::Prim(p) => {
+                if !p.arity.accepts(args.len()) {
+                    return Err(RuntimeError::WrongArgCount {
+                        proc: p.name.to_owned(),
+                        expected: p.arity,
+                        given: args.len(),
+                    });
+                }
+                match p.name {
+                    "apply" => {
+                        let spread = args.pop().expect("`apply` takes at least 2 arguments");
+                        let f = args.remove(0);
+                        match spread.to_vec() {
+                            Some(rest) => {
+                                args.extend(rest);
+                                self.apply(rator, f, args)
+                            }
+                            None => Err(RuntimeError::WrongType {
+                                proc: p.name.to_owned(),
+                                expected: "a list",
+                                given: spread.to_string(),
+                            }),
+                        }
+                    }
+                    "call-with-current-continuation" | "call/cc" => {
+                        let f = args.pop().expect("`call/cc` takes 1 argument");
+                        let k = Continuation {
+                            stack: self.stack.clone(),
+                            winders: self.winders.clone(),
+                            handlers: self.handlers.clone(),
+                            parameters: self.parameters.clone(),
+                        };
+                        self.apply(None, f, vec![Value::Continuation(Rc::new(k))])
+                    }
+                    "dynamic-wind" => {
+                        let after = args.pop().expect("`dynamic-wind` takes 3 arguments");
+                        let thunk = args.pop().expect("`dynamic-wind` takes 3 arguments");
+                        let before = args.pop().expect("`dynamic-wind` takes 3 arguments");
+                        self.stack.push(Kont::Wind {
+                            before: before.clone(),
+                            thunk,
+                            after,
+                        });
+                        self.apply(None, before, Vec::new())
+                    }
+                    "with-exception-handler" => {
+                        let thunk = args
+                            .pop()
+                            .expect("`with-exception-handler` takes 2 arguments");
+                        let handler = args
+                            .pop()
+                            .expect("`with-exception-handler` takes 2 arguments");
+                        if !handler.is_procedure() {
+                            return Err(RuntimeError::WrongType {
+                                proc: p.name.to_owned(),
+                                expected: "a procedure",
+                                given: handler.to_string(),
+                            });
+                        }
+                        let outer = self.handlers.clone();
+                        self.handlers = Value::cons(handler, outer.clone());
+                        self.stack.push(Kont::Handlers(outer));
+                        self.apply(None, thunk, Vec::new())
+                    }
+                    "raise" | "raise-continuable" => {
+                        let obj = args.pop().expect("`raise` takes 1 argument");
+                        self.raise(obj, p.name == "raise-continuable")
+                    }
+                    "error" => {
+                        let obj = builtins::error(args)?;
+                        self.raise(obj, false)
+                    }
+                    "force" => match args.pop().expect("`force` takes 1 argument") {
+                        Value::Promise(p) => self.force(p),
+                        other => Ok(Step::Return(other)),
+                    },
+                    // without a converter, the initial value is used as it is
+                    "make-parameter" if args.len() > 1 => {
+                        let converter = args.pop().expect("just checked");
+                        let init = args.pop().expect("just checked");
+                        self.stack.push(Kont::MakeParameter(converter.clone()));
+                        self.apply(None, converter, vec![init])
+                    }
+                    "%parameterize" => {
+                        let thunk = args.pop().expect("`%parameterize` takes 3 arguments");
+                        let v = args.pop().expect("`%parameterize` takes 3 arguments");
+                        let param = args.pop().expect("`%parameterize` takes 3 arguments");
+                        let inner = builtins::parameterize(&param, v, &self.parameters)?;
+                        let outer = mem::replace(&mut self.parameters, inner);
+                        self.stack.push(Kont::Parameters(outer));
+                        self.apply(None, thunk, Vec::new())
+                    }
+                    name => Ok(Step::Return(builtins::call(name, args, &mut self.out)?)),
+                }
+            }
+            Value::Continuation(k) => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::WrongArgCount {
+                        proc: "continuation".to_owned(),
+                        expected: Arity::Exactly(1),
+                        given: args.len(),
+                    });
+                }
+                let v = args.pop().expect("just checked");
+                self.throw(k, v)
+            }
+            Value::Parameter(p) => {
+                if !args.is_empty() {
+                    return Err(RuntimeError::WrongArgCount {
+                        proc: "parameter".to_owned(),
+                        expected: Arity::Exactly(0),
+                        given: args.len(),
+                    });
+                }
+                Ok(Step::Return(p.value(&self.parameters)))
+            }
+            other => Err(RuntimeError::NotAProcedure(other.to_string())),
+        }
+    }
+
+    /// Carry on from `k` with the value `v`, first running the `after`s of
+    /// the `dynamic-wind`s being left and the `before`s of those re-entered,
+    /// one thunk at a time
+    fn throw(&mut self, k: Rc<Continuation<'a>>, v: Value<'a>) -> RuntimeResult<Step<'a>> {
+        match value::wind_step(&self.winders, &k.winders) {
+            None => {
+                self.stack = k.stack.clone();
+                self.handlers = k.handlers.clone();
+                self.parameters = k.parameters.clone();
+                Ok(Step::Return(v))
+            }
+            Some((thunk, during, then)) => {
+                self.winders = during;
+                self.stack.push(Kont::Rethrow { k, v, then });
+                self.apply(None, thunk, Vec::new())
+            }
+        }
+    }
+
+    /// Force `p`, running thunks until one gives a promise which is done.
+    /// Each thunk returns before the next is run, so a chain of
+    /// `delay-force`s takes no more space however long it is.
+    fn force(&mut self, p: Rc<Promise<'a>>) -> RuntimeResult<Step<'a>> {
+        match p.get() {
+            Forced::Done(v) => Ok(Step::Return(v)),
+            Forced::Delayed(thunk) => {
+                self.stack.push(Kont::Force(p));
+                self.apply(None, thunk, Vec::new())
+            }
+        }
+    }
+
+    /// Call the innermost handler with `obj`, inside the handlers outside it.
+    /// Unless the raise is `continuable`, the handler returning is an error.
+    fn raise(&mut self, obj: Value<'a>, continuable: bool) -> RuntimeResult<Step<'a>> {
+        let Value::Pair(p) = &self.handlers else {
+            return Err(builtins::uncaught(&obj));
+        };
+        let handler = p.car.borrow().clone();
+        let outer = p.cdr.borrow().clone();
+        let handlers = mem::replace(&mut self.handlers, outer);
+        self.stack.push(Kont::Handlers(handlers));
+        if !continuable {
+            self.stack.push(Kont::Raised(obj.clone()));
+        }
+        self.apply(None, handler, vec![obj])
+    }
+
+    /// Raise the error `e` as an error object, if there is a handler to
+    /// catch it
+    fn fault(&mut self, e: RuntimeError) -> RuntimeResult<Step<'a>> {
+        if matches!(self.handlers, Value::Null) {
+            return Err(e);
+        }
+        self.raise(builtins::condition(&e), false)
+    }
+
+    fn variable(&self, name: Symbol, env: &Env<'a>) -> RuntimeResult<Value<'a>> {
+        if let Some(v) = value::lookup(env, name) {
+            return Ok(v);
+        }
+        match self.globals.get(&name) {
+            Some(v) => Ok(v.clone()),
+            None => Err(RuntimeError::UnboundVariable(source_name(&name).to_owned())),
+        }
+    }
+
+    fn constant(&mut self, d: &'a Datum) -> Value<'a> {
+        match d {
+            Datum::Bool(_) | Datum::Fixnum(_) | Datum::Char(_) | Datum::Null => {
+                Value::from_datum(d)
+            }
+            _ => self
+                .constants
+                .entry(d as *const Datum)
+                .or_insert_with(|| Value::from_datum(d))
+                .clone(),
+        }
+    }
+}
+
+/// How to refer to the procedure called by `rator` in an error
+fn shown(rator: &Core) -> String {
+    match rator {
+        Core::Var(name) => source_name(name).to_owned(),
+        Core::Lambda(..) => "lambda".to_owned(),
+        _ => "procedure".to_owned(),
+    }
+}
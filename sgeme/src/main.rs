@@ -1,22 +1,324 @@
 #![allow(unused, dead_code)]
 
+use std::env;
 use std::error::Error;
 use std::fs;
+use std::io;
+use std::process;
+use std::time::Instant;
 
+mod asmgen;
+mod builtins;
+mod bytecode;
+mod cgen;
+mod check;
+mod closure;
+#[cfg(feature = "mir")]
+mod codegen;
+mod compile;
 mod core_former;
 mod datum;
+mod diagnostics;
 mod eval;
 mod expander;
+mod inline;
+mod interp;
+mod number;
+mod optimize;
+mod prim;
 mod primsyn;
 mod read;
+mod resolve;
+#[cfg(feature = "mir")]
+mod runtime;
+mod symbol;
+mod tail;
 mod token;
+mod types;
+mod value;
+mod vm;
+mod wasmgen;
+#[cfg(feature = "wasm")]
+mod wasmrt;
+mod word;
 
+use compile::Compiler;
+use diagnostics::Diagnostics;
+#[cfg(feature = "mir")]
+use eval::Evaluator;
+use eval::Options;
 use expander::Expander;
+use interp::{Interpreter, RuntimeResult};
+use optimize::OptLevel;
 use read::Reader;
+#[cfg(feature = "mir")]
+use rs_mir::{Exec, MIRContext};
 use token::{Logos, Token};
+use value::Value;
+use vm::Vm;
 
+/// Where to run a program, after the passes in `eval::core_program`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    /// Only compile it to MIR
+    Mir,
+    /// Compile it to MIR, and run that in-process
+    Jit,
+    /// Compile it to C, written to the file given by `--emit-c`
+    C,
+    /// Compile it to x86-64 assembly, written to the file given by
+    /// `--emit-asm`
+    Asm,
+    /// Compile it to WebAssembly, written to the file given by `--emit-wasm`
+    Wasm,
+    /// Compile it to WebAssembly, and run that in the embedded interpreter
+    WasmInterp,
+    Interp,
+    Vm,
+}
+
+/// Flags for running a program:
+/// + `disasm`: print the bytecode before running it with `vm::Vm`
+/// + `bench`: run with the interpreter and the VM, reporting how long each
+///   took
+/// + `mir_interp`: run MIR code with MIR's interpreter rather than its
+///   generator
+#[derive(Debug, Clone, Copy, Default)]
+struct RunFlags {
+    disasm: bool,
+    bench: bool,
+    mir_interp: bool,
+}
+
+/// Print the value of a program's last form unless it's unspecified, or
+/// else the error it stopped with
+fn report(res: RuntimeResult<String>) {
+    match res {
+        Ok(v) if v == Value::Unspecified.to_string() => (),
+        Ok(v) => println!("{v}"),
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Run a program without compiling it to native code, printing the value of
+/// its last form unless it's unspecified
+fn run(prgrm: &primsyn::Program, opts: &Options, backend: Backend, flags: RunFlags) {
+    let mut diagnostics = Diagnostics::init();
+    let res = eval::core_program(prgrm, opts, &mut diagnostics);
+    for diagnostic in diagnostics.iter() {
+        eprintln!("{diagnostic}");
+    }
+    let core = match res {
+        Ok((core, _)) => core,
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    };
+
+    if flags.bench {
+        return bench(&core);
+    }
+    let res = match backend {
+        Backend::Vm => {
+            let module = Compiler::init().compile(&core);
+            if flags.disasm {
+                eprint!("{module}");
+            }
+            Vm::init().run(&module).map(|v| v.to_string())
+        }
+        _ => Interpreter::init().run(&core).map(|v| v.to_string()),
+    };
+    report(res)
+}
+
+/// Compile a program to MIR, and run it if `backend` is `Backend::Jit`
+#[cfg(feature = "mir")]
+fn compile(prgrm: &primsyn::Program, opts: &Options, backend: Backend, flags: RunFlags) {
+    let exec = match (flags.mir_interp, opts.opt_level) {
+        (true, _) => Exec::Interp,
+        (false, OptLevel::O0) => Exec::Gen(0),
+        (false, OptLevel::O1) => Exec::Gen(1),
+        (false, OptLevel::O2) => Exec::Gen(2),
+    };
+    let mut ctx = MIRContext::init();
+    let mut evaluator = Evaluator::init(&mut ctx, opts.clone());
+    let res = evaluator.compile_program(prgrm);
+    for diagnostic in evaluator.diagnostics.iter() {
+        eprintln!("{diagnostic}");
+    }
+    match res {
+        Ok(symbols) if backend == Backend::Jit => report(runtime::run(&mut ctx, exec, &symbols)),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(feature = "mir"))]
+fn compile(_: &primsyn::Program, _: &Options, _: Backend, _: RunFlags) {
+    eprintln!("error: sgeme was built without the MIR backend (the `mir` or `system-mir` feature)");
+    process::exit(1);
+}
+
+fn write_out(path: &str, contents: impl AsRef<[u8]>) {
+    if let Err(e) = fs::write(path, contents) {
+        eprintln!("error: {path}: {e}");
+        process::exit(1);
+    }
+}
+
+/// Compile a program to C, or to assembly if `backend` is `Backend::Asm`,
+/// and write that to `path`
+fn emit(prgrm: &primsyn::Program, opts: &Options, backend: Backend, path: &str) {
+    let mut diagnostics = Diagnostics::init();
+    let res = match backend {
+        Backend::Asm => eval::asm_program(prgrm, opts, &mut diagnostics),
+        _ => eval::c_program(prgrm, opts, &mut diagnostics),
+    };
+    for diagnostic in diagnostics.iter() {
+        eprintln!("{diagnostic}");
+    }
+    match res {
+        Ok(c) => write_out(path, c),
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Compile a program to WebAssembly, and run it if `backend` is
+/// `Backend::WasmInterp`, or else write it to `path`, in the binary format
+/// if its name ends in `.wasm` and as text otherwise
+fn wasm(prgrm: &primsyn::Program, opts: &Options, backend: Backend, path: &str) {
+    let mut diagnostics = Diagnostics::init();
+    let res = eval::wasm_program(prgrm, opts, &mut diagnostics);
+    for diagnostic in diagnostics.iter() {
+        eprintln!("{diagnostic}");
+    }
+    let res = res.and_then(|(wat, symbols)| {
+        let binary = backend == Backend::WasmInterp || path.ends_with(".wasm");
+        let bytes = binary.then(|| wasmgen::assemble(&wat)).transpose()?;
+        Ok((wat, bytes, symbols))
+    });
+    match res {
+        #[cfg(feature = "wasm")]
+        Ok((_, Some(bytes), symbols)) if backend == Backend::WasmInterp => {
+            report(wasmrt::run(&bytes, &symbols))
+        }
+        Ok((_, Some(bytes), _)) => write_out(path, bytes),
+        Ok((wat, None, _)) => write_out(path, wat),
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Time the tree walker against the bytecode machine, with the program's
+/// output thrown away, and check that they agree on the result
+fn bench(core: &core_former::Core) {
+    let start = Instant::now();
+    let walked = Interpreter::with_output(Box::new(io::sink()))
+        .run(core)
+        .map(|v| v.to_string());
+    let walk_time = start.elapsed();
+
+    let start = Instant::now();
+    let module = Compiler::init().compile(core);
+    let ran = Vm::with_output(Box::new(io::sink()))
+        .run(&module)
+        .map(|v| v.to_string());
+    let vm_time = start.elapsed();
+
+    eprintln!("interp: {walk_time:?}");
+    eprintln!(
+        "vm:     {vm_time:?} ({:.1}x)",
+        walk_time.as_secs_f64() / vm_time.as_secs_f64()
+    );
+    match (walked, ran) {
+        (Ok(a), Ok(b)) if a == b => {
+            if a != Value::Unspecified.to_string() {
+                println!("{a}")
+            }
+        }
+        (Err(a), Err(b)) if a.to_string() == b.to_string() => eprintln!("error: {a}"),
+        (a, b) => {
+            eprintln!("mismatch: interp gave {a:?}, vm gave {b:?}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Usage: `sgeme [run] [-O0|-O1|-O2] [--dump-core] [--dump-mir]
+/// [--emit-mir=<file>] [--emit-c=<file>] [--emit-asm=<file>]
+/// [--emit-wasm=<file>] [--inline-size=<n>]
+/// [--interp|--vm|--mir-interp|--wasm-interp] [--disasm] [--bench] [file]`,
+/// where `run` runs the program compiled to native code. Without the `mir`
+/// feature, programs run on the VM by default. What `--emit-asm` writes is
+/// for x86-64 Linux, and `cc` builds it into an executable, whose heap
+/// starts at `SGEME_HEAP_SIZE` bytes and which reports on its collector at
+/// exit if `SGEME_GC_STATS` is set. That's the only backend with a
+/// collector: the interpreter and the VM free by reference counting, which
+/// never frees a cycle, and what the others allocate is never freed.
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut file_contents = fs::read_to_string("./test-src/sgeme.ss")?;
+    let mut opts = Options::init();
+    let mut backend = if cfg!(feature = "mir") {
+        Backend::Mir
+    } else {
+        Backend::Vm
+    };
+    let mut flags = RunFlags::default();
+    let mut path = String::from("./test-src/sgeme.ss");
+    // where `--emit-c`, `--emit-asm` and `--emit-wasm` write to
+    let mut out_path = String::new();
+    for arg in env::args().skip(1) {
+        if let Some(level) = OptLevel::from_flag(&arg) {
+            opts.opt_level = level;
+        } else if arg == "--dump-core" {
+            opts.dump_core = true;
+        } else if arg == "--dump-mir" {
+            opts.dump_mir = true;
+        } else if arg == "run" {
+            backend = Backend::Jit;
+        } else if arg == "--mir-interp" {
+            backend = Backend::Jit;
+            flags.mir_interp = true;
+        } else if arg == "--wasm-interp" {
+            backend = Backend::WasmInterp;
+        } else if arg == "--interp" {
+            backend = Backend::Interp;
+        } else if arg == "--vm" {
+            backend = Backend::Vm;
+        } else if arg == "--disasm" {
+            flags.disasm = true;
+        } else if arg == "--bench" {
+            flags.bench = true;
+        } else if let Some(path) = arg.strip_prefix("--emit-mir=") {
+            opts.emit_mir = Some(path.to_owned());
+        } else if let Some(path) = arg.strip_prefix("--emit-c=") {
+            backend = Backend::C;
+            out_path = path.to_owned();
+        } else if let Some(path) = arg.strip_prefix("--emit-asm=") {
+            backend = Backend::Asm;
+            out_path = path.to_owned();
+        } else if let Some(path) = arg.strip_prefix("--emit-wasm=") {
+            backend = Backend::Wasm;
+            out_path = path.to_owned();
+        } else if let Some(n) = arg.strip_prefix("--inline-size=") {
+            opts.inline_size = n.parse()?;
+        } else {
+            path = arg;
+        }
+    }
+
+    let mut file_contents = fs::read_to_string(path)?;
     let tokens = Token::really_lex(file_contents);
     let mut iter = tokens.iter().peekable();
     let mut reader = Reader::init(false, true, &mut iter);
@@ -24,11 +326,26 @@ fn main() -> Result<(), Box<dyn Error>> {
     match res {
         Ok(r) => {
             let expander: Expander = Expander::init();
-            let other_res = expander.expand_prgrm(&r);
-            dbg!(other_res);
+            match expander.expand_prgrm(&r) {
+                Ok(prgrm) if flags.bench || matches!(backend, Backend::Interp | Backend::Vm) => {
+                    run(&prgrm, &opts, backend, flags)
+                }
+                Ok(prgrm) if matches!(backend, Backend::C | Backend::Asm) => {
+                    emit(&prgrm, &opts, backend, &out_path)
+                }
+                Ok(prgrm) if matches!(backend, Backend::Wasm | Backend::WasmInterp) => {
+                    wasm(&prgrm, &opts, backend, &out_path)
+                }
+                Ok(prgrm) => compile(&prgrm, &opts, backend, flags),
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    process::exit(1);
+                }
+            }
         }
         Err(e) => {
-            dbg!(e);
+            eprintln!("error: {e}");
+            process::exit(1);
         }
     };
     Ok(())
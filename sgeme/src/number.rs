@@ -0,0 +1,367 @@
+//! Numbers: exact integers of any size and ratios of them, and inexact
+//! reals as flonums. Values and constants hold each exact number in its
+//! simplest form, as a fixnum wherever it fits, so that equal numbers
+//! always look the same.
+
+use std::cmp::Ordering;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_integer::Roots;
+use num_rational::BigRational;
+use num_traits::{Num, One, Pow, Signed, ToPrimitive, Zero};
+
+use crate::datum::Datum;
+use crate::value::Value;
+
+/// A number taken out of a value or constant to compute with. A `Ratio` is
+/// never a whole number.
+#[derive(Debug, Clone)]
+pub enum Number {
+    Int(BigInt),
+    Ratio(BigRational),
+    Flo(f64),
+}
+
+impl From<i32> for Number {
+    fn from(n: i32) -> Self {
+        Self::Int(BigInt::from(n))
+    }
+}
+
+impl Number {
+    fn ratio(r: BigRational) -> Self {
+        if r.is_integer() {
+            Self::Int(r.to_integer())
+        } else {
+            Self::Ratio(r)
+        }
+    }
+
+    fn to_ratio(&self) -> BigRational {
+        match self {
+            Self::Int(n) => BigRational::from_integer(n.clone()),
+            Self::Ratio(r) => r.clone(),
+            Self::Flo(_) => unreachable!("flonums are only made exact by `exact`"),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Self::Int(n) => n.to_f64().unwrap_or(f64::NAN),
+            Self::Ratio(r) => r.to_f64().unwrap_or(f64::NAN),
+            Self::Flo(x) => *x,
+        }
+    }
+
+    /// The number `v` is, if it's one
+    pub fn of(v: &Value) -> Option<Self> {
+        match v {
+            Value::Fixnum(n) => Some(Self::from(*n)),
+            Value::Big(n) => Some(Self::Int((**n).clone())),
+            Value::Ratio(r) => Some(Self::Ratio((**r).clone())),
+            Value::Flonum(x) => Some(Self::Flo(*x)),
+            _ => None,
+        }
+    }
+
+    /// Read a number written in `radix`, unless a prefix such as `#x` says
+    /// otherwise: an integer, a ratio `n/d` of them, a decimal with an
+    /// optional exponent, or one of `+inf.0`, `-inf.0` and `+nan.0`. The
+    /// prefixes `#e` and `#i` make it exact or inexact.
+    pub fn parse(s: &str, radix: u32) -> Option<Self> {
+        let (mut s, mut radix, mut exact) = (s, radix, None);
+        while let Some(rest) = s.strip_prefix('#') {
+            let mut chars = rest.chars();
+            match chars.next()?.to_ascii_lowercase() {
+                'x' => radix = 16,
+                'd' => radix = 10,
+                'o' => radix = 8,
+                'b' => radix = 2,
+                'e' => exact = Some(true),
+                'i' => exact = Some(false),
+                _ => return None,
+            }
+            s = chars.as_str();
+        }
+
+        let digits = |t: &str| !t.is_empty() && t.chars().all(|c| c.is_digit(radix));
+        let unsigned = s.strip_prefix(['+', '-']).unwrap_or(s);
+        let n = match unsigned.split_once('/') {
+            // these need their sign, or they'd be symbols
+            _ if unsigned != s && unsigned == "inf.0" => Self::Flo(f64::INFINITY),
+            _ if unsigned != s && unsigned == "nan.0" => Self::Flo(f64::NAN),
+            Some((n, d)) if digits(n) && digits(d) => {
+                let n = BigInt::from_str_radix(n, radix).ok()?;
+                let d = BigInt::from_str_radix(d, radix).ok()?;
+                if d.is_zero() {
+                    return None;
+                }
+                Self::ratio(BigRational::new(n, d))
+            }
+            None if digits(unsigned) => Self::Int(BigInt::from_str_radix(unsigned, radix).ok()?),
+            None if radix == 10
+                && unsigned.contains(|c: char| c.is_ascii_digit())
+                && unsigned
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || ".eE+-".contains(c)) =>
+            {
+                Self::Flo(unsigned.parse().ok()?)
+            }
+            _ => return None,
+        };
+        let n = if s.starts_with('-') { -n } else { n };
+        match exact {
+            Some(true) => n.exact(),
+            Some(false) => Some(n.inexact()),
+            None => Some(n),
+        }
+    }
+
+    pub fn into_value<'a>(self) -> Value<'a> {
+        match self {
+            Self::Int(n) => match n.to_i32() {
+                Some(n) => Value::Fixnum(n),
+                None => Value::Big(Rc::new(n)),
+            },
+            Self::Ratio(r) => Value::Ratio(Rc::new(r)),
+            Self::Flo(x) => Value::Flonum(x),
+        }
+    }
+
+    pub fn into_datum(self) -> Datum {
+        match self {
+            Self::Int(n) => match n.to_i32() {
+                Some(n) => Datum::Fixnum(n),
+                None => Datum::Big(n),
+            },
+            Self::Ratio(r) => Datum::Ratio(Box::new(r)),
+            Self::Flo(x) => Datum::Flonum(x),
+        }
+    }
+
+    /// `self` written in `radix`; flonums are only written in decimal
+    pub fn to_string_radix(&self, radix: u32) -> Option<String> {
+        match self {
+            Self::Int(n) => Some(n.to_str_radix(radix)),
+            Self::Ratio(r) => Some(format!(
+                "{}/{}",
+                r.numer().to_str_radix(radix),
+                r.denom().to_str_radix(radix)
+            )),
+            Self::Flo(x) if radix == 10 => Some(flonum_to_string(*x)),
+            Self::Flo(_) => None,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(self, Self::Flo(_))
+    }
+
+    pub fn is_integer(&self) -> bool {
+        match self {
+            Self::Int(_) => true,
+            Self::Ratio(_) => false,
+            Self::Flo(x) => x.is_finite() && x.fract() == 0.0,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Self::Int(n) => n.is_zero(),
+            Self::Ratio(_) => false,
+            Self::Flo(x) => *x == 0.0,
+        }
+    }
+
+    /// The exact number equal to `self`, unless it's an infinity or NaN
+    pub fn exact(self) -> Option<Self> {
+        match self {
+            Self::Flo(x) => BigRational::from_float(x).map(Self::ratio),
+            n => Some(n),
+        }
+    }
+
+    /// The flonum nearest to `self`
+    pub fn inexact(self) -> Self {
+        Self::Flo(self.to_f64())
+    }
+
+    pub fn abs(self) -> Self {
+        match self {
+            Self::Int(n) => Self::Int(n.abs()),
+            Self::Ratio(r) => Self::Ratio(r.abs()),
+            Self::Flo(x) => Self::Flo(x.abs()),
+        }
+    }
+
+    /// The numerator of `self` in lowest terms, unless it's an infinity or
+    /// NaN; inexact if `self` is
+    pub fn numerator(self) -> Option<Self> {
+        let exact = self.is_exact();
+        let n = match self.exact()? {
+            Self::Ratio(r) => Self::Int(r.numer().clone()),
+            n => n,
+        };
+        Some(if exact { n } else { n.inexact() })
+    }
+
+    /// The denominator of `self` in lowest terms, unless it's an infinity
+    /// or NaN; inexact if `self` is
+    pub fn denominator(self) -> Option<Self> {
+        let exact = self.is_exact();
+        let d = match self.exact()? {
+            Self::Ratio(r) => Self::Int(r.denom().clone()),
+            _ => Self::Int(BigInt::one()),
+        };
+        Some(if exact { d } else { d.inexact() })
+    }
+
+    /// `self / other`, unless `other` is an exact zero
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        match (self, other) {
+            (_, Self::Int(d)) if d.is_zero() => None,
+            (a @ Self::Flo(_), b) | (a, b @ Self::Flo(_)) => {
+                Some(Self::Flo(a.to_f64() / b.to_f64()))
+            }
+            (a, b) => Some(Self::ratio(a.to_ratio() / b.to_ratio())),
+        }
+    }
+
+    /// `self` to the power `e`, unless that divides by an exact zero
+    pub fn pow(self, e: i32) -> Option<Self> {
+        match self {
+            Self::Flo(x) => Some(Self::Flo(x.powi(e))),
+            Self::Int(n) if e >= 0 => Some(Self::Int(n.pow(e.unsigned_abs()))),
+            _ if self.is_zero() => None,
+            other => Some(Self::ratio(other.to_ratio().pow(e))),
+        }
+    }
+
+    /// The square root of `self`, exact if `self` is the square of an
+    /// exact number
+    pub fn sqrt(self) -> Self {
+        let exact = match &self {
+            Self::Int(n) if !n.is_negative() => {
+                let s = n.sqrt();
+                (&s * &s == *n).then_some(Self::Int(s))
+            }
+            Self::Ratio(r) if !r.is_negative() => {
+                let (n, d) = (r.numer().sqrt(), r.denom().sqrt());
+                (&n * &n == *r.numer() && &d * &d == *r.denom())
+                    .then(|| Self::ratio(BigRational::new(n, d)))
+            }
+            _ => None,
+        };
+        exact.unwrap_or_else(|| Self::Flo(self.to_f64().sqrt()))
+    }
+
+    /// `self` combined with `other` as integers by `int`, ratios by `ratio`,
+    /// or, if either is inexact, flonums by `flo`
+    fn combine(
+        self,
+        other: Self,
+        int: fn(BigInt, BigInt) -> BigInt,
+        ratio: fn(BigRational, BigRational) -> BigRational,
+        flo: fn(f64, f64) -> f64,
+    ) -> Self {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => Self::Int(int(a, b)),
+            (a @ Self::Flo(_), b) | (a, b @ Self::Flo(_)) => Self::Flo(flo(a.to_f64(), b.to_f64())),
+            (a, b) => Self::ratio(ratio(a.to_ratio(), b.to_ratio())),
+        }
+    }
+
+    /// How `self` compares to the flonum `x`, exactly
+    fn cmp_flonum(&self, x: f64) -> Option<Ordering> {
+        if x.is_nan() {
+            None
+        } else if x.is_infinite() {
+            Some(if x > 0.0 {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            })
+        } else {
+            self.partial_cmp(&Self::Flo(x).exact()?)
+        }
+    }
+}
+
+impl Add for Number {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.combine(other, |a, b| a + b, |a, b| a + b, |a, b| a + b)
+    }
+}
+
+impl Sub for Number {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.combine(other, |a, b| a - b, |a, b| a - b, |a, b| a - b)
+    }
+}
+
+impl Mul for Number {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        self.combine(other, |a, b| a * b, |a, b| a * b, |a, b| a * b)
+    }
+}
+
+impl Neg for Number {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        match self {
+            Self::Int(n) => Self::Int(-n),
+            Self::Ratio(r) => Self::Ratio(-r),
+            Self::Flo(x) => Self::Flo(-x),
+        }
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+/// Exact and inexact numbers are compared exactly, so that comparisons are
+/// transitive even where flonums are too coarse to tell numbers apart
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => Some(a.cmp(b)),
+            (Self::Flo(a), Self::Flo(b)) => a.partial_cmp(b),
+            (a, Self::Flo(x)) => a.cmp_flonum(*x),
+            (Self::Flo(x), b) => b.cmp_flonum(*x).map(Ordering::reverse),
+            (a, b) => Some(a.to_ratio().cmp(&b.to_ratio())),
+        }
+    }
+}
+
+/// Write a flonum so that reading it back gives the same flonum, and shows
+/// it's inexact: with a decimal point or an exponent
+pub fn flonum_to_string(x: f64) -> String {
+    if x.is_nan() {
+        return "+nan.0".to_owned();
+    }
+    if x.is_infinite() {
+        return if x > 0.0 { "+inf.0" } else { "-inf.0" }.to_owned();
+    }
+    let s = if x != 0.0 && !(1e-7..1e21).contains(&x.abs()) {
+        format!("{x:e}")
+    } else {
+        format!("{x}")
+    };
+    if s.contains(['.', 'e']) {
+        s
+    } else {
+        s + ".0"
+    }
+}
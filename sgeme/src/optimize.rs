@@ -0,0 +1,335 @@
+//! Simplifying optimisations over resolved `Core`: constant folding, branch
+//! elimination, copy propagation and dead binding elimination
+
+use std::collections::{HashMap, HashSet};
+
+use crate::core_former::Core;
+use crate::datum::Datum;
+use crate::prim::{self, Prim};
+use crate::symbol::Symbol;
+use crate::types::datum_type;
+
+/// How hard the optimiser tries:
+/// + `O0`: leave the tree alone
+/// + `O1`: fold constant primitive calls and eliminate known branches
+/// + `O2`: also propagate copies and drop dead bindings and expressions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+}
+
+impl OptLevel {
+    /// Parse the level out of a `-O<n>` command line flag, where a bare `-O`
+    /// means `-O1`
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        match flag.strip_prefix("-O")? {
+            "0" => Some(Self::O0),
+            "" | "1" => Some(Self::O1),
+            "2" => Some(Self::O2),
+            _ => None,
+        }
+    }
+}
+
+/// Each round can expose more work for the next, e.g. folding a condition
+/// leaves a `let` binding dead; stop after this many in case it never settles
+const MAX_ROUNDS: usize = 8;
+
+/// Expects its input to have been through `resolve::Resolver`, as it relies on
+/// every local binding having a unique name
+pub struct Optimizer {
+    level: OptLevel,
+    /// Top-level definitions, which shadow any primitive of the same name
+    globals: HashSet<Symbol>,
+    /// Variables bound to a copy of some other variable or constant,
+    /// which get replaced by it wherever they are used
+    copies: HashMap<Symbol, Core>,
+    changed: bool,
+}
+
+impl Optimizer {
+    pub fn init(level: OptLevel) -> Self {
+        Self {
+            level,
+            globals: HashSet::new(),
+            copies: HashMap::new(),
+            changed: false,
+        }
+    }
+
+    /// Optimise a whole program, as produced by `CoreFormer::simplify`
+    pub fn optimize(&mut self, core: Core) -> Core {
+        if self.level == OptLevel::O0 {
+            return core;
+        }
+
+        let mut forms = match core {
+            Core::Begin(forms) => forms,
+            other => vec![other],
+        };
+        for form in &forms {
+            if let Core::Define(name, _) = form {
+                self.globals.insert(*name);
+            }
+        }
+
+        for _ in 0..MAX_ROUNDS {
+            self.changed = false;
+            forms = forms.into_iter().map(|f| self.simplify(f)).collect();
+            if !self.changed {
+                break;
+            }
+        }
+        Core::Begin(forms)
+    }
+
+    /// The primitive `rator` refers to, unless the program redefines it
+    fn prim_of(&self, rator: &Core) -> Option<&'static Prim> {
+        match rator {
+            Core::Var(name) if !self.globals.contains(name) => prim::lookup(name),
+            _ => None,
+        }
+    }
+
+    fn is_pure(&self, core: &Core) -> bool {
+        match core {
+            Core::Var(_) | Core::Const(_) | Core::Lambda(..) => true,
+            Core::If(c, t, e) => self.is_pure(c) && self.is_pure(t) && self.is_pure(e),
+            Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+                self.prim_of(rator)
+                    .is_some_and(|p| p.pure && cannot_fault(p, rands))
+                    && rands.iter().all(|r| self.is_pure(r))
+            }
+            Core::Let(bs, body) | Core::LetRec(bs, body) => {
+                bs.iter().all(|(_, init)| self.is_pure(init)) && self.is_pure(body)
+            }
+            Core::Begin(exprs) => exprs.iter().all(|e| self.is_pure(e)),
+            Core::The(..) | Core::Define(..) => false,
+        }
+    }
+
+    fn simplify(&mut self, core: Core) -> Core {
+        match core {
+            Core::Var(name) => match self.copies.get(&name) {
+                Some(copy) => {
+                    self.changed = true;
+                    copy.clone()
+                }
+                None => Core::Var(name),
+            },
+            Core::Const(_) => core,
+            Core::Lambda(formals, body) => Core::Lambda(formals, Box::new(self.simplify(*body))),
+            Core::If(c, t, e) => self.simplify_if(*c, *t, *e),
+            Core::Call(rator, rands) => self.simplify_call(*rator, rands, false),
+            Core::TailCall(rator, rands) => self.simplify_call(*rator, rands, true),
+            Core::Let(bs, body) => self.simplify_let(bs, *body),
+            Core::LetRec(bs, body) => self.simplify_letrec(bs, *body),
+            Core::Begin(exprs) => self.simplify_begin(exprs),
+            Core::The(ty, expr) => match self.simplify(*expr) {
+                // a constant which passes the check doesn't need checking
+                Core::Const(d) if datum_type(&d).is_some_and(|t| t.within(ty)) => {
+                    self.changed = true;
+                    Core::Const(d)
+                }
+                expr => Core::The(ty, Box::new(expr)),
+            },
+            Core::Define(name, expr) => Core::Define(name, Box::new(self.simplify(*expr))),
+        }
+    }
+
+    fn simplify_if(&mut self, c: Core, t: Core, e: Core) -> Core {
+        match self.simplify(c) {
+            // anything other than `#f` counts as true
+            Core::Const(d) => {
+                self.changed = true;
+                if matches!(d, Datum::Bool(false)) {
+                    self.simplify(e)
+                } else {
+                    self.simplify(t)
+                }
+            }
+            // (if (not x) t e) => (if x e t)
+            Core::Call(rator, mut rands)
+                if rands.len() == 1 && self.prim_of(&rator).is_some_and(|p| p.name == "not") =>
+            {
+                self.changed = true;
+                Core::If(
+                    Box::new(rands.pop().unwrap()),
+                    Box::new(self.simplify(e)),
+                    Box::new(self.simplify(t)),
+                )
+            }
+            c => Core::If(
+                Box::new(c),
+                Box::new(self.simplify(t)),
+                Box::new(self.simplify(e)),
+            ),
+        }
+    }
+
+    fn simplify_call(&mut self, rator: Core, rands: Vec<Core>, tail: bool) -> Core {
+        let rator = self.simplify(rator);
+        let rands: Vec<Core> = rands.into_iter().map(|r| self.simplify(r)).collect();
+
+        if let Some(p) = self.prim_of(&rator) {
+            let consts: Option<Vec<Datum>> = rands
+                .iter()
+                .map(|r| match r {
+                    Core::Const(d) => Some(d.clone()),
+                    _ => None,
+                })
+                .collect();
+            if let Some(folded) = consts.and_then(|ds| prim::fold(p.name, &ds)) {
+                self.changed = true;
+                return Core::Const(folded);
+            }
+        }
+
+        match rator {
+            // ((lambda (x ...) body) arg ...) => (let ([x arg] ...) body)
+            Core::Lambda(formals, body)
+                if self.level >= OptLevel::O2 && formals.len() == rands.len() =>
+            {
+                self.changed = true;
+                Core::Let(formals.into_iter().zip(rands).collect(), body)
+            }
+            rator if tail => Core::TailCall(Box::new(rator), rands),
+            rator => Core::Call(Box::new(rator), rands),
+        }
+    }
+
+    fn simplify_let(&mut self, bs: Vec<(Symbol, Core)>, body: Core) -> Core {
+        let mut kept = Vec::new();
+        for (name, init) in bs {
+            let init = self.simplify(init);
+            let is_copy = match &init {
+                Core::Var(_) => true,
+                Core::Const(d) => prim::is_immediate(d),
+                _ => false,
+            };
+            if is_copy && self.level >= OptLevel::O2 {
+                self.changed = true;
+                self.copies.insert(name, init);
+            } else {
+                kept.push((name, init));
+            }
+        }
+
+        let body = self.simplify(body);
+        if self.level >= OptLevel::O2 {
+            let before = kept.len();
+            kept.retain(|(name, init)| occurrences(&body, *name) > 0 || !self.is_pure(init));
+            self.changed |= kept.len() != before;
+        }
+
+        if kept.is_empty() {
+            body
+        } else {
+            Core::Let(kept, Box::new(body))
+        }
+    }
+
+    fn simplify_letrec(&mut self, bs: Vec<(Symbol, Core)>, body: Core) -> Core {
+        let mut bs: Vec<(Symbol, Core)> = bs
+            .into_iter()
+            .map(|(name, init)| (name, self.simplify(init)))
+            .collect();
+        let body = self.simplify(body);
+
+        if self.level >= OptLevel::O2 {
+            // a binding is live if the body uses it, or another live binding does
+            let mut live: HashSet<Symbol> = bs
+                .iter()
+                .filter(|(name, init)| occurrences(&body, *name) > 0 || !self.is_pure(init))
+                .map(|(name, _)| *name)
+                .collect();
+            loop {
+                let more: Vec<Symbol> = bs
+                    .iter()
+                    .filter(|(name, _)| !live.contains(name))
+                    .filter(|(name, _)| {
+                        bs.iter().any(|(other, init)| {
+                            live.contains(other) && occurrences(init, *name) > 0
+                        })
+                    })
+                    .map(|(name, _)| *name)
+                    .collect();
+                if more.is_empty() {
+                    break;
+                }
+                live.extend(more);
+            }
+            let before = bs.len();
+            bs.retain(|(name, _)| live.contains(name));
+            self.changed |= bs.len() != before;
+        }
+
+        if bs.is_empty() {
+            body
+        } else {
+            Core::LetRec(bs, Box::new(body))
+        }
+    }
+
+    fn simplify_begin(&mut self, exprs: Vec<Core>) -> Core {
+        let mut flat = Vec::new();
+        for expr in exprs {
+            match self.simplify(expr) {
+                Core::Begin(inner) => {
+                    self.changed = true;
+                    flat.extend(inner)
+                }
+                expr => flat.push(expr),
+            }
+        }
+
+        if self.level >= OptLevel::O2 {
+            if let Some(last) = flat.pop() {
+                let before = flat.len();
+                flat.retain(|e| !self.is_pure(e));
+                self.changed |= flat.len() != before;
+                flat.push(last);
+            }
+        }
+
+        match flat.len() {
+            0 => Core::Const(Datum::Undefined),
+            1 => flat.pop().unwrap(),
+            _ => Core::Begin(flat),
+        }
+    }
+}
+
+/// Whether calling `p` with `rands` can't fault: it takes that many arguments,
+/// and every one it restricts is a constant of the right kind
+fn cannot_fault(p: &Prim, rands: &[Core]) -> bool {
+    p.arity.accepts(rands.len())
+        && prim::is_total(p.name)
+        && rands
+            .iter()
+            .enumerate()
+            .all(|(i, r)| match prim::arg_kind(p.name, i) {
+                Some(kind) => matches!(r, Core::Const(d) if kind.admits(d)),
+                None => true,
+            })
+}
+
+/// How many times `name` is referenced in `core`
+pub fn occurrences(core: &Core, name: Symbol) -> usize {
+    match core {
+        Core::Var(n) => (*n == name) as usize,
+        Core::Const(_) => 0,
+        Core::Lambda(_, body) => occurrences(body, name),
+        Core::If(c, t, e) => occurrences(c, name) + occurrences(t, name) + occurrences(e, name),
+        Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+            occurrences(rator, name) + rands.iter().map(|r| occurrences(r, name)).sum::<usize>()
+        }
+        Core::Let(bs, body) | Core::LetRec(bs, body) => {
+            bs.iter().map(|(_, i)| occurrences(i, name)).sum::<usize>() + occurrences(body, name)
+        }
+        Core::Begin(exprs) => exprs.iter().map(|e| occurrences(e, name)).sum(),
+        Core::The(_, expr) | Core::Define(_, expr) => occurrences(expr, name),
+    }
+}
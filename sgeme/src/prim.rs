@@ -0,0 +1,450 @@
+//! Primitive procedures the compiler knows about, and what it is allowed to
+//! assume about them
+
+use crate::datum::Datum;
+use crate::primsyn::Type;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    /// From the first to the second, inclusive
+    Between(usize, usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match self {
+            Self::Exactly(m) => n == *m,
+            Self::AtLeast(m) => n >= *m,
+            Self::Between(lo, hi) => (*lo..=*hi).contains(&n),
+        }
+    }
+}
+
+/// A primitive procedure:
+/// + `arity`: how many arguments it takes
+/// + `pure`: calling it has no side effects, so an unused call can be dropped
+///   if it can't fault either
+#[derive(Debug)]
+pub struct Prim {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub pure: bool,
+}
+
+const fn prim(name: &'static str, arity: Arity, pure: bool) -> Prim {
+    Prim { name, arity, pure }
+}
+
+use Arity::*;
+
+pub static PRIMS: &[Prim] = &[
+    prim("+", AtLeast(0), true),
+    prim("-", AtLeast(1), true),
+    prim("*", AtLeast(0), true),
+    prim("/", AtLeast(1), true),
+    prim("quotient", Exactly(2), true),
+    prim("remainder", Exactly(2), true),
+    prim("modulo", Exactly(2), true),
+    prim("gcd", AtLeast(0), true),
+    prim("lcm", AtLeast(0), true),
+    prim("expt", Exactly(2), true),
+    prim("exact-integer-sqrt", Exactly(1), true),
+    prim("numerator", Exactly(1), true),
+    prim("denominator", Exactly(1), true),
+    prim("exact", Exactly(1), true),
+    prim("inexact", Exactly(1), true),
+    prim("floor", Exactly(1), true),
+    prim("ceiling", Exactly(1), true),
+    prim("truncate", Exactly(1), true),
+    prim("round", Exactly(1), true),
+    prim("sqrt", Exactly(1), true),
+    prim("exp", Exactly(1), true),
+    prim("log", Between(1, 2), true),
+    prim("sin", Exactly(1), true),
+    prim("cos", Exactly(1), true),
+    prim("tan", Exactly(1), true),
+    prim("asin", Exactly(1), true),
+    prim("acos", Exactly(1), true),
+    prim("atan", Between(1, 2), true),
+    prim("add1", Exactly(1), true),
+    prim("sub1", Exactly(1), true),
+    prim("=", AtLeast(1), true),
+    prim("<", AtLeast(1), true),
+    prim(">", AtLeast(1), true),
+    prim("<=", AtLeast(1), true),
+    prim(">=", AtLeast(1), true),
+    prim("zero?", Exactly(1), true),
+    prim("positive?", Exactly(1), true),
+    prim("negative?", Exactly(1), true),
+    prim("not", Exactly(1), true),
+    prim("eq?", Exactly(2), true),
+    prim("eqv?", Exactly(2), true),
+    prim("equal?", Exactly(2), true),
+    prim("null?", Exactly(1), true),
+    prim("pair?", Exactly(1), true),
+    prim("boolean?", Exactly(1), true),
+    prim("char?", Exactly(1), true),
+    prim("fixnum?", Exactly(1), true),
+    prim("integer?", Exactly(1), true),
+    prim("number?", Exactly(1), true),
+    prim("complex?", Exactly(1), true),
+    prim("real?", Exactly(1), true),
+    prim("rational?", Exactly(1), true),
+    prim("exact?", Exactly(1), true),
+    prim("inexact?", Exactly(1), true),
+    prim("exact-integer?", Exactly(1), true),
+    prim("nan?", Exactly(1), true),
+    prim("infinite?", Exactly(1), true),
+    prim("finite?", Exactly(1), true),
+    prim("string?", Exactly(1), true),
+    prim("symbol?", Exactly(1), true),
+    prim("vector?", Exactly(1), true),
+    prim("procedure?", Exactly(1), true),
+    prim("cons", Exactly(2), true),
+    prim("car", Exactly(1), true),
+    prim("cdr", Exactly(1), true),
+    prim("set-car!", Exactly(2), false),
+    prim("set-cdr!", Exactly(2), false),
+    prim("list", AtLeast(0), true),
+    prim("length", Exactly(1), true),
+    prim("vector", AtLeast(0), true),
+    prim("make-vector", Exactly(2), true),
+    prim("vector-length", Exactly(1), true),
+    prim("vector-ref", Exactly(2), true),
+    prim("vector-set!", Exactly(3), false),
+    prim("string-length", Exactly(1), true),
+    prim("string-ref", Exactly(2), true),
+    prim("char->integer", Exactly(1), true),
+    prim("integer->char", Exactly(1), true),
+    prim("list?", Exactly(1), true),
+    prim("append", AtLeast(0), true),
+    prim("reverse", Exactly(1), true),
+    prim("apply", AtLeast(2), false),
+    prim("call-with-current-continuation", Exactly(1), false),
+    prim("call/cc", Exactly(1), false),
+    prim("dynamic-wind", Exactly(3), false),
+    prim("with-exception-handler", Exactly(2), false),
+    prim("raise", Exactly(1), false),
+    prim("raise-continuable", Exactly(1), false),
+    prim("error", AtLeast(1), false),
+    prim("error-object?", Exactly(1), true),
+    prim("error-object-message", Exactly(1), true),
+    prim("error-object-irritants", Exactly(1), true),
+    prim("make-promise", Exactly(1), true),
+    prim("promise?", Exactly(1), true),
+    prim("force", Exactly(1), false),
+    prim("make-parameter", Between(1, 2), false),
+    prim("abs", Exactly(1), true),
+    prim("min", AtLeast(1), true),
+    prim("max", AtLeast(1), true),
+    prim("even?", Exactly(1), true),
+    prim("odd?", Exactly(1), true),
+    prim("string=?", AtLeast(1), true),
+    prim("string-append", AtLeast(0), true),
+    prim("symbol->string", Exactly(1), true),
+    prim("string->symbol", Exactly(1), true),
+    prim("number->string", Between(1, 2), true),
+    prim("string->number", Between(1, 2), true),
+    prim("char=?", AtLeast(1), true),
+    prim("display", Exactly(1), false),
+    prim("write", Exactly(1), false),
+    prim("newline", Exactly(0), false),
+    // runs the copying collector of the assembly backend's runtime; the
+    // other backends have no collector, so it does nothing on them
+    prim("collect", Exactly(0), false),
+    prim("%make-record", AtLeast(1), true),
+    prim("%record?", Exactly(2), true),
+    prim("%record-ref", Exactly(3), true),
+    prim("%make-lazy-promise", Exactly(1), true),
+    prim("%promise-done?", Exactly(1), true),
+    prim("%promise-value", Exactly(1), true),
+    prim("%promise-update!", Exactly(2), false),
+    prim("%make-parameter", Exactly(2), true),
+    prim("%parameter-converter", Exactly(1), true),
+    prim("%parameterize", Exactly(3), false),
+];
+
+pub fn lookup(name: &str) -> Option<&'static Prim> {
+    PRIMS.iter().find(|p| p.name == name)
+}
+
+/// Primitives which call the procedures they are given, or the handlers of
+/// the exceptions they raise, so are run by the machine itself rather than
+/// `builtins`
+pub fn calls_back(p: &Prim) -> bool {
+    matches!(
+        p.name,
+        "apply"
+            | "call-with-current-continuation"
+            | "call/cc"
+            | "dynamic-wind"
+            | "with-exception-handler"
+            | "raise"
+            | "raise-continuable"
+            | "error"
+            | "force"
+            | "make-parameter"
+            | "%parameterize"
+    )
+}
+
+/// The sort of value a primitive needs for one of its arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Number,
+    Pair,
+    Vector,
+    Str,
+    Char,
+    Symbol,
+}
+
+impl Kind {
+    /// The annotation type holding exactly the values of this kind
+    pub fn as_type(&self) -> Type {
+        match self {
+            Self::Number => Type::Number,
+            Self::Pair => Type::Pair,
+            Self::Vector => Type::Vector,
+            Self::Str => Type::Str,
+            Self::Char => Type::Char,
+            Self::Symbol => Type::Symbol,
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::Number => "a number",
+            Self::Pair => "a pair",
+            Self::Vector => "a vector",
+            Self::Str => "a string",
+            Self::Char => "a character",
+            Self::Symbol => "a symbol",
+        }
+    }
+
+    /// Does the constant `d` have this kind?
+    pub fn admits(&self, d: &Datum) -> bool {
+        match self {
+            Self::Number => matches!(
+                d,
+                Datum::Fixnum(_) | Datum::Big(_) | Datum::Ratio(_) | Datum::Flonum(_)
+            ),
+            Self::Pair => {
+                matches!(d, Datum::DottedList(..)) || matches!(d, Datum::List(ls) if !ls.is_empty())
+            }
+            Self::Vector => matches!(d, Datum::Vector(_)),
+            Self::Str => matches!(d, Datum::Str(_)),
+            Self::Char => matches!(d, Datum::Char(_)),
+            Self::Symbol => matches!(d, Datum::Symbol(_)),
+        }
+    }
+}
+
+/// The type every call to the primitive `name` returns, if it's always the same
+pub fn result_type(name: &str) -> Option<Type> {
+    match name {
+        "length" | "vector-length" | "string-length" | "char->integer" => Some(Type::Fixnum),
+        "+" | "-" | "*" | "/" | "quotient" | "remainder" | "modulo" | "add1" | "sub1" | "abs"
+        | "min" | "max" | "gcd" | "lcm" | "expt" | "numerator" | "denominator" | "exact"
+        | "inexact" | "floor" | "ceiling" | "truncate" | "round" | "sqrt" | "exp" | "log"
+        | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" => Some(Type::Number),
+        "=" | "<" | ">" | "<=" | ">=" | "zero?" | "positive?" | "negative?" | "not" | "eq?"
+        | "eqv?" | "equal?" | "null?" | "pair?" | "boolean?" | "char?" | "fixnum?" | "integer?"
+        | "number?" | "complex?" | "real?" | "rational?" | "exact?" | "inexact?"
+        | "exact-integer?" | "nan?" | "infinite?" | "finite?" | "string?" | "symbol?"
+        | "vector?" | "procedure?" | "%record?" | "list?" | "even?" | "odd?" | "string=?"
+        | "char=?" | "error-object?" | "promise?" | "%promise-done?" => Some(Type::Bool),
+        "cons" => Some(Type::Pair),
+        "vector" | "make-vector" => Some(Type::Vector),
+        "integer->char" | "string-ref" => Some(Type::Char),
+        "string-append" | "symbol->string" | "number->string" => Some(Type::Str),
+        "string->symbol" => Some(Type::Symbol),
+        _ => None,
+    }
+}
+
+/// What kind of value argument `i` (from 0) of the primitive `name` must be,
+/// if it's restricted at all
+pub fn arg_kind(name: &str, i: usize) -> Option<Kind> {
+    match (name, i) {
+        (
+            "+" | "-" | "*" | "/" | "quotient" | "remainder" | "modulo" | "gcd" | "lcm" | "expt"
+            | "exact-integer-sqrt" | "numerator" | "denominator" | "add1" | "sub1" | "=" | "<"
+            | ">" | "<=" | ">=" | "zero?" | "positive?" | "negative?" | "abs" | "min" | "max"
+            | "even?" | "odd?" | "exact?" | "inexact?" | "exact" | "inexact" | "floor" | "ceiling"
+            | "truncate" | "round" | "sqrt" | "exp" | "log" | "sin" | "cos" | "tan" | "asin"
+            | "acos" | "atan" | "nan?" | "infinite?" | "finite?",
+            _,
+        ) => Some(Kind::Number),
+        ("string=?" | "string-append", _) => Some(Kind::Str),
+        ("char=?", _) => Some(Kind::Char),
+        ("symbol->string", 0) => Some(Kind::Symbol),
+        ("string->symbol" | "string->number", 0) => Some(Kind::Str),
+        ("number->string", 0) => Some(Kind::Number),
+        ("number->string" | "string->number", 1) => Some(Kind::Number),
+        ("car" | "cdr" | "set-car!" | "set-cdr!", 0) => Some(Kind::Pair),
+        ("vector-length" | "vector-ref" | "vector-set!", 0) => Some(Kind::Vector),
+        ("vector-ref" | "vector-set!" | "string-ref", 1) => Some(Kind::Number),
+        ("make-vector" | "integer->char", 0) => Some(Kind::Number),
+        ("string-length" | "string-ref", 0) => Some(Kind::Str),
+        ("char->integer", 0) => Some(Kind::Char),
+        _ => None,
+    }
+}
+
+/// Whether the primitive `name` always returns when given arguments of the
+/// kinds `arg_kind` asks for, rather than faulting on some of their values
+pub fn is_total(name: &str) -> bool {
+    matches!(
+        name,
+        "=" | "<"
+            | ">"
+            | "<="
+            | ">="
+            | "zero?"
+            | "positive?"
+            | "negative?"
+            | "not"
+            | "eq?"
+            | "eqv?"
+            | "equal?"
+            | "null?"
+            | "pair?"
+            | "boolean?"
+            | "char?"
+            | "fixnum?"
+            | "integer?"
+            | "number?"
+            | "complex?"
+            | "real?"
+            | "rational?"
+            | "exact?"
+            | "inexact?"
+            | "exact-integer?"
+            | "string?"
+            | "symbol?"
+            | "vector?"
+            | "procedure?"
+            | "cons"
+            | "car"
+            | "cdr"
+            | "list"
+            | "vector"
+            | "vector-length"
+            | "string-length"
+            | "char->integer"
+            | "list?"
+            | "string=?"
+            | "string-append"
+            | "symbol->string"
+            | "string->symbol"
+            | "char=?"
+    )
+}
+
+/// Data which can be duplicated freely without anyone noticing, as they
+/// don't have an identity of their own
+pub fn is_immediate(d: &Datum) -> bool {
+    matches!(
+        d,
+        Datum::Bool(_) | Datum::Fixnum(_) | Datum::Char(_) | Datum::Null | Datum::Undefined
+    )
+}
+
+fn fixnums(args: &[Datum]) -> Option<Vec<i32>> {
+    args.iter()
+        .map(|a| match a {
+            Datum::Fixnum(n) => Some(*n),
+            _ => None,
+        })
+        .collect()
+}
+
+fn compare(args: &[Datum], f: fn(i32, i32) -> bool) -> Option<Datum> {
+    let ns = fixnums(args)?;
+    Some(Datum::Bool(ns.windows(2).all(|w| f(w[0], w[1]))))
+}
+
+/// Evaluate a primitive on constant arguments at compile time. Gives up
+/// (returning `None`) on anything that would error or overflow at runtime,
+/// so that the runtime gets to report it instead.
+pub fn fold(name: &str, args: &[Datum]) -> Option<Datum> {
+    if !lookup(name)?.arity.accepts(args.len()) {
+        return None;
+    }
+    match (name, args) {
+        ("+", _) => fixnums(args)?
+            .into_iter()
+            .try_fold(0i32, |acc, n| acc.checked_add(n))
+            .map(Datum::Fixnum),
+        ("*", _) => fixnums(args)?
+            .into_iter()
+            .try_fold(1i32, |acc, n| acc.checked_mul(n))
+            .map(Datum::Fixnum),
+        ("-", [Datum::Fixnum(n)]) => n.checked_neg().map(Datum::Fixnum),
+        ("-", [first, rest @ ..]) => {
+            let first = fixnums(std::slice::from_ref(first))?[0];
+            fixnums(rest)?
+                .into_iter()
+                .try_fold(first, |acc, n| acc.checked_sub(n))
+                .map(Datum::Fixnum)
+        }
+        ("quotient", [Datum::Fixnum(a), Datum::Fixnum(b)]) => a.checked_div(*b).map(Datum::Fixnum),
+        ("remainder", [Datum::Fixnum(a), Datum::Fixnum(b)]) => a.checked_rem(*b).map(Datum::Fixnum),
+        ("modulo", [Datum::Fixnum(a), Datum::Fixnum(b)]) => a
+            .checked_rem(*b)
+            .map(|r| {
+                if r != 0 && (r < 0) != (*b < 0) {
+                    r + b
+                } else {
+                    r
+                }
+            })
+            .map(Datum::Fixnum),
+        ("add1", [Datum::Fixnum(n)]) => n.checked_add(1).map(Datum::Fixnum),
+        ("sub1", [Datum::Fixnum(n)]) => n.checked_sub(1).map(Datum::Fixnum),
+        ("=", _) => compare(args, |a, b| a == b),
+        ("<", _) => compare(args, |a, b| a < b),
+        (">", _) => compare(args, |a, b| a > b),
+        ("<=", _) => compare(args, |a, b| a <= b),
+        (">=", _) => compare(args, |a, b| a >= b),
+        ("zero?", [Datum::Fixnum(n)]) => Some(Datum::Bool(*n == 0)),
+        ("not", [d]) => Some(Datum::Bool(matches!(d, Datum::Bool(false)))),
+        ("eq?" | "eqv?", [a, b]) if is_immediate(a) && is_immediate(b) => {
+            Some(Datum::Bool(immediate_eqv(a, b)))
+        }
+        ("null?", [d]) => Some(Datum::Bool(
+            matches!(d, Datum::Null) || matches!(d, Datum::List(ls) if ls.is_empty()),
+        )),
+        ("boolean?", [d]) => Some(Datum::Bool(matches!(d, Datum::Bool(_)))),
+        ("char?", [d]) => Some(Datum::Bool(matches!(d, Datum::Char(_)))),
+        ("fixnum?", [d]) => Some(Datum::Bool(matches!(d, Datum::Fixnum(_)))),
+        ("integer?", [Datum::Flonum(x)]) => Some(Datum::Bool(x.is_finite() && x.fract() == 0.0)),
+        ("rational?", [Datum::Flonum(x)]) => Some(Datum::Bool(x.is_finite())),
+        ("integer?" | "exact-integer?", [d]) => {
+            Some(Datum::Bool(matches!(d, Datum::Fixnum(_) | Datum::Big(_))))
+        }
+        ("number?" | "complex?" | "real?" | "rational?", [d]) => {
+            Some(Datum::Bool(Kind::Number.admits(d)))
+        }
+        ("string?", [d]) => Some(Datum::Bool(matches!(d, Datum::Str(_)))),
+        ("symbol?", [d]) => Some(Datum::Bool(matches!(d, Datum::Symbol(_)))),
+        ("vector?", [d]) => Some(Datum::Bool(matches!(d, Datum::Vector(_)))),
+        ("char->integer", [Datum::Char(c)]) => Some(Datum::Fixnum(*c as i32)),
+        ("integer->char", [Datum::Fixnum(n)]) => char::from_u32(*n as u32).map(Datum::Char),
+        _ => None,
+    }
+}
+
+fn immediate_eqv(a: &Datum, b: &Datum) -> bool {
+    match (a, b) {
+        (Datum::Bool(x), Datum::Bool(y)) => x == y,
+        (Datum::Fixnum(x), Datum::Fixnum(y)) => x == y,
+        (Datum::Char(x), Datum::Char(y)) => x == y,
+        (Datum::Null, Datum::Null) | (Datum::Undefined, Datum::Undefined) => true,
+        _ => false,
+    }
+}
@@ -2,6 +2,7 @@
 //! grant of me (the compiler)
 
 use crate::datum::Datum;
+use crate::symbol::Symbol;
 
 #[derive(Debug)]
 pub struct Program {
@@ -32,14 +33,14 @@ pub enum Stmt {
 
 #[derive(Debug)]
 pub enum Def {
-    DefValue(String, Expr),
-    DefFunc(String, Vec<String>, Expr),
-    DefRecord(String, Vec<String>),
+    DefValue(Symbol, Expr),
+    DefFunc(Symbol, Vec<Formal>, Expr),
+    DefRecord(Symbol, Vec<Symbol>),
 }
 
 #[derive(Debug)]
 pub enum Expr {
-    Symbol(String),
+    Symbol(Symbol),
     Bool(bool),
     Fixnum(i32),
     Vector(Vec<Datum>),
@@ -48,7 +49,7 @@ pub enum Expr {
     Quote(Datum),
     Unquote(Datum),
     ProcCall(Box<Self>, Vec<Self>),
-    Lambda(Vec<String>, Box<Self>),
+    Lambda(Vec<Formal>, Box<Self>),
     If(Box<Self>, Box<Self>, Box<Self>),
     Cond(Vec<(Self, Self)>, Box<Self>),
     Case(Box<Self>, Vec<(Vec<Datum>, Sequence)>, Sequence),
@@ -61,6 +62,79 @@ pub enum Expr {
     Begin(Sequence),
 }
 
-pub type BindingSpec = (String, Expr);
+pub type BindingSpec = (Symbol, Expr);
+
+/// A procedure parameter, optionally annotated as `[<ident> : <type>]`
+pub type Formal = (Symbol, Option<Type>);
+
+/// Types which can be given in annotations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Fixnum,
+    /// Any number, fixnums included
+    Number,
+    Bool,
+    Char,
+    Str,
+    Symbol,
+    Pair,
+    Vector,
+    Proc,
+}
+
+impl Type {
+    /// Every type, in the order declared, so `ALL[ty as usize] == ty`
+    pub const ALL: [Self; 9] = [
+        Self::Fixnum,
+        Self::Number,
+        Self::Bool,
+        Self::Char,
+        Self::Str,
+        Self::Symbol,
+        Self::Pair,
+        Self::Vector,
+        Self::Proc,
+    ];
+
+    /// Parse the name of a type as written in an annotation
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "Fixnum" => Some(Self::Fixnum),
+            "Number" => Some(Self::Number),
+            "Boolean" => Some(Self::Bool),
+            "Char" => Some(Self::Char),
+            "String" => Some(Self::Str),
+            "Symbol" => Some(Self::Symbol),
+            "Pair" => Some(Self::Pair),
+            "Vector" => Some(Self::Vector),
+            "Procedure" => Some(Self::Proc),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Fixnum => "Fixnum",
+            Self::Number => "Number",
+            Self::Bool => "Boolean",
+            Self::Char => "Char",
+            Self::Str => "String",
+            Self::Symbol => "Symbol",
+            Self::Pair => "Pair",
+            Self::Vector => "Vector",
+            Self::Proc => "Procedure",
+        }
+    }
+
+    /// Is every value of this type also of type `other`?
+    pub fn within(self, other: Self) -> bool {
+        self == other || (self, other) == (Self::Fixnum, Self::Number)
+    }
+
+    /// Could a value be of both this type and `other`?
+    pub fn overlaps(self, other: Self) -> bool {
+        self.within(other) || other.within(self)
+    }
+}
 
 pub type Sequence = Vec<Expr>;
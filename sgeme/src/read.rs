@@ -1,11 +1,14 @@
 //! Bootsrapping reader with some associated options,
 //! not fully R7RS compliant
 
+use std::fmt;
 use std::iter::Peekable;
 use std::slice::Iter;
 
 use crate::datum::{AbbrevPrefix, Datum};
 use crate::expander::{Expander, ExpanderError};
+use crate::number::Number;
+use crate::symbol::Symbol;
 use crate::token::{Logos, Token};
 
 /// Reader struct which contains reading options:
@@ -22,11 +25,32 @@ pub enum ReadError {
     UnknownSymbol(Token),
     UnexpectedListTerminator(Token),
     ExpectedListTerminator(Token),
+    /// What the lexer took for a number, such as `#xZZ`, with no number
+    /// written that way
+    MalformedNumber(String),
     UnhandledQuote,
     ExpandError(ExpanderError),
     Nothing,
 }
 
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownSymbol(tok) => write!(f, "unexpected token {tok:?}"),
+            Self::UnexpectedListTerminator(tok) => {
+                write!(f, "unexpected {tok:?}, with no list to close")
+            }
+            Self::ExpectedListTerminator(tok) => {
+                write!(f, "expected the end of a list, found {tok:?}")
+            }
+            Self::MalformedNumber(s) => write!(f, "malformed number `{s}`"),
+            Self::UnhandledQuote => write!(f, "nothing to quote at the end of input"),
+            Self::ExpandError(e) => write!(f, "{e}"),
+            Self::Nothing => write!(f, "nothing to read"),
+        }
+    }
+}
+
 impl From<ExpanderError> for ReadError {
     fn from(value: ExpanderError) -> Self {
         Self::ExpandError(value)
@@ -62,6 +86,10 @@ impl<'a> Reader<'a> {
         let curr = self.src.peek().unwrap();
         match curr {
             Token::Dot => return Ok(Datum::List(sexpr)),
+            t if *t == &terminator => {
+                self.src.next();
+                return Ok(Datum::List(sexpr));
+            }
             _ => (),
         }
 
@@ -74,7 +102,6 @@ impl<'a> Reader<'a> {
                 self.src.next();
                 let other = self.read_expr()?;
                 let maybe_term = self.src.next().unwrap();
-                dbg!("here");
                 if maybe_term == &terminator {
                     return Ok(Datum::DottedList(sexpr, Box::new(other)));
                 } else {
@@ -138,13 +165,15 @@ impl<'a> Reader<'a> {
             match curr {
                 Token::Ident(i) | Token::Prim(i) => {
                     if self.case_insensitive {
-                        Ok(Datum::Symbol(i.to_ascii_lowercase()))
+                        Ok(Datum::Symbol(Symbol::intern(&i.to_ascii_lowercase())))
                     } else {
-                        Ok(Datum::Symbol(i.clone()))
+                        Ok(Datum::Symbol(Symbol::intern(i)))
                     }
                 }
                 Token::Bool(b) => Ok(Datum::Bool(*b)),
-                Token::Fixnum(f) => Ok(Datum::Fixnum(*f)),
+                Token::Number(n) => Number::parse(n, 10)
+                    .map(Number::into_datum)
+                    .ok_or_else(|| ReadError::MalformedNumber(n.clone())),
                 Token::Char(c) => Ok(Datum::Char(*c)),
                 Token::Str(s) => Ok(Datum::Str(s.clone())),
                 Token::Ellipses => Ok(Datum::Ellipses),
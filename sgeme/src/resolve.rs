@@ -0,0 +1,106 @@
+//! Scope resolution: give every locally bound variable a unique name, so
+//! later passes can move code around without worrying about shadowing
+
+use std::collections::HashMap;
+
+use crate::core_former::Core;
+use crate::symbol::Symbol;
+
+/// The name a resolved variable was written with in the source
+pub fn source_name(name: &str) -> &str {
+    name.split('#').next().unwrap_or(name)
+}
+
+/// Renames every `lambda`, `let` and `letrec` binding to an uninterned
+/// `<name>#<n>`. Anything left with its original name afterwards refers to a
+/// top-level definition or a primitive.
+pub struct Resolver {
+    count: usize,
+    scopes: Vec<HashMap<Symbol, Symbol>>,
+}
+
+impl Resolver {
+    pub fn init() -> Self {
+        Self {
+            count: 0,
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Generate a fresh name for a binding, which never clashes with a
+    /// global as it's uninterned; the number only tells them apart in dumps
+    fn fresh(&mut self, name: Symbol) -> Symbol {
+        self.count += 1;
+        Symbol::uninterned(&format!("{}#{}", source_name(&name), self.count))
+    }
+
+    fn lookup(&self, name: Symbol) -> Option<Symbol> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name).copied())
+    }
+
+    fn bind(&mut self, names: &[Symbol]) -> Vec<Symbol> {
+        let mut scope = HashMap::new();
+        let mut renamed = Vec::new();
+        for name in names {
+            let fresh = self.fresh(*name);
+            scope.insert(*name, fresh);
+            renamed.push(fresh);
+        }
+        self.scopes.push(scope);
+        renamed
+    }
+
+    /// Rename every binding in `core`, including its parameters if it's a
+    /// `lambda`; both the first resolution of a program and the inliner copying
+    /// a procedure body go through here, so names stay unique across both
+    pub fn resolve(&mut self, core: Core) -> Core {
+        match core {
+            Core::Var(name) => match self.lookup(name) {
+                Some(renamed) => Core::Var(renamed),
+                None => Core::Var(name),
+            },
+            Core::Const(_) => core,
+            Core::Lambda(formals, body) => {
+                let formals = self.bind(&formals);
+                let body = self.resolve(*body);
+                self.scopes.pop();
+                Core::Lambda(formals, Box::new(body))
+            }
+            Core::If(c, t, e) => Core::If(
+                Box::new(self.resolve(*c)),
+                Box::new(self.resolve(*t)),
+                Box::new(self.resolve(*e)),
+            ),
+            Core::Call(rator, rands) => Core::Call(
+                Box::new(self.resolve(*rator)),
+                rands.into_iter().map(|r| self.resolve(r)).collect(),
+            ),
+            Core::TailCall(rator, rands) => Core::TailCall(
+                Box::new(self.resolve(*rator)),
+                rands.into_iter().map(|r| self.resolve(r)).collect(),
+            ),
+            Core::Let(bs, body) => {
+                let (names, inits): (Vec<_>, Vec<_>) = bs.into_iter().unzip();
+                let inits: Vec<Core> = inits.into_iter().map(|i| self.resolve(i)).collect();
+                let names = self.bind(&names);
+                let body = self.resolve(*body);
+                self.scopes.pop();
+                Core::Let(names.into_iter().zip(inits).collect(), Box::new(body))
+            }
+            Core::LetRec(bs, body) => {
+                let (names, inits): (Vec<_>, Vec<_>) = bs.into_iter().unzip();
+                let names = self.bind(&names);
+                let inits: Vec<Core> = inits.into_iter().map(|i| self.resolve(i)).collect();
+                let body = self.resolve(*body);
+                self.scopes.pop();
+                Core::LetRec(names.into_iter().zip(inits).collect(), Box::new(body))
+            }
+            Core::Begin(exprs) => Core::Begin(exprs.into_iter().map(|e| self.resolve(e)).collect()),
+            Core::The(ty, expr) => Core::The(ty, Box::new(self.resolve(*expr))),
+            Core::Define(name, expr) => Core::Define(name, Box::new(self.resolve(*expr))),
+        }
+    }
+}
@@ -0,0 +1,282 @@
+/* The runtime of the C programs made by `cgen::CGen`, which come right after
+   it in the same translation unit.
+
+   Values are laid out as `word` describes: a fixnum `n` is `n << 32`, and
+   otherwise the low three bits tag a pointer to a pair `[car, cdr]` or a
+   closure `[code, arity, free...]`, or mark an immediate, such as a symbol,
+   which holds its index into the program's table of symbols.
+   Errors are reported here, ending the program. Numbers are fixnums alone,
+   so a result too big for one is reported as an overflow rather than
+   promoted as in the interpreter. */
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef int64_t sg_value;
+/* The code of a closure, cast to the type for its arity when called */
+typedef void (*sg_code)(void);
+
+#define SG_FIXNUM_SHIFT 32
+#define SG_PAIR_TAG 1
+#define SG_CLOSURE_TAG 2
+#define SG_FALSE 0x07
+#define SG_TRUE 0x0F
+#define SG_NULL 0x17
+#define SG_UNSPECIFIED 0x1F
+#define SG_UNBOUND 0x27
+#define SG_CHAR_TAG 0x2F
+/* What a procedure returns to have the trampoline of the call it returns to
+   make a tail call for it, never a value */
+#define SG_TAIL_CALL 0x4F
+#define SG_SYMBOL_TAG 0x57
+
+#define SG_FIX(n) ((sg_value)((uint64_t)(int64_t)(n) << SG_FIXNUM_SHIFT))
+#define SG_UNFIX(v) ((int64_t)((v) >> SG_FIXNUM_SHIFT))
+#define SG_CHAR(c) ((sg_value)(((uint64_t)(c) << 8) | SG_CHAR_TAG))
+#define SG_SYMBOL(i) ((sg_value)(((uint64_t)(i) << 8) | SG_SYMBOL_TAG))
+#define SG_BOOL(b) ((b) ? SG_TRUE : SG_FALSE)
+
+#define SG_FIXNUM_P(v) (((v) & 0xFFFFFFFF) == 0)
+#define SG_BOOLEAN_P(v) (((v) & ~0x08) == SG_FALSE)
+#define SG_CHAR_P(v) (((v) & 0xFF) == SG_CHAR_TAG)
+#define SG_SYMBOL_P(v) (((v) & 0xFF) == SG_SYMBOL_TAG)
+#define SG_PAIR_P(v) (((v) & 0x07) == SG_PAIR_TAG)
+#define SG_PROCEDURE_P(v) (((v) & 0x07) == SG_CLOSURE_TAG)
+
+#define SG_CAR(v) (((sg_value *)(intptr_t)((v) - SG_PAIR_TAG))[0])
+#define SG_CDR(v) (((sg_value *)(intptr_t)((v) - SG_PAIR_TAG))[1])
+#define SG_CODE(v) (((sg_value *)(intptr_t)((v) - SG_CLOSURE_TAG))[0])
+#define SG_ARITY(v) (((sg_value *)(intptr_t)((v) - SG_CLOSURE_TAG))[1])
+#define SG_FREE(v, i) (((sg_value *)(intptr_t)((v) - SG_CLOSURE_TAG))[2 + (i)])
+
+/* The name of the procedure whose code is at `code`, or NULL if it has
+   none. Made along with the program. */
+static const char *sg_proc_name(sg_value code);
+
+/* The name of the symbol at index `i` into the program's table of symbols.
+   Made along with the program. */
+static const char *sg_symbol_name(int64_t i);
+
+static inline sg_value *sg_alloc(size_t words) {
+    sg_value *block = malloc(words * sizeof(sg_value));
+    if (block == NULL) {
+        fflush(stdout);
+        fputs("error: out of memory\n", stderr);
+        exit(1);
+    }
+    return block;
+}
+
+static inline sg_value sg_cons(sg_value car, sg_value cdr) {
+    sg_value *pair = sg_alloc(2);
+    pair[0] = car;
+    pair[1] = cdr;
+    return (sg_value)(intptr_t)pair + SG_PAIR_TAG;
+}
+
+/* A closure of `code` without its free variables filled in */
+static inline sg_value sg_closure(sg_code code, int64_t arity, size_t free) {
+    sg_value *clo = sg_alloc(2 + free);
+    clo[0] = (sg_value)(intptr_t)code;
+    clo[1] = arity;
+    return (sg_value)(intptr_t)clo + SG_CLOSURE_TAG;
+}
+
+static inline void sg_put_char(FILE *out, uint32_t c) {
+    if (c < 0x80) {
+        fputc((int)c, out);
+    } else if (c < 0x800) {
+        fputc((int)(0xC0 | c >> 6), out);
+        fputc((int)(0x80 | (c & 0x3F)), out);
+    } else if (c < 0x10000) {
+        fputc((int)(0xE0 | c >> 12), out);
+        fputc((int)(0x80 | (c >> 6 & 0x3F)), out);
+        fputc((int)(0x80 | (c & 0x3F)), out);
+    } else {
+        fputc((int)(0xF0 | c >> 18), out);
+        fputc((int)(0x80 | (c >> 12 & 0x3F)), out);
+        fputc((int)(0x80 | (c >> 6 & 0x3F)), out);
+        fputc((int)(0x80 | (c & 0x3F)), out);
+    }
+}
+
+/* Print `v` as `write` does if `write` is set, and as `display` does
+   otherwise */
+static inline void sg_print(FILE *out, sg_value v, int write) {
+    if (SG_FIXNUM_P(v)) {
+        fprintf(out, "%lld", (long long)SG_UNFIX(v));
+    } else if (v == SG_FALSE) {
+        fputs("#f", out);
+    } else if (v == SG_TRUE) {
+        fputs("#t", out);
+    } else if (v == SG_NULL) {
+        fputs("()", out);
+    } else if (v == SG_UNSPECIFIED) {
+        fputs("#<unspecified>", out);
+    } else if (SG_CHAR_P(v)) {
+        uint32_t c = (uint32_t)((uint64_t)v >> 8);
+        if (!write) {
+            sg_put_char(out, c);
+        } else if (c == ' ') {
+            fputs("#\\space", out);
+        } else if (c == '\n') {
+            fputs("#\\newline", out);
+        } else {
+            fputs("#\\", out);
+            sg_put_char(out, c);
+        }
+    } else if (SG_SYMBOL_P(v)) {
+        fputs(sg_symbol_name((int64_t)((uint64_t)v >> 8)), out);
+    } else if (SG_PAIR_P(v)) {
+        sg_value tail = SG_CDR(v);
+        fputc('(', out);
+        sg_print(out, SG_CAR(v), write);
+        while (tail != SG_NULL) {
+            if (SG_PAIR_P(tail)) {
+                fputc(' ', out);
+                sg_print(out, SG_CAR(tail), write);
+                tail = SG_CDR(tail);
+            } else {
+                fputs(" . ", out);
+                sg_print(out, tail, write);
+                break;
+            }
+        }
+        fputc(')', out);
+    } else if (SG_PROCEDURE_P(v)) {
+        const char *name = sg_proc_name(SG_CODE(v));
+        if (name != NULL) {
+            fprintf(out, "#<procedure %s>", name);
+        } else {
+            fputs("#<procedure>", out);
+        }
+    } else {
+        fprintf(out, "#<unknown %#llx>", (unsigned long long)v);
+    }
+}
+
+static inline sg_value sg_display(sg_value v) {
+    sg_print(stdout, v, 0);
+    return SG_UNSPECIFIED;
+}
+
+static inline sg_value sg_write(sg_value v) {
+    sg_print(stdout, v, 1);
+    return SG_UNSPECIFIED;
+}
+
+static inline sg_value sg_newline(void) {
+    fputc('\n', stdout);
+    return SG_UNSPECIFIED;
+}
+
+/* Start reporting an error, which the caller finishes with `sg_fail` */
+static inline void sg_error(void) {
+    fflush(stdout);
+    fputs("error: ", stderr);
+}
+
+static inline void sg_fail(void) {
+    fputc('\n', stderr);
+    exit(1);
+}
+
+static inline void sg_unbound(const char *name) {
+    sg_error();
+    fprintf(stderr, "unbound variable `%s`", name);
+    sg_fail();
+}
+
+static inline void sg_wrong_type(const char *prim, const char *expected, sg_value v) {
+    sg_error();
+    fprintf(stderr, "`%s` expects %s, but is given `", prim, expected);
+    sg_print(stderr, v, 1);
+    fputc('`', stderr);
+    sg_fail();
+}
+
+static inline void sg_annotation_failed(const char *type, sg_value v) {
+    sg_error();
+    fputc('`', stderr);
+    sg_print(stderr, v, 1);
+    fprintf(stderr, "` is not a %s", type);
+    sg_fail();
+}
+
+static inline void sg_division_by_zero(const char *prim) {
+    sg_error();
+    fprintf(stderr, "`%s`: division by zero", prim);
+    sg_fail();
+}
+
+static inline void sg_overflow(const char *prim) {
+    sg_error();
+    fprintf(stderr, "`%s`: fixnum overflow, not supported on this backend", prim);
+    sg_fail();
+}
+
+/* Fail unless `v` is a procedure taking `n` arguments */
+static inline void sg_check_call(sg_value v, int64_t n) {
+    const char *name;
+    if (!SG_PROCEDURE_P(v)) {
+        sg_error();
+        fputc('`', stderr);
+        sg_print(stderr, v, 1);
+        fputs("` is not a procedure, but is called", stderr);
+        sg_fail();
+    }
+    if (SG_ARITY(v) != n) {
+        name = sg_proc_name(SG_CODE(v));
+        sg_error();
+        fprintf(stderr, "`%s` expects %lld argument(s), but is called with %lld",
+                name != NULL ? name : "procedure", (long long)SG_ARITY(v), (long long)n);
+        sg_fail();
+    }
+}
+
+/* `n` as a fixnum, if it fits in 32 bits */
+static inline sg_value sg_fixnum(int64_t n, const char *prim) {
+    if (n < INT32_MIN || n > INT32_MAX) {
+        sg_overflow(prim);
+    }
+    return SG_FIX(n);
+}
+
+static inline sg_value sg_add(sg_value a, sg_value b, const char *prim) {
+    return sg_fixnum(SG_UNFIX(a) + SG_UNFIX(b), prim);
+}
+
+static inline sg_value sg_sub(sg_value a, sg_value b, const char *prim) {
+    return sg_fixnum(SG_UNFIX(a) - SG_UNFIX(b), prim);
+}
+
+static inline sg_value sg_mul(sg_value a, sg_value b, const char *prim) {
+    return sg_fixnum(SG_UNFIX(a) * SG_UNFIX(b), prim);
+}
+
+/* `quotient`, `remainder` or `modulo`, as `op` is 'q', 'r' or 'm' */
+static inline sg_value sg_divide(sg_value a, sg_value b, char op, const char *prim) {
+    int64_t x = SG_UNFIX(a), y = SG_UNFIX(b), r;
+    if (y == 0) {
+        sg_division_by_zero(prim);
+    }
+    if (op == 'q') {
+        return sg_fixnum(x / y, prim);
+    }
+    r = x % y;
+    /* `modulo` takes the sign of the divisor rather than the dividend */
+    if (op == 'm' && r != 0 && (r < 0) != (y < 0)) {
+        r += y;
+    }
+    return SG_FIX(r);
+}
+
+/* Print the value of the program unless it's unspecified */
+static inline int sg_finish(sg_value v) {
+    if (v != SG_UNSPECIFIED) {
+        sg_write(v);
+        sg_newline();
+    }
+    return 0;
+}
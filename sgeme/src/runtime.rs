@@ -0,0 +1,143 @@
+//! What code made by `codegen::CodeGen` calls into as it runs, and running
+//! it in-process through MIR's generator or interpreter
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::io::{self, Write};
+use std::mem;
+
+use rs_mir::{Exec, MIRContext};
+
+use crate::interp::{RuntimeError, RuntimeResult};
+use crate::symbol::Symbol;
+use crate::word::{Fault, Memory, Symbols};
+
+/// The state of the program running, which the imports can only reach
+/// through a thread local
+struct State {
+    /// Every block allocated, all freed once the program is done
+    heap: Vec<Box<[u64]>>,
+    /// The names of the procedures, by the address of their code
+    names: HashMap<i64, Option<String>>,
+    globals: Vec<Symbol>,
+    symbols: Vec<Symbol>,
+    /// The fault reported, after which the code returns `FAULT` to `main`
+    fault: Option<RuntimeError>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State {
+        heap: Vec::new(),
+        names: HashMap::new(),
+        globals: Vec::new(),
+        symbols: Vec::new(),
+        fault: None,
+    });
+}
+
+/// The memory of the program running, whose tagged pointers only come from
+/// `sgeme_alloc`
+fn memory<T>(state: &State, f: impl FnOnce(&Memory) -> T) -> T {
+    // SAFETY: the blocks `sgeme_alloc` makes live until the program is done
+    let load = |addr: i64| unsafe { *(addr as *const i64) };
+    f(&Memory {
+        names: &state.names,
+        globals: &state.globals,
+        symbols: &state.symbols,
+        load: &load,
+    })
+}
+
+extern "C" fn sgeme_alloc(size: i64) -> i64 {
+    let mut block = vec![0u64; (size as usize).div_ceil(8)].into_boxed_slice();
+    let addr = block.as_mut_ptr() as i64;
+    STATE.with(|state| state.borrow_mut().heap.push(block));
+    addr
+}
+
+extern "C" fn sgeme_error(code: i64, detail: i64, value: i64) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let error = memory(&state, |m| {
+            m.fault(Fault::ALL[code as usize], detail, value)
+        });
+        state.fault.get_or_insert(error);
+    })
+}
+
+fn print(s: &str) {
+    if let Err(e) = io::stdout().write_all(s.as_bytes()) {
+        STATE.with(|state| {
+            state
+                .borrow_mut()
+                .fault
+                .get_or_insert(RuntimeError::Io(e.to_string()));
+        })
+    }
+}
+
+fn print_value(value: i64, write: bool) {
+    let mut out = String::new();
+    STATE.with(|state| memory(&state.borrow(), |m| m.show(&mut out, value, write)));
+    print(&out)
+}
+
+extern "C" fn sgeme_display(value: i64) {
+    print_value(value, false)
+}
+
+extern "C" fn sgeme_write(value: i64) {
+    print_value(value, true)
+}
+
+extern "C" fn sgeme_newline() {
+    print("\n")
+}
+
+/// Link the modules compiled into `ctx` against the runtime, and call their
+/// `main`, returning its value as `write` would print it. `symbols` are
+/// those of the last module compiled.
+pub fn run(ctx: &mut MIRContext, exec: Exec, symbols: &Symbols) -> RuntimeResult<String> {
+    let imports: [(&str, *mut c_void); 5] = [
+        ("sgeme_alloc", sgeme_alloc as *mut c_void),
+        ("sgeme_error", sgeme_error as *mut c_void),
+        ("sgeme_display", sgeme_display as *mut c_void),
+        ("sgeme_write", sgeme_write as *mut c_void),
+        ("sgeme_newline", sgeme_newline as *mut c_void),
+    ];
+    for (name, addr) in imports {
+        // SAFETY: these match the prototypes `CodeGen` calls them through
+        unsafe { ctx.load_external(name, addr) }.expect("the imports have valid names");
+    }
+    ctx.link(exec).expect("the runtime provides every import");
+
+    let names = symbols
+        .procs
+        .iter()
+        .filter_map(|(func, name)| Some((ctx.function(func)? as i64, name.clone())))
+        .collect();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.names = names;
+        state.globals = symbols.globals.clone();
+        state.symbols = symbols.symbols.clone();
+        state.fault = None;
+    });
+
+    let main = ctx.function("main").expect("`CodeGen` always makes `main`");
+    // SAFETY: `main` takes nothing and returns a value
+    let main: extern "C" fn() -> i64 = unsafe { mem::transmute(main) };
+    let value = main();
+    let _ = io::stdout().flush();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let res = match state.fault.take() {
+            Some(e) => Err(e),
+            None => Ok(memory(&state, |m| m.shown(value))),
+        };
+        state.heap.clear();
+        res
+    })
+}
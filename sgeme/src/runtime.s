@@ -0,0 +1,854 @@
+# The runtime of the programs made by `asmgen::AsmGen`, which come right
+# after it in the same file, for the GNU assembler on x86-64 Linux.
+#
+# Values are laid out as `word` describes. The heap pointer lives in %r15,
+# and the end of the heap in %r14, which the C library leaves be as they're
+# callee-saved. Generated code calls a
+# procedure with its closure in %rdi, the return address at (%rsp) and the
+# arguments below that, at -8(%rsp), -16(%rsp) and so on, and gets its
+# value back in %rax. It calls the functions here the C way, with %rsp
+# below its frame but not necessarily aligned, which they see to.
+
+        .set HEAP_SIZE, 1048576
+        .set PAIR_TAG, 1
+        .set CLOSURE_TAG, 2
+        .set VECTOR_TAG, 3
+        .set STRING_TAG, 4
+        .set RECORD_TAG, 5
+        .set FORWARD, 0x47
+
+        # load the C library's `FILE *` called `name`
+        .macro load_stream name, reg
+        movq \name@GOTPCREL(%rip), \reg
+        movq (\reg), \reg
+        .endm
+
+        # start a frame with %rsp aligned for calls into the C library
+        .macro align_frame
+        pushq %rbp
+        movq %rsp, %rbp
+        andq $-16, %rsp
+        .endm
+
+        .section .rodata
+.Lfixnum:
+        .string "%lld"
+.Lfalse:
+        .string "#f"
+.Ltrue:
+        .string "#t"
+.Lnull:
+        .string "()"
+.Lunspecified:
+        .string "#<unspecified>"
+.Lspace:
+        .string "#\\space"
+.Lnewline:
+        .string "#\\newline"
+.Lchar:
+        .string "#\\"
+.Ldot:
+        .string " . "
+.Lnamed:
+        .string "#<procedure %s>"
+.Lprocedure:
+        .string "#<procedure>"
+.Lunknown:
+        .string "#<unknown %#llx>"
+.Lerror:
+        .string "error: "
+.Lout_of_memory:
+        .string "out of memory"
+.Lheap_size_var:
+        .string "SGEME_HEAP_SIZE"
+.Lgc_stats_var:
+        .string "SGEME_GC_STATS"
+.Lgc_stats:
+        .string "gc: %lld collection(s), %lld bytes allocated, %lld copied, a heap of %lld\n"
+.Lunbound:
+        .string "unbound variable `%s`"
+.Lnot_a_procedure:
+        .string "` is not a procedure, but is called"
+.Lwrong_arg_count:
+        .string "`%s` expects %lld argument(s), but is called with %lld"
+.Lanonymous:
+        .string "procedure"
+.Lwrong_type:
+        .string "`%s` expects %s, but is given `"
+.Lnot_a:
+        .string "` is not a %s"
+.Lbacktick:
+        .string "`"
+.Ldivision_by_zero:
+        .string "`%s`: division by zero"
+.Loverflow:
+        .string "`%s`: fixnum overflow, not supported on this backend"
+
+        .text
+
+# The name of the procedure whose code is at %rdi, or 0 if it has none, from
+# the table `sg_names` made along with the program
+sg_proc_name:
+        leaq sg_names(%rip), %rax
+1:      movq (%rax), %rcx
+        testq %rcx, %rcx
+        jz 2f
+        cmpq %rdi, %rcx
+        je 3f
+        addq $16, %rax
+        jmp 1b
+2:      xorl %eax, %eax
+        ret
+3:      movq 8(%rax), %rax
+        ret
+
+# Write the code point %edi to the stream %rsi in UTF-8
+sg_put_char:
+        pushq %rbp
+        movq %rsp, %rbp
+        subq $16, %rsp
+        movl %edi, %eax
+        movq %rsi, %rcx
+        cmpl $0x80, %eax
+        jae 1f
+        movb %al, -16(%rbp)
+        movl $1, %edx
+        jmp 4f
+1:      cmpl $0x800, %eax
+        jae 2f
+        movl %eax, %r8d
+        shrl $6, %r8d
+        orl $0xC0, %r8d
+        movb %r8b, -16(%rbp)
+        andl $0x3F, %eax
+        orl $0x80, %eax
+        movb %al, -15(%rbp)
+        movl $2, %edx
+        jmp 4f
+2:      cmpl $0x10000, %eax
+        jae 3f
+        movl %eax, %r8d
+        shrl $12, %r8d
+        orl $0xE0, %r8d
+        movb %r8b, -16(%rbp)
+        movl %eax, %r8d
+        shrl $6, %r8d
+        andl $0x3F, %r8d
+        orl $0x80, %r8d
+        movb %r8b, -15(%rbp)
+        andl $0x3F, %eax
+        orl $0x80, %eax
+        movb %al, -14(%rbp)
+        movl $3, %edx
+        jmp 4f
+3:      movl %eax, %r8d
+        shrl $18, %r8d
+        orl $0xF0, %r8d
+        movb %r8b, -16(%rbp)
+        movl %eax, %r8d
+        shrl $12, %r8d
+        andl $0x3F, %r8d
+        orl $0x80, %r8d
+        movb %r8b, -15(%rbp)
+        movl %eax, %r8d
+        shrl $6, %r8d
+        andl $0x3F, %r8d
+        orl $0x80, %r8d
+        movb %r8b, -14(%rbp)
+        andl $0x3F, %eax
+        orl $0x80, %eax
+        movb %al, -13(%rbp)
+        movl $4, %edx
+4:      leaq -16(%rbp), %rdi
+        movl $1, %esi
+        call fwrite@PLT
+        leave
+        ret
+
+# Print the value %rdi to the stream %rdx, as `write` does if %esi is set
+# and as `display` does otherwise
+sg_print:
+        pushq %rbp
+        movq %rsp, %rbp
+        pushq %rbx
+        pushq %r12
+        pushq %r13
+        subq $8, %rsp
+        movq %rdi, %rbx
+        movl %esi, %r12d
+        movq %rdx, %r13
+        testl %ebx, %ebx
+        jnz 1f
+        movq %r13, %rdi
+        leaq .Lfixnum(%rip), %rsi
+        movq %rbx, %rdx
+        sarq $32, %rdx
+        xorl %eax, %eax
+        call fprintf@PLT
+        jmp .Lprint_done
+1:      leaq .Lfalse(%rip), %rdi
+        cmpq $0x07, %rbx
+        je .Lprint_string
+        leaq .Ltrue(%rip), %rdi
+        cmpq $0x0F, %rbx
+        je .Lprint_string
+        leaq .Lnull(%rip), %rdi
+        cmpq $0x17, %rbx
+        je .Lprint_string
+        leaq .Lunspecified(%rip), %rdi
+        cmpq $0x1F, %rbx
+        je .Lprint_string
+        cmpb $0x2F, %bl
+        jne .Lprint_symbol
+        shrq $8, %rbx
+        testl %r12d, %r12d
+        jz .Lprint_char
+        leaq .Lspace(%rip), %rdi
+        cmpq $0x20, %rbx
+        je .Lprint_string
+        leaq .Lnewline(%rip), %rdi
+        cmpq $0x0A, %rbx
+        je .Lprint_string
+        leaq .Lchar(%rip), %rdi
+        movq %r13, %rsi
+        call fputs@PLT
+.Lprint_char:
+        movl %ebx, %edi
+        movq %r13, %rsi
+        call sg_put_char
+        jmp .Lprint_done
+.Lprint_symbol:
+        cmpb $0x57, %bl
+        jne .Lprint_pair
+        # the name at its index into the table `sg_symbols` made along with
+        # the program
+        shrq $8, %rbx
+        leaq sg_symbols(%rip), %rax
+        movq (%rax,%rbx,8), %rdi
+        jmp .Lprint_string
+.Lprint_pair:
+        movl %ebx, %eax
+        andl $7, %eax
+        cmpl $1, %eax
+        jne .Lprint_procedure
+        movl $40, %edi                  # (
+        movq %r13, %rsi
+        call fputc@PLT
+        movq -1(%rbx), %rdi
+        movl %r12d, %esi
+        movq %r13, %rdx
+        call sg_print
+        movq 7(%rbx), %rbx
+.Lprint_tail:
+        cmpq $0x17, %rbx
+        je .Lprint_close
+        movl %ebx, %eax
+        andl $7, %eax
+        cmpl $1, %eax
+        jne .Lprint_dotted
+        movl $32, %edi                  # space
+        movq %r13, %rsi
+        call fputc@PLT
+        movq -1(%rbx), %rdi
+        movl %r12d, %esi
+        movq %r13, %rdx
+        call sg_print
+        movq 7(%rbx), %rbx
+        jmp .Lprint_tail
+.Lprint_dotted:
+        leaq .Ldot(%rip), %rdi
+        movq %r13, %rsi
+        call fputs@PLT
+        movq %rbx, %rdi
+        movl %r12d, %esi
+        movq %r13, %rdx
+        call sg_print
+.Lprint_close:
+        movl $41, %edi                  # )
+        movq %r13, %rsi
+        call fputc@PLT
+        jmp .Lprint_done
+.Lprint_procedure:
+        cmpl $2, %eax
+        jne .Lprint_unknown
+        movq -2(%rbx), %rdi
+        call sg_proc_name
+        leaq .Lprocedure(%rip), %rdi
+        testq %rax, %rax
+        jz .Lprint_string
+        movq %r13, %rdi
+        leaq .Lnamed(%rip), %rsi
+        movq %rax, %rdx
+        xorl %eax, %eax
+        call fprintf@PLT
+        jmp .Lprint_done
+.Lprint_unknown:
+        movq %r13, %rdi
+        leaq .Lunknown(%rip), %rsi
+        movq %rbx, %rdx
+        xorl %eax, %eax
+        call fprintf@PLT
+        jmp .Lprint_done
+.Lprint_string:
+        movq %r13, %rsi
+        call fputs@PLT
+.Lprint_done:
+        addq $8, %rsp
+        popq %r13
+        popq %r12
+        popq %rbx
+        popq %rbp
+        ret
+
+sg_display:
+        align_frame
+        xorl %esi, %esi
+        load_stream stdout, %rdx
+        call sg_print
+        movl $0x1F, %eax
+        leave
+        ret
+
+sg_write:
+        align_frame
+        movl $1, %esi
+        load_stream stdout, %rdx
+        call sg_print
+        movl $0x1F, %eax
+        leave
+        ret
+
+sg_newline:
+        align_frame
+        movl $10, %edi
+        load_stream stdout, %rsi
+        call fputc@PLT
+        movl $0x1F, %eax
+        leave
+        ret
+
+# The collector: a copying one, moving everything reachable from the roots to
+# a new space and leaving the old one behind. The roots are the globals,
+# between `sg_globals` and `sg_globals_end`, and the slots of the frames on
+# the stack. The program makes a stack map along with its code, `sg_frames`,
+# of the return address of each call that may collect and the bytes of the
+# caller's frame in use there, sorted by address, with `sg_frame_count`
+# entries. Any slot in use holds a value, as functions clear their slots on
+# entry, and the frame of a procedure is followed by the return address to
+# its caller's, up to `sg_main`. The code of a procedure is preceded by the
+# number of free variables of its closures, giving their size.
+#
+# Objects carry no header, so the copies still to scan are kept on a stack
+# of their own. Once the live data take more than half the heap, it's
+# doubled, keeping collections rare.
+
+        .bss
+        .p2align 3
+sg_heap:                                # the start of the current space
+        .zero 8
+sg_heap_size:
+        .zero 8
+sg_stats:                               # whether to report at exit
+        .zero 8
+sg_collections:
+        .zero 8
+sg_allocated:                           # bytes, up to the last collection
+        .zero 8
+sg_live:                                # bytes, since the last collection
+        .zero 8
+sg_copied:
+        .zero 8
+
+        .text
+
+# Allocate %rdi bytes into %rax when they don't fit in the heap left, with
+# %r15 already moved past them, collecting first
+sg_grow:
+        movq %rsp, %rsi
+        jmp sg_gc
+
+# (collect)
+sg_collect:
+        movq %rsp, %rsi
+        subq $8, %rsp
+        xorl %edi, %edi
+        call sg_gc
+        addq $8, %rsp
+        movl $0x1F, %eax
+        ret
+
+# Collect, or set up the heap on the first allocation, and allocate %rdi
+# bytes into %rax, with %rsi the return address of the newest frame
+sg_gc:
+        pushq %rbp
+        movq %rsp, %rbp
+        pushq %rbx
+        pushq %r12
+        andq $-16, %rsp
+        movq %rdi, %rbx
+        movq %rsi, %r12
+        cmpq $0, sg_heap(%rip)
+        jne 1f
+        call sg_heap_init
+        jmp 2f
+1:      # the bytes in use, less the allocation that didn't fit
+        movq %r15, %rdx
+        subq %rbx, %rdx
+        subq sg_heap(%rip), %rdx
+        movq %rdx, %rax
+        subq sg_live(%rip), %rax
+        addq %rax, sg_allocated(%rip)
+        movq sg_heap_size(%rip), %rdi
+        movq %r12, %rsi
+        call sg_copy
+2:      # twice the live data and the allocation must fit
+        movq %r15, %rax
+        subq sg_heap(%rip), %rax
+        addq %rbx, %rax
+        addq %rax, %rax
+        movq sg_heap_size(%rip), %rdi
+        cmpq %rdi, %rax
+        jbe 4f
+3:      addq %rdi, %rdi
+        cmpq %rdi, %rax
+        ja 3b
+        movq %r15, %rdx
+        subq sg_heap(%rip), %rdx
+        movq %r12, %rsi
+        call sg_copy
+4:      movq %r15, %rax
+        addq %rbx, %r15
+        movq -8(%rbp), %rbx
+        movq -16(%rbp), %r12
+        leave
+        ret
+
+# Allocate the first space, of `SGEME_HEAP_SIZE` bytes if that's set
+sg_heap_init:
+        pushq %rbx
+        movl $HEAP_SIZE, %ebx
+        leaq .Lheap_size_var(%rip), %rdi
+        call getenv@PLT
+        testq %rax, %rax
+        jz 1f
+        movq %rax, %rdi
+        xorl %esi, %esi
+        movl $10, %edx
+        call strtoll@PLT
+        cmpq $64, %rax
+        jl 1f
+        andq $-8, %rax
+        movq %rax, %rbx
+1:      movq %rbx, %rdi
+        movl $1, %esi
+        call calloc@PLT
+        testq %rax, %rax
+        jz .Lout_of_memory_error
+        movq %rax, sg_heap(%rip)
+        movq %rbx, sg_heap_size(%rip)
+        movq %rax, %r15
+        leaq (%rax,%rbx), %r14
+        popq %rbx
+        ret
+
+# Copy what's live into a new space of %rdi bytes, with %rsi the return
+# address of the newest frame and %rdx the bytes in use in the old one
+sg_copy:
+        pushq %rbp
+        movq %rsp, %rbp
+        pushq %rbx
+        pushq %r12
+        pushq %r13
+        subq $40, %rsp
+        movq %rsi, -32(%rbp)            # the frame being scanned
+        movq %rdi, -40(%rbp)            # the size of the new space
+        movq %rdx, -64(%rbp)
+        incq sg_collections(%rip)
+        movl $1, %esi
+        call calloc@PLT
+        testq %rax, %rax
+        jz .Lout_of_memory_error
+        movq %rax, -48(%rbp)            # the new space
+        movq %rax, %r13
+        # every object takes two words or more, so the stack can't hold
+        # more than half as many words as are in use
+        movq -64(%rbp), %rdi
+        addq $8, %rdi
+        call malloc@PLT
+        testq %rax, %rax
+        jz .Lout_of_memory_error
+        movq %rax, -56(%rbp)            # the stack of copies to scan
+        movq %rax, %r12
+        movq sg_heap(%rip), %rbx
+        movq sg_heap_size(%rip), %r14
+        addq %rbx, %r14
+        # now %rbx and %r14 bound the old space, %r13 is where the next copy
+        # goes and %r12 is the top of the stack
+        leaq sg_globals(%rip), %r15
+1:      leaq sg_globals_end(%rip), %rax
+        cmpq %rax, %r15
+        jae 2f
+        movq %r15, %rdi
+        call sg_forward
+        addq $8, %r15
+        jmp 1b
+2:      movq -32(%rbp), %r15
+3:      movq (%r15), %rdi
+        leaq sg_main_return(%rip), %rax
+        cmpq %rax, %rdi
+        je 5f
+        call sg_frame_size
+        leaq 8(%r15,%rax), %rax         # the caller's return address
+        movq %rax, -64(%rbp)
+        addq $8, %r15
+4:      cmpq -64(%rbp), %r15
+        jae 3b
+        movq %r15, %rdi
+        call sg_forward
+        addq $8, %r15
+        jmp 4b
+5:      cmpq -56(%rbp), %r12
+        je 6f
+        subq $8, %r12
+        movq (%r12), %rdi
+        call sg_scan
+        jmp 5b
+6:      movq -56(%rbp), %rdi
+        call free@PLT
+        movq %rbx, %rdi
+        call free@PLT
+        movq -48(%rbp), %rax
+        movq -40(%rbp), %rcx
+        movq %rax, sg_heap(%rip)
+        movq %rcx, sg_heap_size(%rip)
+        movq %r13, %rdx
+        subq %rax, %rdx
+        addq %rdx, sg_copied(%rip)
+        movq %rdx, sg_live(%rip)
+        movq %r13, %r15
+        leaq (%rax,%rcx), %r14
+        movq -8(%rbp), %rbx
+        movq -16(%rbp), %r12
+        movq -24(%rbp), %r13
+        leave
+        ret
+
+# The bytes in use in the frame whose return address is %rdi, found in
+# `sg_frames` by binary search
+sg_frame_size:
+        leaq sg_frames(%rip), %rsi
+        movq sg_frame_count(%rip), %rcx
+1:      testq %rcx, %rcx
+        jz 3f
+        movq %rcx, %rdx
+        shrq $1, %rdx
+        movq %rdx, %rax
+        shlq $4, %rax
+        cmpq %rdi, (%rsi,%rax)
+        jae 2f
+        leaq 16(%rsi,%rax), %rsi
+        subq %rdx, %rcx
+        decq %rcx
+        jmp 1b
+2:      movq %rdx, %rcx
+        jmp 1b
+3:      movq 8(%rsi), %rax
+        ret
+
+# Move the object the word at %rdi points to, if it's in the old space, and
+# point the word at its copy. This keeps to %rax, %rcx, %rdx, %rsi, %rdi and
+# %r8 to %r10.
+sg_forward:
+        movq (%rdi), %rax
+        testl %eax, %eax
+        jz 9f                           # a fixnum
+        movl %eax, %edx
+        andl $7, %edx
+        jz 9f
+        cmpl $RECORD_TAG, %edx
+        ja 9f                           # an immediate
+        movq %rax, %rsi
+        andq $-8, %rsi
+        cmpq %rbx, %rsi
+        jb 9f
+        cmpq %r14, %rsi
+        jae 9f
+        cmpq $FORWARD, (%rsi)
+        jne 1f
+        movq 8(%rsi), %rax
+        movq %rax, (%rdi)
+        ret
+1:      movq %rdi, %r8
+        movq %rdx, %r9
+        movq %rsi, %r10
+        # the size of the object, in words
+        movl $2, %ecx
+        cmpl $CLOSURE_TAG, %edx
+        jne 2f
+        movq (%rsi), %rcx
+        movq -8(%rcx), %rcx
+        addq $2, %rcx
+        jmp 5f
+2:      cmpl $VECTOR_TAG, %edx
+        jne 3f
+        movq (%rsi), %rcx
+        incq %rcx
+        jmp 5f
+3:      cmpl $STRING_TAG, %edx
+        jne 4f
+        movq (%rsi), %rcx
+        incq %rcx
+        shrq $1, %rcx
+        incq %rcx
+        jmp 5f
+4:      cmpl $RECORD_TAG, %edx
+        jne 5f
+        movq 8(%rsi), %rcx
+        addq $2, %rcx
+5:      cmpq $2, %rcx
+        jae 6f
+        movl $2, %ecx
+6:      movq %r13, %rdi
+        rep movsq
+        movq %r13, %rax
+        orq %r9, %rax
+        movq %rdi, %r13
+        movq $FORWARD, (%r10)
+        movq %rax, 8(%r10)
+        movq %rax, (%r12)
+        addq $8, %r12
+        movq %rax, (%r8)
+9:      ret
+
+# Forward the fields of the copy %rdi
+sg_scan:
+        pushq %r15
+        movl %edi, %eax
+        andl $7, %eax
+        movq %rdi, %r15
+        andq $-8, %r15
+        leaq 16(%r15), %r11
+        cmpl $PAIR_TAG, %eax
+        je 2f
+        cmpl $CLOSURE_TAG, %eax
+        jne 1f
+        movq (%r15), %rcx
+        movq -8(%rcx), %rcx
+        leaq (%r11,%rcx,8), %r11
+        addq $16, %r15
+        jmp 2f
+1:      cmpl $VECTOR_TAG, %eax
+        jne 3f
+        movq (%r15), %rcx
+        leaq 8(%r15,%rcx,8), %r11
+        addq $8, %r15
+        jmp 2f
+3:      cmpl $RECORD_TAG, %eax
+        jne 4f
+        movq 8(%r15), %rcx
+        leaq (%r11,%rcx,8), %r11
+        movq %r15, %rdi                 # the type
+        call sg_forward
+        addq $16, %r15
+2:      cmpq %r11, %r15
+        jae 4f
+        movq %r15, %rdi
+        call sg_forward
+        addq $8, %r15
+        jmp 2b
+4:      popq %r15
+        ret
+
+.Lout_of_memory_error:
+        call sg_error_start
+        leaq .Lout_of_memory(%rip), %rdi
+        load_stream stderr, %rsi
+        call fputs@PLT
+        call sg_error_end
+
+# Start reporting an error, with the output so far flushed
+sg_error_start:
+        subq $8, %rsp
+        load_stream stdout, %rdi
+        call fflush@PLT
+        leaq .Lerror(%rip), %rdi
+        load_stream stderr, %rsi
+        call fputs@PLT
+        addq $8, %rsp
+        ret
+
+# Finish reporting an error, and exit
+sg_error_end:
+        subq $8, %rsp
+        movl $10, %edi
+        load_stream stderr, %rsi
+        call fputc@PLT
+        movl $1, %edi
+        call exit@PLT
+
+# The faults, which never return: these keep their arguments in registers
+# the C library keeps, without saving them for a caller
+
+# %rdi: the name of the global
+sg_unbound:
+        align_frame
+        movq %rdi, %rbx
+        call sg_error_start
+        load_stream stderr, %rdi
+        leaq .Lunbound(%rip), %rsi
+        movq %rbx, %rdx
+        xorl %eax, %eax
+        call fprintf@PLT
+        call sg_error_end
+
+# %rdi: the value called
+sg_not_a_procedure:
+        align_frame
+        movq %rdi, %rbx
+        call sg_error_start
+        leaq .Lbacktick(%rip), %rdi
+        load_stream stderr, %rsi
+        call fputs@PLT
+        movq %rbx, %rdi
+        movl $1, %esi
+        load_stream stderr, %rdx
+        call sg_print
+        leaq .Lnot_a_procedure(%rip), %rdi
+        load_stream stderr, %rsi
+        call fputs@PLT
+        call sg_error_end
+
+# %rdi: the closure called, %rsi: the number of arguments given
+sg_wrong_arg_count:
+        align_frame
+        movq %rdi, %rbx
+        movq %rsi, %r12
+        call sg_error_start
+        movq -2(%rbx), %rdi
+        call sg_proc_name
+        testq %rax, %rax
+        jnz 1f
+        leaq .Lanonymous(%rip), %rax
+1:      movq %rax, %rdx
+        load_stream stderr, %rdi
+        leaq .Lwrong_arg_count(%rip), %rsi
+        movq 6(%rbx), %rcx
+        movq %r12, %r8
+        xorl %eax, %eax
+        call fprintf@PLT
+        call sg_error_end
+
+# %rdi: the primitive, %rsi: what it expects, %rdx: the value given
+sg_wrong_type:
+        align_frame
+        movq %rdi, %rbx
+        movq %rsi, %r12
+        movq %rdx, %r13
+        call sg_error_start
+        load_stream stderr, %rdi
+        leaq .Lwrong_type(%rip), %rsi
+        movq %rbx, %rdx
+        movq %r12, %rcx
+        xorl %eax, %eax
+        call fprintf@PLT
+        movq %r13, %rdi
+        movl $1, %esi
+        load_stream stderr, %rdx
+        call sg_print
+        leaq .Lbacktick(%rip), %rdi
+        load_stream stderr, %rsi
+        call fputs@PLT
+        call sg_error_end
+
+# %rdi: the name of the type, %rsi: the value
+sg_annotation_failed:
+        align_frame
+        movq %rdi, %rbx
+        movq %rsi, %r12
+        call sg_error_start
+        leaq .Lbacktick(%rip), %rdi
+        load_stream stderr, %rsi
+        call fputs@PLT
+        movq %r12, %rdi
+        movl $1, %esi
+        load_stream stderr, %rdx
+        call sg_print
+        load_stream stderr, %rdi
+        leaq .Lnot_a(%rip), %rsi
+        movq %rbx, %rdx
+        xorl %eax, %eax
+        call fprintf@PLT
+        call sg_error_end
+
+# %rdi: the primitive
+sg_division_by_zero:
+        align_frame
+        movq %rdi, %rbx
+        call sg_error_start
+        load_stream stderr, %rdi
+        leaq .Ldivision_by_zero(%rip), %rsi
+        movq %rbx, %rdx
+        xorl %eax, %eax
+        call fprintf@PLT
+        call sg_error_end
+
+# %rdi: the primitive
+sg_overflow:
+        align_frame
+        movq %rdi, %rbx
+        call sg_error_start
+        load_stream stderr, %rdi
+        leaq .Loverflow(%rip), %rsi
+        movq %rbx, %rdx
+        xorl %eax, %eax
+        call fprintf@PLT
+        call sg_error_end
+
+# The entry stub: run the program's top-level forms, print their value
+# unless it's unspecified, and report on the heap if `SGEME_GC_STATS` is set
+        .globl main
+main:
+        pushq %rbp
+        movq %rsp, %rbp
+        pushq %rbx
+        pushq %r12
+        pushq %r13
+        pushq %r14
+        pushq %r15
+        subq $8, %rsp
+        # no heap, so the first allocation sets one up
+        xorl %r15d, %r15d
+        xorl %r14d, %r14d
+        leaq .Lgc_stats_var(%rip), %rdi
+        call getenv@PLT
+        movq %rax, sg_stats(%rip)
+        xorl %edi, %edi
+        call sg_main
+sg_main_return:
+        cmpq $0x1F, %rax
+        je 1f
+        movq %rax, %rdi
+        call sg_write
+        call sg_newline
+1:      cmpq $0, sg_stats(%rip)
+        je 2f
+        load_stream stderr, %rdi
+        leaq .Lgc_stats(%rip), %rsi
+        movq sg_collections(%rip), %rdx
+        movq %r15, %rcx
+        subq sg_heap(%rip), %rcx
+        subq sg_live(%rip), %rcx
+        addq sg_allocated(%rip), %rcx
+        movq sg_copied(%rip), %r8
+        movq sg_heap_size(%rip), %r9
+        xorl %eax, %eax
+        call fprintf@PLT
+2:      xorl %eax, %eax
+        addq $8, %rsp
+        popq %r15
+        popq %r14
+        popq %r13
+        popq %r12
+        popq %rbx
+        popq %rbp
+        ret
@@ -0,0 +1,103 @@
+  ;; The runtime of the modules made by `wasmgen::WasmGen`, which come right
+  ;; after it in the same module.
+  ;;
+  ;; Values are laid out as `word` describes, with the pointers into linear
+  ;; memory and the index of a procedure's function in the table as the code
+  ;; of its closures. Printing and faults are left to the host: once a fault
+  ;; is reported, the code traps. The program declares the memory, holding its
+  ;; constants from address 8, and `$hp`, the next free address after them,
+  ;; kept a multiple of 8 so pointers can be tagged.
+
+  ;; the fault, its detail, and the offending value
+  (import "sgeme" "error" (func $error (param i32 i64 i64)))
+  (import "sgeme" "display" (func $display (param i64)))
+  (import "sgeme" "write" (func $write (param i64)))
+  (import "sgeme" "newline" (func $newline))
+
+  (func $alloc (param $size i32) (result i32)
+    (local $p i32)
+    (local.set $p (global.get $hp))
+    (global.set $hp (i32.add (local.get $p) (local.get $size)))
+    (if (i32.gt_u (global.get $hp) (i32.shl (memory.size) (i32.const 16)))
+      (then
+        (drop
+          (memory.grow
+            (i32.shr_u
+              (i32.add
+                (i32.sub (global.get $hp) (i32.shl (memory.size) (i32.const 16)))
+                (i32.const 0xFFFF))
+              (i32.const 16))))))
+    (local.get $p))
+
+  ;; the address a tagged pointer points to
+  (func $untag (param $v i64) (result i32)
+    (i32.and (i32.wrap_i64 (local.get $v)) (i32.const -8)))
+
+  (func $cons (param $car i64) (param $cdr i64) (result i64)
+    (local $p i32)
+    (local.set $p (call $alloc (i32.const 16)))
+    (i64.store (local.get $p) (local.get $car))
+    (i64.store offset=8 (local.get $p) (local.get $cdr))
+    (i64.or (i64.extend_i32_u (local.get $p)) (i64.const 1)))
+
+  ;; a closure of the function at `code` in the table, without its free
+  ;; variables filled in
+  (func $closure (param $code i32) (param $arity i32) (param $free i32) (result i64)
+    (local $p i32)
+    (local.set $p
+      (call $alloc (i32.add (i32.const 16) (i32.shl (local.get $free) (i32.const 3)))))
+    (i64.store (local.get $p) (i64.extend_i32_u (local.get $code)))
+    (i64.store offset=8 (local.get $p) (i64.extend_i32_u (local.get $arity)))
+    (i64.or (i64.extend_i32_u (local.get $p)) (i64.const 2)))
+
+  ;; turn 0 or 1 into `#f` or `#t`
+  (func $bool (param $b i32) (result i64)
+    (i64.or (i64.shl (i64.extend_i32_u (local.get $b)) (i64.const 3)) (i64.const 0x07)))
+
+  ;; `n` as a fixnum, reporting an overflow in the primitive `prim` unless it
+  ;; fits in 32 bits
+  (func $fixnum (param $n i64) (param $prim i64) (result i64)
+    (if (i64.ne (local.get $n) (i64.extend_i32_s (i32.wrap_i64 (local.get $n))))
+      (then
+        ;; `Fault::Overflow`
+        (call $error (i32.const 5) (local.get $prim) (i64.const 0))
+        (unreachable)))
+    (i64.shl (local.get $n) (i64.const 32)))
+
+  (func $add (param $a i64) (param $b i64) (param $prim i64) (result i64)
+    (call $fixnum
+      (i64.add (i64.shr_s (local.get $a) (i64.const 32)) (i64.shr_s (local.get $b) (i64.const 32)))
+      (local.get $prim)))
+
+  (func $sub (param $a i64) (param $b i64) (param $prim i64) (result i64)
+    (call $fixnum
+      (i64.sub (i64.shr_s (local.get $a) (i64.const 32)) (i64.shr_s (local.get $b) (i64.const 32)))
+      (local.get $prim)))
+
+  (func $mul (param $a i64) (param $b i64) (param $prim i64) (result i64)
+    (call $fixnum
+      (i64.mul (i64.shr_s (local.get $a) (i64.const 32)) (i64.shr_s (local.get $b) (i64.const 32)))
+      (local.get $prim)))
+
+  ;; `quotient`, `remainder` or `modulo`, as `op` is 0, 1 or 2
+  (func $divide (param $a i64) (param $b i64) (param $op i32) (param $prim i64) (result i64)
+    (local $x i64) (local $y i64) (local $r i64)
+    (local.set $x (i64.shr_s (local.get $a) (i64.const 32)))
+    (local.set $y (i64.shr_s (local.get $b) (i64.const 32)))
+    (if (i64.eqz (local.get $y))
+      (then
+        ;; `Fault::DivisionByZero`
+        (call $error (i32.const 4) (local.get $prim) (i64.const 0))
+        (unreachable)))
+    (if (i32.eqz (local.get $op))
+      (then
+        (return (call $fixnum (i64.div_s (local.get $x) (local.get $y)) (local.get $prim)))))
+    (local.set $r (i64.rem_s (local.get $x) (local.get $y)))
+    ;; `modulo` takes the sign of the divisor rather than the dividend
+    (if (i32.and
+          (i32.eq (local.get $op) (i32.const 2))
+          (i32.and
+            (i64.ne (local.get $r) (i64.const 0))
+            (i64.lt_s (i64.xor (local.get $r) (local.get $y)) (i64.const 0))))
+      (then (local.set $r (i64.add (local.get $r) (local.get $y)))))
+    (i64.shl (local.get $r) (i64.const 32)))
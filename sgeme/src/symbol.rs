@@ -0,0 +1,107 @@
+//! Symbols, interned in one table for the whole process so that comparing
+//! two of them compares two numbers, plus uninterned ones for the names the
+//! compiler makes up
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{LazyLock, Mutex};
+
+/// A symbol, standing for its entry in the table
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+struct Table {
+    /// The name of every symbol, which lives as long as the process does
+    names: Vec<&'static str>,
+    /// The interned symbols, by name
+    interned: HashMap<&'static str, Symbol>,
+}
+
+static TABLE: LazyLock<Mutex<Table>> = LazyLock::new(|| {
+    Mutex::new(Table {
+        names: Vec::new(),
+        interned: HashMap::new(),
+    })
+});
+
+impl Table {
+    fn add(&mut self, name: &str) -> Symbol {
+        let sym = Symbol(self.names.len() as u32);
+        self.names.push(Box::leak(name.into()));
+        sym
+    }
+}
+
+impl Symbol {
+    /// The symbol named `name`, the same one every time
+    pub fn intern(name: &str) -> Self {
+        let mut table = TABLE.lock().unwrap();
+        if let Some(sym) = table.interned.get(name) {
+            return *sym;
+        }
+        let sym = table.add(name);
+        let name = table.names[sym.0 as usize];
+        table.interned.insert(name, sym);
+        sym
+    }
+
+    /// A new symbol named `name`, different from every other symbol of that
+    /// name, interned or not
+    pub fn uninterned(name: &str) -> Self {
+        TABLE.lock().unwrap().add(name)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        TABLE.lock().unwrap().names[self.0 as usize]
+    }
+
+    pub fn is_interned(self) -> bool {
+        let table = TABLE.lock().unwrap();
+        table.interned.get(table.names[self.0 as usize]) == Some(&self)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Self::intern(name)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
@@ -0,0 +1,66 @@
+//! Find every procedure call in tail position, so that backends know which
+//! calls must reuse the caller's frame instead of growing the native stack
+
+use std::mem;
+
+use crate::core_former::Core;
+use crate::datum::Datum;
+
+/// Rewrites `Core::Call` into `Core::TailCall` wherever the call is the last
+/// thing its enclosing `lambda` does. As `cond`, `case`, `when`, `unless`,
+/// `and` and `or` have all been simplified into `if`, `let` and `begin` by
+/// this point, only those need to pass tail position down to their children.
+pub struct TailMarker {}
+
+impl TailMarker {
+    pub fn init() -> Self {
+        Self {}
+    }
+
+    /// Mark the tail calls of a whole simplified program; top-level forms
+    /// themselves are not in tail position
+    pub fn mark(&self, core: &mut Core) {
+        self.mark_expr(core, false)
+    }
+
+    fn mark_expr(&self, core: &mut Core, tail: bool) {
+        match core {
+            Core::Var(_) | Core::Const(_) => (),
+            Core::Lambda(_, body) => self.mark_expr(body, true),
+            Core::If(c, t, e) => {
+                self.mark_expr(c, false);
+                self.mark_expr(t, tail);
+                self.mark_expr(e, tail);
+            }
+            Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+                self.mark_expr(rator, false);
+                for rand in rands.iter_mut() {
+                    self.mark_expr(rand, false);
+                }
+                let rator = mem::replace(rator.as_mut(), Core::Const(Datum::Undefined));
+                let rands = mem::take(rands);
+                *core = if tail {
+                    Core::TailCall(Box::new(rator), rands)
+                } else {
+                    Core::Call(Box::new(rator), rands)
+                };
+            }
+            Core::Let(bs, body) | Core::LetRec(bs, body) => {
+                for (_, expr) in bs.iter_mut() {
+                    self.mark_expr(expr, false);
+                }
+                self.mark_expr(body, tail);
+            }
+            Core::Begin(exprs) => {
+                if let Some((last, init)) = exprs.split_last_mut() {
+                    for expr in init {
+                        self.mark_expr(expr, false);
+                    }
+                    self.mark_expr(last, tail);
+                }
+            }
+            // the check happens after the inner expression returns
+            Core::The(_, expr) | Core::Define(_, expr) => self.mark_expr(expr, false),
+        }
+    }
+}
@@ -4,12 +4,19 @@ pub use logos::Logos;
 #[derive(Logos, Debug, Clone, PartialEq, Eq)]
 #[logos(skip "[ \t\n\r]+")]
 pub enum Token {
-    #[regex(r"-?[0-9]+", 
-            |lex| lex.slice().parse::<i32>().unwrap())]
-    Fixnum(i32),
-
-    #[regex(r#""[^"](\\" | .*)""#,
+    /// A number of any kind, read by `number::Number::parse`
+    #[regex(r"[+-]?([0-9]+(\.[0-9]*)?|\.[0-9]+)([eE][+-]?[0-9]+)?",
+            |lex| lex.slice().to_owned())]
+    #[regex(r"[+-]?[0-9]+/[0-9]+",
+            |lex| lex.slice().to_owned())]
+    #[regex(r"[+-](inf|nan)\.0",
             |lex| lex.slice().to_owned())]
+    #[regex(r"(#[xXbBoOdDeEiI])+[0-9a-zA-Z+\-./]+",
+            |lex| lex.slice().to_owned())]
+    Number(String),
+
+    #[regex(r#""([^"\\]|\\.)*""#,
+            |lex| unescape(lex.slice()))]
     Str(String),
 
     #[regex(r"([a-zA-Z]|!|\$|%|&|\*|/|:|<|=|>|\?|~|_|\^)([a-zA-Z]|!|\$|%|&|\*|/|:|<|=|>|\?|~|_|\^|[0-9]|\.|\+|\-)*",
@@ -24,8 +31,8 @@ pub enum Token {
             |_| '\n')]
     #[token("#\\space",
             |_| ' ')]
-    #[regex(r#"#\."#,
-            |lex| lex.slice().parse::<char>().unwrap())]
+    #[regex(r"#\\.",
+            |lex| lex.slice().chars().nth(2))]
     Char(char),
 
     #[token("#t",
@@ -87,6 +94,25 @@ pub enum Token {
     Eof,
 }
 
+/// Strip the quotes from a string literal and interpret its escapes
+fn unescape(lit: &str) -> String {
+    let mut s = String::new();
+    let mut chars = lit[1..lit.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                Some(other) => s.push(other),
+                None => (),
+            }
+        } else {
+            s.push(c)
+        }
+    }
+    s
+}
+
 impl Token {
     pub fn really_lex(src: String) -> Vec<Token> {
         let mut tokens = Vec::new();
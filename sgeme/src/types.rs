@@ -0,0 +1,347 @@
+//! Local type inference over resolved `Core`, seeded by the type annotations
+//! on procedure parameters. Backends use the result to leave out tag checks,
+//! and to keep values known to be fixnums unboxed.
+
+use std::collections::HashMap;
+
+use crate::core_former::Core;
+use crate::datum::Datum;
+use crate::diagnostics::Diagnostics;
+use crate::prim;
+use crate::primsyn::Type;
+use crate::resolve::source_name;
+use crate::symbol::Symbol;
+
+/// The type of a constant, if it has one of the annotation types
+pub fn datum_type(d: &Datum) -> Option<Type> {
+    match d {
+        Datum::Fixnum(_) => Some(Type::Fixnum),
+        Datum::Big(_) | Datum::Ratio(_) | Datum::Flonum(_) => Some(Type::Number),
+        Datum::Bool(_) => Some(Type::Bool),
+        Datum::Char(_) => Some(Type::Char),
+        Datum::Str(_) => Some(Type::Str),
+        Datum::Symbol(_) => Some(Type::Symbol),
+        Datum::Vector(_) => Some(Type::Vector),
+        Datum::DottedList(..) => Some(Type::Pair),
+        Datum::List(ls) if !ls.is_empty() => Some(Type::Pair),
+        _ => None,
+    }
+}
+
+/// What is known about an expression: `Bottom` is "nothing flows here yet",
+/// used to start off recursive procedures, and `Any` is "could be anything"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ty {
+    Bottom,
+    Is(Type),
+    Any,
+}
+
+impl Ty {
+    fn join(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Bottom, t) | (t, Self::Bottom) => t,
+            (Self::Is(a), Self::Is(b)) if a.within(b) => Self::Is(b),
+            (Self::Is(a), Self::Is(b)) if b.within(a) => Self::Is(a),
+            _ => Self::Any,
+        }
+    }
+
+    fn known(self) -> Option<Type> {
+        match self {
+            Self::Is(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+fn from_option(t: Option<Type>) -> Ty {
+    t.map_or(Ty::Any, Ty::Is)
+}
+
+/// The inferred types of a program. Every local variable has a unique name
+/// after `resolve::Resolver`, so a flat map from names is enough.
+#[derive(Debug, Default)]
+pub struct Types {
+    vars: HashMap<Symbol, Type>,
+    /// Return types of top-level procedures
+    returns: HashMap<Symbol, Ty>,
+    /// Annotated parameter types of top-level procedures
+    params: HashMap<Symbol, Vec<Option<Type>>>,
+    /// Top-level definitions, which shadow any primitive of the same name
+    globals: HashMap<Symbol, usize>,
+}
+
+impl Types {
+    /// Infer the types of a program, warning about values which are bound to
+    /// fail an annotation or a primitive's requirements
+    pub fn infer(core: &Core, diags: &mut Diagnostics) -> Self {
+        let mut types = Self::default();
+        let forms = match core {
+            Core::Begin(forms) => forms.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+
+        for form in forms {
+            if let Core::Define(name, _) = form {
+                *types.globals.entry(*name).or_default() += 1;
+            }
+        }
+        for form in forms {
+            types.annotations(form);
+        }
+        for form in forms {
+            if let Core::Define(name, expr) = form {
+                if types.globals[name] == 1 {
+                    if let Core::Lambda(formals, body) = expr.as_ref() {
+                        types.returns.insert(*name, Ty::Bottom);
+                        types.params.insert(*name, leading_checks(formals, body));
+                    }
+                }
+            }
+        }
+
+        // Return types take a round per call to settle along a chain of
+        // procedures, and each moves at most a few steps from `Bottom` to
+        // `Any`. Rounds past that are taken never to settle, and every return
+        // is widened to `Any` rather than trusting one that may still change.
+        let annotated = types.vars.clone();
+        let max_rounds = 3 * types.returns.len() + 1;
+        let mut rounds = 0;
+        loop {
+            let before = types.returns.clone();
+            types.vars = annotated.clone();
+            for form in forms {
+                types.bindings(form);
+                if let Core::Define(name, expr) = form {
+                    if let (Some(_), Core::Lambda(_, body)) =
+                        (types.returns.get(name), expr.as_ref())
+                    {
+                        let ret = types.ty(body);
+                        types.returns.insert(*name, ret);
+                    }
+                }
+            }
+            if types.returns == before {
+                break;
+            }
+            rounds += 1;
+            if rounds == max_rounds {
+                types.returns.values_mut().for_each(|ret| *ret = Ty::Any);
+                types.vars = annotated;
+                forms.iter().for_each(|form| types.bindings(form));
+                break;
+            }
+        }
+
+        for form in forms {
+            types.report(form, diags);
+        }
+        types
+    }
+
+    /// The type of the variable `name`, if it always holds one kind of value
+    pub fn of_var(&self, name: Symbol) -> Option<Type> {
+        self.vars.get(&name).copied()
+    }
+
+    /// The type of `core`, if it always evaluates to one kind of value
+    pub fn type_of(&self, core: &Core) -> Option<Type> {
+        self.ty(core).known()
+    }
+
+    pub fn is_fixnum(&self, core: &Core) -> bool {
+        self.type_of(core) == Some(Type::Fixnum)
+    }
+
+    fn prim_name<'c>(&self, rator: &'c Core) -> Option<&'c str> {
+        match rator {
+            Core::Var(name) if !self.globals.contains_key(name) => {
+                prim::lookup(name).map(|p| p.name)
+            }
+            _ => None,
+        }
+    }
+
+    fn ty(&self, core: &Core) -> Ty {
+        match core {
+            Core::Var(name) => from_option(self.of_var(*name)),
+            Core::Const(d) => from_option(datum_type(d)),
+            Core::Lambda(..) => Ty::Is(Type::Proc),
+            Core::If(_, t, e) => self.ty(t).join(self.ty(e)),
+            Core::Call(rator, _) | Core::TailCall(rator, _) => {
+                if let Some(name) = self.prim_name(rator) {
+                    from_option(prim::result_type(name))
+                } else if let Core::Var(name) = rator.as_ref() {
+                    self.returns.get(name).copied().unwrap_or(Ty::Any)
+                } else {
+                    Ty::Any
+                }
+            }
+            Core::Let(_, body) | Core::LetRec(_, body) => self.ty(body),
+            Core::Begin(exprs) => exprs.last().map_or(Ty::Any, |e| self.ty(e)),
+            Core::The(ty, _) => Ty::Is(*ty),
+            Core::Define(..) => Ty::Any,
+        }
+    }
+
+    /// Record the types of parameters checked on entry, `(lambda (x) (begin
+    /// (the Fixnum x) ...))`; the same shape is left behind when the inliner
+    /// turns such a procedure into a `let`
+    fn annotations(&mut self, core: &Core) {
+        match core {
+            Core::Var(_) | Core::Const(_) => (),
+            Core::Lambda(formals, body) => {
+                for (name, ty) in formals.iter().zip(leading_checks(formals, body)) {
+                    if let Some(ty) = ty {
+                        self.vars.insert(*name, ty);
+                    }
+                }
+                self.annotations(body)
+            }
+            Core::Let(bs, body) | Core::LetRec(bs, body) => {
+                let names: Vec<Symbol> = bs.iter().map(|(name, _)| *name).collect();
+                for (name, ty) in names.iter().zip(leading_checks(&names, body)) {
+                    if let Some(ty) = ty {
+                        self.vars.insert(*name, ty);
+                    }
+                }
+                bs.iter().for_each(|(_, init)| self.annotations(init));
+                self.annotations(body)
+            }
+            _ => children(core).into_iter().for_each(|c| self.annotations(c)),
+        }
+    }
+
+    /// Give `let`, `letrec` and top-level bound variables the type of their
+    /// initial value, unless an annotation already said otherwise
+    fn bindings(&mut self, core: &Core) {
+        match core {
+            Core::Let(bs, body) | Core::LetRec(bs, body) => {
+                for (name, init) in bs {
+                    self.bindings(init);
+                    if let Some(ty) = self.type_of(init) {
+                        self.vars.entry(*name).or_insert(ty);
+                    }
+                }
+                self.bindings(body)
+            }
+            Core::Define(name, expr) => {
+                self.bindings(expr);
+                if self.globals.get(name) == Some(&1) {
+                    if let Some(ty) = self.type_of(expr) {
+                        self.vars.entry(*name).or_insert(ty);
+                    }
+                }
+            }
+            _ => children(core).into_iter().for_each(|c| self.bindings(c)),
+        }
+    }
+
+    fn report(&self, core: &Core, diags: &mut Diagnostics) {
+        match core {
+            Core::The(ty, expr) => {
+                if let Some(actual) = self.type_of(expr).filter(|t| !t.overlaps(*ty)) {
+                    diags.warn(format!(
+                        "`{}` is annotated {}, but is always a {}",
+                        shown(expr),
+                        ty.name(),
+                        actual.name()
+                    ));
+                }
+            }
+            Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+                if let Some(name) = self.prim_name(rator) {
+                    for (i, rand) in rands.iter().enumerate() {
+                        let expected = prim::arg_kind(name, i).map(|k| k.as_type());
+                        // constants were already reported by `check::Checker`
+                        if matches!(rand, Core::Const(_)) {
+                            continue;
+                        }
+                        if let (Some(expected), Some(actual)) = (expected, self.type_of(rand)) {
+                            if !expected.overlaps(actual) {
+                                diags.warn(format!(
+                                    "`{name}` expects a {} as argument {}, but `{}` is a {}",
+                                    expected.name(),
+                                    i + 1,
+                                    shown(rand),
+                                    actual.name()
+                                ));
+                            }
+                        }
+                    }
+                } else if let Some(params) = match rator.as_ref() {
+                    Core::Var(name) => self.params.get(name).map(|ps| (name, ps)),
+                    _ => None,
+                } {
+                    let (name, params) = params;
+                    for (i, (rand, expected)) in rands.iter().zip(params).enumerate() {
+                        if let (Some(expected), Some(actual)) = (expected, self.type_of(rand)) {
+                            if !expected.overlaps(actual) {
+                                diags.warn(format!(
+                                    "parameter {} of `{name}` is annotated {}, but is given a {}",
+                                    i + 1,
+                                    expected.name(),
+                                    actual.name()
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+        children(core)
+            .into_iter()
+            .for_each(|c| self.report(c, diags));
+    }
+}
+
+/// How to refer to an expression in a warning
+fn shown(core: &Core) -> String {
+    match core {
+        Core::Var(name) => source_name(name).to_owned(),
+        other => other.to_string(),
+    }
+}
+
+/// The type each of `names` is checked against at the very start of `body`
+fn leading_checks(names: &[Symbol], body: &Core) -> Vec<Option<Type>> {
+    let mut types = vec![None; names.len()];
+    let checks = match body {
+        Core::Begin(exprs) => exprs.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+    for check in checks {
+        match check {
+            Core::The(ty, expr) => {
+                if let Core::Var(v) = expr.as_ref() {
+                    if let Some(i) = names.iter().position(|n| n == v) {
+                        types[i] = Some(*ty);
+                    }
+                }
+            }
+            _ => break,
+        }
+    }
+    types
+}
+
+/// The immediate subexpressions of `core`
+fn children(core: &Core) -> Vec<&Core> {
+    match core {
+        Core::Var(_) | Core::Const(_) => Vec::new(),
+        Core::Lambda(_, body) => vec![body],
+        Core::If(c, t, e) => vec![c, t, e],
+        Core::Call(rator, rands) | Core::TailCall(rator, rands) => {
+            std::iter::once(rator.as_ref()).chain(rands).collect()
+        }
+        Core::Let(bs, body) | Core::LetRec(bs, body) => bs
+            .iter()
+            .map(|(_, init)| init)
+            .chain(std::iter::once(body.as_ref()))
+            .collect(),
+        Core::Begin(exprs) => exprs.iter().collect(),
+        Core::The(_, expr) | Core::Define(_, expr) => vec![expr],
+    }
+}
@@ -0,0 +1,630 @@
+//! Runtime values of the interpreter, and how constants turn into them
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
+use crate::bytecode;
+use crate::core_former::Core;
+use crate::datum::{AbbrevPrefix, Datum};
+use crate::interp;
+use crate::number;
+use crate::prim::Prim;
+use crate::primsyn::Type;
+use crate::symbol::Symbol;
+use crate::vm;
+
+/// A Scheme value. Everything with an identity of its own (pairs, strings,
+/// vectors, procedures) lives behind an `Rc`, so that copying a `Value` copies
+/// the reference, as `eq?` expects. Closures borrow their code from the
+/// program, hence the lifetime.
+#[derive(Debug, Clone)]
+pub enum Value<'a> {
+    Unspecified,
+    Null,
+    Eof,
+    Bool(bool),
+    Fixnum(i32),
+    /// An integer too big to be a fixnum
+    Big(Rc<BigInt>),
+    /// A ratio of integers which isn't a whole number
+    Ratio(Rc<BigRational>),
+    /// An inexact real, held unboxed as it fits in a `Value` anyway
+    Flonum(f64),
+    Char(char),
+    Symbol(Symbol),
+    Str(Rc<RefCell<String>>),
+    Pair(Rc<Pair<'a>>),
+    Vector(Rc<RefCell<Vec<Self>>>),
+    Closure(Rc<Closure<'a>>),
+    /// A procedure compiled for `vm::Vm`
+    Compiled(Rc<bytecode::Closure<'a>>),
+    Prim(&'static Prim),
+    Record(Rc<Record<'a>>),
+    Promise(Rc<Promise<'a>>),
+    Parameter(Rc<Parameter<'a>>),
+    /// A continuation captured by `interp::Interpreter`
+    Continuation(Rc<interp::Continuation<'a>>),
+    /// A continuation captured by `vm::Vm`
+    VmContinuation(Rc<vm::Continuation<'a>>),
+    /// Holds a `letrec` variable in `vm::Vm`, and is never seen by programs
+    Cell(Rc<RefCell<Self>>),
+}
+
+#[derive(Debug)]
+pub struct Pair<'a> {
+    pub car: RefCell<Value<'a>>,
+    pub cdr: RefCell<Value<'a>>,
+}
+
+/// Dropping a list frees its pairs one at a time, as a list a million pairs
+/// long would overflow the stack if each dropped the next
+impl Drop for Pair<'_> {
+    fn drop(&mut self) {
+        let owned = |v: &Value| matches!(v, Value::Pair(p) if Rc::strong_count(p) == 1);
+        if !owned(self.car.get_mut()) && !owned(self.cdr.get_mut()) {
+            return;
+        }
+        let mut rest = vec![self.car.replace(Value::Null), self.cdr.replace(Value::Null)];
+        while let Some(v) = rest.pop() {
+            if let Value::Pair(p) = v {
+                if let Ok(p) = Rc::try_unwrap(p) {
+                    rest.push(p.car.replace(Value::Null));
+                    rest.push(p.cdr.replace(Value::Null));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Closure<'a> {
+    pub formals: &'a [Symbol],
+    pub body: &'a Core,
+    pub env: Env<'a>,
+}
+
+/// An instance of a `define-record-type`, tagged with the type's name
+#[derive(Debug)]
+pub struct Record<'a> {
+    pub tag: Value<'a>,
+    pub fields: RefCell<Vec<Value<'a>>>,
+}
+
+/// A promise, made by `delay`, `delay-force` or `make-promise`. Forcing one
+/// promise by way of another leaves them sharing the same state, so that the
+/// chain `delay-force` makes can be forced one link at a time.
+#[derive(Debug)]
+pub struct Promise<'a> {
+    state: RefCell<Rc<RefCell<Forced<'a>>>>,
+}
+
+/// What a promise holds: its value, or the thunk to compute it with
+#[derive(Debug, Clone)]
+pub enum Forced<'a> {
+    Done(Value<'a>),
+    Delayed(Value<'a>),
+}
+
+impl<'a> Promise<'a> {
+    pub fn init(forced: Forced<'a>) -> Self {
+        Self {
+            state: RefCell::new(Rc::new(RefCell::new(forced))),
+        }
+    }
+
+    pub fn get(&self) -> Forced<'a> {
+        self.state.borrow().borrow().clone()
+    }
+
+    /// Take over the state of `new`, the promise the thunk of `self` gave
+    /// back, and share it with `new` from now on; unless `self` was forced
+    /// while the thunk ran
+    pub fn update(&self, new: &Self) {
+        if let Forced::Done(_) = self.get() {
+            return;
+        }
+        let forced = new.get();
+        *self.state.borrow().borrow_mut() = forced;
+        let shared = self.state.borrow().clone();
+        *new.state.borrow_mut() = shared;
+    }
+}
+
+/// A parameter object, made by `make-parameter`. Its value is `init` but
+/// where `parameterize` binds it to another.
+#[derive(Debug)]
+pub struct Parameter<'a> {
+    pub init: Value<'a>,
+    /// What values are passed through when the parameter is bound, or `#f`
+    pub converter: Value<'a>,
+}
+
+impl<'a> Parameter<'a> {
+    /// The value of `self` inside the bindings `bound`, a list of
+    /// `(parameter . value)` pairs, innermost first
+    pub fn value(self: &Rc<Self>, bound: &Value<'a>) -> Value<'a> {
+        let mut curr = bound.clone();
+        while let Value::Pair(p) = curr {
+            if let Value::Pair(binding) = &*p.car.borrow() {
+                if matches!(&*binding.car.borrow(), Value::Parameter(q) if Rc::ptr_eq(q, self)) {
+                    return binding.cdr.borrow().clone();
+                }
+            }
+            let next = p.cdr.borrow().clone();
+            curr = next;
+        }
+        self.init.clone()
+    }
+}
+
+/// The local variables in scope. As `resolve::Resolver` gave every local a
+/// unique name, a frame can be searched by name without worrying about
+/// shadowing; globals live in the interpreter instead.
+pub type Env<'a> = Option<Rc<Scope<'a>>>;
+
+#[derive(Debug)]
+pub struct Scope<'a> {
+    pub names: Names<'a>,
+    pub values: RefCell<Vec<Value<'a>>>,
+    pub parent: Env<'a>,
+}
+
+/// The names bound by a scope, borrowed from the program: the parameters of
+/// a `lambda`, or the bindings of a `let` or `letrec`
+#[derive(Debug, Clone, Copy)]
+pub enum Names<'a> {
+    Formals(&'a [Symbol]),
+    Bindings(&'a [(Symbol, Core)]),
+}
+
+impl Names<'_> {
+    fn position(&self, name: Symbol) -> Option<usize> {
+        match self {
+            Self::Formals(fs) => fs.iter().position(|f| *f == name),
+            Self::Bindings(bs) => bs.iter().position(|(b, _)| *b == name),
+        }
+    }
+}
+
+/// Find the value of the local `name` in `env`
+pub fn lookup<'a>(env: &Env<'a>, name: Symbol) -> Option<Value<'a>> {
+    let mut env = env;
+    while let Some(scope) = env {
+        if let Some(i) = scope.names.position(name) {
+            return Some(scope.values.borrow()[i].clone());
+        }
+        env = &scope.parent;
+    }
+    None
+}
+
+pub fn extend<'a>(env: &Env<'a>, names: Names<'a>, values: Vec<Value<'a>>) -> Env<'a> {
+    Some(Rc::new(Scope {
+        names,
+        values: RefCell::new(values),
+        parent: env.clone(),
+    }))
+}
+
+impl<'a> Value<'a> {
+    pub fn cons(car: Self, cdr: Self) -> Self {
+        Self::Pair(Rc::new(Pair {
+            car: RefCell::new(car),
+            cdr: RefCell::new(cdr),
+        }))
+    }
+
+    pub fn string(s: String) -> Self {
+        Self::Str(Rc::new(RefCell::new(s)))
+    }
+
+    pub fn symbol(s: &str) -> Self {
+        Self::Symbol(Symbol::intern(s))
+    }
+
+    /// Build a proper list out of `items`, ending in `tail`
+    pub fn list(items: Vec<Self>, tail: Self) -> Self {
+        items
+            .into_iter()
+            .rev()
+            .fold(tail, |acc, item| Self::cons(item, acc))
+    }
+
+    /// The elements of a proper list, or `None` if `self` isn't one, which
+    /// includes a list whose `cdr`s lead back into itself
+    pub fn to_vec(&self) -> Option<Vec<Self>> {
+        let mut items = Vec::new();
+        let mut curr = self.clone();
+        // `lagging` takes a step for every two of `curr`, and is met by it
+        // if the list is circular
+        let mut lagging = self.clone();
+        loop {
+            match curr {
+                Self::Null => return Some(items),
+                Self::Pair(p) => {
+                    items.push(p.car.borrow().clone());
+                    let next = p.cdr.borrow().clone();
+                    curr = next;
+                }
+                _ => return None,
+            }
+            if items.len() % 2 == 0 {
+                let next = match &lagging {
+                    Self::Pair(p) => p.cdr.borrow().clone(),
+                    _ => unreachable!("`lagging` is behind `curr`, in the pairs"),
+                };
+                lagging = next;
+                if matches!((&curr, &lagging), (Self::Pair(a), Self::Pair(b)) if Rc::ptr_eq(a, b)) {
+                    return None;
+                }
+            }
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        !matches!(self, Self::Bool(false))
+    }
+
+    pub fn is_procedure(&self) -> bool {
+        matches!(
+            self,
+            Self::Closure(_)
+                | Self::Compiled(_)
+                | Self::Prim(_)
+                | Self::Continuation(_)
+                | Self::VmContinuation(_)
+                | Self::Parameter(_)
+        )
+    }
+
+    /// Does `self` pass the check `(the ty self)`?
+    pub fn has_type(&self, ty: Type) -> bool {
+        match ty {
+            Type::Fixnum => matches!(self, Self::Fixnum(_)),
+            Type::Number => matches!(
+                self,
+                Self::Fixnum(_) | Self::Big(_) | Self::Ratio(_) | Self::Flonum(_)
+            ),
+            Type::Bool => matches!(self, Self::Bool(_)),
+            Type::Char => matches!(self, Self::Char(_)),
+            Type::Str => matches!(self, Self::Str(_)),
+            Type::Symbol => matches!(self, Self::Symbol(_)),
+            Type::Pair => matches!(self, Self::Pair(_)),
+            Type::Vector => matches!(self, Self::Vector(_)),
+            Type::Proc => self.is_procedure(),
+        }
+    }
+
+    /// Turn a constant from the program into a value
+    pub fn from_datum(d: &Datum) -> Self {
+        match d {
+            Datum::Quote(prefix, d) => {
+                let name = match prefix {
+                    AbbrevPrefix::Quote => "quote",
+                    AbbrevPrefix::Quasi => "quasiquote",
+                    AbbrevPrefix::Comma => "unquote",
+                    AbbrevPrefix::CommaAt => "unquote-splicing",
+                };
+                Self::list(vec![Self::symbol(name), Self::from_datum(d)], Self::Null)
+            }
+            Datum::Bool(b) => Self::Bool(*b),
+            Datum::ByteVector(bs) => Self::Vector(Rc::new(RefCell::new(
+                bs.iter().map(|b| Self::Fixnum(*b as i32)).collect(),
+            ))),
+            Datum::Char(c) => Self::Char(*c),
+            Datum::DottedList(ds, tl) => Self::list(
+                ds.iter().map(Self::from_datum).collect(),
+                Self::from_datum(tl),
+            ),
+            Datum::Fixnum(n) => Self::Fixnum(*n),
+            Datum::Big(n) => Self::Big(Rc::new(n.clone())),
+            Datum::Ratio(r) => Self::Ratio(Rc::new((**r).clone())),
+            Datum::Flonum(x) => Self::Flonum(*x),
+            Datum::List(ds) => Self::list(ds.iter().map(Self::from_datum).collect(), Self::Null),
+            Datum::Str(s) => Self::string(s.clone()),
+            Datum::Symbol(s) => Self::Symbol(*s),
+            Datum::Vector(ds) => Self::Vector(Rc::new(RefCell::new(
+                ds.iter().map(Self::from_datum).collect(),
+            ))),
+            Datum::Ellipses => Self::symbol("..."),
+            Datum::Null => Self::Null,
+            Datum::Eof => Self::Eof,
+            Datum::Label(_) | Datum::Set(..) | Datum::Undefined => Self::Unspecified,
+        }
+    }
+
+    /// Print `self` the way `display` does: like `write`, but with strings
+    /// and characters shown as their contents
+    pub fn display(&self) -> String {
+        let mut s = String::new();
+        self.write_to(&mut s, false)
+            .expect("writing to a String can't fail");
+        s
+    }
+
+    /// Print `self` without recursing, so that a list nested a million deep
+    /// can be printed; a pair or vector inside itself is labelled, as in
+    /// `#0=(1 2 . #0#)`, rather than printed forever
+    fn write_to(&self, f: &mut impl fmt::Write, write: bool) -> fmt::Result {
+        enum Step<'v, 'a> {
+            Value(Value<'a>),
+            /// The rest of a list, after its first element
+            Tail(Value<'a>),
+            Text(&'v str),
+        }
+        let cycles = cycles(self);
+        let mut labels = HashMap::new();
+        let mut steps = vec![Step::Value(self.clone())];
+        while let Some(step) = steps.pop() {
+            let v = match step {
+                Step::Text(s) => {
+                    f.write_str(s)?;
+                    continue;
+                }
+                Step::Tail(Self::Null) => continue,
+                Step::Tail(v) => {
+                    // a labelled pair can't go on with the list it's in
+                    if let Self::Pair(p) = &v {
+                        if !cycles.contains(&address(&v)) {
+                            f.write_str(" ")?;
+                            steps.push(Step::Tail(p.cdr.borrow().clone()));
+                            steps.push(Step::Value(p.car.borrow().clone()));
+                            continue;
+                        }
+                    }
+                    f.write_str(" . ")?;
+                    v
+                }
+                Step::Value(v) => v,
+            };
+            let at = address(&v);
+            if cycles.contains(&at) {
+                match labels.get(&at) {
+                    Some(n) => {
+                        write!(f, "#{n}#")?;
+                        continue;
+                    }
+                    None => {
+                        write!(f, "#{}=", labels.len())?;
+                        labels.insert(at, labels.len());
+                    }
+                }
+            }
+            match &v {
+                Self::Pair(p) => {
+                    f.write_str("(")?;
+                    steps.push(Step::Text(")"));
+                    steps.push(Step::Tail(p.cdr.borrow().clone()));
+                    steps.push(Step::Value(p.car.borrow().clone()));
+                }
+                Self::Vector(vs) => {
+                    f.write_str("#(")?;
+                    steps.push(Step::Text(")"));
+                    for (i, v) in vs.borrow().iter().enumerate().rev() {
+                        steps.push(Step::Value(v.clone()));
+                        if i > 0 {
+                            steps.push(Step::Text(" "));
+                        }
+                    }
+                }
+                Self::Record(r) => {
+                    f.write_str("#<")?;
+                    steps.push(Step::Text(">"));
+                    steps.push(Step::Value(r.tag.clone()));
+                }
+                other => other.write_atom(f, write)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Print anything but a pair, vector or record
+    fn write_atom(&self, f: &mut impl fmt::Write, write: bool) -> fmt::Result {
+        match self {
+            Self::Unspecified => f.write_str("#<unspecified>"),
+            Self::Null => f.write_str("()"),
+            Self::Eof => f.write_str("#<eof>"),
+            Self::Bool(true) => f.write_str("#t"),
+            Self::Bool(false) => f.write_str("#f"),
+            Self::Fixnum(n) => write!(f, "{n}"),
+            Self::Big(n) => write!(f, "{n}"),
+            Self::Ratio(r) => write!(f, "{r}"),
+            Self::Flonum(x) => f.write_str(&number::flonum_to_string(*x)),
+            Self::Char(c) if !write => write!(f, "{c}"),
+            Self::Char(c) => write!(f, "{}", Datum::Char(*c)),
+            Self::Symbol(s) => f.write_str(s),
+            Self::Str(s) if !write => f.write_str(&s.borrow()),
+            Self::Str(s) => write!(f, "{:?}", s.borrow()),
+            Self::Closure(_) => f.write_str("#<procedure>"),
+            Self::Compiled(c) => match &c.proto.name {
+                Some(name) => write!(f, "#<procedure {name}>"),
+                None => f.write_str("#<procedure>"),
+            },
+            Self::Continuation(_) | Self::VmContinuation(_) => f.write_str("#<continuation>"),
+            Self::Promise(_) => f.write_str("#<promise>"),
+            Self::Parameter(_) => f.write_str("#<parameter>"),
+            Self::Cell(_) => f.write_str("#<cell>"),
+            Self::Prim(p) => write!(f, "#<procedure {}>", p.name),
+            Self::Pair(_) | Self::Vector(_) | Self::Record(_) => {
+                unreachable!("`write_to` prints the values holding others")
+            }
+        }
+    }
+}
+
+/// Where a pair or vector lives, to tell it apart from others with the same
+/// contents; 0 for any other value
+fn address(v: &Value) -> usize {
+    match v {
+        Value::Pair(p) => Rc::as_ptr(p) as *const () as usize,
+        Value::Vector(vs) => Rc::as_ptr(vs) as *const () as usize,
+        _ => 0,
+    }
+}
+
+/// The addresses of the pairs and vectors inside `v` which are also inside
+/// themselves, and would be printed forever without a label
+fn cycles(v: &Value) -> HashSet<usize> {
+    enum Visit<'a> {
+        Enter(Value<'a>),
+        Leave(usize),
+    }
+    let mut cycles = HashSet::new();
+    // whether each pair or vector seen is still being visited
+    let mut seen = HashMap::new();
+    let mut visits = vec![Visit::Enter(v.clone())];
+    while let Some(visit) = visits.pop() {
+        match visit {
+            Visit::Leave(at) => {
+                seen.insert(at, false);
+            }
+            Visit::Enter(v) => {
+                let at = address(&v);
+                if at == 0 {
+                    continue;
+                }
+                match seen.get(&at) {
+                    Some(true) => {
+                        cycles.insert(at);
+                        continue;
+                    }
+                    Some(false) => continue,
+                    None => {
+                        seen.insert(at, true);
+                        visits.push(Visit::Leave(at));
+                    }
+                }
+                match &v {
+                    Value::Pair(p) => {
+                        visits.push(Visit::Enter(p.cdr.borrow().clone()));
+                        visits.push(Visit::Enter(p.car.borrow().clone()));
+                    }
+                    Value::Vector(vs) => {
+                        visits.extend(vs.borrow().iter().map(|v| Visit::Enter(v.clone())));
+                    }
+                    _ => unreachable!("only pairs and vectors have an address"),
+                }
+            }
+        }
+    }
+    cycles
+}
+
+/// `eqv?`: the same object, or equal numbers or characters
+pub fn eqv<'a>(a: &Value<'a>, b: &Value<'a>) -> bool {
+    match (a, b) {
+        (Value::Unspecified, Value::Unspecified)
+        | (Value::Null, Value::Null)
+        | (Value::Eof, Value::Eof) => true,
+        (Value::Bool(x), Value::Bool(y)) => x == y,
+        (Value::Fixnum(x), Value::Fixnum(y)) => x == y,
+        (Value::Big(x), Value::Big(y)) => x == y,
+        (Value::Ratio(x), Value::Ratio(y)) => x == y,
+        (Value::Flonum(x), Value::Flonum(y)) => x.to_bits() == y.to_bits(),
+        (Value::Char(x), Value::Char(y)) => x == y,
+        (Value::Symbol(x), Value::Symbol(y)) => x == y,
+        (Value::Str(x), Value::Str(y)) => Rc::ptr_eq(x, y),
+        (Value::Pair(x), Value::Pair(y)) => Rc::ptr_eq(x, y),
+        (Value::Vector(x), Value::Vector(y)) => Rc::ptr_eq(x, y),
+        (Value::Closure(x), Value::Closure(y)) => Rc::ptr_eq(x, y),
+        (Value::Compiled(x), Value::Compiled(y)) => Rc::ptr_eq(x, y),
+        (Value::Prim(x), Value::Prim(y)) => x.name == y.name,
+        (Value::Record(x), Value::Record(y)) => Rc::ptr_eq(x, y),
+        (Value::Promise(x), Value::Promise(y)) => Rc::ptr_eq(x, y),
+        (Value::Parameter(x), Value::Parameter(y)) => Rc::ptr_eq(x, y),
+        (Value::Continuation(x), Value::Continuation(y)) => Rc::ptr_eq(x, y),
+        (Value::VmContinuation(x), Value::VmContinuation(y)) => Rc::ptr_eq(x, y),
+        _ => false,
+    }
+}
+
+/// `equal?`: `eqv?`, or the same structure all the way down. Compares
+/// without recursing, and takes two pairs or vectors it has already started
+/// on to be equal, so that it finishes on circular structures.
+pub fn equal<'a>(a: &Value<'a>, b: &Value<'a>) -> bool {
+    let mut compared = HashSet::new();
+    let mut pending = vec![(a.clone(), b.clone())];
+    while let Some((a, b)) = pending.pop() {
+        if eqv(&a, &b) {
+            continue;
+        }
+        match (&a, &b) {
+            (Value::Str(x), Value::Str(y)) if *x.borrow() == *y.borrow() => continue,
+            (Value::Pair(_), Value::Pair(_)) | (Value::Vector(_), Value::Vector(_))
+                if !compared.insert((address(&a), address(&b))) =>
+            {
+                continue
+            }
+            (Value::Pair(x), Value::Pair(y)) => {
+                pending.push((x.cdr.borrow().clone(), y.cdr.borrow().clone()));
+                pending.push((x.car.borrow().clone(), y.car.borrow().clone()));
+            }
+            (Value::Vector(x), Value::Vector(y)) if x.borrow().len() == y.borrow().len() => {
+                let (x, y) = (x.borrow(), y.borrow());
+                pending.extend(x.iter().cloned().zip(y.iter().cloned()).rev());
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// The next `dynamic-wind` thunk to run on the way from the winders `from`
+/// to the winders `to`, or `None` once there. Both are lists of `(before .
+/// after)` pairs, innermost first, which share the `dynamic-wind`s they are
+/// both inside of. Gives the thunk, the winders to run it in, and the ones in
+/// place once it returns: every `after` out of `from` is run, innermost
+/// first, then every `before` into `to`, outermost first.
+pub fn wind_step<'a>(
+    from: &Value<'a>,
+    to: &Value<'a>,
+) -> Option<(Value<'a>, Value<'a>, Value<'a>)> {
+    let (from_tails, to_tails) = (tails(from), tails(to));
+    let shared = from_tails
+        .iter()
+        .rev()
+        .zip(to_tails.iter().rev())
+        .take_while(|(a, b)| eqv(a, b))
+        .count();
+    if shared < from_tails.len() {
+        // leave the innermost `dynamic-wind` of `from`
+        let (wind, outer) = uncons(from);
+        Some((uncons(&wind).1, outer.clone(), outer))
+    } else if shared < to_tails.len() {
+        // enter the outermost one of `to` not entered yet
+        let into = &to_tails[to_tails.len() - shared - 1];
+        let (wind, outer) = uncons(into);
+        Some((uncons(&wind).0, outer, into.clone()))
+    } else {
+        None
+    }
+}
+
+/// `list`, its `cdr`, and so on down to the empty list
+fn tails<'a>(list: &Value<'a>) -> Vec<Value<'a>> {
+    let mut tails = vec![list.clone()];
+    while let Value::Pair(p) = &tails[tails.len() - 1] {
+        let next = p.cdr.borrow().clone();
+        tails.push(next);
+    }
+    tails
+}
+
+fn uncons<'a>(v: &Value<'a>) -> (Value<'a>, Value<'a>) {
+    match v {
+        Value::Pair(p) => (p.car.borrow().clone(), p.cdr.borrow().clone()),
+        _ => unreachable!("winders are lists of pairs"),
+    }
+}
+
+/// Print a value the way `write` would
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_to(f, true)
+    }
+}
@@ -0,0 +1,785 @@
+//! A stack machine running the bytecode made by `compile::Compiler`
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+
+use crate::builtins;
+use crate::bytecode::{Capture, Closure, Module, Op, Proto};
+use crate::interp::{RuntimeError, RuntimeResult};
+use crate::prim::{self, Arity, PRIMS};
+use crate::symbol::Symbol;
+use crate::value::{self, eqv, Value};
+
+/// A procedure call in progress. Its locals start at `base`, and the slot
+/// just below holds the procedure itself.
+#[derive(Clone)]
+struct Frame<'a> {
+    closure: Rc<Closure<'a>>,
+    pc: usize,
+    base: usize,
+}
+
+/// The rest of the program at some point, captured by `call/cc`: the stack
+/// and frames to go back to, and the `dynamic-wind`s, exception handlers and
+/// `parameterize` bindings it is inside of. One captured by a tail call
+/// returns from `frame` rather than carrying on in it.
+pub struct Continuation<'a> {
+    stack: Vec<Value<'a>>,
+    frames: Vec<Frame<'a>>,
+    frame: Frame<'a>,
+    tail: bool,
+    winders: Value<'a>,
+    handlers: Value<'a>,
+    parameters: Value<'a>,
+}
+
+impl fmt::Debug for Continuation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Continuation({} frames)", self.frames.len() + 1)
+    }
+}
+
+/// Runs a `bytecode::Module`. Primitives are shared with `interp::Interpreter`
+/// through `builtins`, so the two agree on everything but speed.
+pub struct Vm<'a> {
+    globals: Vec<Option<Value<'a>>>,
+    global_names: Vec<Symbol>,
+    stack: Vec<Value<'a>>,
+    /// The callers of the running procedure
+    frames: Vec<Frame<'a>>,
+    /// The `dynamic-wind`s being run, as `(before . after)` pairs, innermost
+    /// first
+    winders: Value<'a>,
+    /// The handlers installed by `with-exception-handler`, innermost first
+    handlers: Value<'a>,
+    /// The bindings made by `parameterize`, as `(parameter . value)` pairs,
+    /// innermost first
+    parameters: Value<'a>,
+    /// `dynamic-wind` itself, which calls back into the program
+    dynamic_wind: Rc<Closure<'a>>,
+    /// `with-exception-handler`, likewise
+    with_handler: Rc<Closure<'a>>,
+    /// What `raise` and `raise-continuable` call once they know there's a
+    /// handler
+    raise: Rc<Closure<'a>>,
+    raise_continuable: Rc<Closure<'a>>,
+    /// `force`, for promises
+    force: Rc<Closure<'a>>,
+    /// `make-parameter` with a converter, and what `parameterize` calls with
+    /// the new bindings
+    make_parameter: Rc<Closure<'a>>,
+    parameterize: Rc<Closure<'a>>,
+    out: Box<dyn Write>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn init() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    /// A machine sending everything `display`ed to `out`
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        Self {
+            globals: Vec::new(),
+            global_names: Vec::new(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            winders: Value::Null,
+            handlers: Value::Null,
+            parameters: Value::Null,
+            dynamic_wind: dynamic_wind(),
+            with_handler: with_handler(),
+            raise: raise(false),
+            raise_continuable: raise(true),
+            force: force(),
+            make_parameter: make_parameter(),
+            parameterize: parameterize(),
+            out,
+        }
+    }
+
+    /// Run a program, returning the value of its last form
+    pub fn run(&mut self, module: &Module<'a>) -> RuntimeResult<Value<'a>> {
+        self.globals = vec![None; module.globals.len()];
+        self.global_names = module.globals.clone();
+        self.stack.clear();
+        self.frames.clear();
+        self.winders = Value::Null;
+        self.handlers = Value::Null;
+        self.parameters = Value::Null;
+
+        let main = Rc::new(Closure {
+            proto: module.main.clone(),
+            upvals: Vec::new(),
+        });
+        self.stack.push(Value::Compiled(main.clone()));
+        let res = self.execute(Frame {
+            closure: main,
+            pc: 0,
+            base: 1,
+        });
+        self.out
+            .flush()
+            .map_err(|e| RuntimeError::Io(e.to_string()))?;
+        res
+    }
+
+    fn pop(&mut self) -> Value<'a> {
+        self.stack
+            .pop()
+            .expect("the compiler keeps the stack balanced")
+    }
+
+    /// Pop the operands of a binary primitive
+    fn pop2(&mut self) -> (Value<'a>, Value<'a>) {
+        let b = self.pop();
+        let a = self.pop();
+        (a, b)
+    }
+
+    /// Finish the call running in `frame` with `v`. Returns `v` when that was
+    /// the outermost call.
+    fn ret(&mut self, frame: &mut Frame<'a>, v: Value<'a>) -> Option<Value<'a>> {
+        self.stack.truncate(frame.base - 1);
+        match self.frames.pop() {
+            None => Some(v),
+            Some(caller) => {
+                *frame = caller;
+                self.stack.push(v);
+                None
+            }
+        }
+    }
+
+    fn execute(&mut self, mut frame: Frame<'a>) -> RuntimeResult<Value<'a>> {
+        loop {
+            match self.dispatch(&mut frame) {
+                Err(e) => {
+                    if let Some(v) = self.fault(&mut frame, e)? {
+                        return Ok(v);
+                    }
+                }
+                done => return done,
+            }
+        }
+    }
+
+    /// Run the code of `frame` and its callees, until the program finishes
+    /// or fails
+    fn dispatch(&mut self, frame: &mut Frame<'a>) -> RuntimeResult<Value<'a>> {
+        loop {
+            let op = frame.closure.proto.code[frame.pc];
+            frame.pc += 1;
+            match op {
+                Op::Const(i) => {
+                    let v = frame.closure.proto.consts[i as usize].clone();
+                    self.stack.push(v)
+                }
+                Op::Local(i) => {
+                    let v = self.stack[frame.base + i as usize].clone();
+                    self.stack.push(v)
+                }
+                Op::Upval(i) => self.stack.push(frame.closure.upvals[i as usize].clone()),
+                Op::Global(i) => match &self.globals[i as usize] {
+                    Some(v) => self.stack.push(v.clone()),
+                    None => {
+                        return Err(RuntimeError::UnboundVariable(
+                            self.global_names[i as usize].to_string(),
+                        ))
+                    }
+                },
+                Op::DefGlobal(i) => self.globals[i as usize] = Some(self.pop()),
+                Op::PushCell => self
+                    .stack
+                    .push(Value::Cell(Rc::new(RefCell::new(Value::Unspecified)))),
+                Op::Unbox => {
+                    if let Value::Cell(c) = self.pop() {
+                        self.stack.push(c.borrow().clone())
+                    }
+                }
+                Op::SetCell(i) => {
+                    let v = self.pop();
+                    if let Value::Cell(c) = &self.stack[frame.base + i as usize] {
+                        *c.borrow_mut() = v;
+                    }
+                }
+                Op::Closure(i) => {
+                    let proto = frame.closure.proto.protos[i as usize].clone();
+                    let upvals = proto
+                        .captures
+                        .iter()
+                        .map(|c| match c {
+                            Capture::Local(slot) => self.stack[frame.base + *slot as usize].clone(),
+                            Capture::Upval(j) => frame.closure.upvals[*j as usize].clone(),
+                        })
+                        .collect();
+                    self.stack
+                        .push(Value::Compiled(Rc::new(Closure { proto, upvals })))
+                }
+                Op::Jump(t) => frame.pc = t as usize,
+                Op::JumpUnless(t) => {
+                    if !self.pop().is_true() {
+                        frame.pc = t as usize
+                    }
+                }
+                Op::Call(n) | Op::TailCall(n) => {
+                    let tail = matches!(op, Op::TailCall(_));
+                    if let Some(v) = self.call(frame, n as usize, tail)? {
+                        return Ok(v);
+                    }
+                }
+                Op::Prim(i, n) => {
+                    let args = self.stack.split_off(self.stack.len() - n as usize);
+                    let v = builtins::call(PRIMS[i as usize].name, args, &mut self.out)?;
+                    self.stack.push(v)
+                }
+                Op::Add | Op::Sub | Op::Mul => {
+                    let (a, b) = self.pop2();
+                    let v = match (op, &a, &b) {
+                        (Op::Add, Value::Fixnum(x), Value::Fixnum(y)) => x.checked_add(*y),
+                        (Op::Sub, Value::Fixnum(x), Value::Fixnum(y)) => x.checked_sub(*y),
+                        (Op::Mul, Value::Fixnum(x), Value::Fixnum(y)) => x.checked_mul(*y),
+                        _ => None,
+                    };
+                    let v = match v {
+                        Some(n) => Value::Fixnum(n),
+                        // let the primitive report the type error or overflow
+                        None => builtins::call(op_name(op), vec![a, b], &mut self.out)?,
+                    };
+                    self.stack.push(v)
+                }
+                Op::NumEq | Op::Lt | Op::Gt | Op::Le | Op::Ge => {
+                    let (a, b) = self.pop2();
+                    let v = match (op, &a, &b) {
+                        (Op::NumEq, Value::Fixnum(x), Value::Fixnum(y)) => Value::Bool(x == y),
+                        (Op::Lt, Value::Fixnum(x), Value::Fixnum(y)) => Value::Bool(x < y),
+                        (Op::Gt, Value::Fixnum(x), Value::Fixnum(y)) => Value::Bool(x > y),
+                        (Op::Le, Value::Fixnum(x), Value::Fixnum(y)) => Value::Bool(x <= y),
+                        (Op::Ge, Value::Fixnum(x), Value::Fixnum(y)) => Value::Bool(x >= y),
+                        _ => builtins::call(op_name(op), vec![a, b], &mut self.out)?,
+                    };
+                    self.stack.push(v)
+                }
+                Op::Eq => {
+                    let (a, b) = self.pop2();
+                    self.stack.push(Value::Bool(eqv(&a, &b)))
+                }
+                Op::Cons => {
+                    let (a, b) = self.pop2();
+                    self.stack.push(Value::cons(a, b))
+                }
+                Op::Car | Op::Cdr => {
+                    let v = match self.pop() {
+                        Value::Pair(p) if op == Op::Car => p.car.borrow().clone(),
+                        Value::Pair(p) => p.cdr.borrow().clone(),
+                        other => builtins::call(op_name(op), vec![other], &mut self.out)?,
+                    };
+                    self.stack.push(v)
+                }
+                Op::NullP => {
+                    let v = self.pop();
+                    self.stack.push(Value::Bool(matches!(v, Value::Null)))
+                }
+                Op::Not => {
+                    let v = self.pop();
+                    self.stack.push(Value::Bool(!v.is_true()))
+                }
+                Op::Check(ty) => {
+                    let v = self.stack.last().expect("`check` has an operand");
+                    if !v.has_type(ty) {
+                        return Err(RuntimeError::AnnotationFailed {
+                            expected: ty,
+                            given: v.to_string(),
+                        });
+                    }
+                }
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Slide(n) => {
+                    let v = self.pop();
+                    self.stack.truncate(self.stack.len() - n as usize);
+                    self.stack.push(v)
+                }
+                Op::Return => {
+                    let v = self.pop();
+                    if let Some(v) = self.ret(frame, v) {
+                        return Ok(v);
+                    }
+                }
+                Op::Winders => self.stack.push(self.winders.clone()),
+                Op::SetWinders => self.winders = self.pop(),
+                Op::Handlers => self.stack.push(self.handlers.clone()),
+                Op::SetHandlers => self.handlers = self.pop(),
+                Op::Parameters => self.stack.push(self.parameters.clone()),
+                Op::SetParameters => self.parameters = self.pop(),
+            }
+        }
+    }
+
+    /// Raise the error `e`, found while running `frame`, as an error object
+    /// if there is a handler to catch it. The rest of `frame` never runs, as
+    /// the handler can't return to it.
+    fn fault(
+        &mut self,
+        frame: &mut Frame<'a>,
+        e: RuntimeError,
+    ) -> RuntimeResult<Option<Value<'a>>> {
+        if matches!(self.handlers, Value::Null) {
+            return Err(e);
+        }
+        let raise = prim::lookup("raise").expect("`raise` is a primitive");
+        self.stack.push(Value::Prim(raise));
+        self.stack.push(builtins::condition(&e));
+        self.call(frame, 1, false)
+    }
+
+    /// Call the procedure under the top `n` values of the stack. A tail call
+    /// replaces `frame` rather than saving it; if that finishes the program,
+    /// its value is returned.
+    fn call(
+        &mut self,
+        frame: &mut Frame<'a>,
+        mut n: usize,
+        tail: bool,
+    ) -> RuntimeResult<Option<Value<'a>>> {
+        loop {
+            let at = self.stack.len() - n - 1;
+            match self.stack[at].clone() {
+                Value::Compiled(c) => {
+                    if c.proto.arity != n {
+                        return Err(RuntimeError::WrongArgCount {
+                            proc: c.name().to_owned(),
+                            expected: Arity::Exactly(c.proto.arity),
+                            given: n,
+                        });
+                    }
+                    if tail {
+                        self.stack.drain(frame.base - 1..at);
+                        frame.closure = c;
+                        frame.pc = 0;
+                    } else {
+                        let callee = Frame {
+                            closure: c,
+                            pc: 0,
+                            base: at + 1,
+                        };
+                        self.frames.push(mem::replace(frame, callee));
+                    }
+                    return Ok(None);
+                }
+                Value::Prim(p) => {
+                    if !p.arity.accepts(n) {
+                        return Err(RuntimeError::WrongArgCount {
+                            proc: p.name.to_owned(),
+                            expected: p.arity,
+                            given: n,
+                        });
+                    }
+                    match p.name {
+                        "apply" => {
+                            // spread the last argument, and call again
+                            let spread = self.pop();
+                            let Some(rest) = spread.to_vec() else {
+                                return Err(RuntimeError::WrongType {
+                                    proc: p.name.to_owned(),
+                                    expected: "a list",
+                                    given: spread.to_string(),
+                                });
+                            };
+                            self.stack.remove(at);
+                            n = n - 2 + rest.len();
+                            self.stack.extend(rest);
+                            continue;
+                        }
+                        "call-with-current-continuation" | "call/cc" => {
+                            // call the argument with what this call returns to
+                            let k = Continuation {
+                                stack: self.stack[..at].to_vec(),
+                                frames: self.frames.clone(),
+                                frame: frame.clone(),
+                                tail,
+                                winders: self.winders.clone(),
+                                handlers: self.handlers.clone(),
+                                parameters: self.parameters.clone(),
+                            };
+                            self.stack.remove(at);
+                            self.stack.push(Value::VmContinuation(Rc::new(k)));
+                            continue;
+                        }
+                        "dynamic-wind" => {
+                            self.stack[at] = Value::Compiled(self.dynamic_wind.clone());
+                            continue;
+                        }
+                        "with-exception-handler" => {
+                            let handler = &self.stack[at + 1];
+                            if !handler.is_procedure() {
+                                return Err(RuntimeError::WrongType {
+                                    proc: p.name.to_owned(),
+                                    expected: "a procedure",
+                                    given: handler.to_string(),
+                                });
+                            }
+                            self.stack[at] = Value::Compiled(self.with_handler.clone());
+                            continue;
+                        }
+                        "raise" | "raise-continuable" | "error" => {
+                            let obj = if p.name == "error" {
+                                builtins::error(self.stack.split_off(at + 1))?
+                            } else {
+                                self.pop()
+                            };
+                            if matches!(self.handlers, Value::Null) {
+                                return Err(builtins::uncaught(&obj));
+                            }
+                            let raise = if p.name == "raise-continuable" {
+                                &self.raise_continuable
+                            } else {
+                                &self.raise
+                            };
+                            self.stack[at] = Value::Compiled(raise.clone());
+                            self.stack.push(obj);
+                            n = 1;
+                            continue;
+                        }
+                        "force" => {
+                            if let Value::Promise(_) = self.stack[at + 1] {
+                                self.stack[at] = Value::Compiled(self.force.clone());
+                                continue;
+                            }
+                        }
+                        // without a converter, the initial value is used as it is
+                        "make-parameter" if n > 1 => {
+                            self.stack[at] = Value::Compiled(self.make_parameter.clone());
+                            continue;
+                        }
+                        "%parameterize" => {
+                            let thunk = self.pop();
+                            let v = self.pop();
+                            let param = self.pop();
+                            let inner = builtins::parameterize(&param, v, &self.parameters)?;
+                            self.stack[at] = Value::Compiled(self.parameterize.clone());
+                            self.stack.push(inner);
+                            self.stack.push(thunk);
+                            n = 2;
+                            continue;
+                        }
+                        _ => (),
+                    }
+                    let args = self.stack.split_off(at + 1);
+                    self.stack.pop();
+                    let v = builtins::call(p.name, args, &mut self.out)?;
+                    if tail {
+                        return Ok(self.ret(frame, v));
+                    }
+                    self.stack.push(v);
+                    return Ok(None);
+                }
+                Value::VmContinuation(k) => {
+                    if n != 1 {
+                        return Err(RuntimeError::WrongArgCount {
+                            proc: "continuation".to_owned(),
+                            expected: Arity::Exactly(1),
+                            given: n,
+                        });
+                    }
+                    let v = self.pop();
+                    match value::wind_step(&self.winders, &k.winders) {
+                        None => {
+                            self.stack.clone_from(&k.stack);
+                            self.frames.clone_from(&k.frames);
+                            self.handlers = k.handlers.clone();
+                            self.parameters = k.parameters.clone();
+                            *frame = k.frame.clone();
+                            if k.tail {
+                                return Ok(self.ret(frame, v));
+                            }
+                            self.stack.push(v);
+                            return Ok(None);
+                        }
+                        Some((thunk, during, then)) => {
+                            // run the thunk, then try again from where it leaves
+                            let step = rethrow(thunk, during, then, Value::VmContinuation(k), v);
+                            self.stack[at] = Value::Compiled(step);
+                            n = 0;
+                        }
+                    }
+                }
+                Value::Parameter(param) => {
+                    if n != 0 {
+                        return Err(RuntimeError::WrongArgCount {
+                            proc: "parameter".to_owned(),
+                            expected: Arity::Exactly(0),
+                            given: n,
+                        });
+                    }
+                    self.stack.pop();
+                    let v = param.value(&self.parameters);
+                    if tail {
+                        return Ok(self.ret(frame, v));
+                    }
+                    self.stack.push(v);
+                    return Ok(None);
+                }
+                other => return Err(RuntimeError::NotAProcedure(other.to_string())),
+            }
+        }
+    }
+}
+
+/// `(dynamic-wind before thunk after)`, which runs `thunk` with the pair
+/// `(before . after)` pushed on the winders
+fn dynamic_wind<'a>() -> Rc<Closure<'a>> {
+    let code = vec![
+        Op::Local(0),
+        Op::Call(0),
+        Op::Pop,
+        // the winders outside, in slot 3
+        Op::Winders,
+        Op::Local(0),
+        Op::Local(2),
+        Op::Cons,
+        Op::Local(3),
+        Op::Cons,
+        Op::SetWinders,
+        Op::Local(1),
+        Op::Call(0),
+        Op::Local(3),
+        Op::SetWinders,
+        Op::Local(2),
+        Op::Call(0),
+        Op::Pop,
+        Op::Return,
+    ];
+    let proto = Proto {
+        name: Some("dynamic-wind".to_owned()),
+        arity: 3,
+        code,
+        ..Default::default()
+    };
+    Rc::new(Closure {
+        proto: Rc::new(proto),
+        upvals: Vec::new(),
+    })
+}
+
+/// `(with-exception-handler handler thunk)`, which runs `thunk` with
+/// `handler` pushed on the handlers
+fn with_handler<'a>() -> Rc<Closure<'a>> {
+    let code = vec![
+        // the handlers outside, in slot 2
+        Op::Handlers,
+        Op::Local(0),
+        Op::Local(2),
+        Op::Cons,
+        Op::SetHandlers,
+        Op::Local(1),
+        Op::Call(0),
+        Op::Local(2),
+        Op::SetHandlers,
+        Op::Return,
+    ];
+    let proto = Proto {
+        name: Some("with-exception-handler".to_owned()),
+        arity: 2,
+        code,
+        ..Default::default()
+    };
+    Rc::new(Closure {
+        proto: Rc::new(proto),
+        upvals: Vec::new(),
+    })
+}
+
+/// Calls the innermost handler with the object raised, inside the handlers
+/// outside it, given there is one. Unless the raise is `continuable`, the
+/// handler returning raises an error in turn.
+fn raise<'a>(continuable: bool) -> Rc<Closure<'a>> {
+    let mut code = vec![
+        // the handlers inside, in slot 1
+        Op::Handlers,
+        Op::Local(1),
+        Op::Cdr,
+        Op::SetHandlers,
+        Op::Local(1),
+        Op::Car,
+        Op::Local(0),
+        Op::Call(1),
+    ];
+    let mut consts = Vec::new();
+    if continuable {
+        code.extend([Op::Local(1), Op::SetHandlers, Op::Return]);
+    } else {
+        let error = prim::lookup("error").expect("`error` is a primitive");
+        consts = vec![
+            Value::Prim(error),
+            Value::string(builtins::HANDLER_RETURNED.to_owned()),
+        ];
+        code.extend([
+            Op::Pop,
+            Op::Const(0),
+            Op::Const(1),
+            Op::Local(0),
+            Op::TailCall(2),
+        ]);
+    }
+    let proto = Proto {
+        name: Some(
+            if continuable {
+                "raise-continuable"
+            } else {
+                "raise"
+            }
+            .to_owned(),
+        ),
+        arity: 1,
+        code,
+        consts,
+        ..Default::default()
+    };
+    Rc::new(Closure {
+        proto: Rc::new(proto),
+        upvals: Vec::new(),
+    })
+}
+
+/// `(force promise)`, which runs thunks until one gives a promise which is
+/// done, sharing its state with `promise` each time; they all return to the
+/// same frame, so a chain of `delay-force`s runs in constant space
+fn force<'a>() -> Rc<Closure<'a>> {
+    let prim = |name: &str, n: u16| {
+        let i = PRIMS.iter().position(|p| p.name == name).unwrap();
+        Op::Prim(i as u16, n)
+    };
+    let code = vec![
+        Op::Local(0),
+        prim("%promise-done?", 1),
+        Op::JumpUnless(6),
+        Op::Local(0),
+        prim("%promise-value", 1),
+        Op::Return,
+        Op::Local(0),
+        prim("%promise-value", 1),
+        Op::Call(0),
+        // take over from the promise the thunk gives
+        Op::Local(0),
+        prim("%promise-update!", 2),
+        Op::Pop,
+        Op::Jump(0),
+    ];
+    let proto = Proto {
+        name: Some("force".to_owned()),
+        arity: 1,
+        code,
+        ..Default::default()
+    };
+    Rc::new(Closure {
+        proto: Rc::new(proto),
+        upvals: Vec::new(),
+    })
+}
+
+/// `(make-parameter init converter)`, whose initial value is what the
+/// converter makes of `init`
+fn make_parameter<'a>() -> Rc<Closure<'a>> {
+    let i = PRIMS
+        .iter()
+        .position(|p| p.name == "%make-parameter")
+        .unwrap();
+    let code = vec![
+        Op::Local(1),
+        Op::Local(0),
+        Op::Call(1),
+        Op::Local(1),
+        Op::Prim(i as u16, 2),
+        Op::Return,
+    ];
+    let proto = Proto {
+        name: Some("make-parameter".to_owned()),
+        arity: 2,
+        code,
+        ..Default::default()
+    };
+    Rc::new(Closure {
+        proto: Rc::new(proto),
+        upvals: Vec::new(),
+    })
+}
+
+/// Runs `thunk` inside the `parameterize` bindings `bound`
+fn parameterize<'a>() -> Rc<Closure<'a>> {
+    let code = vec![
+        // the bindings outside, in slot 2
+        Op::Parameters,
+        Op::Local(0),
+        Op::SetParameters,
+        Op::Local(1),
+        Op::Call(0),
+        Op::Local(2),
+        Op::SetParameters,
+        Op::Return,
+    ];
+    let proto = Proto {
+        name: Some("parameterize".to_owned()),
+        arity: 2,
+        code,
+        ..Default::default()
+    };
+    Rc::new(Closure {
+        proto: Rc::new(proto),
+        upvals: Vec::new(),
+    })
+}
+
+/// A thunk running `thunk` inside the winders `during`, then calling the
+/// continuation `k` with `v` from inside the winders `then`; one step of
+/// the way to `k`, as `value::wind_step` gives it
+fn rethrow<'a>(
+    thunk: Value<'a>,
+    during: Value<'a>,
+    then: Value<'a>,
+    k: Value<'a>,
+    v: Value<'a>,
+) -> Rc<Closure<'a>> {
+    let code = vec![
+        Op::Const(1),
+        Op::SetWinders,
+        Op::Const(0),
+        Op::Call(0),
+        Op::Pop,
+        Op::Const(2),
+        Op::SetWinders,
+        Op::Const(3),
+        Op::Const(4),
+        Op::TailCall(1),
+    ];
+    let proto = Proto {
+        code,
+        consts: vec![thunk, during, then, k, v],
+        ..Default::default()
+    };
+    Rc::new(Closure {
+        proto: Rc::new(proto),
+        upvals: Vec::new(),
+    })
+}
+
+/// The primitive behind one of the arithmetic or pair instructions
+fn op_name(op: Op) -> &'static str {
+    match op {
+        Op::Add => "+",
+        Op::Sub => "-",
+        Op::Mul => "*",
+        Op::NumEq => "=",
+        Op::Lt => "<",
+        Op::Gt => ">",
+        Op::Le => "<=",
+        Op::Ge => ">=",
+        Op::Car => "car",
+        Op::Cdr => "cdr",
+        other => unreachable!("{other:?} is not a primitive"),
+    }
+}
@@ -0,0 +1,745 @@
+//! Lower a closure-converted program into a WebAssembly module, in the text
+//! format, made of the runtime in `runtime.wat` and then the program
+//!
+//! Values are `i64` words laid out as `word` describes, with the heap in
+//! linear memory, which starts out holding the program's constants. Every
+//! procedure becomes a function in the table, taking its closure and then
+//! its arguments, and every call in tail position becomes a tail call, so
+//! loops run in constant stack. The module exports
+//! `main` and its memory, and imports the printing and fault reporting the
+//! host provides.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::closure::{Flat, Program};
+use crate::datum::Datum;
+use crate::eval::{EvalError, EvalResult};
+use crate::prim::{self, Prim, PRIMS};
+use crate::primsyn::Type;
+use crate::symbol::Symbol;
+use crate::types::Types;
+use crate::word::*;
+
+const RUNTIME: &str = include_str!("runtime.wat");
+
+/// Turn a name into something safe to use as part of an identifier
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// The local holding a parameter
+fn formal(name: &str) -> String {
+    format!("a_{}", sanitize(name))
+}
+
+fn get(local: &str) -> String {
+    format!("local.get ${local}")
+}
+
+fn int(n: i64) -> String {
+    format!("i64.const {n}")
+}
+
+/// The type of the functions of procedures taking `n` arguments
+fn call_type(n: usize) -> String {
+    format!("$call{n}")
+}
+
+/// Assemble the text of a module into the binary format
+#[cfg(feature = "wasm")]
+pub fn assemble(wat: &str) -> EvalResult<Vec<u8>> {
+    Ok(wat::parse_str(wat).expect("`WasmGen` makes valid modules"))
+}
+
+#[cfg(not(feature = "wasm"))]
+pub fn assemble(_: &str) -> EvalResult<Vec<u8>> {
+    Err(EvalError::Unsupported(
+        "binary WebAssembly without the `wasm` feature".to_owned(),
+    ))
+}
+
+/// What the code generator needs to know about a procedure besides its body
+struct ProcInfo {
+    func: String,
+    arity: usize,
+}
+
+/// The function being generated
+struct Function {
+    /// Its name, type and parameters
+    head: String,
+    lines: Vec<String>,
+    /// Every local it uses other than its parameters
+    locals: Vec<String>,
+    /// The locals holding the variables in scope
+    regs: HashMap<Symbol, String>,
+    /// The names of the free variables of its closure
+    free: Vec<Symbol>,
+    depth: usize,
+    temps: usize,
+}
+
+impl Function {
+    fn init(head: String, free: Vec<Symbol>) -> Self {
+        Self {
+            head,
+            lines: Vec::new(),
+            locals: Vec::new(),
+            regs: HashMap::new(),
+            free,
+            depth: 2,
+            temps: 0,
+        }
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        let name = format!("t{}", self.temps);
+        self.locals.push(name.clone());
+        name
+    }
+
+    /// A fresh local for the variable `name`
+    fn bind(&mut self, name: Symbol) -> String {
+        self.temps += 1;
+        let r = format!("v{}_{}", self.temps, sanitize(&name));
+        self.locals.push(r.clone());
+        self.regs.insert(name, r.clone());
+        r
+    }
+
+    fn push(&mut self, line: impl Into<String>) {
+        self.lines
+            .push(format!("{}{}", "  ".repeat(self.depth), line.into()))
+    }
+
+    /// A new local holding the value on top of the stack
+    fn set(&mut self) -> String {
+        let t = self.temp();
+        self.push(format!("local.set ${t}"));
+        t
+    }
+
+    /// A new local holding the value of `instrs`
+    fn assign(&mut self, instrs: &[String]) -> String {
+        for instr in instrs {
+            self.push(instr.clone());
+        }
+        self.set()
+    }
+
+    fn constant(&mut self, n: i64) -> String {
+        self.assign(&[int(n)])
+    }
+
+    /// Turn the `i32` on top of the stack, 0 or 1, into a new local holding
+    /// `#f` or `#t`
+    fn boolean(&mut self) -> String {
+        self.push("call $bool");
+        self.set()
+    }
+
+    /// Start a block
+    fn open(&mut self, instr: &str) {
+        self.push(instr);
+        self.depth += 1;
+    }
+
+    /// Close a block, and start the one following it
+    fn reopen(&mut self, instr: &str) {
+        self.depth -= 1;
+        self.open(instr);
+    }
+
+    fn close(&mut self) {
+        self.depth -= 1;
+        self.push("end");
+    }
+
+    fn finish(self, out: &mut String) {
+        writeln!(out, "  {}", self.head).unwrap();
+        for chunk in self.locals.chunks(8) {
+            let locals: Vec<String> = chunk.iter().map(|l| format!("(local ${l} i64)")).collect();
+            writeln!(out, "    {}", locals.join(" ")).unwrap();
+        }
+        for line in &self.lines {
+            writeln!(out, "{line}").unwrap();
+        }
+        out.push_str("  )\n\n");
+    }
+}
+
+/// Generates a WebAssembly module for a whole program. Calls check the
+/// callee's tag and arity before calling through the table, except for
+/// calls to top-level procedures defined once, which are called directly.
+pub struct WasmGen<'t> {
+    types: &'t Types,
+    procs: Vec<ProcInfo>,
+    globals: Vec<Symbol>,
+    /// Top-level procedures defined once, with no free variables
+    direct: HashMap<Symbol, usize>,
+    /// The arities of every procedure and call, each needing a type
+    arities: BTreeSet<usize>,
+    /// The words of the constants, from address 8
+    data: Vec<i64>,
+    /// The symbols the program quotes, by the index their words hold
+    symbols: Vec<Symbol>,
+}
+
+impl<'t> WasmGen<'t> {
+    pub fn init(types: &'t Types) -> Self {
+        Self {
+            types,
+            procs: Vec::new(),
+            globals: Vec::new(),
+            direct: HashMap::new(),
+            arities: BTreeSet::new(),
+            data: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+
+    /// The text of the module, and what the host needs to describe its
+    /// values, where the code of a closure is its index into `procs`
+    pub fn generate(mut self, program: &Program) -> EvalResult<(String, Symbols)> {
+        self.globals = program.globals.clone();
+        for (i, proc) in program.procs.iter().enumerate() {
+            let name = proc.name.as_deref().unwrap_or("lambda");
+            self.procs.push(ProcInfo {
+                func: format!("$f{i}_{}", sanitize(name)),
+                arity: proc.formals.len(),
+            });
+            self.arities.insert(proc.formals.len());
+            if let (Some(name), true) = (&proc.global, proc.free.is_empty()) {
+                self.direct.insert(*name, i);
+            }
+        }
+
+        let mut funcs = Vec::new();
+        for (i, proc) in program.procs.iter().enumerate() {
+            let params: Vec<String> = std::iter::once("clo".to_owned())
+                .chain(proc.formals.iter().map(|f| formal(f)))
+                .map(|p| format!("(param ${p} i64)"))
+                .collect();
+            let head = format!(
+                "(func {} (type {}) {} (result i64)",
+                self.procs[i].func,
+                call_type(proc.formals.len()),
+                params.join(" ")
+            );
+            let mut f = Function::init(head, proc.free.clone());
+            for formal in &proc.formals {
+                f.regs.insert(*formal, self::formal(formal));
+            }
+            let r = self.expr(&mut f, &proc.body)?;
+            f.push(get(&r));
+            funcs.push(f);
+        }
+
+        let head = "(func $main (export \"main\") (result i64)".to_owned();
+        let mut f = Function::init(head, vec![]);
+        let r = self.expr(&mut f, &program.main)?;
+        f.push(get(&r));
+        funcs.push(f);
+
+        let mut out = String::from("(module\n");
+        out.push_str(RUNTIME);
+        out.push_str("\n  ;; The program\n\n");
+        for n in &self.arities {
+            let params = vec!["i64"; n + 1].join(" ");
+            writeln!(
+                out,
+                "  (type {} (func (param {params}) (result i64)))",
+                call_type(*n)
+            )
+            .unwrap();
+        }
+        writeln!(out, "  (table {} funcref)", self.procs.len()).unwrap();
+        if !self.procs.is_empty() {
+            let funcs: Vec<&str> = self.procs.iter().map(|p| p.func.as_str()).collect();
+            writeln!(out, "  (elem (i32.const 0) func {})", funcs.join(" ")).unwrap();
+        }
+        let heap = 8 * (self.data.len() + 1);
+        let pages = heap.div_ceil(1 << 16);
+        writeln!(out, "  (memory (export \"memory\") {pages})").unwrap();
+        if !self.data.is_empty() {
+            let bytes: String = (self.data.iter())
+                .flat_map(|word| word.to_le_bytes())
+                .map(|b| format!("\\{b:02x}"))
+                .collect();
+            writeln!(out, "  (data (i32.const 8) \"{bytes}\")").unwrap();
+        }
+        writeln!(out, "  (global $hp (mut i32) (i32.const {heap}))").unwrap();
+        for i in 0..self.globals.len() {
+            writeln!(
+                out,
+                "  (global {} (mut i64) (i64.const {UNBOUND}))",
+                self.global(i)
+            )
+            .unwrap();
+        }
+        out.push('\n');
+        for f in funcs {
+            f.finish(&mut out);
+        }
+        out.push_str(")\n");
+
+        let symbols = Symbols {
+            globals: program.globals.clone(),
+            procs: (self.procs.iter().zip(&program.procs))
+                .map(|(info, proc)| (info.func.clone(), proc.name.clone()))
+                .collect(),
+            symbols: self.symbols,
+        };
+        Ok((out, symbols))
+    }
+
+    /// The global holding `globals[i]`
+    fn global(&self, i: usize) -> String {
+        format!("$g{i}_{}", sanitize(&self.globals[i]))
+    }
+
+    fn global_index(&self, name: Symbol) -> usize {
+        self.globals
+            .iter()
+            .position(|g| *g == name)
+            .expect("`Converter` collects every global")
+    }
+
+    /// Report a fault to the host, and trap
+    fn fail(&mut self, f: &mut Function, fault: Fault, detail: i64, value: String) {
+        f.push(format!("i32.const {}", fault as i64));
+        f.push(int(detail));
+        f.push(value);
+        f.push("call $error");
+        f.push("unreachable");
+    }
+
+    /// Fail unless `r` holds a value of type `ty`
+    fn guard(&mut self, f: &mut Function, r: &str, ty: Type, fault: Fault, detail: i64) {
+        let Some((mask, expected)) = type_test(ty) else {
+            return self.fail(f, fault, detail, get(r));
+        };
+        f.push(get(r));
+        f.push(int(mask));
+        f.push("i64.and");
+        f.push(int(expected));
+        f.push("i64.ne");
+        f.open("if");
+        self.fail(f, fault, detail, get(r));
+        f.close();
+    }
+
+    /// The address of the block the pointer in `r` points to
+    fn untag(&mut self, f: &mut Function, r: &str) {
+        f.push(get(r));
+        f.push("call $untag");
+    }
+
+    /// Load the word at `offset` into the block the pointer in `r` points to
+    fn load(&mut self, f: &mut Function, r: &str, offset: i64) -> String {
+        self.untag(f, r);
+        f.push(format!("i64.load offset={offset}"));
+        f.set()
+    }
+
+    /// Store the value in `v` at `offset` into the block `r` points to
+    fn store(&mut self, f: &mut Function, r: &str, offset: i64, v: &str) {
+        self.untag(f, r);
+        f.push(get(v));
+        f.push(format!("i64.store offset={offset}"));
+    }
+
+    fn cons(&mut self, f: &mut Function, car: &str, cdr: &str) -> String {
+        f.assign(&[get(car), get(cdr), "call $cons".to_owned()])
+    }
+
+    /// Make a closure of `procs[i]` in `dst`, without filling in its free
+    /// variables
+    fn closure(&mut self, f: &mut Function, i: usize, free: usize, dst: &str) {
+        f.push(format!("i32.const {i}"));
+        f.push(format!("i32.const {}", self.procs[i].arity));
+        f.push(format!("i32.const {free}"));
+        f.push("call $closure");
+        f.push(format!("local.set ${dst}"));
+    }
+
+    /// Fill in the free variables of the closure in `dst`
+    fn capture(&mut self, f: &mut Function, dst: &str, captured: &[Flat]) -> EvalResult<()> {
+        for (i, c) in captured.iter().enumerate() {
+            let v = self.expr(f, c)?;
+            self.store(f, dst, 16 + 8 * i as i64, &v);
+        }
+        Ok(())
+    }
+
+    /// The constant `d`, laid out among the module's data if it needs any
+    /// objects, so that they're made once as in the other backends
+    fn constant(&mut self, f: &mut Function, d: &Datum) -> EvalResult<String> {
+        let data = &mut self.data;
+        let mut alloc = |words: &[i64]| {
+            let addr = 8 * (data.len() as i64 + 1);
+            data.extend_from_slice(words);
+            addr
+        };
+        match Word::from_datum(d, &mut self.symbols, &mut alloc) {
+            Some(Word(word)) => Ok(f.constant(word)),
+            None => Err(EvalError::Unsupported(format!(
+                "the constant `{d}` in compiled code"
+            ))),
+        }
+    }
+
+    /// Is `e` known to evaluate to a value of type `ty`?
+    fn known(&self, f: &Function, e: &Flat, ty: Type) -> bool {
+        let t = match e {
+            Flat::Local(name) => self.types.of_var(*name),
+            Flat::Free(i) => self.types.of_var(f.free[*i]),
+            Flat::Const(d) => crate::types::datum_type(d),
+            Flat::Prim(p, _) => prim::result_type(p.name),
+            Flat::Closure(..) => Some(Type::Proc),
+            Flat::The(t, _) => Some(*t),
+            _ => None,
+        };
+        t.is_some_and(|t| t.within(ty))
+    }
+
+    fn expr(&mut self, f: &mut Function, e: &Flat) -> EvalResult<String> {
+        let r = match e {
+            Flat::Local(name) => f.regs[name].clone(),
+            Flat::Free(i) => self.load(f, "clo", 16 + 8 * *i as i64),
+            Flat::Global(name) => {
+                let i = self.global_index(*name);
+                let r = f.assign(&[format!("global.get {}", self.global(i))]);
+                f.push(get(&r));
+                f.push(int(UNBOUND));
+                f.push("i64.eq");
+                f.open("if");
+                self.fail(f, Fault::Unbound, i as i64, int(0));
+                f.close();
+                r
+            }
+            Flat::Const(d) => self.constant(f, d)?,
+            Flat::PrimRef(p) => {
+                return Err(EvalError::Unsupported(format!(
+                    "the primitive `{}` as a value in compiled code",
+                    p.name
+                )))
+            }
+            Flat::Closure(i, captured) => {
+                let r = f.temp();
+                self.closure(f, *i, captured.len(), &r);
+                self.capture(f, &r, captured)?;
+                r
+            }
+            Flat::If(c, t, e) => {
+                let r = f.temp();
+                let c = self.expr(f, c)?;
+                f.push(get(&c));
+                f.push(int(FALSE));
+                f.push("i64.ne");
+                f.open("if");
+                let t = self.expr(f, t)?;
+                f.push(get(&t));
+                f.push(format!("local.set ${r}"));
+                f.reopen("else");
+                let e = self.expr(f, e)?;
+                f.push(get(&e));
+                f.push(format!("local.set ${r}"));
+                f.close();
+                r
+            }
+            Flat::Call(rator, rands, tail) => self.call(f, rator, rands, *tail)?,
+            Flat::Prim(p, rands) => self.prim(f, p, rands)?,
+            Flat::Let(bs, body) => {
+                for (name, init) in bs {
+                    let v = self.expr(f, init)?;
+                    let r = f.bind(*name);
+                    f.push(get(&v));
+                    f.push(format!("local.set ${r}"));
+                }
+                self.expr(f, body)?
+            }
+            Flat::LetRec(procs, body) => {
+                for (name, i, captured) in procs {
+                    let r = f.bind(*name);
+                    self.closure(f, *i, captured.len(), &r);
+                }
+                for (name, _, captured) in procs {
+                    let r = f.regs[name].clone();
+                    self.capture(f, &r, captured)?;
+                }
+                self.expr(f, body)?
+            }
+            Flat::Begin(es) => {
+                let mut r = None;
+                for e in es {
+                    r = Some(self.expr(f, e)?);
+                }
+                match r {
+                    Some(r) => r,
+                    None => f.constant(UNSPECIFIED),
+                }
+            }
+            Flat::The(ty, e) => {
+                let r = self.expr(f, e)?;
+                // the types of variables are inferred trusting this check
+                let var = matches!(**e, Flat::Local(_) | Flat::Free(_));
+                if var || !self.known(f, e, *ty) {
+                    self.guard(f, &r, *ty, Fault::AnnotationFailed, *ty as i64);
+                }
+                r
+            }
+            Flat::Define(name, e) => {
+                let v = self.expr(f, e)?;
+                let g = self.global(self.global_index(*name));
+                f.push(get(&v));
+                f.push(format!("global.set {g}"));
+                f.constant(UNSPECIFIED)
+            }
+        };
+        Ok(r)
+    }
+
+    fn call(
+        &mut self,
+        f: &mut Function,
+        rator: &Flat,
+        rands: &[Flat],
+        tail: bool,
+    ) -> EvalResult<String> {
+        let direct = match rator {
+            Flat::Global(name) => self
+                .direct
+                .get(name)
+                .copied()
+                .filter(|i| self.procs[*i].arity == rands.len()),
+            _ => None,
+        };
+        let callee = self.expr(f, rator)?;
+        let mut args = Vec::new();
+        for rand in rands {
+            args.push(self.expr(f, rand)?);
+        }
+
+        let n = rands.len();
+        if direct.is_none() {
+            self.guard(f, &callee, Type::Proc, Fault::NotAProcedure, 0);
+            let arity = self.load(f, &callee, 8);
+            f.push(get(&arity));
+            f.push(int(n as i64));
+            f.push("i64.ne");
+            f.open("if");
+            self.fail(f, Fault::WrongArgCount, n as i64, get(&callee));
+            f.close();
+        }
+        f.push(get(&callee));
+        for a in &args {
+            f.push(get(a));
+        }
+        let ret = if tail { "return_" } else { "" };
+        match direct {
+            Some(i) => f.push(format!("{ret}call {}", self.procs[i].func)),
+            None => {
+                self.arities.insert(n);
+                self.untag(f, &callee);
+                f.push("i64.load");
+                f.push("i32.wrap_i64");
+                f.push(format!("{ret}call_indirect (type {})", call_type(n)));
+            }
+        }
+        // after a tail call, this is never reached
+        Ok(f.set())
+    }
+
+    fn prim(&mut self, f: &mut Function, p: &'static Prim, rands: &[Flat]) -> EvalResult<String> {
+        let index = PRIMS
+            .iter()
+            .position(|q| std::ptr::eq(q, p))
+            .expect("`Flat::Prim` holds one of `PRIMS`") as i64;
+        let mut args = Vec::new();
+        for (i, rand) in rands.iter().enumerate() {
+            let a = self.expr(f, rand)?;
+            if let Some(kind) = prim::arg_kind(p.name, i) {
+                if !self.known(f, rand, kind.as_type()) {
+                    let detail = index | (i as i64) << 16;
+                    self.guard(f, &a, kind.as_type(), Fault::WrongType, detail);
+                }
+            }
+            args.push(a);
+        }
+
+        let r = match (p.name, args.as_slice()) {
+            ("+", []) => f.constant(0),
+            ("*", []) => f.constant(fixnum(1)),
+            ("+" | "*" | "-", [first, rest @ ..]) if !rest.is_empty() => {
+                let op = match p.name {
+                    "+" => "call $add",
+                    "*" => "call $mul",
+                    _ => "call $sub",
+                };
+                f.push(get(first));
+                for a in rest {
+                    f.push(get(a));
+                    f.push(int(index));
+                    f.push(op);
+                }
+                f.set()
+            }
+            ("+" | "*", [a]) => a.clone(),
+            ("-", [a]) => f.assign(&[int(0), get(a), int(index), "call $sub".to_owned()]),
+            ("add1" | "sub1", [a]) => {
+                let op = if p.name == "add1" { "$add" } else { "$sub" };
+                f.assign(&[get(a), int(fixnum(1)), int(index), format!("call {op}")])
+            }
+            ("quotient" | "remainder" | "modulo", [a, b]) => {
+                let op = match p.name {
+                    "quotient" => 0,
+                    "remainder" => 1,
+                    _ => 2,
+                };
+                f.assign(&[
+                    get(a),
+                    get(b),
+                    format!("i32.const {op}"),
+                    int(index),
+                    "call $divide".to_owned(),
+                ])
+            }
+            ("abs", [a]) => {
+                let r = f.temp();
+                f.push(get(a));
+                f.push(int(0));
+                f.push("i64.lt_s");
+                f.open("if");
+                f.push(int(0));
+                f.push(get(a));
+                f.push(int(index));
+                f.push("call $sub");
+                f.push(format!("local.set ${r}"));
+                f.reopen("else");
+                f.push(get(a));
+                f.push(format!("local.set ${r}"));
+                f.close();
+                r
+            }
+            ("=" | "<" | ">" | "<=" | ">=", [_]) => f.constant(TRUE),
+            ("=" | "<" | ">" | "<=" | ">=", _) => {
+                let op = match p.name {
+                    "=" => "i64.eq",
+                    "<" => "i64.lt_s",
+                    ">" => "i64.gt_s",
+                    "<=" => "i64.le_s",
+                    _ => "i64.ge_s",
+                };
+                // shifting keeps the order, so compare the words themselves
+                for (i, pair) in args.windows(2).enumerate() {
+                    f.push(get(&pair[0]));
+                    f.push(get(&pair[1]));
+                    f.push(op);
+                    if i > 0 {
+                        f.push("i32.and");
+                    }
+                }
+                f.boolean()
+            }
+            ("zero?", [a]) => {
+                f.push(get(a));
+                f.push("i64.eqz");
+                f.boolean()
+            }
+            ("even?" | "odd?", [a]) => {
+                f.push(get(a));
+                f.push(int(fixnum(1)));
+                f.push("i64.and");
+                f.push("i64.eqz");
+                if p.name == "odd?" {
+                    f.push("i32.eqz");
+                }
+                f.boolean()
+            }
+            ("not", [a]) => {
+                f.push(get(a));
+                f.push(int(FALSE));
+                f.push("i64.eq");
+                f.boolean()
+            }
+            ("eq?" | "eqv?", [a, b]) => {
+                f.push(get(a));
+                f.push(get(b));
+                f.push("i64.eq");
+                f.boolean()
+            }
+            ("null?", [a]) => {
+                f.push(get(a));
+                f.push(int(NULL));
+                f.push("i64.eq");
+                f.boolean()
+            }
+            (
+                "pair?" | "procedure?" | "boolean?" | "char?" | "symbol?" | "fixnum?" | "integer?"
+                | "number?",
+                [a],
+            ) => {
+                let ty = match p.name {
+                    "pair?" => Type::Pair,
+                    "procedure?" => Type::Proc,
+                    "boolean?" => Type::Bool,
+                    "char?" => Type::Char,
+                    "symbol?" => Type::Symbol,
+                    _ => Type::Fixnum,
+                };
+                let (mask, expected) = type_test(ty).expect("these types have tests");
+                f.push(get(a));
+                f.push(int(mask));
+                f.push("i64.and");
+                f.push(int(expected));
+                f.push("i64.eq");
+                f.boolean()
+            }
+            ("cons", [a, b]) => self.cons(f, a, b),
+            ("car", [a]) => self.load(f, a, 0),
+            ("cdr", [a]) => self.load(f, a, 8),
+            ("set-car!" | "set-cdr!", [a, b]) => {
+                let offset = if p.name == "set-car!" { 0 } else { 8 };
+                self.store(f, a, offset, b);
+                f.constant(UNSPECIFIED)
+            }
+            ("list", items) => {
+                let mut r = f.constant(NULL);
+                for item in items.iter().rev() {
+                    r = self.cons(f, item, &r);
+                }
+                r
+            }
+            ("char->integer", [a]) => f.assign(&[
+                get(a),
+                int(8),
+                "i64.shr_u".to_owned(),
+                int(FIXNUM_SHIFT),
+                "i64.shl".to_owned(),
+            ]),
+            ("display" | "write", [a]) => {
+                f.push(get(a));
+                f.push(format!("call ${}", p.name));
+                f.constant(UNSPECIFIED)
+            }
+            ("newline", []) => {
+                f.push("call $newline");
+                f.constant(UNSPECIFIED)
+            }
+            // the runtime has no collector, and the heap only grows
+            ("collect", []) => f.constant(UNSPECIFIED),
+            _ => {
+                return Err(EvalError::Unsupported(format!(
+                    "the primitive `{}` in compiled code",
+                    p.name
+                )))
+            }
+        };
+        Ok(r)
+    }
+}
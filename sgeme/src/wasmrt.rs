@@ -0,0 +1,129 @@
+//! Running the modules made by `wasmgen::WasmGen` in an embedded
+//! interpreter, as the host they import printing and fault reporting from
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use wasmi::{Caller, Config, Engine, Extern, Linker, Module, Store};
+
+use crate::interp::{RuntimeError, RuntimeResult};
+use crate::symbol::Symbol;
+use crate::word::{Fault, Memory, Symbols};
+
+/// The state of the program running
+struct State {
+    /// The names of the procedures, by their index into the table
+    names: HashMap<i64, Option<String>>,
+    globals: Vec<Symbol>,
+    symbols: Vec<Symbol>,
+    /// The fault reported, after which the code traps
+    fault: Option<RuntimeError>,
+}
+
+/// Read the values in the linear memory `data`
+fn memory<T>(state: &State, data: &[u8], f: impl FnOnce(&Memory) -> T) -> T {
+    let load = |addr: i64| {
+        let addr = addr as u32 as usize;
+        data.get(addr..addr + 8)
+            .map_or(0, |word| i64::from_le_bytes(word.try_into().unwrap()))
+    };
+    f(&Memory {
+        names: &state.names,
+        globals: &state.globals,
+        symbols: &state.symbols,
+        load: &load,
+    })
+}
+
+/// Call `f` with the memory of the module calling an import
+fn with_memory<T>(caller: &mut Caller<State>, f: impl FnOnce(&mut State, &[u8]) -> T) -> T {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .expect("`WasmGen` exports the memory");
+    let (data, state) = memory.data_and_store_mut(caller);
+    f(state, data)
+}
+
+fn print(state: &mut State, s: &str) {
+    if let Err(e) = io::stdout().write_all(s.as_bytes()) {
+        state.fault.get_or_insert(RuntimeError::Io(e.to_string()));
+    }
+}
+
+fn print_value(caller: &mut Caller<State>, value: i64, write: bool) {
+    with_memory(caller, |state, data| {
+        let mut out = String::new();
+        memory(state, data, |m| m.show(&mut out, value, write));
+        print(state, &out)
+    })
+}
+
+/// Instantiate the module `wasm` and call its `main`, returning its value as
+/// `write` would print it. `symbols` are those `WasmGen` made along with it.
+pub fn run(wasm: &[u8], symbols: &Symbols) -> RuntimeResult<String> {
+    let mut config = Config::default();
+    config.wasm_tail_call(true);
+    let engine = Engine::new(&config);
+    let module = Module::new(&engine, wasm).expect("`WasmGen` makes valid modules");
+
+    let names = (symbols.procs.iter().enumerate())
+        .map(|(i, (_, name))| (i as i64, name.clone()))
+        .collect();
+    let state = State {
+        names,
+        globals: symbols.globals.clone(),
+        symbols: symbols.symbols.clone(),
+        fault: None,
+    };
+    let mut store = Store::new(&engine, state);
+    let mut linker = <Linker<State>>::new(&engine);
+    linker
+        .func_wrap(
+            "sgeme",
+            "error",
+            |mut caller: Caller<State>, code: i32, detail: i64, value: i64| {
+                with_memory(&mut caller, |state, data| {
+                    let fault = Fault::ALL[code as usize];
+                    let error = memory(state, data, |m| m.fault(fault, detail, value));
+                    state.fault.get_or_insert(error);
+                })
+            },
+        )
+        .and_then(|l| {
+            l.func_wrap("sgeme", "display", |mut caller: Caller<State>, v: i64| {
+                print_value(&mut caller, v, false)
+            })
+        })
+        .and_then(|l| {
+            l.func_wrap("sgeme", "write", |mut caller: Caller<State>, v: i64| {
+                print_value(&mut caller, v, true)
+            })
+        })
+        .and_then(|l| {
+            l.func_wrap("sgeme", "newline", |mut caller: Caller<State>| {
+                print(caller.data_mut(), "\n")
+            })
+        })
+        .expect("the imports are each defined once");
+
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|i| i.start(&mut store))
+        .expect("`WasmGen` makes modules with just the imports above");
+    let main = instance
+        .get_typed_func::<(), i64>(&store, "main")
+        .expect("`WasmGen` always exports `main`");
+    let res = main.call(&mut store, ());
+    let _ = io::stdout().flush();
+
+    if let Some(e) = store.data_mut().fault.take() {
+        return Err(e);
+    }
+    let value = res.map_err(|trap| RuntimeError::Trap(trap.to_string()))?;
+    let memory = instance
+        .get_memory(&store, "memory")
+        .expect("`WasmGen` exports the memory");
+    let data = memory.data(&store);
+    Ok(self::memory(store.data(), data, |m| m.shown(value)))
+}
@@ -0,0 +1,483 @@
+//! How the native backends lay out values, as 64-bit words, and reading
+//! them back out of a running program
+//!
+//! A fixnum `n` is `n << 32`, so its low half is zero and overflow checks
+//! on the whole word catch exactly the results that don't fit an `i32`.
+//! Otherwise the low three bits tag a pointer to an object in the heap, or
+//! mark an immediate such as `#f`. A symbol is an immediate too, holding its
+//! index into a table of the symbols the program quotes, which the backend
+//! makes along with it. Objects are made of words:
+//! + a pair is `[car, cdr]`
+//! + a closure is `[code, arity, free...]`, where what `code` holds depends
+//!   on the backend
+//! + a vector is `[length, items...]`
+//! + a string is `[length, code points...]`, with the code points as 32-bit
+//!   numbers two to a word, the first in the low half
+//! + a record is `[type, field count, fields...]`
+//!
+//! Every object takes at least two words, leaving room for the forwarding
+//! pointer of a copying collector.
+//!
+//! There are no bignums in this layout, so a result too big for a fixnum is
+//! reported as an overflow, where the interpreter and the VM promote it.
+
+use std::collections::HashMap;
+
+use crate::datum::Datum;
+use crate::interp::RuntimeError;
+use crate::prim::{self, Arity, PRIMS};
+use crate::primsyn::Type;
+use crate::symbol::Symbol;
+
+pub const FIXNUM_SHIFT: i64 = 32;
+pub const PAIR_TAG: i64 = 1;
+pub const CLOSURE_TAG: i64 = 2;
+pub const VECTOR_TAG: i64 = 3;
+pub const STRING_TAG: i64 = 4;
+pub const RECORD_TAG: i64 = 5;
+/// The low bits of every immediate but fixnums
+pub const IMMEDIATE_TAG: i64 = 7;
+pub const FALSE: i64 = 0x07;
+pub const TRUE: i64 = 0x0F;
+pub const NULL: i64 = 0x17;
+pub const UNSPECIFIED: i64 = 0x1F;
+/// What a global holds until it's defined
+pub const UNBOUND: i64 = 0x27;
+/// The low byte of a character, whose code point sits above it
+pub const CHAR_TAG: i64 = 0x2F;
+/// What code returns after reporting a fault, never a value
+pub const FAULT: i64 = 0x37;
+pub const EOF: i64 = 0x3F;
+/// What a copying collector leaves in the first word of an object it moved,
+/// with the new pointer in the second
+pub const FORWARD: i64 = 0x47;
+/// What the C backend's procedures return to have their caller make a tail
+/// call for them, never a value
+pub const TAIL_CALL: i64 = 0x4F;
+/// The low byte of a symbol, whose index into the program's table of
+/// symbols sits above it
+pub const SYMBOL_TAG: i64 = 0x57;
+
+/// Why compiled code reported a fault to its runtime. Alongside comes a
+/// detail depending on the fault, and the offending value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The value called
+    NotAProcedure,
+    /// With the number of arguments given, and the closure called
+    WrongArgCount,
+    /// With the index of the primitive into `prim::PRIMS`, plus the index
+    /// of the argument shifted left by 16
+    WrongType,
+    /// With the `primsyn::Type` expected, as an index into `Type::ALL`
+    AnnotationFailed,
+    /// With the index of the primitive
+    DivisionByZero,
+    /// With the index of the primitive
+    Overflow,
+    /// With the index of the global
+    Unbound,
+}
+
+impl Fault {
+    /// Every fault, in the order declared, so `ALL[fault as usize] == fault`
+    pub const ALL: [Self; 7] = [
+        Self::NotAProcedure,
+        Self::WrongArgCount,
+        Self::WrongType,
+        Self::AnnotationFailed,
+        Self::DivisionByZero,
+        Self::Overflow,
+        Self::Unbound,
+    ];
+}
+
+pub fn fixnum(n: i64) -> i64 {
+    n << FIXNUM_SHIFT
+}
+
+/// A value, as the single word the native backends hold it in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Word(pub i64);
+
+/// What a word holds, going by its tag. Objects come with their address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tagged {
+    Fixnum(i32),
+    Bool(bool),
+    Char(char),
+    /// With its index into the program's table of symbols
+    Symbol(u32),
+    Null,
+    Unspecified,
+    Eof,
+    Unbound,
+    Pair(i64),
+    Closure(i64),
+    Vector(i64),
+    Str(i64),
+    Record(i64),
+    /// Nothing a program can hold, such as `FAULT` or a misaligned fixnum
+    Invalid,
+}
+
+impl Word {
+    pub const FALSE: Self = Self(FALSE);
+    pub const TRUE: Self = Self(TRUE);
+    pub const NULL: Self = Self(NULL);
+    pub const UNSPECIFIED: Self = Self(UNSPECIFIED);
+    pub const EOF: Self = Self(EOF);
+
+    pub fn fixnum(n: i32) -> Self {
+        Self(fixnum(i64::from(n)))
+    }
+
+    pub fn bool(b: bool) -> Self {
+        if b {
+            Self::TRUE
+        } else {
+            Self::FALSE
+        }
+    }
+
+    pub fn char(c: char) -> Self {
+        Self((i64::from(u32::from(c)) << 8) | CHAR_TAG)
+    }
+
+    /// The symbol at index `i` into the program's table of symbols
+    pub fn symbol(i: u32) -> Self {
+        Self((i64::from(i) << 8) | SYMBOL_TAG)
+    }
+
+    /// A pointer to the object at `addr`, a multiple of 8, tagged with `tag`
+    pub fn pointer(addr: i64, tag: i64) -> Self {
+        debug_assert!(addr & 0x07 == 0, "objects are aligned to 8 bytes");
+        Self(addr | tag)
+    }
+
+    pub fn tagged(self) -> Tagged {
+        let Self(word) = self;
+        if word & 0xFFFF_FFFF == 0 {
+            return Tagged::Fixnum((word >> FIXNUM_SHIFT) as i32);
+        }
+        let addr = word & !0x07;
+        match word & 0x07 {
+            PAIR_TAG => return Tagged::Pair(addr),
+            CLOSURE_TAG => return Tagged::Closure(addr),
+            VECTOR_TAG => return Tagged::Vector(addr),
+            STRING_TAG => return Tagged::Str(addr),
+            RECORD_TAG => return Tagged::Record(addr),
+            IMMEDIATE_TAG => (),
+            _ => return Tagged::Invalid,
+        }
+        match word {
+            FALSE => Tagged::Bool(false),
+            TRUE => Tagged::Bool(true),
+            NULL => Tagged::Null,
+            UNSPECIFIED => Tagged::Unspecified,
+            EOF => Tagged::Eof,
+            UNBOUND => Tagged::Unbound,
+            _ if word & 0xFF == CHAR_TAG => {
+                char::from_u32((word >> 8) as u32).map_or(Tagged::Invalid, Tagged::Char)
+            }
+            _ if word & 0xFF == SYMBOL_TAG => Tagged::Symbol((word >> 8) as u32),
+            _ => Tagged::Invalid,
+        }
+    }
+
+    /// The word for the constant `d`, if it needs neither an object made for
+    /// it nor an entry in the table of symbols
+    pub fn immediate(d: &Datum) -> Option<Self> {
+        match d {
+            Datum::Fixnum(n) => Some(Self::fixnum(*n)),
+            Datum::Bool(b) => Some(Self::bool(*b)),
+            Datum::Char(c) => Some(Self::char(*c)),
+            Datum::Null => Some(Self::NULL),
+            Datum::List(items) if items.is_empty() => Some(Self::NULL),
+            Datum::Eof => Some(Self::EOF),
+            Datum::Label(_) | Datum::Set(..) | Datum::Undefined => Some(Self::UNSPECIFIED),
+            _ => None,
+        }
+    }
+
+    /// Lay out the constant `d`, adding the symbols in it to `symbols`, with
+    /// `alloc` storing the words of each object somewhere and returning their
+    /// address
+    pub fn from_datum(
+        d: &Datum,
+        symbols: &mut Vec<Symbol>,
+        alloc: &mut dyn FnMut(&[i64]) -> i64,
+    ) -> Option<Self> {
+        if let Some(word) = Self::immediate(d) {
+            return Some(word);
+        }
+        let word = match d {
+            Datum::Symbol(sym) => Self::symbol(intern(symbols, *sym)),
+            Datum::List(items) => Self::list(items, &Datum::Null, symbols, alloc)?,
+            Datum::DottedList(items, tail) => Self::list(items, tail, symbols, alloc)?,
+            Datum::Vector(items) => {
+                let mut words = vec![items.len() as i64];
+                for item in items {
+                    words.push(Self::from_datum(item, symbols, alloc)?.0);
+                }
+                words.resize(words.len().max(2), 0);
+                Self::pointer(alloc(&words), VECTOR_TAG)
+            }
+            Datum::ByteVector(bytes) => {
+                let items = bytes.iter().map(|b| Self::fixnum(i32::from(*b)).0);
+                let mut words: Vec<i64> =
+                    std::iter::once(bytes.len() as i64).chain(items).collect();
+                words.resize(words.len().max(2), 0);
+                Self::pointer(alloc(&words), VECTOR_TAG)
+            }
+            Datum::Str(s) => {
+                let chars: Vec<i64> = s.chars().map(|c| i64::from(u32::from(c))).collect();
+                let mut words = vec![chars.len() as i64];
+                words.extend(
+                    chars
+                        .chunks(2)
+                        .map(|two| two[0] | two.get(1).map_or(0, |c| c << 32)),
+                );
+                words.resize(words.len().max(2), 0);
+                Self::pointer(alloc(&words), STRING_TAG)
+            }
+            _ => return None,
+        };
+        Some(word)
+    }
+
+    fn list(
+        items: &[Datum],
+        tail: &Datum,
+        symbols: &mut Vec<Symbol>,
+        alloc: &mut dyn FnMut(&[i64]) -> i64,
+    ) -> Option<Self> {
+        let mut word = Self::from_datum(tail, symbols, alloc)?;
+        for item in items.iter().rev() {
+            let car = Self::from_datum(item, symbols, alloc)?;
+            word = Self::pointer(alloc(&[car.0, word.0]), PAIR_TAG);
+        }
+        Some(word)
+    }
+}
+
+/// The index of `sym` into the table of symbols `symbols`, adding it if it's
+/// not there yet
+pub fn intern(symbols: &mut Vec<Symbol>, sym: Symbol) -> u32 {
+    let i = match symbols.iter().position(|s| *s == sym) {
+        Some(i) => i,
+        None => {
+            symbols.push(sym);
+            symbols.len() - 1
+        }
+    };
+    i as u32
+}
+
+/// The bits to mask a value with, and what's left when it has type `ty`
+pub fn type_test(ty: Type) -> Option<(i64, i64)> {
+    match ty {
+        // every number compiled code makes is a fixnum
+        Type::Fixnum | Type::Number => Some((0xFFFF_FFFF, 0)),
+        // `#f` and `#t` differ only in bit 3
+        Type::Bool => Some((!0x08, FALSE)),
+        Type::Char => Some((0xFF, CHAR_TAG)),
+        Type::Pair => Some((0x07, PAIR_TAG)),
+        Type::Proc => Some((0x07, CLOSURE_TAG)),
+        Type::Str => Some((0x07, STRING_TAG)),
+        Type::Vector => Some((0x07, VECTOR_TAG)),
+        Type::Symbol => Some((0xFF, SYMBOL_TAG)),
+    }
+}
+
+/// What a runtime needs to describe the values and faults of a program
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    /// The source names of the globals, by index
+    pub globals: Vec<Symbol>,
+    /// The function of every procedure, and the name it's bound to
+    pub procs: Vec<(String, Option<String>)>,
+    /// The symbols the program quotes, by the index their words hold
+    pub symbols: Vec<Symbol>,
+}
+
+/// The memory of a running program, as far as reading its values goes
+pub struct Memory<'m> {
+    /// The names of the procedures, by the code of their closures
+    pub names: &'m HashMap<i64, Option<String>>,
+    pub globals: &'m [Symbol],
+    /// The program's table of symbols
+    pub symbols: &'m [Symbol],
+    /// The word at an address
+    pub load: &'m dyn Fn(i64) -> i64,
+}
+
+impl<'m> Memory<'m> {
+    /// Write the value in `word` the way `Value` would, as `write` does if
+    /// `write` is set and as `display` does otherwise
+    pub fn show(&self, out: &mut String, word: i64, write: bool) {
+        let load = self.load;
+        match Word(word).tagged() {
+            Tagged::Fixnum(n) => out.push_str(&n.to_string()),
+            Tagged::Bool(true) => out.push_str("#t"),
+            Tagged::Bool(false) => out.push_str("#f"),
+            Tagged::Null => out.push_str("()"),
+            Tagged::Unspecified => out.push_str("#<unspecified>"),
+            Tagged::Eof => out.push_str("#<eof>"),
+            Tagged::Char(c) if write => out.push_str(&Datum::Char(c).to_string()),
+            Tagged::Char(c) => out.push(c),
+            Tagged::Symbol(i) => match self.symbols.get(i as usize) {
+                Some(sym) => out.push_str(sym.as_str()),
+                None => out.push_str(&format!("#<unknown {word:#x}>")),
+            },
+            Tagged::Pair(addr) => {
+                out.push('(');
+                self.show(out, load(addr), write);
+                let mut tail = load(addr + 8);
+                loop {
+                    match Word(tail).tagged() {
+                        Tagged::Null => break,
+                        Tagged::Pair(addr) => {
+                            out.push(' ');
+                            self.show(out, load(addr), write);
+                            tail = load(addr + 8);
+                        }
+                        _ => {
+                            out.push_str(" . ");
+                            self.show(out, tail, write);
+                            break;
+                        }
+                    }
+                }
+                out.push(')');
+            }
+            Tagged::Closure(addr) => match self.names.get(&load(addr)) {
+                Some(Some(name)) => out.push_str(&format!("#<procedure {name}>")),
+                _ => out.push_str("#<procedure>"),
+            },
+            Tagged::Vector(addr) => {
+                out.push_str("#(");
+                for i in 0..load(addr) {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    self.show(out, load(addr + 8 * (i + 1)), write);
+                }
+                out.push(')');
+            }
+            Tagged::Str(addr) if write => out.push_str(&format!("{:?}", self.string(addr))),
+            Tagged::Str(addr) => out.push_str(&self.string(addr)),
+            Tagged::Record(addr) => {
+                out.push_str("#<");
+                self.show(out, load(addr), write);
+                out.push('>');
+            }
+            Tagged::Unbound | Tagged::Invalid => out.push_str(&format!("#<unknown {word:#x}>")),
+        }
+    }
+
+    /// The contents of the string at `addr`
+    fn string(&self, addr: i64) -> String {
+        (0..(self.load)(addr))
+            .map(|i| {
+                let two = (self.load)(addr + 8 + 8 * (i / 2));
+                let c = if i % 2 == 0 {
+                    two as u32
+                } else {
+                    (two >> 32) as u32
+                };
+                char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER)
+            })
+            .collect()
+    }
+
+    /// The constant that would make the value in `word`, unless it holds a
+    /// procedure or a record somewhere
+    pub fn datum(&self, word: i64) -> Option<Datum> {
+        let load = self.load;
+        let d = match Word(word).tagged() {
+            Tagged::Fixnum(n) => Datum::Fixnum(n),
+            Tagged::Bool(b) => Datum::Bool(b),
+            Tagged::Char(c) => Datum::Char(c),
+            Tagged::Symbol(i) => Datum::Symbol(*self.symbols.get(i as usize)?),
+            Tagged::Null => Datum::Null,
+            Tagged::Unspecified => Datum::Undefined,
+            Tagged::Eof => Datum::Eof,
+            Tagged::Pair(addr) => {
+                let mut items = vec![self.datum(load(addr))?];
+                let mut tail = load(addr + 8);
+                while let Tagged::Pair(addr) = Word(tail).tagged() {
+                    items.push(self.datum(load(addr))?);
+                    tail = load(addr + 8);
+                }
+                match self.datum(tail)? {
+                    Datum::Null => Datum::List(items),
+                    tail => Datum::DottedList(items, Box::new(tail)),
+                }
+            }
+            Tagged::Vector(addr) => Datum::Vector(
+                (0..load(addr))
+                    .map(|i| self.datum(load(addr + 8 * (i + 1))))
+                    .collect::<Option<_>>()?,
+            ),
+            Tagged::Str(addr) => Datum::Str(self.string(addr)),
+            Tagged::Closure(_) | Tagged::Record(_) | Tagged::Unbound | Tagged::Invalid => {
+                return None
+            }
+        };
+        Some(d)
+    }
+
+    /// `word` as `write` would print it, which for a value made only of
+    /// constants is how its `Datum` prints
+    pub fn shown(&self, word: i64) -> String {
+        if let Some(d) = self.datum(word) {
+            return d.to_string();
+        }
+        let mut out = String::new();
+        self.show(&mut out, word, true);
+        out
+    }
+
+    /// Describe a fault reported by compiled code
+    pub fn fault(&self, fault: Fault, detail: i64, value: i64) -> RuntimeError {
+        let prim = |detail: i64| PRIMS[(detail & 0xFFFF) as usize].name.to_owned();
+        match fault {
+            Fault::NotAProcedure => RuntimeError::NotAProcedure(self.shown(value)),
+            Fault::WrongArgCount => {
+                // the value called is a closure, `[code, arity, ...]`
+                let code = (self.load)(value - CLOSURE_TAG);
+                let arity = (self.load)(value + 8 - CLOSURE_TAG);
+                let proc = match self.names.get(&code) {
+                    Some(Some(name)) => name.clone(),
+                    _ => "procedure".to_owned(),
+                };
+                RuntimeError::WrongArgCount {
+                    proc,
+                    expected: Arity::Exactly(arity as usize),
+                    given: detail as usize,
+                }
+            }
+            Fault::WrongType => {
+                let proc = prim(detail);
+                let expected = prim::arg_kind(&proc, (detail >> 16) as usize)
+                    .expect("only arguments with a kind are checked")
+                    .describe();
+                RuntimeError::WrongType {
+                    proc,
+                    expected,
+                    given: self.shown(value),
+                }
+            }
+            Fault::AnnotationFailed => RuntimeError::AnnotationFailed {
+                expected: Type::ALL[detail as usize],
+                given: self.shown(value),
+            },
+            Fault::DivisionByZero => RuntimeError::DivisionByZero(prim(detail)),
+            Fault::Overflow => RuntimeError::Overflow(prim(detail)),
+            Fault::Unbound => {
+                RuntimeError::UnboundVariable(self.globals[detail as usize].to_string())
+            }
+        }
+    }
+}